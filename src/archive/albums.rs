@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::digest::Digest;
+use crate::archive::encryption::ArchiveCipher;
+
+/// File of the archive root holding the albums
+pub const ALBUMS_FILE: &str = "albums.ndjson";
+
+/// Named selection of photos, independent of their capture date and source
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Album {
+    #[serde(rename = "nam")]
+    pub name: String,
+    /// Digests of the photos, in the order they were added
+    #[serde(rename = "crc", default)]
+    pub photos: Vec<Digest>,
+}
+
+impl Album {
+    pub fn contains(&self, digest: &Digest) -> bool {
+        self.photos.contains(digest)
+    }
+}
+
+/// Albums of the archive, organizing its photos on top of the date and source layout.
+///
+/// Photos are referenced by digest: an album holds every copy of a photo, and keeps it across
+/// syncs, moves and re-imports. Album names are compared ignoring case.
+#[derive(Default)]
+pub struct Albums {
+    albums: Vec<Album>,
+}
+
+impl Albums {
+    /// Albums of the archive, none when the file was not created yet
+    pub fn load(target_base_dir: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<Self> {
+        let mut albums = Vec::new();
+        let file = match File::open(albums_path(target_base_dir)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        for res_line in BufReader::new(file).lines() {
+            let line = res_line?;
+            let line = match cipher {
+                Some(cipher) => cipher.open_line(&line)?,
+                None => line,
+            };
+            albums.push(serde_json::from_str(&line)?);
+        }
+        Ok(Self { albums })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Album> {
+        self.albums.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Album> {
+        self.albums.iter().find(|album| album.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Names of the albums holding the photo
+    pub fn names_of(&self, digest: &Digest) -> Vec<String> {
+        self.albums.iter()
+            .filter(|album| album.contains(digest))
+            .map(|album| album.name.clone())
+            .collect()
+    }
+
+    pub fn create(&mut self, name: &str) -> anyhow::Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("Album name is empty")
+        }
+        if self.get(name).is_some() {
+            anyhow::bail!("Album '{name}' already exists")
+        }
+        self.albums.push(Album { name: String::from(name), photos: Vec::new() });
+        Ok(())
+    }
+
+    /// Drop the album, its photos stay in the archive
+    pub fn delete(&mut self, name: &str) -> anyhow::Result<Album> {
+        let idx = self.position(name)?;
+        Ok(self.albums.remove(idx))
+    }
+
+    /// Add the photos to the album, returns how many it did not hold yet
+    pub fn add(&mut self, name: &str, digests: impl IntoIterator<Item = Digest>) -> anyhow::Result<usize> {
+        let idx = self.position(name)?;
+        let album = &mut self.albums[idx];
+        let mut known = album.photos.iter().cloned().collect::<HashSet<_>>();
+        let before = album.photos.len();
+        album.photos.extend(digests.into_iter().filter(|digest| known.insert(digest.clone())));
+        Ok(album.photos.len() - before)
+    }
+
+    /// Take the photos out of the album, returns how many it held
+    pub fn remove(&mut self, name: &str, digests: impl IntoIterator<Item = Digest>) -> anyhow::Result<usize> {
+        let idx = self.position(name)?;
+        let album = &mut self.albums[idx];
+        let digests = digests.into_iter().collect::<HashSet<_>>();
+        let before = album.photos.len();
+        album.photos.retain(|digest| !digests.contains(digest));
+        Ok(before - album.photos.len())
+    }
+
+    pub fn store(&self, target_base_dir: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<()> {
+        let path = albums_path(target_base_dir);
+        let temp_path = path.with_extension("ndjson.tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        for album in self.albums.iter() {
+            let line = serde_json::to_string(album)?;
+            let line = match cipher {
+                Some(cipher) => cipher.seal_line(&line)?,
+                None => line,
+            };
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> anyhow::Result<usize> {
+        self.albums.iter()
+            .position(|album| album.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow::anyhow!("No album named '{name}'"))
+    }
+}
+
+fn albums_path(target_base_dir: &Path) -> PathBuf {
+    target_base_dir.join(ALBUMS_FILE)
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::archive::albums::Albums;
+use crate::archive::common::build_record_paths;
+use crate::archive::digest::Digest;
+use crate::archive::encryption::{ArchiveCipher, ArchiveSecret};
+use crate::archive::exif_fields::ExifFields;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::motion_photo::MotionClip;
+use crate::archive::user_metadata::UserMetadata;
+use crate::archive::originals::OriginalsStore;
+use crate::archive::records_store::{PhotoArchiveJsonRow, PhotoArchiveRecordsStore};
+use crate::common::fs::list_mounted_partitions;
+use crate::repository::sources::{SourceJsonRow, SourcesRepo};
+
+/// Photo of the archive, as shown to users
+#[derive(Clone, Debug)]
+pub struct CatalogEntry {
+    pub source_id: String,
+    /// Path of the file relative to the source root
+    pub source_path: PathBuf,
+    pub timestamp: Option<NaiveDateTime>,
+    pub digest: Digest,
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Video of motion photos, within the original file
+    pub motion_clip: Option<MotionClip>,
+    /// Missing for images indexed without thumbnail
+    pub thumbnail_path: Option<PathBuf>,
+    /// Original stored in the archive, relative to its root
+    pub stored_original: Option<PathBuf>,
+    /// The file was deleted from the source after being archived
+    pub deleted: bool,
+    pub exif: ExifFields,
+    /// Caption set by the user, else the description embedded in the photo
+    pub caption: Option<String>,
+    /// Stars from 1 to 5 set by the user, else embedded in the photo
+    pub rating: Option<u8>,
+    pub favorite: bool,
+    /// Names of the albums holding the photo
+    pub albums: Vec<String>,
+    /// Tags attached to the photo by the user
+    pub tags: Vec<String>,
+}
+
+impl CatalogEntry {
+    pub fn file_name(&self) -> String {
+        self.source_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+    }
+}
+
+/// Content of a photo copied out of the archive
+pub struct PhotoFile {
+    pub file_name: String,
+    pub content: Vec<u8>,
+    /// The original file, not the thumbnail
+    pub original: bool,
+}
+
+/// Which photos of the catalog are selected, an empty filter matches all of them
+#[derive(Clone, Debug, Default)]
+pub struct CatalogFilter {
+    /// First capture day included, undated photos are excluded when a date bound is set
+    pub from: Option<NaiveDate>,
+    /// Last capture day included
+    pub to: Option<NaiveDate>,
+    pub source_id: Option<String>,
+    /// File name of the photo, compared ignoring case
+    pub name: Option<String>,
+    /// Digest of the photo as printed by the archive, compared ignoring case
+    pub digest: Option<String>,
+    /// Part of the make or model of the camera, compared ignoring case
+    pub camera: Option<String>,
+    /// Fewest stars of the photos, unrated ones are excluded
+    pub min_rating: Option<u8>,
+    /// Only the photos marked favorite
+    pub favorites: bool,
+    /// Only the motion photos
+    pub motion: bool,
+    /// Name of an album holding the photos, compared ignoring case
+    pub album: Option<String>,
+    /// Tags the photos must all have, compared ignoring case
+    pub tags: Vec<String>,
+}
+
+impl CatalogFilter {
+    pub fn matches(&self, entry: &CatalogEntry) -> bool {
+        let date = entry.timestamp.map(|ts| ts.date());
+        let in_range = match (self.from, self.to) {
+            (None, None) => true,
+            (from, to) => date.is_some_and(|date| from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to)),
+        };
+        in_range
+            && self.source_id.as_ref().is_none_or(|source_id| *source_id == entry.source_id)
+            && self.name.as_ref().is_none_or(|name| name.eq_ignore_ascii_case(&entry.file_name()))
+            && self.digest.as_ref().is_none_or(|digest| digest.eq_ignore_ascii_case(&entry.digest.to_string()))
+            && self.camera.as_ref().is_none_or(|camera| {
+                let camera = camera.to_lowercase();
+                [&entry.exif.make, &entry.exif.model].into_iter()
+                    .flatten()
+                    .any(|field| field.to_lowercase().contains(&camera))
+            })
+            && self.min_rating.is_none_or(|min_rating| entry.rating.is_some_and(|rating| rating >= min_rating))
+            && (!self.favorites || entry.favorite)
+            && (!self.motion || entry.motion_clip.is_some())
+            && self.album.as_ref().is_none_or(|album| entry.albums.iter().any(|name| name.eq_ignore_ascii_case(album)))
+            && self.tags.iter().all(|tag| entry.tags.iter().any(|entry_tag| entry_tag.eq_ignore_ascii_case(tag)))
+    }
+}
+
+/// Read-only view of the photos of an archive, sorted by capture date (undated ones last),
+/// knowing which of their sources are currently mounted
+pub struct Catalog {
+    target: PathBuf,
+    cipher: Option<Arc<ArchiveCipher>>,
+    originals: OriginalsStore,
+    entries: Vec<CatalogEntry>,
+    sources: Vec<SourceJsonRow>,
+    mount_points: HashMap<String, PathBuf>,
+}
+
+impl Catalog {
+    pub fn load(target: &Path, secret: Option<&ArchiveSecret>) -> anyhow::Result<Self> {
+        Self::load_rows(target, secret, |store, f| store.for_each(f))
+    }
+
+    /// Catalog of the photos with the given digest only, their rows are found through the
+    /// lookup file instead of reading the whole index
+    pub fn load_digest(target: &Path, secret: Option<&ArchiveSecret>, digest: &str) -> anyhow::Result<Self> {
+        Self::load_rows(target, secret, |store, f| {
+            store.find_by_digest(digest)?.into_iter().for_each(f);
+            Ok(())
+        })
+    }
+
+    fn load_rows(
+        target: &Path,
+        secret: Option<&ArchiveSecret>,
+        read_rows: impl FnOnce(&PhotoArchiveRecordsStore, &mut dyn FnMut(PhotoArchiveJsonRow)) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Self> {
+        let manifest = ArchiveManifest::load_or_default(target)?;
+        let cipher = manifest.cipher(secret)?;
+
+        let user_metadata = UserMetadata::load(target, cipher.as_deref())?;
+        let albums = Albums::load(target, cipher.as_deref())?;
+        // Later rows replace the earlier ones of the same source file
+        let mut entries = HashMap::new();
+        read_rows(&PhotoArchiveRecordsStore::with_cipher(target, cipher.clone()), &mut |row| {
+            let thumbnail_path = (!row.no_thumbnail())
+                .then(|| build_record_paths(&manifest.layout, target, &row).ok().zip(row.thumbnail_name(&manifest.layout).ok()))
+                .flatten()
+                .map(|(paths, thumbnail_name)| paths.img_path.join(thumbnail_name));
+            let user = user_metadata.get(row.digest());
+            let entry = CatalogEntry {
+                source_id: String::from(row.source_id()),
+                source_path: row.source_path(),
+                timestamp: row.timestamp(),
+                digest: row.digest().clone(),
+                size: row.size(),
+                width: row.width(),
+                height: row.height(),
+                motion_clip: row.motion_clip(),
+                thumbnail_path,
+                stored_original: row.original().map(Path::to_path_buf),
+                deleted: row.deleted_at().is_some(),
+                exif: row.exif_fields(),
+                caption: user
+                    .and_then(|metadata| metadata.caption.clone())
+                    .or_else(|| row.description().map(String::from)),
+                rating: user
+                    .and_then(|metadata| metadata.rating)
+                    .or(row.rating())
+                    .filter(|rating| *rating > 0),
+                favorite: user.is_some_and(|metadata| metadata.favorite),
+                albums: albums.names_of(row.digest()),
+                tags: user.map(|metadata| metadata.tags.clone()).unwrap_or_default(),
+            };
+            entries.insert((entry.source_id.clone(), entry.source_path.clone()), entry);
+        })?;
+        let mut entries = entries.into_values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            (a.timestamp.is_none(), a.timestamp, &a.source_id, &a.source_path)
+                .cmp(&(b.timestamp.is_none(), b.timestamp, &b.source_id, &b.source_path))
+        });
+
+        // Unsupported platforms or an unreadable mount table only mean that no source is mounted
+        let sources = SourcesRepo::new(target.to_path_buf()).all()?;
+        let mount_points = list_mounted_partitions()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|partition| {
+                let source = sources.iter().find(|source| source.identified_by(&partition.info.partition_id))?;
+                Some((source.id.clone(), partition.mount_point))
+            })
+            .collect();
+
+        Ok(Self {
+            target: target.to_path_buf(),
+            originals: manifest.originals_store(target, cipher.clone()),
+            cipher,
+            entries,
+            sources,
+            mount_points,
+        })
+    }
+
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    pub fn filter<'a>(&'a self, filter: &'a CatalogFilter) -> impl Iterator<Item = &'a CatalogEntry> + 'a {
+        self.entries.iter().filter(|entry| filter.matches(entry))
+    }
+
+    pub fn sources(&self) -> &[SourceJsonRow] {
+        &self.sources
+    }
+
+    /// Name of the source of the entry, its id when it is no longer registered
+    pub fn source_name<'a>(&'a self, entry: &'a CatalogEntry) -> &'a str {
+        self.sources.iter()
+            .find(|source| source.id == entry.source_id)
+            .map(|source| source.name.as_str())
+            .unwrap_or(&entry.source_id)
+    }
+
+    /// Path of the photo on its source, when the source is mounted and the file still there
+    pub fn mounted_original(&self, entry: &CatalogEntry) -> Option<PathBuf> {
+        self.mount_points.get(&entry.source_id)
+            .map(|mount_point| mount_point.join(&entry.source_path))
+            .filter(|path| path.is_file())
+    }
+
+    /// Content of the thumbnail of the entry, decrypted if needed
+    pub fn read_thumbnail(&self, entry: &CatalogEntry) -> anyhow::Result<Vec<u8>> {
+        let thumbnail_path = entry.thumbnail_path.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("{} was indexed without thumbnail", entry.source_path.display()))?;
+        let content = fs::read(thumbnail_path)?;
+        match &self.cipher {
+            Some(cipher) => cipher.open(&content),
+            None => Ok(content),
+        }
+    }
+
+    /// Content of the original of the entry, read from its mounted source or from the archive
+    pub fn read_original(&self, entry: &CatalogEntry) -> anyhow::Result<Option<PhotoFile>> {
+        let content = if let Some(original) = self.mounted_original(entry) {
+            fs::read(original)?
+        } else if let Some(stored_original) = entry.stored_original.as_ref().filter(|path| self.target.join(path).is_file()) {
+            let mut content = Vec::new();
+            self.originals.open(stored_original)?.read_to_end(&mut content)?;
+            content
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(PhotoFile { file_name: entry.file_name(), content, original: true }))
+    }
+
+    /// Content of the original of the entry when available, else of its thumbnail
+    pub fn read_photo(&self, entry: &CatalogEntry) -> anyhow::Result<PhotoFile> {
+        if let Some(original) = self.read_original(entry)? {
+            return Ok(original);
+        }
+        let stem = entry.source_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(PhotoFile { file_name: format!("{stem}.jpg"), content: self.read_thumbnail(entry)?, original: false })
+    }
+
+    /// Tags of the registered source of the entry
+    pub fn source_tags(&self, entry: &CatalogEntry) -> &[String] {
+        self.sources.iter()
+            .find(|source| source.id == entry.source_id)
+            .map(|source| source.tags.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// File an external viewer can open for the entry: the original on its mounted source, else
+    /// the one stored in the archive, else the thumbnail.
+    ///
+    /// Encrypted or compressed files are first extracted into a temporary directory.
+    pub fn viewable_file(&self, entry: &CatalogEntry) -> anyhow::Result<PathBuf> {
+        if let Some(original) = self.mounted_original(entry) {
+            return Ok(original);
+        }
+
+        let extension = entry.source_path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+        if let Some(stored_original) = entry.stored_original.as_ref().filter(|path| self.target.join(path).is_file()) {
+            let stored_path = self.target.join(stored_original);
+            if self.cipher.is_none() && stored_path.extension() == entry.source_path.extension() {
+                return Ok(stored_path);
+            }
+            let mut content = Vec::new();
+            self.originals.open(stored_original)?.read_to_end(&mut content)?;
+            return extract_temp_file(&format!("{}.{extension}", entry.digest), &content);
+        }
+
+        self.thumbnail_file(entry)
+    }
+
+    /// Thumbnail file of the entry, first decrypted into a temporary directory for encrypted archives
+    pub fn thumbnail_file(&self, entry: &CatalogEntry) -> anyhow::Result<PathBuf> {
+        match (&entry.thumbnail_path, &self.cipher) {
+            (Some(thumbnail_path), None) => Ok(thumbnail_path.clone()),
+            _ => extract_temp_file(&format!("{}-thumbnail.jpg", entry.digest), &self.read_thumbnail(entry)?),
+        }
+    }
+}
+
+/// Write a decrypted or decompressed file where viewers can read it
+fn extract_temp_file(file_name: &str, content: &[u8]) -> anyhow::Result<PathBuf> {
+    let temp_dir = std::env::temp_dir().join("photo-archive");
+    fs::create_dir_all(&temp_dir)?;
+    let path = temp_dir.join(file_name);
+    fs::write(&path, content)?;
+    Ok(path)
+}
@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use image::{DynamicImage, GrayImage, ImageOutputFormat, RgbImage};
+use jpeg_decoder::PixelFormat;
+
+pub struct DecodedImage {
+    pub image: DynamicImage,
+    /// Width of the original image, larger than the decoded one when decoded at a reduced scale
+    pub width: u32,
+    /// Height of the original image, larger than the decoded one when decoded at a reduced scale
+    pub height: u32,
+}
+
+impl From<DynamicImage> for DecodedImage {
+    fn from(image: DynamicImage) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            image,
+        }
+    }
+}
+
+/// Decode an image from disk.
+///
+/// With the `turbojpeg` feature enabled JPEG files are decoded through libjpeg-turbo, falling back
+/// to the pure-Rust `image` decoder for anything it cannot handle.
+pub fn decode_image(image_path: &Path) -> anyhow::Result<DynamicImage> {
+    #[cfg(feature = "turbojpeg")]
+    match turbo::decode(image_path) {
+        Ok(img) => return Ok(img),
+        Err(err) => eprintln!("Error decoding {image_path:?} with libjpeg-turbo, falling back - {err}"),
+    }
+
+    Ok(image::open(image_path)?)
+}
+
+/// Decode a JPEG at the smallest DCT scale (1/8, 1/4, 1/2) still producing an image of at least
+/// `min_size` pixels on its longest side, falling back to a full decode for other formats.
+///
+/// Since only a thumbnail is needed this cuts decode time and memory by an order of magnitude on
+/// large photos.
+pub fn decode_image_scaled(image_path: &Path, min_size: u32) -> anyhow::Result<DecodedImage> {
+    #[cfg(feature = "turbojpeg")]
+    match turbo::decode_scaled(image_path, min_size) {
+        Ok(decoded) => return Ok(decoded),
+        Err(err) => eprintln!("Error decoding {image_path:?} with libjpeg-turbo, falling back - {err}"),
+    }
+
+    match decode_jpeg_scaled(image_path, min_size) {
+        Ok(Some(decoded)) => Ok(decoded),
+        Ok(None) | Err(_) => Ok(DecodedImage::from(image::open(image_path)?)),
+    }
+}
+
+fn decode_jpeg_scaled(image_path: &Path, min_size: u32) -> anyhow::Result<Option<DecodedImage>> {
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(File::open(image_path)?));
+    decoder.read_info()?;
+    let info = decoder.info().ok_or_else(|| anyhow::anyhow!("Missing jpeg header"))?;
+
+    let requested_size = u16::try_from(min_size).unwrap_or(u16::MAX);
+    let (scaled_width, scaled_height) = decoder.scale(requested_size, requested_size)?;
+    let pixels = decoder.decode()?;
+
+    let image = match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(scaled_width as u32, scaled_height as u32, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::RGB24 => RgbImage::from_raw(scaled_width as u32, scaled_height as u32, pixels).map(DynamicImage::ImageRgb8),
+        PixelFormat::L16 | PixelFormat::CMYK32 => None,
+    };
+
+    Ok(image.map(|image| DecodedImage {
+        image,
+        width: info.width as u32,
+        height: info.height as u32,
+    }))
+}
+
+/// Encode an image as JPEG with the given quality (1-100).
+pub fn encode_jpeg(img: &DynamicImage, quality: u8) -> anyhow::Result<Vec<u8>> {
+    #[cfg(feature = "turbojpeg")]
+    match turbo::encode(img, quality) {
+        Ok(encoded) => return Ok(encoded),
+        Err(err) => eprintln!("Error encoding with libjpeg-turbo, falling back - {err}"),
+    }
+
+    let mut encoded = Vec::new();
+    img.write_to(&mut Cursor::new(&mut encoded), ImageOutputFormat::Jpeg(quality))?;
+    Ok(encoded)
+}
+
+#[cfg(feature = "turbojpeg")]
+mod turbo {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::path::Path;
+
+    use anyhow::anyhow;
+    use image::{DynamicImage, GrayImage, RgbImage};
+    use mozjpeg::{ColorSpace, Compress, Decompress, Format};
+
+    use crate::archive::codec::DecodedImage;
+
+    /// libjpeg reports fatal errors by unwinding, turn them back into plain errors
+    fn guarded<T>(f: impl FnOnce() -> std::io::Result<T>) -> anyhow::Result<T> {
+        match catch_unwind(AssertUnwindSafe(f)) {
+            Ok(out) => Ok(out?),
+            Err(cause) => {
+                let msg = cause.downcast_ref::<String>().cloned().unwrap_or_else(|| String::from("unknown error"));
+                Err(anyhow!("libjpeg error - {msg}"))
+            }
+        }
+    }
+
+    pub fn decode(image_path: &Path) -> anyhow::Result<DynamicImage> {
+        decode_with_scale(image_path, 8)
+    }
+
+    pub fn decode_scaled(image_path: &Path, min_size: u32) -> anyhow::Result<DecodedImage> {
+        let (width, height) = guarded(|| Ok(Decompress::new_path(image_path)?.size()))?;
+        let longest_side = width.max(height) as u32;
+        let numerator = (1..8)
+            .find(|numerator| longest_side * numerator / 8 >= min_size)
+            .unwrap_or(8);
+
+        Ok(DecodedImage {
+            image: decode_with_scale(image_path, numerator as u8)?,
+            width: width as u32,
+            height: height as u32,
+        })
+    }
+
+    fn decode_with_scale(image_path: &Path, numerator: u8) -> anyhow::Result<DynamicImage> {
+        let decoded = guarded(|| {
+            let mut decompress = Decompress::new_path(image_path)?;
+            decompress.scale(numerator);
+            match decompress.image()? {
+                Format::RGB(mut started) => {
+                    let (width, height) = (started.width() as u32, started.height() as u32);
+                    let pixels = started.read_scanlines::<u8>()?;
+                    started.finish()?;
+                    Ok(RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8))
+                }
+                Format::Gray(mut started) => {
+                    let (width, height) = (started.width() as u32, started.height() as u32);
+                    let pixels = started.read_scanlines::<u8>()?;
+                    started.finish()?;
+                    Ok(GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8))
+                }
+                Format::CMYK(_) => Ok(None),
+            }
+        })?;
+
+        decoded.ok_or_else(|| anyhow!("unsupported color space"))
+    }
+
+    pub fn encode(img: &DynamicImage, quality: u8) -> anyhow::Result<Vec<u8>> {
+        let rgb = img.to_rgb8();
+        guarded(|| {
+            let mut compress = Compress::new(ColorSpace::JCS_RGB);
+            compress.set_size(rgb.width() as usize, rgb.height() as usize);
+            compress.set_quality(quality as f32);
+            let mut started = compress.start_compress(Vec::new())?;
+            started.write_scanlines(rgb.as_raw())?;
+            started.finish()
+        })
+    }
+}
@@ -1,8 +1,9 @@
-use std::os::unix::prelude::OsStrExt;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
-use chrono::{Datelike, DateTime, NaiveDateTime, Utc};
-use crate::archive::sync::CASTAGNOLI;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::archive::digest::Digest;
+use crate::archive::layout::{crc_link_dir, ArchiveLayout};
+use crate::archive::records_store::PhotoArchiveJsonRow;
 
 pub struct ArchivedPhotoPaths {
     pub date_path: PathBuf,
@@ -11,32 +12,19 @@ pub struct ArchivedPhotoPaths {
     pub link_file_path: PathBuf,
 }
 
+/// Archive paths of a photo, `link_dir` is the link directory relative to the date directory
 pub fn build_paths(
-    partition_crc: u32,
+    layout: &ArchiveLayout,
     target_base_dir: &Path,
+    link_dir: &Path,
     source_relative_path: &Path,
     photo_timestamp: Option<&NaiveDateTime>,
 ) -> anyhow::Result<ArchivedPhotoPaths> {
-    let date_path = if let Some(datetime) = photo_timestamp {
-        target_base_dir
-            .join(datetime.year().to_string())
-            .join(datetime.format("%m.%d").to_string())
-    } else {
-        target_base_dir.join("no-date")
-    };
+    let date_path = target_base_dir.join(layout.date_dir(photo_timestamp));
 
     let img_path = date_path.join("img");
 
-    let source_dir = source_relative_path.parent().expect("No source dir found");
-    let link_dir_path = date_path.join(format!(
-        "{:08X}.{:08X}.{}",
-        partition_crc,
-        CASTAGNOLI.checksum(source_dir.as_os_str().as_bytes()),
-        source_dir
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("ROOT"),
-    ));
+    let link_dir_path = date_path.join(link_dir);
     let link_file_path = link_dir_path.join(source_relative_path.file_name().expect("Error extracting filename"));
 
     Ok(ArchivedPhotoPaths {
@@ -47,22 +35,47 @@ pub fn build_paths(
     })
 }
 
+/// Build the archive paths of an already indexed photo
+pub fn build_record_paths(layout: &ArchiveLayout, target_base_dir: &Path, row: &PhotoArchiveJsonRow) -> anyhow::Result<ArchivedPhotoPaths> {
+    let source_path = row.source_path();
+    let link_dir = row.link_dir()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| crc_link_dir(row.source_id(), &source_path));
+    build_paths(
+        layout,
+        target_base_dir,
+        &link_dir,
+        &source_path,
+        row.timestamp().as_ref(),
+    )
+}
+
+/// Append a disambiguating suffix to a thumbnail file name, `seq` 0 leaves it untouched
+pub fn disambiguate_filename(file_name: &str, seq: u32) -> String {
+    match (seq, file_name.rsplit_once('.')) {
+        (0, _) => String::from(file_name),
+        (_, Some((stem, ext))) => format!("{stem}-{seq}.{ext}"),
+        (_, None) => format!("{file_name}-{seq}"),
+    }
+}
+
 pub fn build_filename(
+    layout: &ArchiveLayout,
     photo_ts: Option<&NaiveDateTime>,
     file_ts: SystemTime,
-    crc: u32,
+    digest: &Digest,
 ) -> anyhow::Result<String> {
     let file_name = if let Some(datetime) = photo_ts {
         format!(
-            "{}_{:08X}.jpg",
-            datetime.format("%H%M%S"),
-            crc,
+            "{}_{}.jpg",
+            datetime.format(layout.time_format()),
+            digest,
         )
     } else {
         format!(
-            "{}_{:08X}.jpg",
+            "{}_{}.jpg",
             DateTime::<Utc>::from(file_ts).format("%Y%m%d-%H%M%S"),
-            crc,
+            digest,
         )
     };
 
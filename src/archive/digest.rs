@@ -0,0 +1,155 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use image::DynamicImage;
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::archive::sync::CASTAGNOLI;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    /// CRC32 (Castagnoli) of the decoded pixels, the historical archive digest
+    #[default]
+    Crc32,
+    /// CRC32 (Castagnoli) of the original file bytes
+    #[serde(rename = "crc32-file")]
+    Crc32File,
+    /// xxHash64 of the original file bytes
+    Xxh64,
+    /// BLAKE3 of the original file bytes
+    Blake3,
+}
+
+impl Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DigestAlgorithm::Crc32 => write!(f, "crc32"),
+            DigestAlgorithm::Crc32File => write!(f, "crc32-file"),
+            DigestAlgorithm::Xxh64 => write!(f, "xxh64"),
+            DigestAlgorithm::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "crc32" => Ok(DigestAlgorithm::Crc32),
+            "crc32-file" => Ok(DigestAlgorithm::Crc32File),
+            "xxh64" => Ok(DigestAlgorithm::Xxh64),
+            "blake3" => Ok(DigestAlgorithm::Blake3),
+            other => anyhow::bail!("Unknown digest algorithm '{other}', expected one of crc32, crc32-file, xxh64, blake3"),
+        }
+    }
+}
+
+/// Digest identifying the content of an archived photo.
+///
+/// File digests are streamed from disk so that, when the thumbnail already exists, the photo
+/// does not need to be decoded at all. CRC32 digests are kept numeric to stay compatible with the
+/// existing index files, the other algorithms are stored as uppercase hex strings.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(untagged)]
+pub enum Digest {
+    Crc32(u32),
+    Hex(String),
+}
+
+// Written by hand, the derived untagged deserializer buffers every value before trying the
+// variants, a noticeable cost when whole indexes are read
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DigestVisitor;
+
+        impl Visitor<'_> for DigestVisitor {
+            type Value = Digest;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                write!(f, "a CRC32 number or a hex string")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                u32::try_from(v).map(Digest::Crc32).map_err(|_| E::invalid_value(Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u32::try_from(v).map(Digest::Crc32).map_err(|_| E::invalid_value(Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Digest::Hex(String::from(v)))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+                Ok(Digest::Hex(v))
+            }
+        }
+
+        deserializer.deserialize_any(DigestVisitor)
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Digest::Crc32(crc) => write!(f, "{crc:08X}"),
+            Digest::Hex(hex) => write!(f, "{hex}"),
+        }
+    }
+}
+
+impl DigestAlgorithm {
+    /// Whether the digest is computed on the original file bytes instead of the decoded pixels
+    pub fn is_file_based(&self) -> bool {
+        !matches!(self, DigestAlgorithm::Crc32)
+    }
+}
+
+pub fn digest_pixels(img: &DynamicImage) -> Digest {
+    Digest::Crc32(CASTAGNOLI.checksum(img.as_bytes()))
+}
+
+fn for_each_chunk(path: &Path, mut f: impl FnMut(&[u8])) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        f(&buf[..read]);
+    }
+}
+
+pub fn digest_file(path: &Path, algorithm: DigestAlgorithm) -> anyhow::Result<Digest> {
+    let digest = match algorithm {
+        DigestAlgorithm::Crc32 => anyhow::bail!("crc32 digests are computed on decoded pixels"),
+        DigestAlgorithm::Crc32File => {
+            let mut digest = CASTAGNOLI.digest();
+            for_each_chunk(path, |chunk| digest.update(chunk))?;
+            Digest::Crc32(digest.finalize())
+        }
+        DigestAlgorithm::Xxh64 => {
+            let mut hasher = Xxh64::new(0);
+            for_each_chunk(path, |chunk| hasher.update(chunk))?;
+            Digest::Hex(format!("{:016X}", hasher.digest()))
+        }
+        DigestAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            for_each_chunk(path, |chunk| {
+                hasher.update(chunk);
+            })?;
+            Digest::Hex(hasher.finalize().to_hex().to_uppercase())
+        }
+    };
+
+    Ok(digest)
+}
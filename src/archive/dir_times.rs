@@ -0,0 +1,158 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::encryption::ArchiveCipher;
+
+const DIR_TIMES_DIR: &str = "scans";
+/// Directories modified this close to the scan are read again by the next one, whatever the
+/// resolution of the filesystem timestamps (2 seconds on FAT)
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct DirEntry {
+    /// Path relative to the source root
+    #[serde(rename = "dir")]
+    path: PathBuf,
+    #[serde(rename = "mts")]
+    mtime_secs: u64,
+    #[serde(rename = "mtn")]
+    mtime_nanos: u32,
+    /// Names of the subdirectories
+    #[serde(rename = "sub", default, skip_serializing_if = "Vec::is_empty")]
+    subdirs: Vec<String>,
+    /// Digest of the options the directory was scanned and its files archived with
+    #[serde(rename = "opt", default)]
+    options: u32,
+}
+
+/// Modification times of the source directories read by the last sync, with their subdirectories.
+///
+/// The modification time of a directory changes when entries are added, removed or renamed in
+/// it, not when a file is rewritten in place: the scanner does not read again the directories
+/// that kept it, only their subdirectories are looked at. Filesystems not updating directory
+/// times, or files edited in place, call for a full scan. Directories with subdirectories whose
+/// name is not UTF-8 are not recorded, they are read at every scan.
+pub struct DirTimes {
+    previous: HashMap<PathBuf, DirEntry>,
+    /// Digest of the scan and filter options of this sync, times recorded with others are ignored
+    options: u32,
+    current: Mutex<HashMap<PathBuf, DirEntry>>,
+    failed: Mutex<HashSet<PathBuf>>,
+    started_at: SystemTime,
+    /// Some directory was not read, its files were not handed to the workers
+    skipped: AtomicBool,
+}
+
+impl DirTimes {
+    /// Times recorded by the last sync of the source, `full_scan` ignores them and so do changed
+    /// `options`, files left out by the previous ones may be wanted now
+    pub fn load(target_base_dir: &Path, source_id: &str, cipher: Option<&ArchiveCipher>, full_scan: bool, options: u32) -> anyhow::Result<Self> {
+        let mut previous = HashMap::new();
+        let file = match File::open(dir_times_path(target_base_dir, source_id)) {
+            Ok(file) if !full_scan => Some(file),
+            Ok(_) => None,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        for res_line in file.into_iter().flat_map(|file| BufReader::new(file).lines()) {
+            let line = res_line?;
+            let line = match cipher {
+                Some(cipher) => cipher.open_line(&line)?,
+                None => line,
+            };
+            let entry: DirEntry = serde_json::from_str(&line)?;
+            if entry.options == options {
+                previous.insert(entry.path.clone(), entry);
+            }
+        }
+        Ok(Self {
+            previous,
+            options,
+            current: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashSet::new()),
+            started_at: SystemTime::now(),
+            skipped: AtomicBool::new(false),
+        })
+    }
+
+    /// Subdirectories of `relative_dir` when it did not change since the last sync, which are
+    /// then all it has to scan
+    pub fn unchanged(&self, relative_dir: &Path, metadata: &fs::Metadata) -> Option<Vec<String>> {
+        let (mtime_secs, mtime_nanos) = mtime(metadata)?;
+        let entry = self.previous.get(relative_dir)
+            .filter(|entry| entry.mtime_secs == mtime_secs && entry.mtime_nanos == mtime_nanos)?;
+        self.skipped.store(true, Ordering::Relaxed);
+        self.current.lock().expect("Dir times lock poisoned").insert(relative_dir.to_path_buf(), entry.clone());
+        Some(entry.subdirs.clone())
+    }
+
+    /// Remember a directory read by the scan, `metadata` being taken before reading it
+    pub fn read(&self, relative_dir: &Path, metadata: &fs::Metadata, subdirs: Vec<String>) {
+        let Some((mtime_secs, mtime_nanos)) = mtime(metadata) else {
+            return;
+        };
+        let settled = metadata.modified().is_ok_and(|modified| modified + SETTLE_TIME < self.started_at);
+        // Recorded paths are JSON strings
+        if settled && relative_dir.to_str().is_some() {
+            self.current.lock().expect("Dir times lock poisoned").insert(relative_dir.to_path_buf(), DirEntry {
+                path: relative_dir.to_path_buf(),
+                mtime_secs,
+                mtime_nanos,
+                subdirs,
+                options: self.options,
+            });
+        }
+    }
+
+    /// Forget `relative_dir`, a file of which could not be archived, so that the next scan reads it
+    pub fn failed(&self, relative_dir: &Path) {
+        self.failed.lock().expect("Dir times lock poisoned").insert(relative_dir.to_path_buf());
+    }
+
+    /// Whether the scan left some directories unread
+    pub fn has_skipped(&self) -> bool {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Replace the times of the source with the ones of the directories found by this scan
+    pub fn store(&self, target_base_dir: &Path, source_id: &str, cipher: Option<&ArchiveCipher>) -> anyhow::Result<()> {
+        let path = dir_times_path(target_base_dir, source_id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("ndjson.tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        let failed = self.failed.lock().expect("Dir times lock poisoned");
+        let current = self.current.lock().expect("Dir times lock poisoned");
+        for entry in current.values().filter(|entry| !failed.contains(&entry.path)) {
+            let line = serde_json::to_string(entry)?;
+            let line = match cipher {
+                Some(cipher) => cipher.seal_line(&line)?,
+                None => line,
+            };
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+fn mtime(metadata: &fs::Metadata) -> Option<(u64, u32)> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+fn dir_times_path(target_base_dir: &Path, source_id: &str) -> PathBuf {
+    target_base_dir.join(DIR_TIMES_DIR).join(format!("{source_id}.ndjson"))
+}
@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::archive::common::build_record_paths;
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::PhotoArchiveRecordsStore;
+use crate::repository::sources::{SourceJsonRow, SourcesRepo};
+
+/// Affected items listed by each finding, the others are only counted
+const MAX_EXAMPLES: usize = 5;
+const PROBE_FILE: &str = ".doctor-probe";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Problem found in the archive, with a few of the affected items and how to fix it
+#[derive(Debug)]
+pub struct DoctorFinding {
+    pub severity: Severity,
+    pub problem: String,
+    pub count: usize,
+    pub examples: Vec<String>,
+    pub suggestion: &'static str,
+}
+
+#[derive(Default)]
+struct Findings {
+    findings: Vec<DoctorFinding>,
+}
+
+impl Findings {
+    fn add(&mut self, severity: Severity, problem: &str, suggestion: &'static str, example: impl Into<String>) {
+        let finding = match self.findings.iter_mut().position(|finding| finding.problem == problem) {
+            Some(idx) => &mut self.findings[idx],
+            None => {
+                self.findings.push(DoctorFinding {
+                    severity,
+                    problem: String::from(problem),
+                    count: 0,
+                    examples: Vec::new(),
+                    suggestion,
+                });
+                self.findings.last_mut().expect("Finding just added")
+            }
+        };
+        finding.count += 1;
+        if finding.examples.len() < MAX_EXAMPLES {
+            finding.examples.push(example.into());
+        }
+    }
+}
+
+/// Check the archive end-to-end without changing it: manifest, sources, index rows, thumbnails,
+/// links, originals and permissions. An empty result means that no problem was found.
+pub fn diagnose_archive(target: &Path, secret: Option<&ArchiveSecret>) -> anyhow::Result<Vec<DoctorFinding>> {
+    let mut findings = Findings::default();
+    let sources_repo = SourcesRepo::new(target.to_path_buf());
+
+    check_writable(target, &mut findings);
+    let manifest = match ArchiveManifest::load(target) {
+        Ok(Some(manifest)) => Some(manifest),
+        Ok(None) if sources_repo.exists() => Some(ArchiveManifest::default()),
+        Ok(None) => anyhow::bail!("{target:?} is not a photo archive"),
+        Err(err) => {
+            findings.add(
+                Severity::Error,
+                "Manifest cannot be read",
+                "Upgrade photo-archive if the archive was written by a newer release, otherwise restore manifest.toml with restore-metadata",
+                err.to_string(),
+            );
+            None
+        }
+    };
+    let sources = check_sources(target, &mut findings)?;
+
+    // Without the manifest the paths of thumbnails and links are unknown
+    if let Some(manifest) = manifest {
+        match manifest.cipher(secret) {
+            Ok(cipher) => check_records(target, &manifest, PhotoArchiveRecordsStore::with_cipher(target, cipher), &sources, &mut findings)?,
+            Err(err) => findings.add(
+                Severity::Warning,
+                "Index rows were not checked",
+                "Provide the passphrase or key file of the archive",
+                err.to_string(),
+            ),
+        }
+    }
+    check_tree(target, &mut findings);
+
+    let mut findings = findings.findings;
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    Ok(findings)
+}
+
+fn check_writable(target: &Path, findings: &mut Findings) {
+    let probe_path = target.join(PROBE_FILE);
+    if let Err(err) = fs::write(&probe_path, b"").and_then(|_| fs::remove_file(&probe_path)) {
+        findings.add(
+            Severity::Error,
+            "Archive directory is not writable",
+            "Check the owner and the permissions of the archive directory, or whether its filesystem is mounted read-only",
+            format!("{} - {err}", target.display()),
+        );
+    }
+}
+
+fn check_sources(target: &Path, findings: &mut Findings) -> anyhow::Result<HashSet<String>> {
+    let sources_path = target.join("sources.ndjson");
+    let mut sources = HashSet::new();
+    if !sources_path.is_file() {
+        return Ok(sources);
+    }
+
+    let content = match fs::read_to_string(&sources_path) {
+        Ok(content) => content,
+        Err(err) => {
+            findings.add(Severity::Error, "Sources cannot be read", "Check the permissions of sources.ndjson", err.to_string());
+            return Ok(sources);
+        }
+    };
+    for (idx, line) in content.lines().enumerate() {
+        match serde_json::from_str::<SourceJsonRow>(line) {
+            Ok(source) => {
+                if !sources.insert(source.id.clone()) {
+                    findings.add(
+                        Severity::Error,
+                        "Source ids registered more than once",
+                        "Remove the duplicated entries from sources.ndjson, keeping the one with the right name and group",
+                        source.id,
+                    );
+                }
+            }
+            Err(err) => findings.add(
+                Severity::Error,
+                "Unreadable lines in sources.ndjson",
+                "Fix or remove the malformed lines, the sources they describe are ignored",
+                format!("line {} - {err}", idx + 1),
+            ),
+        }
+    }
+    Ok(sources)
+}
+
+fn check_records(
+    target: &Path,
+    manifest: &ArchiveManifest,
+    store: PhotoArchiveRecordsStore,
+    sources: &HashSet<String>,
+    findings: &mut Findings,
+) -> anyhow::Result<()> {
+    let linker = manifest.linker(target)?;
+    let mut unknown_sources = HashMap::new();
+    let out = store.for_each_line(|index_path, line, row| {
+        let row = match row {
+            Ok(row) => row,
+            Err(err) => {
+                findings.add(
+                    Severity::Error,
+                    "Unreadable index lines",
+                    "Restore the index from a metadata snapshot with restore-metadata, or remove the malformed lines",
+                    format!("{}:{line} - {err}", index_path.display()),
+                );
+                return;
+            }
+        };
+
+        if !sources.contains(row.source_id()) {
+            *unknown_sources.entry(String::from(row.source_id())).or_insert(0) += 1;
+        }
+        if let Some(original) = row.original().filter(|original| !target.join(original).exists()) {
+            findings.add(
+                Severity::Error,
+                "Stored originals are missing",
+                "Sync the source again with originals enabled, or restore them from a mirror",
+                original.display().to_string(),
+            );
+        }
+        if row.no_thumbnail() {
+            return;
+        }
+
+        let paths = match build_record_paths(&manifest.layout, target, &row) {
+            Ok(paths) => paths,
+            Err(err) => {
+                findings.add(Severity::Error, "Index rows with invalid paths", "Remove the rows from the index", err.to_string());
+                return;
+            }
+        };
+        let thumbnail_exists = row.thumbnail_name(&manifest.layout)
+            .is_ok_and(|thumbnail_name| paths.img_path.join(thumbnail_name).exists());
+        if !thumbnail_exists {
+            findings.add(
+                Severity::Error,
+                "Thumbnails are missing from the img directories",
+                "Restore them from a mirror, or remove the source and import it again",
+                row.source_path().display().to_string(),
+            );
+        }
+        if !linker.link_exists(&paths) {
+            findings.add(
+                Severity::Warning,
+                "Archived photos have no link",
+                "Run repair-links to recreate them from the index",
+                paths.link_file_path.display().to_string(),
+            );
+        }
+    });
+    if let Err(err) = out {
+        findings.add(Severity::Error, "Index files cannot be read", "Check the permissions of the index files", err.to_string());
+    }
+
+    for (source_id, rows) in unknown_sources {
+        findings.add(
+            Severity::Warning,
+            "Index rows reference unregistered sources",
+            "Register the source again with import-source, or drop its rows with remove-source",
+            format!("{source_id} ({rows} rows)"),
+        );
+    }
+    Ok(())
+}
+
+/// Walk the archive looking for dangling symlinks and unreadable directories
+fn check_tree(dir: &Path, findings: &mut Findings) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            findings.add(
+                Severity::Error,
+                "Unreadable directories",
+                "Check the owner and the permissions of the archive files",
+                format!("{} - {err}", dir.display()),
+            );
+            return;
+        }
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() && fs::metadata(&path).is_err() => findings.add(
+                Severity::Error,
+                "Dangling symlinks",
+                "Run relink if the archive was moved, otherwise the thumbnails they point to are missing",
+                path.display().to_string(),
+            ),
+            Ok(file_type) if file_type.is_dir() => check_tree(&path, findings),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::archive::catalog::{Catalog, CatalogEntry, CatalogFilter};
+use crate::archive::digest::Digest;
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::remove::{preview_retain_images, retain_images, RemovalSummary};
+use crate::repository::sources::SourcesRepo;
+
+/// Archived files sharing the same digest, in catalog order
+pub struct DuplicateGroup {
+    pub digest: Digest,
+    pub copies: Vec<CatalogEntry>,
+}
+
+/// Group the photos of the catalog matching `filter` by digest, keeping only the digests with
+/// more than one copy. Groups are sorted by the capture date of their first copy.
+pub fn find_duplicates(catalog: &Catalog, filter: &CatalogFilter) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut group_by_digest: HashMap<Digest, usize> = HashMap::new();
+    for entry in catalog.filter(filter) {
+        match group_by_digest.get(&entry.digest) {
+            Some(idx) => groups[*idx].copies.push(entry.clone()),
+            None => {
+                group_by_digest.insert(entry.digest.clone(), groups.len());
+                groups.push(DuplicateGroup { digest: entry.digest.clone(), copies: vec![entry.clone()] });
+            }
+        }
+    }
+    groups.retain(|group| group.copies.len() > 1);
+    groups
+}
+
+/// Copy dropped from the archive by a [`RetainPlan`]
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedRemoval {
+    pub source_id: String,
+    pub source_path: PathBuf,
+    pub digest: Digest,
+}
+
+/// Outcome of a duplicates review: the copies to drop, every other row of the archive is retained
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RetainPlan {
+    pub remove: Vec<PlannedRemoval>,
+}
+
+impl RetainPlan {
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty()
+    }
+}
+
+/// What [`execute_plan`] would remove, the archive is left untouched
+pub fn preview_plan(target: &Path, secret: Option<&ArchiveSecret>, plan: &RetainPlan) -> anyhow::Result<RemovalSummary> {
+    let removed = planned_removals(plan);
+    preview_retain_images(target, secret, |row| !removed.contains(&(row.source_id(), row.source_path().as_path())))
+}
+
+/// Drop the planned copies from the index, together with the thumbnails, links and originals no
+/// retained row still references. Only the archive is touched, the source files stay where they are.
+pub fn execute_plan(target: PathBuf, secret: Option<&ArchiveSecret>, plan: &RetainPlan) -> anyhow::Result<()> {
+    let removed = planned_removals(plan);
+    retain_images(target.clone(), secret, |row| !removed.contains(&(row.source_id(), row.source_path().as_path())))?;
+
+    // Stats of the touched sources are recomputed on demand
+    let repo = SourcesRepo::new(target);
+    let source_ids = plan.remove.iter().map(|removal| removal.source_id.as_str()).collect::<HashSet<_>>();
+    for source_id in source_ids {
+        if let Some(mut entry) = repo.find_by_id(source_id)? {
+            entry.stats = None;
+            repo.update_entry(&entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn planned_removals(plan: &RetainPlan) -> HashSet<(&str, &Path)> {
+    plan.remove.iter()
+        .map(|removal| (removal.source_id.as_str(), removal.source_path.as_path()))
+        .collect()
+}
@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use exif::{Context, Exif, In, Tag, Value};
+
+use crate::archive::motion_photo::{locate_motion_clip, DeclaredMotionClip, MotionClip};
+
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const XMP_NAMESPACE: &str = "http://ns.adobe.com/xap/1.0/";
+const RDF_NAMESPACE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const GCAMERA_NAMESPACE: &str = "http://ns.google.com/photos/1.0/camera/";
+const CONTAINER_ITEM_NAMESPACE: &str = "http://ns.google.com/photos/1.0/container/item/";
+/// Photoshop image resource holding the IPTC-NAA record
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+/// IPTC application record, dataset of the caption/abstract
+const IPTC_CAPTION: (u8, u8) = (2, 120);
+/// Star rating written by Windows and photo managers, not among the tags known to the EXIF reader
+const EXIF_RATING: Tag = Tag(Context::Tiff, 0x4746);
+/// Descriptions some cameras write instead of leaving the field empty
+const CAMERA_PLACEHOLDERS: [&str; 4] = ["OLYMPUS DIGITAL CAMERA", "SONY DSC", "DIGITAL CAMERA", "DCIM\\100MEDIA"];
+
+/// Metadata written into the photo by cameras and photo managers
+#[derive(Clone, Debug, Default)]
+pub struct EmbeddedMetadata {
+    pub description: Option<String>,
+    /// Stars, from 1 to 5, unrated photos have none
+    pub rating: Option<u8>,
+    /// Video of motion photos, appended to the still image
+    pub motion_clip: Option<MotionClip>,
+}
+
+/// Description and rating embedded in the photo. The description is the XMP `dc:description`,
+/// else the IPTC caption, else the EXIF image description; the rating is the XMP `xmp:Rating`,
+/// else the EXIF one. Only the header segments of JPEG files are read, and the end of the file
+/// to look for the video of motion photos.
+pub fn read_embedded_metadata(image_path: &Path, exif: Option<&Exif>) -> anyhow::Result<EmbeddedMetadata> {
+    let mut file = File::open(image_path)?;
+    let segments = jpeg_header_segments(&mut file, &[0xE1, 0xED])?;
+    let xmp_documents = segments.iter()
+        .filter_map(|(marker, payload)| (*marker == 0xE1).then(|| payload.strip_prefix(XMP_SIGNATURE)).flatten())
+        .filter_map(|packet| std::str::from_utf8(packet).ok())
+        .collect::<Vec<_>>();
+    let xmp_documents = xmp_documents.iter()
+        .filter_map(|packet| roxmltree::Document::parse(packet.trim_end_matches('\0')).ok())
+        .collect::<Vec<_>>();
+
+    let xmp_description = xmp_documents.iter().find_map(xmp_description);
+    let iptc_description = || segments.iter()
+        .filter_map(|(marker, payload)| (*marker == 0xED).then(|| payload.strip_prefix(PHOTOSHOP_SIGNATURE)).flatten())
+        .find_map(iptc_caption);
+    let exif_description = || exif.and_then(exif_description);
+    let description = xmp_description.or_else(iptc_description).or_else(exif_description).and_then(|description| clean(&description));
+
+    // Negative ratings mark rejected photos, which are left unrated
+    let rating = xmp_documents.iter().find_map(xmp_rating)
+        .or_else(|| exif.and_then(exif_rating))
+        .filter(|rating| (1..=5).contains(rating))
+        .map(|rating| rating as u8);
+    let motion_clip = locate_motion_clip(&mut file, xmp_documents.iter().find_map(xmp_motion_clip))?;
+    Ok(EmbeddedMetadata { description, rating, motion_clip })
+}
+
+/// Segments with one of the `markers` of a JPEG file, up to the start of the image data. Files
+/// not starting with a JPEG SOI have none.
+pub(crate) fn jpeg_header_segments(file: &mut File, markers: &[u8]) -> anyhow::Result<Vec<(u8, Vec<u8>)>> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+    let mut segments = Vec::new();
+    let mut soi = [0u8; 2];
+    if reader.read_exact(&mut soi).is_err() || soi != [0xFF, 0xD8] {
+        return Ok(segments);
+    }
+    loop {
+        let mut marker = [0u8; 2];
+        if reader.read_exact(&mut marker).is_err() || marker[0] != 0xFF {
+            break;
+        }
+        match marker[1] {
+            // Start of scan or end of image, no metadata follows
+            0xDA | 0xD9 => break,
+            // Markers without payload
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let Some(payload_length) = usize::from(u16::from_be_bytes(length)).checked_sub(2) else {
+            break;
+        };
+        let mut payload = vec![0u8; payload_length];
+        reader.read_exact(&mut payload)?;
+        if markers.contains(&marker[1]) {
+            segments.push((marker[1], payload));
+        }
+    }
+    Ok(segments)
+}
+
+/// `dc:description` of an XMP packet, in its default language when there are several
+fn xmp_description(document: &roxmltree::Document) -> Option<String> {
+    let description = document.descendants()
+        .find(|node| node.tag_name().namespace() == Some(DC_NAMESPACE) && node.tag_name().name() == "description")?;
+    let items = description.descendants()
+        .filter(|node| node.tag_name().namespace() == Some(RDF_NAMESPACE) && node.tag_name().name() == "li")
+        .collect::<Vec<_>>();
+    let item = items.iter()
+        .find(|item| item.attribute(("http://www.w3.org/XML/1998/namespace", "lang")) == Some("x-default"))
+        .or(items.first())?;
+    item.text().map(String::from)
+}
+
+/// `xmp:Rating` of an XMP packet, written either as an attribute or as an element
+fn xmp_rating(document: &roxmltree::Document) -> Option<i64> {
+    document.descendants().find_map(|node| {
+        let rating = node.attribute((XMP_NAMESPACE, "Rating"))
+            .or_else(|| (node.tag_name().namespace() == Some(XMP_NAMESPACE) && node.tag_name().name() == "Rating").then(|| node.text()).flatten())?;
+        rating.trim().parse::<f64>().ok().map(|rating| rating.round() as i64)
+    })
+}
+
+/// Video at the end of a motion photo: `GCamera:MicroVideoOffset` of the first version of the
+/// format, else the `Item:Length` of the motion photo item of the container, whose other
+/// secondary items are appended to the still too
+fn xmp_motion_clip(document: &roxmltree::Document) -> Option<DeclaredMotionClip> {
+    let micro_video = document.descendants().find_map(|node| {
+        node.attribute((GCAMERA_NAMESPACE, "MicroVideoOffset"))
+            .or_else(|| (node.tag_name().namespace() == Some(GCAMERA_NAMESPACE) && node.tag_name().name() == "MicroVideoOffset").then(|| node.text()).flatten())
+    });
+    if let Some(length) = micro_video.and_then(|offset| offset.trim().parse().ok()) {
+        return Some(DeclaredMotionClip { length, appended_length: length });
+    }
+
+    let item_length = |node: &roxmltree::Node| node.attribute((CONTAINER_ITEM_NAMESPACE, "Length")).and_then(|length| length.trim().parse::<u64>().ok());
+    let items = document.descendants()
+        .filter(|node| node.attribute((CONTAINER_ITEM_NAMESPACE, "Semantic")).is_some_and(|semantic| semantic != "Primary"))
+        .collect::<Vec<_>>();
+    let length = items.iter()
+        .find(|node| node.attribute((CONTAINER_ITEM_NAMESPACE, "Semantic")) == Some("MotionPhoto"))
+        .and_then(item_length)?;
+    Some(DeclaredMotionClip { length, appended_length: items.iter().filter_map(item_length).sum() })
+}
+
+fn exif_rating(exif: &Exif) -> Option<i64> {
+    exif.get_field(EXIF_RATING, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .map(i64::from)
+}
+
+/// Caption of the IPTC record held in the Photoshop image resources
+fn iptc_caption(resources: &[u8]) -> Option<String> {
+    let mut rest = resources;
+    while rest.len() >= 12 && rest.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([rest[4], rest[5]]);
+        // Pascal string name, padded to an even length together with its length byte
+        let name_length = usize::from(rest[6]);
+        let name_end = 6 + (name_length + 2) / 2 * 2;
+        let size_bytes = rest.get(name_end..name_end + 4)?;
+        let size = u32::from_be_bytes(size_bytes.try_into().ok()?) as usize;
+        let data = rest.get(name_end + 4..name_end + 4 + size)?;
+        if id == IPTC_RESOURCE_ID {
+            return iptc_dataset(data, IPTC_CAPTION);
+        }
+        rest = rest.get(name_end + 4 + size + size % 2..)?;
+    }
+    None
+}
+
+fn iptc_dataset(mut record: &[u8], (record_number, dataset_number): (u8, u8)) -> Option<String> {
+    while record.len() >= 5 && record[0] == 0x1C {
+        let size = u16::from_be_bytes([record[3], record[4]]);
+        // Extended datasets are not used by captions
+        if size & 0x8000 != 0 {
+            return None;
+        }
+        let data = record.get(5..5 + usize::from(size))?;
+        if record[1] == record_number && record[2] == dataset_number {
+            return Some(String::from_utf8_lossy(data).into_owned());
+        }
+        record = &record[5 + usize::from(size)..];
+    }
+    None
+}
+
+fn exif_description(exif: &Exif) -> Option<String> {
+    match &exif.get_field(Tag::ImageDescription, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first().map(|value| String::from_utf8_lossy(value).into_owned()),
+        _ => None,
+    }
+}
+
+fn clean(description: &str) -> Option<String> {
+    let description = description.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    let placeholder = CAMERA_PLACEHOLDERS.iter().any(|placeholder| placeholder.eq_ignore_ascii_case(description));
+    (!description.is_empty() && !placeholder).then(|| description.to_string())
+}
@@ -0,0 +1,235 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::digest::Digest;
+
+const SEALED_MAGIC: &[u8; 4] = b"PAE1";
+const STREAM_MAGIC: &[u8; 4] = b"PAS1";
+const STREAM_NONCE_SIZE: usize = 19;
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const KEY_CHECK: &[u8] = b"photo-archive";
+
+/// Secret unlocking an encrypted archive, either a passphrase or the content of a key file
+#[derive(Clone)]
+pub struct ArchiveSecret(Vec<u8>);
+
+impl ArchiveSecret {
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        Self(passphrase.as_bytes().to_vec())
+    }
+
+    pub fn from_key_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read(path)?;
+        let trimmed_len = content.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |idx| idx + 1);
+        if trimmed_len == 0 {
+            anyhow::bail!("Key file {path:?} is empty");
+        }
+        Ok(Self(content[..trimmed_len].to_vec()))
+    }
+}
+
+/// Encryption parameters stored in the archive manifest, the key itself is never stored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptionManifest {
+    /// Argon2 salt, base64 encoded
+    salt: String,
+    /// Known value sealed with the archive key, used to reject wrong secrets early
+    check: String,
+}
+
+impl EncryptionManifest {
+    pub fn create(secret: &ArchiveSecret) -> anyhow::Result<(Self, ArchiveCipher)> {
+        let salt: [u8; 16] = rand_bytes();
+        let cipher = ArchiveCipher::derive(secret, &salt)?;
+        let manifest = Self {
+            salt: STANDARD.encode(salt),
+            check: STANDARD.encode(cipher.seal(KEY_CHECK)?),
+        };
+        Ok((manifest, cipher))
+    }
+
+    pub fn unlock(&self, secret: &ArchiveSecret) -> anyhow::Result<ArchiveCipher> {
+        let cipher = ArchiveCipher::derive(secret, &STANDARD.decode(&self.salt)?)?;
+        match cipher.open(&STANDARD.decode(&self.check)?) {
+            Ok(check) if check == KEY_CHECK => Ok(cipher),
+            _ => anyhow::bail!("Wrong archive passphrase or key file"),
+        }
+    }
+}
+
+pub(crate) fn rand_bytes<const N: usize>() -> [u8; N] {
+    use chacha20poly1305::aead::rand_core::RngCore;
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Authenticated encryption (XChaCha20-Poly1305) of thumbnails, index rows and stored originals.
+///
+/// Small payloads are sealed at once, originals are encrypted as a stream of 64 KiB chunks so
+/// that large RAW files and videos never need to fit in memory.
+///
+/// Thumbnails and content-addressed originals are named with a keyed hash. What stays readable
+/// is the layout of the archive: the date directories, the `crc` link directories (ending with
+/// the name of the source folder) and the links (named after the source files), the sizes of
+/// the files, the manifest and `sources.ndjson` (names, groups and mount points of the sources).
+pub struct ArchiveCipher {
+    aead: XChaCha20Poly1305,
+    names_key: [u8; 32],
+}
+
+impl ArchiveCipher {
+    fn derive(secret: &ArchiveSecret, salt: &[u8]) -> anyhow::Result<Self> {
+        let mut master_key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&secret.0, salt, &mut master_key)
+            .map_err(|err| anyhow::anyhow!("Error deriving archive key - {err}"))?;
+
+        let content_key = blake3::derive_key("photo-archive content encryption", &master_key);
+        Ok(Self {
+            aead: XChaCha20Poly1305::new(Key::from_slice(&content_key)),
+            names_key: blake3::derive_key("photo-archive object names", &master_key),
+        })
+    }
+
+    /// Hasher used to name content-addressed objects without disclosing their plain hash
+    pub fn names_hasher(&self) -> blake3::Hasher {
+        blake3::Hasher::new_keyed(&self.names_key)
+    }
+
+    /// Name of the thumbnail of a photo, plain names hold its shooting time and digest
+    pub fn thumbnail_name(&self, digest: &Digest) -> String {
+        let mut hasher = self.names_hasher();
+        hasher.update(digest.to_string().as_bytes());
+        format!("{}.jpg", &hasher.finalize().to_hex()[..32])
+    }
+
+    pub fn seal(&self, plain: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = self.aead.encrypt(&nonce, plain)
+            .map_err(|_| anyhow::anyhow!("Encryption error"))?;
+
+        let mut sealed = Vec::with_capacity(SEALED_MAGIC.len() + nonce.len() + encrypted.len());
+        sealed.extend_from_slice(SEALED_MAGIC);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&encrypted);
+        Ok(sealed)
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let body = sealed.strip_prefix(SEALED_MAGIC.as_slice())
+            .ok_or_else(|| anyhow::anyhow!("Not an encrypted archive file"))?;
+        if body.len() < 24 {
+            anyhow::bail!("Truncated encrypted archive file");
+        }
+        let (nonce, encrypted) = body.split_at(24);
+        self.aead.decrypt(XNonce::from_slice(nonce), encrypted)
+            .map_err(|_| anyhow::anyhow!("Decryption error, the file is corrupted or was encrypted with another key"))
+    }
+
+    /// Seal a text line, the output is base64 encoded so that it can be stored in line based files
+    pub fn seal_line(&self, line: &str) -> anyhow::Result<String> {
+        Ok(STANDARD.encode(self.seal(line.as_bytes())?))
+    }
+
+    pub fn open_line(&self, line: &str) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.open(&STANDARD.decode(line.trim_end())?)?)?)
+    }
+
+    pub fn encrypt_stream(&self, mut reader: impl Read, mut writer: impl Write) -> anyhow::Result<()> {
+        let nonce: [u8; STREAM_NONCE_SIZE] = rand_bytes();
+        writer.write_all(STREAM_MAGIC)?;
+        writer.write_all(&nonce)?;
+
+        let mut encryptor = EncryptorBE32::from_aead(self.aead.clone(), GenericArray::from_slice(&nonce));
+        // One chunk of lookahead is needed to know which chunk is the last one
+        let mut current = read_chunk(&mut reader, STREAM_CHUNK_SIZE)?;
+        loop {
+            let next = read_chunk(&mut reader, STREAM_CHUNK_SIZE)?;
+            if next.is_empty() {
+                let encrypted = encryptor.encrypt_last(current.as_slice())
+                    .map_err(|_| anyhow::anyhow!("Encryption error"))?;
+                writer.write_all(&encrypted)?;
+                return Ok(());
+            }
+            let encrypted = encryptor.encrypt_next(current.as_slice())
+                .map_err(|_| anyhow::anyhow!("Encryption error"))?;
+            writer.write_all(&encrypted)?;
+            current = next;
+        }
+    }
+
+    pub fn decrypt_stream<R: Read>(&self, mut reader: R) -> anyhow::Result<DecryptingReader<R>> {
+        let mut header = [0u8; STREAM_MAGIC.len() + STREAM_NONCE_SIZE];
+        reader.read_exact(&mut header)?;
+        let (magic, nonce) = header.split_at(STREAM_MAGIC.len());
+        if magic != STREAM_MAGIC {
+            anyhow::bail!("Not an encrypted archive stream");
+        }
+
+        let pending = read_chunk(&mut reader, STREAM_CHUNK_SIZE + TAG_SIZE)?;
+        Ok(DecryptingReader {
+            decryptor: Some(DecryptorBE32::from_aead(self.aead.clone(), GenericArray::from_slice(nonce))),
+            inner: reader,
+            pending,
+            plain: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+fn read_chunk(reader: &mut impl Read, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+pub struct DecryptingReader<R> {
+    inner: R,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    pending: Vec<u8>,
+    plain: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    fn decrypt_pending(&mut self) -> io::Result<()> {
+        let Some(mut decryptor) = self.decryptor.take() else {
+            return Ok(());
+        };
+        let next = read_chunk(&mut self.inner, STREAM_CHUNK_SIZE + TAG_SIZE)?;
+        let decrypted = if next.is_empty() {
+            decryptor.decrypt_last(self.pending.as_slice())
+        } else {
+            let decrypted = decryptor.decrypt_next(self.pending.as_slice());
+            self.decryptor = Some(decryptor);
+            decrypted
+        };
+        self.plain = decrypted.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Decryption error"))?;
+        self.position = 0;
+        self.pending = next;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plain.len() && self.decryptor.is_some() {
+            self.decrypt_pending()?;
+        }
+        let read = (self.plain.len() - self.position).min(buf.len());
+        buf[..read].copy_from_slice(&self.plain[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
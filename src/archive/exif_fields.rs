@@ -0,0 +1,122 @@
+use std::fmt::{Display, Formatter};
+
+use exif::{Exif, In, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+/// Shooting details of a photo, read from its EXIF data when it is archived
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExifFields {
+    #[serde(rename = "mk", default, skip_serializing_if = "Option::is_none")]
+    pub make: Option<String>,
+    #[serde(rename = "mdl", default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(rename = "lns", default, skip_serializing_if = "Option::is_none")]
+    pub lens: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iso: Option<u32>,
+    /// F-number
+    #[serde(rename = "fno", default, skip_serializing_if = "Option::is_none")]
+    pub aperture: Option<f64>,
+    /// Exposure time, in seconds
+    #[serde(rename = "exp", default, skip_serializing_if = "Option::is_none")]
+    pub exposure: Option<f64>,
+    /// The flash fired
+    #[serde(rename = "fls", default, skip_serializing_if = "Option::is_none")]
+    pub flash: Option<bool>,
+    /// Decimal degrees, negative south of the equator
+    #[serde(rename = "lat", default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    /// Decimal degrees, negative west of Greenwich
+    #[serde(rename = "lon", default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+impl ExifFields {
+    pub fn from_exif(exif: &Exif) -> Self {
+        Self {
+            make: ascii_field(exif, Tag::Make),
+            model: ascii_field(exif, Tag::Model),
+            lens: ascii_field(exif, Tag::LensModel),
+            iso: exif.get_field(Tag::PhotographicSensitivity, In::PRIMARY).and_then(|field| field.value.get_uint(0)),
+            aperture: rational_field(exif, Tag::FNumber),
+            exposure: rational_field(exif, Tag::ExposureTime),
+            // Bit 0 of the flash status tells whether it fired
+            flash: exif.get_field(Tag::Flash, In::PRIMARY).and_then(|field| field.value.get_uint(0)).map(|flash| flash & 1 == 1),
+            latitude: gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+            longitude: gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Make and model of the camera, the model alone when it already names the make
+    pub fn camera(&self) -> Option<String> {
+        match (&self.make, &self.model) {
+            (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model.clone()),
+            (Some(make), Some(model)) => Some(format!("{make} {model}")),
+            (make, model) => make.clone().or_else(|| model.clone()),
+        }
+    }
+
+    /// Latitude and longitude where the photo was taken
+    pub fn position(&self) -> Option<(f64, f64)> {
+        self.latitude.zip(self.longitude)
+    }
+
+    /// Exposure time as usually written on cameras, e.g. `1/250` or `2s`
+    pub fn shutter_speed(&self) -> Option<String> {
+        self.exposure.filter(|exposure| *exposure > 0.0).map(|exposure| match exposure {
+            exposure if exposure < 1.0 => format!("1/{}", (1.0 / exposure).round()),
+            exposure => format!("{exposure}s"),
+        })
+    }
+}
+
+impl Display for ExifFields {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts = [
+            self.camera(),
+            self.lens.clone(),
+            self.aperture.map(|aperture| format!("f/{aperture:.1}")),
+            self.shutter_speed(),
+            self.iso.map(|iso| format!("ISO {iso}")),
+            self.flash.filter(|flash| *flash).map(|_| String::from("flash")),
+        ];
+        write!(f, "{}", parts.into_iter().flatten().collect::<Vec<_>>().join(", "))
+    }
+}
+
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let value = values.first()?;
+    let value = String::from_utf8_lossy(value);
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Degrees, minutes and seconds of a GPS tag, negated when its reference is `negative_ref`
+fn gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let Value::Rational(values) = &field.value else {
+        return None;
+    };
+    if values.len() < 3 || values.iter().any(|value| value.denom == 0) {
+        return None;
+    }
+    let degrees = values[0].to_f64() + values[1].to_f64() / 60.0 + values[2].to_f64() / 3600.0;
+    let negative = ascii_field(exif, ref_tag).is_some_and(|reference| reference.eq_ignore_ascii_case(negative_ref));
+    Some(if negative { -degrees } else { degrees })
+}
+
+fn rational_field(exif: &Exif, tag: Tag) -> Option<f64> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    match &field.value {
+        Value::Rational(values) => values.first().filter(|value| value.denom != 0).map(|value| value.to_f64()),
+        _ => None,
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{NaiveDateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use crate::archive::catalog::{Catalog, CatalogEntry, PhotoFile};
+use crate::archive::export::{entry_tags, ExportOutcome, ExportStats};
+use crate::archive::remote::api::api_error;
+
+/// Device the uploaded assets are attributed to, Immich keys them by device and digest
+const DEVICE_ID: &str = "photo-archive";
+
+#[derive(Clone, Debug)]
+pub struct ImmichOpts {
+    /// Url of the server, e.g. `https://photos.example.com`
+    pub server: String,
+    /// Key created in the account settings of the Immich user receiving the photos
+    pub api_key: String,
+    /// Skip the photos whose original is neither on a mounted source nor stored in the archive,
+    /// instead of uploading their thumbnail
+    pub originals_only: bool,
+}
+
+#[derive(Deserialize)]
+struct UploadResponse {
+    id: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct TagResponse {
+    id: String,
+    value: String,
+}
+
+/// Client of the REST API of an Immich server, authenticated by an API key
+pub struct ImmichClient {
+    /// Url of the API, ending with a slash
+    api: Url,
+    api_key: String,
+    agent: ureq::Agent,
+}
+
+impl ImmichClient {
+    /// Client of the server, checking that it accepts the key
+    pub fn connect(server: &str, api_key: &str) -> anyhow::Result<Self> {
+        let mut api = Url::parse(server)?;
+        let path = api.path().trim_end_matches('/').trim_end_matches("/api").to_string();
+        api.set_path(&format!("{path}/api/"));
+        let client = Self { api, api_key: String::from(api_key), agent: ureq::AgentBuilder::new().build() };
+
+        let url = client.url("users/me")?;
+        client.agent.request_url("GET", &url)
+            .set("x-api-key", &client.api_key)
+            .call()
+            .map_err(|err| api_error(&format!("Error connecting to {server}"), err))?;
+        Ok(client)
+    }
+
+    fn url(&self, path: &str) -> anyhow::Result<Url> {
+        Ok(self.api.join(path)?)
+    }
+
+    fn send_json<T: serde::de::DeserializeOwned>(&self, method: &str, path: &str, body: &serde_json::Value) -> anyhow::Result<T> {
+        let url = self.url(path)?;
+        let response = self.agent.request_url(method, &url)
+            .set("x-api-key", &self.api_key)
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map_err(|err| api_error(&format!("Error calling {url}"), err))?;
+        Ok(serde_json::from_reader(response.into_reader())?)
+    }
+
+    /// Upload the photo, returns the id of its asset and whether the server already had it
+    fn upload(&self, entry: &CatalogEntry, file: &PhotoFile) -> anyhow::Result<(String, bool)> {
+        let created_at = immich_date(entry.timestamp);
+        let boundary = format!("photo-archive-{}-{}", entry.digest, SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos());
+        let mut body = Vec::with_capacity(file.content.len() + 1024);
+        for (name, value) in [
+            ("deviceAssetId", entry.digest.to_string()),
+            ("deviceId", String::from(DEVICE_ID)),
+            ("fileCreatedAt", created_at.clone()),
+            ("fileModifiedAt", created_at),
+            ("isFavorite", entry.favorite.to_string()),
+        ] {
+            body.extend_from_slice(format!("--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n").as_bytes());
+        }
+        let file_name = file.file_name.replace(['"', '\r', '\n'], "_");
+        body.extend_from_slice(format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"assetData\"; filename=\"{file_name}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        ).as_bytes());
+        body.extend_from_slice(&file.content);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let url = self.url("assets")?;
+        let response = self.agent.request_url("POST", &url)
+            .set("x-api-key", &self.api_key)
+            .set("Content-Type", &format!("multipart/form-data; boundary={boundary}"))
+            .send_bytes(&body)
+            .map_err(|err| api_error(&format!("Error uploading {}", entry.source_path.display()), err))?;
+        let response: UploadResponse = serde_json::from_reader(response.into_reader())?;
+        Ok((response.id, response.status == "duplicate"))
+    }
+
+    /// Set the date, position, description, rating and favorite mark of the asset, which the
+    /// server would otherwise only read from the file, missing from thumbnails unless generated with `--keep-exif`
+    fn update_asset(&self, asset_id: &str, entry: &CatalogEntry) -> anyhow::Result<()> {
+        let mut body = json!({ "isFavorite": entry.favorite });
+        if let Some(timestamp) = entry.timestamp {
+            body["dateTimeOriginal"] = json!(immich_date(Some(timestamp)));
+        }
+        if let Some((latitude, longitude)) = entry.exif.position() {
+            body["latitude"] = json!(latitude);
+            body["longitude"] = json!(longitude);
+        }
+        if let Some(caption) = &entry.caption {
+            body["description"] = json!(caption);
+        }
+        if let Some(rating) = entry.rating {
+            body["rating"] = json!(rating);
+        }
+        let _: serde_json::Value = self.send_json("PUT", &format!("assets/{asset_id}"), &body)?;
+        Ok(())
+    }
+
+    /// Attach the tags to the assets, creating the missing ones
+    fn tag_assets(&self, assets_by_tag: &BTreeMap<String, Vec<String>>) -> anyhow::Result<()> {
+        if assets_by_tag.is_empty() {
+            return Ok(());
+        }
+        let tags: Vec<TagResponse> = self.send_json("PUT", "tags", &json!({ "tags": assets_by_tag.keys().collect::<Vec<_>>() }))?;
+        for tag in tags {
+            if let Some(asset_ids) = assets_by_tag.get(&tag.value) {
+                let _: serde_json::Value = self.send_json("PUT", "tags/assets", &json!({ "tagIds": [tag.id], "assetIds": asset_ids }))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Upload the photos to an Immich server, with their date, GPS position, caption, rating and
+/// tags (the ones of the photo and of its source).
+///
+/// Copies of a photo are uploaded once. Originals are read from the mounted sources or from
+/// the archive, the thumbnail is uploaded when neither is available. Photos the server already
+/// holds, e.g. exported before, are not uploaded again but their metadata is updated.
+pub fn export_to_immich(
+    catalog: &Catalog,
+    entries: &[&CatalogEntry],
+    opts: &ImmichOpts,
+    mut on_outcome: impl FnMut(&CatalogEntry, &ExportOutcome),
+) -> anyhow::Result<ExportStats> {
+    let client = ImmichClient::connect(&opts.server, &opts.api_key)?;
+    let mut stats = ExportStats::default();
+    let mut exported = HashSet::new();
+    let mut assets_by_tag: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| exported.insert(entry.digest.clone())) {
+        let res = export_entry(&client, catalog, entry, opts);
+        let outcome = match res {
+            Ok(Some((asset_id, existing))) => {
+                for tag in entry_tags(catalog, entry) {
+                    assets_by_tag.entry(tag).or_default().push(asset_id.clone());
+                }
+                if existing { ExportOutcome::Existing } else { ExportOutcome::Exported }
+            }
+            Ok(None) => ExportOutcome::Skipped(String::from("original not available")),
+            Err(err) => ExportOutcome::Failed(err),
+        };
+        stats.record(&outcome);
+        on_outcome(entry, &outcome);
+    }
+    client.tag_assets(&assets_by_tag)?;
+    Ok(stats)
+}
+
+fn export_entry(client: &ImmichClient, catalog: &Catalog, entry: &CatalogEntry, opts: &ImmichOpts) -> anyhow::Result<Option<(String, bool)>> {
+    let file = match opts.originals_only {
+        true => catalog.read_original(entry)?,
+        false => Some(catalog.read_photo(entry)?),
+    };
+    let Some(file) = file else {
+        return Ok(None);
+    };
+    let (asset_id, existing) = client.upload(entry, &file)?;
+    client.update_asset(&asset_id, entry)?;
+    Ok(Some((asset_id, existing)))
+}
+
+/// Immich dates are ISO 8601, capture times without zone are sent as such; undated photos
+/// are given the time of the export
+fn immich_date(timestamp: Option<NaiveDateTime>) -> String {
+    match timestamp {
+        Some(timestamp) => timestamp.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        None => Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::archive::albums::Albums;
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::{PhotoArchiveJsonRow, PhotoArchiveRecordsStore};
+use crate::archive::user_metadata::UserMetadata;
+use crate::repository::sources::{SourceJsonRow, SourcesRepo};
+
+/// File format the index is exported to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexExportFormat {
+    #[default]
+    Sqlite,
+}
+
+impl Display for IndexExportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexExportFormat::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+impl FromStr for IndexExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sqlite" => Ok(IndexExportFormat::Sqlite),
+            other => anyhow::bail!("Unknown index export format '{other}', expected sqlite"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IndexExportStats {
+    pub photos: usize,
+    pub sources: usize,
+    pub albums: usize,
+}
+
+/// What the archive knows about its photos, read once for the export
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+struct IndexSnapshot {
+    rows: Vec<PhotoArchiveJsonRow>,
+    sources: Vec<SourceJsonRow>,
+    user_metadata: UserMetadata,
+    albums: Albums,
+}
+
+/// Write the index of the archive, with its sources, user metadata and albums, into a single
+/// file that other tools can query; the archive keeps its own ndjson files.
+///
+/// Only the last row of each source file is exported. The file is written aside and renamed
+/// once complete, an existing one is only replaced with `overwrite`.
+pub fn export_index(target: &Path, secret: Option<&ArchiveSecret>, output: &Path, format: IndexExportFormat, overwrite: bool) -> anyhow::Result<IndexExportStats> {
+    if output.exists() && !overwrite {
+        anyhow::bail!("{} already exists", output.display())
+    }
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let cipher = manifest.cipher(secret)?;
+
+    // Later rows replace the earlier ones of the same source file
+    let mut rows = HashMap::new();
+    PhotoArchiveRecordsStore::with_cipher(target, cipher.clone()).for_each(|row| {
+        rows.insert((String::from(row.source_id()), row.source_path()), row);
+    })?;
+    let snapshot = IndexSnapshot {
+        rows: rows.into_values().collect(),
+        sources: SourcesRepo::new(target.to_path_buf()).all()?,
+        user_metadata: UserMetadata::load(target, cipher.as_deref())?,
+        albums: Albums::load(target, cipher.as_deref())?,
+    };
+
+    let file_name = output.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp_path = output.with_file_name(format!(".{file_name}.tmp"));
+    if temp_path.exists() {
+        std::fs::remove_file(&temp_path)?;
+    }
+    let res = match format {
+        IndexExportFormat::Sqlite => write_sqlite(&snapshot, &temp_path),
+    };
+    if let Err(err) = res {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    std::fs::rename(&temp_path, output)?;
+    Ok(IndexExportStats {
+        photos: snapshot.rows.len(),
+        sources: snapshot.sources.len(),
+        albums: snapshot.albums.iter().count(),
+    })
+}
+
+#[cfg(feature = "sqlite")]
+const SQLITE_SCHEMA: &str = "
+CREATE TABLE sources (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    source_group TEXT NOT NULL,
+    -- UTC, missing until the first sync
+    last_sync TEXT
+);
+CREATE TABLE source_tags (
+    source_id TEXT NOT NULL REFERENCES sources(id),
+    tag TEXT NOT NULL,
+    PRIMARY KEY (source_id, tag)
+);
+CREATE TABLE photos (
+    id INTEGER PRIMARY KEY,
+    source_id TEXT NOT NULL,
+    -- Relative to the source root
+    source_path TEXT NOT NULL,
+    digest TEXT NOT NULL,
+    -- Local capture time, missing for undated photos
+    taken_at TEXT,
+    -- The capture time is the file modification time, no EXIF date was found
+    taken_at_estimated INTEGER NOT NULL,
+    -- UTC
+    file_modified_at TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    -- Bytes of the video of motion photos, counted in size
+    motion_clip_size INTEGER,
+    -- Bytes of the still image of motion photos, size of the photo file without what follows it
+    motion_still_size INTEGER,
+    -- Original stored in the archive, relative to its root
+    stored_original TEXT,
+    deleted_at TEXT,
+    -- Embedded in the photo, user captions and ratings are in user_metadata
+    description TEXT,
+    rating INTEGER,
+    camera_make TEXT,
+    camera_model TEXT,
+    lens TEXT,
+    iso INTEGER,
+    aperture REAL,
+    -- Seconds
+    exposure REAL,
+    flash INTEGER,
+    latitude REAL,
+    longitude REAL,
+    UNIQUE (source_id, source_path)
+);
+CREATE INDEX photos_digest ON photos(digest);
+CREATE INDEX photos_taken_at ON photos(taken_at);
+CREATE TABLE user_metadata (
+    digest TEXT PRIMARY KEY,
+    caption TEXT,
+    rating INTEGER,
+    favorite INTEGER NOT NULL
+);
+CREATE TABLE photo_tags (
+    digest TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (digest, tag)
+);
+CREATE INDEX photo_tags_tag ON photo_tags(tag);
+CREATE TABLE albums (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE album_photos (
+    album_id INTEGER NOT NULL REFERENCES albums(id),
+    digest TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (album_id, digest)
+);
+CREATE INDEX album_photos_digest ON album_photos(digest);
+";
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(snapshot: &IndexSnapshot, path: &Path) -> anyhow::Result<()> {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use rusqlite::params;
+
+    const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+    let format_naive = |ts: NaiveDateTime| ts.format(DATE_FORMAT).to_string();
+
+    let mut connection = rusqlite::Connection::open(path)?;
+    connection.execute_batch(SQLITE_SCHEMA)?;
+    let tx = connection.transaction()?;
+    {
+        let mut insert_source = tx.prepare("INSERT INTO sources (id, name, source_group, last_sync) VALUES (?1, ?2, ?3, ?4)")?;
+        let mut insert_source_tag = tx.prepare("INSERT OR IGNORE INTO source_tags (source_id, tag) VALUES (?1, ?2)")?;
+        for source in snapshot.sources.iter() {
+            let last_sync = source.last_sync
+                .and_then(|ts| DateTime::<Utc>::from_timestamp(ts, 0))
+                .map(|ts| ts.format(DATE_FORMAT).to_string());
+            insert_source.execute(params![source.id, source.name, source.group, last_sync])?;
+            for tag in source.tags.iter() {
+                insert_source_tag.execute(params![source.id, tag])?;
+            }
+        }
+
+        let mut insert_photo = tx.prepare("
+            INSERT INTO photos (
+                source_id, source_path, digest, taken_at, taken_at_estimated, file_modified_at, size, width, height,
+                motion_clip_size, motion_still_size, stored_original, deleted_at, description, rating,
+                camera_make, camera_model, lens, iso, aperture, exposure, flash, latitude, longitude
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24)
+        ")?;
+        for row in snapshot.rows.iter() {
+            let exif = row.exif_fields();
+            let file_modified_at = DateTime::<Utc>::from(row.file_timestamp()).format(DATE_FORMAT).to_string();
+            insert_photo.execute(params![
+                row.source_id(),
+                row.source_path().to_string_lossy(),
+                row.digest().to_string(),
+                row.timestamp().map(format_naive),
+                row.is_partial(),
+                file_modified_at,
+                row.size(),
+                row.width(),
+                row.height(),
+                row.motion_clip().map(|clip| clip.length),
+                row.motion_clip().map(|clip| clip.still_length),
+                row.original().map(|original| original.to_string_lossy().into_owned()),
+                row.deleted_at().map(format_naive),
+                row.description(),
+                row.rating(),
+                exif.make,
+                exif.model,
+                exif.lens,
+                exif.iso,
+                exif.aperture,
+                exif.exposure,
+                exif.flash,
+                exif.latitude,
+                exif.longitude,
+            ])?;
+        }
+
+        let mut insert_metadata = tx.prepare("INSERT INTO user_metadata (digest, caption, rating, favorite) VALUES (?1, ?2, ?3, ?4)")?;
+        let mut insert_tag = tx.prepare("INSERT OR IGNORE INTO photo_tags (digest, tag) VALUES (?1, ?2)")?;
+        for (digest, metadata) in snapshot.user_metadata.iter() {
+            insert_metadata.execute(params![digest.to_string(), metadata.caption, metadata.rating, metadata.favorite])?;
+            for tag in metadata.tags.iter() {
+                insert_tag.execute(params![digest.to_string(), tag])?;
+            }
+        }
+
+        let mut insert_album = tx.prepare("INSERT INTO albums (name) VALUES (?1)")?;
+        let mut insert_album_photo = tx.prepare("INSERT OR IGNORE INTO album_photos (album_id, digest, position) VALUES (?1, ?2, ?3)")?;
+        for album in snapshot.albums.iter() {
+            insert_album.execute(params![album.name])?;
+            let album_id = tx.last_insert_rowid();
+            for (position, digest) in album.photos.iter().enumerate() {
+                insert_album_photo.execute(params![album_id, digest.to_string(), position])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(_snapshot: &IndexSnapshot, _path: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("SQLite export is not available, photo-archive was built without the sqlite feature")
+}
@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::archive::catalog::{Catalog, CatalogEntry};
+
+pub mod immich;
+pub mod index;
+pub mod motion_clips;
+pub mod photoprism;
+pub mod year_review;
+
+/// What became of a photo handed to an exporter
+#[derive(Debug)]
+pub enum ExportOutcome {
+    Exported,
+    /// The destination already held the photo, only its metadata was updated
+    Existing,
+    Skipped(String),
+    Failed(anyhow::Error),
+}
+
+#[derive(Debug, Default)]
+pub struct ExportStats {
+    pub exported: usize,
+    pub existing: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl ExportStats {
+    pub fn record(&mut self, outcome: &ExportOutcome) {
+        match outcome {
+            ExportOutcome::Exported => self.exported += 1,
+            ExportOutcome::Existing => self.existing += 1,
+            ExportOutcome::Skipped(_) => self.skipped += 1,
+            ExportOutcome::Failed(_) => self.failed += 1,
+        }
+    }
+}
+
+/// Tags of the photo followed by the ones of its source, without duplicates ignoring case
+fn entry_tags(catalog: &Catalog, entry: &CatalogEntry) -> Vec<String> {
+    let mut seen = HashSet::new();
+    entry.tags.iter()
+        .chain(catalog.source_tags(entry))
+        .filter(|tag| seen.insert(tag.to_lowercase()))
+        .cloned()
+        .collect()
+}
+
+/// Write `content` to a hidden file of the directory then rename it to `path`
+fn write_hidden_first(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let dir = path.parent().ok_or_else(|| anyhow::anyhow!("Invalid export path {}", path.display()))?;
+    fs::create_dir_all(dir)?;
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp_path = dir.join(format!(".{file_name}.tmp"));
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
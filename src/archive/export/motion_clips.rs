@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::archive::catalog::{Catalog, CatalogEntry};
+use crate::archive::export::{write_hidden_first, ExportOutcome, ExportStats};
+use crate::archive::motion_photo::extract_motion_clip;
+
+/// Extract the videos of the motion photos into `output_dir`, read from their originals on the
+/// mounted sources or in the archive; thumbnails hold no video. Other photos are ignored.
+///
+/// Videos are laid out as `<year>/<month>/<name>-<digest>.mp4`, undated ones under `unknown`,
+/// so that runs into the same folder only write the videos missing from it.
+pub fn export_motion_clips(
+    catalog: &Catalog,
+    entries: &[&CatalogEntry],
+    output_dir: &Path,
+    mut on_outcome: impl FnMut(&CatalogEntry, &ExportOutcome),
+) -> anyhow::Result<ExportStats> {
+    let mut stats = ExportStats::default();
+    let mut exported = HashSet::new();
+    let motion_photos = entries.iter().filter(|entry| entry.motion_clip.is_some());
+    for entry in motion_photos.filter(|entry| exported.insert(entry.digest.clone())) {
+        let outcome = match export_entry(catalog, entry, output_dir) {
+            Ok(outcome) => outcome,
+            Err(err) => ExportOutcome::Failed(err),
+        };
+        stats.record(&outcome);
+        on_outcome(entry, &outcome);
+    }
+    Ok(stats)
+}
+
+fn export_entry(catalog: &Catalog, entry: &CatalogEntry, output_dir: &Path) -> anyhow::Result<ExportOutcome> {
+    let Some(clip) = entry.motion_clip else {
+        return Ok(ExportOutcome::Skipped(String::from("not a motion photo")));
+    };
+    let dir = output_dir.join(match entry.timestamp {
+        Some(timestamp) => PathBuf::from(timestamp.format("%Y").to_string()).join(timestamp.format("%m").to_string()),
+        None => PathBuf::from("unknown"),
+    });
+    let stem = entry.source_path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let clip_path = dir.join(format!("{stem}-{}.mp4", entry.digest));
+    if clip_path.is_file() {
+        return Ok(ExportOutcome::Existing);
+    }
+
+    let Some(original) = catalog.read_original(entry)? else {
+        return Ok(ExportOutcome::Skipped(String::from("original not available")));
+    };
+    write_hidden_first(&clip_path, extract_motion_clip(&original.content, &clip)?)?;
+    Ok(ExportOutcome::Exported)
+}
@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive::catalog::{Catalog, CatalogEntry};
+use crate::archive::export::{entry_tags, write_hidden_first, ExportOutcome, ExportStats};
+
+#[derive(Clone, Debug, Default)]
+pub struct PhotoPrismOpts {
+    /// Skip the photos whose original is neither on a mounted source nor stored in the archive,
+    /// instead of copying their thumbnail
+    pub originals_only: bool,
+}
+
+/// Copy the photos into a PhotoPrism import folder, each with a YAML sidecar holding its
+/// capture time, GPS position, caption, favorite mark and tags as keywords.
+///
+/// Photos are laid out as `<year>/<month>/<name>-<digest>.<ext>`, undated ones under `unknown`,
+/// so that runs into the same folder only write the photos missing from it; their sidecars are
+/// always rewritten. Files are written under a hidden name first, ignored by PhotoPrism until
+/// they are complete.
+pub fn export_to_photoprism(
+    catalog: &Catalog,
+    entries: &[&CatalogEntry],
+    import_dir: &Path,
+    opts: &PhotoPrismOpts,
+    mut on_outcome: impl FnMut(&CatalogEntry, &ExportOutcome),
+) -> anyhow::Result<ExportStats> {
+    fs::create_dir_all(import_dir)?;
+    let mut stats = ExportStats::default();
+    let mut exported = HashSet::new();
+    for entry in entries.iter().filter(|entry| exported.insert(entry.digest.clone())) {
+        let outcome = match export_entry(catalog, entry, import_dir, opts) {
+            Ok(outcome) => outcome,
+            Err(err) => ExportOutcome::Failed(err),
+        };
+        stats.record(&outcome);
+        on_outcome(entry, &outcome);
+    }
+    Ok(stats)
+}
+
+fn export_entry(catalog: &Catalog, entry: &CatalogEntry, import_dir: &Path, opts: &PhotoPrismOpts) -> anyhow::Result<ExportOutcome> {
+    let dir = import_dir.join(match entry.timestamp {
+        Some(timestamp) => PathBuf::from(timestamp.format("%Y").to_string()).join(timestamp.format("%m").to_string()),
+        None => PathBuf::from("unknown"),
+    });
+    let base_name = format!("{}-{}", entry.source_path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default(), entry.digest);
+    let sidecar_path = dir.join(format!("{base_name}.yml"));
+    // The original keeps its extension, thumbnails are JPEGs
+    let source_extension = entry.source_path.extension().map(|ext| ext.to_string_lossy().into_owned());
+    let existing = source_extension.iter().map(String::as_str).chain(["jpg"])
+        .map(|extension| dir.join(format!("{base_name}.{extension}")))
+        .any(|path| path.is_file());
+
+    let outcome = match existing {
+        true => ExportOutcome::Existing,
+        false => {
+            let file = match opts.originals_only {
+                true => catalog.read_original(entry)?,
+                false => Some(catalog.read_photo(entry)?),
+            };
+            let Some(file) = file else {
+                return Ok(ExportOutcome::Skipped(String::from("original not available")));
+            };
+            let extension = Path::new(&file.file_name).extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_else(|| String::from("jpg"));
+            write_hidden_first(&dir.join(format!("{base_name}.{extension}")), &file.content)?;
+            ExportOutcome::Exported
+        }
+    };
+    write_hidden_first(&sidecar_path, sidecar(catalog, entry).as_bytes())?;
+    Ok(outcome)
+}
+
+/// PhotoPrism sidecar of the photo, values are written as JSON strings which YAML reads as
+/// double-quoted scalars
+fn sidecar(catalog: &Catalog, entry: &CatalogEntry) -> String {
+    let quote = |value: &str| serde_json::Value::from(value).to_string();
+    let mut yaml = String::from("Type: image\n");
+    if let Some(timestamp) = entry.timestamp {
+        // Capture times are local, PhotoPrism is not told any zone
+        let _ = writeln!(yaml, "TakenAt: {}", timestamp.format("%Y-%m-%dT%H:%M:%SZ"));
+        let _ = writeln!(yaml, "TakenAtLocal: {}", timestamp.format("%Y-%m-%dT%H:%M:%SZ"));
+        yaml.push_str("TakenSrc: meta\n");
+    }
+    if let Some(caption) = &entry.caption {
+        let _ = writeln!(yaml, "Description: {}", quote(caption));
+        yaml.push_str("DescriptionSrc: meta\n");
+    }
+    let _ = writeln!(yaml, "Favorite: {}", entry.favorite);
+    if let Some((latitude, longitude)) = entry.exif.position() {
+        let _ = writeln!(yaml, "Lat: {latitude}");
+        let _ = writeln!(yaml, "Lng: {longitude}");
+        yaml.push_str("PlaceSrc: meta\n");
+    }
+    let tags = entry_tags(catalog, entry);
+    if !tags.is_empty() {
+        yaml.push_str("Details:\n");
+        let _ = writeln!(yaml, "  Keywords: {}", quote(&tags.join(", ")));
+        yaml.push_str("  KeywordsSrc: meta\n");
+    }
+    yaml
+}
@@ -0,0 +1,691 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, NaiveDate};
+use exif::{In, Tag};
+use jpeg_decoder::PixelFormat;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+
+use crate::archive::catalog::{Catalog, CatalogEntry};
+use crate::archive::encryption::ArchiveSecret;
+
+/// A4 portrait, in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 48.0;
+const CONTENT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+const REGULAR_FONT: Name = Name(b"F1");
+const BOLD_FONT: Name = Name(b"F2");
+/// Columns of the photo grid of the monthly sections
+const GRID_COLUMNS: usize = 3;
+const GRID_GAP: f32 = 14.0;
+/// Height of the date and caption lines below each photo of the grid
+const CAPTION_HEIGHT: f32 = 30.0;
+/// Places listed below the map
+const LISTED_PLACES: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct YearReviewOpts {
+    /// Years to review, every year with dated photos when empty
+    pub years: Vec<i32>,
+    /// Only the photos of this source
+    pub source_id: Option<String>,
+    /// Photos shown in the section of each month
+    pub photos_per_month: usize,
+    /// Replace the documents already in the output directory
+    pub overwrite: bool,
+}
+
+impl Default for YearReviewOpts {
+    fn default() -> Self {
+        Self {
+            years: Vec::new(),
+            source_id: None,
+            photos_per_month: 9,
+            overwrite: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct YearReview {
+    pub year: i32,
+    pub path: PathBuf,
+    pub photos: usize,
+    /// Photos placed on the map
+    pub geotagged: usize,
+    pub pages: usize,
+}
+
+/// Write a PDF per year into `output_dir`, named `year-review-<year>.pdf`: a summary of the
+/// year with the photos taken each month, a map of the geotagged ones and a section per month
+/// showing its favorite, best rated and otherwise evenly spread photos.
+///
+/// Only thumbnails and index data are used, sources do not need to be mounted. Photos
+/// archived from several sources are counted once, identified by their digest.
+pub fn export_year_review(target: &Path, secret: Option<&ArchiveSecret>, output_dir: &Path, opts: &YearReviewOpts) -> anyhow::Result<Vec<YearReview>> {
+    let catalog = Catalog::load(target, secret)?;
+    let source_name = opts.source_id.as_ref()
+        .map(|source_id| catalog.sources().iter().find(|source| source.id == *source_id).map(|source| source.name.clone()).unwrap_or_else(|| source_id.clone()));
+
+    let mut counted = HashSet::new();
+    let mut by_year: BTreeMap<i32, Vec<&CatalogEntry>> = BTreeMap::new();
+    for entry in catalog.entries() {
+        let Some(timestamp) = entry.timestamp else {
+            continue;
+        };
+        if opts.source_id.as_ref().is_some_and(|source_id| *source_id != entry.source_id) || !counted.insert(&entry.digest) {
+            continue;
+        }
+        by_year.entry(timestamp.year()).or_default().push(entry);
+    }
+
+    let years = if opts.years.is_empty() { by_year.keys().copied().collect() } else { opts.years.clone() };
+    if years.is_empty() {
+        anyhow::bail!("The archive holds no dated photos")
+    }
+    std::fs::create_dir_all(output_dir)?;
+
+    let mut reviews = Vec::new();
+    for year in years {
+        let entries = by_year.get(&year).ok_or_else(|| anyhow::anyhow!("No photo of the archive was taken in {year}"))?;
+        let path = output_dir.join(format!("year-review-{year}.pdf"));
+        if path.exists() && !opts.overwrite {
+            anyhow::bail!("{} already exists", path.display())
+        }
+        let (content, review) = render_year_review(&catalog, year, entries, source_name.as_deref(), opts.photos_per_month);
+
+        let temp_path = output_dir.join(format!(".year-review-{year}.pdf.tmp"));
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(&temp_path, &path)?;
+        reviews.push(YearReview { path, ..review });
+    }
+    Ok(reviews)
+}
+
+fn render_year_review(catalog: &Catalog, year: i32, entries: &[&CatalogEntry], source_name: Option<&str>, photos_per_month: usize) -> (Vec<u8>, YearReview) {
+    let mut months: BTreeMap<u32, Vec<&CatalogEntry>> = BTreeMap::new();
+    for entry in entries {
+        months.entry(entry.timestamp.map_or(1, |ts| ts.month())).or_default().push(entry);
+    }
+    let located = entries.iter()
+        .filter_map(|entry| entry.exif.position().map(|(latitude, longitude)| LocatedPhoto { latitude, longitude, month: entry.timestamp.map_or(1, |ts| ts.month()) }))
+        .collect::<Vec<_>>();
+
+    let mut doc = Document::new(&format!("{year} in photos"));
+    doc.add_page(cover_page(year, entries, &months, located.len(), source_name));
+    if !located.is_empty() {
+        doc.add_page(map_page(&located));
+    }
+    for (month, month_entries) in months.iter() {
+        month_pages(&mut doc, catalog, year, *month, month_entries, photos_per_month);
+    }
+
+    let review = YearReview {
+        year,
+        path: PathBuf::new(),
+        photos: entries.len(),
+        geotagged: located.len(),
+        pages: doc.pages.len(),
+    };
+    (doc.finish(), review)
+}
+
+fn cover_page(year: i32, entries: &[&CatalogEntry], months: &BTreeMap<u32, Vec<&CatalogEntry>>, geotagged: usize, source_name: Option<&str>) -> Page {
+    let mut page = Page::default();
+    let mut y = PAGE_HEIGHT - MARGIN - 64.0;
+    page.text(BOLD_FONT, 64.0, MARGIN, y, &year.to_string());
+    y -= 32.0;
+    let subtitle = match source_name {
+        Some(source_name) => format!("A year in photos of {source_name}"),
+        None => String::from("A year in photos"),
+    };
+    page.text(REGULAR_FONT, 20.0, MARGIN, y, &fit_text(&subtitle, 20.0, CONTENT_WIDTH));
+
+    let mut days: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+    let mut cameras: HashMap<String, usize> = HashMap::new();
+    for entry in entries {
+        if let Some(ts) = entry.timestamp {
+            *days.entry(ts.date()).or_default() += 1;
+        }
+        if let Some(camera) = entry.exif.camera() {
+            *cameras.entry(camera).or_default() += 1;
+        }
+    }
+    let mut cameras = cameras.into_iter().collect::<Vec<_>>();
+    cameras.sort_by_key(|(camera, photos)| (Reverse(*photos), camera.clone()));
+
+    let mut lines = vec![format!("{} photos taken on {} days", entries.len(), days.len())];
+    if let Some((month, photos)) = months.iter().max_by_key(|(month, photos)| (photos.len(), Reverse(**month))) {
+        lines.push(format!("Busiest month: {} ({} photos)", month_name(year, *month), photos.len()));
+    }
+    if let Some((day, photos)) = days.iter().max_by_key(|(day, photos)| (**photos, Reverse(**day))) {
+        lines.push(format!("Busiest day: {} ({photos} photos)", day.format("%A %-d %B")));
+    }
+    if !cameras.is_empty() {
+        let top = cameras.iter().take(3).map(|(camera, photos)| format!("{camera} ({photos})")).collect::<Vec<_>>();
+        lines.push(format!("Cameras: {}", top.join(", ")));
+    }
+    let favorites = entries.iter().filter(|entry| entry.favorite).count();
+    let rated = entries.iter().filter(|entry| entry.rating.is_some()).count();
+    lines.push(format!("Favorites: {favorites}, rated: {rated}, geotagged: {geotagged}"));
+
+    y -= 56.0;
+    for line in lines {
+        page.text(REGULAR_FONT, 13.0, MARGIN, y, &fit_text(&line, 13.0, CONTENT_WIDTH));
+        y -= 22.0;
+    }
+
+    // Photos per month, as a bar chart
+    y -= 30.0;
+    page.text(BOLD_FONT, 16.0, MARGIN, y, "Photos per month");
+    let chart_top = y - 30.0;
+    let chart_bottom = MARGIN + 60.0;
+    let slot = CONTENT_WIDTH / 12.0;
+    let max = months.values().map(Vec::len).max().unwrap_or(1).max(1);
+    page.content.save_state();
+    page.content.set_stroke_gray(0.6).set_line_width(0.5);
+    page.content.move_to(MARGIN, chart_bottom).line_to(PAGE_WIDTH - MARGIN, chart_bottom).stroke();
+    page.content.restore_state();
+    for month in 1..=12 {
+        let photos = months.get(&month).map_or(0, Vec::len);
+        let x = MARGIN + slot * (month - 1) as f32;
+        let height = (chart_top - chart_bottom - 16.0) * photos as f32 / max as f32;
+        if photos > 0 {
+            let (r, g, b) = month_color(month);
+            page.content.save_state();
+            page.content.set_fill_rgb(r, g, b).rect(x + slot * 0.15, chart_bottom, slot * 0.7, height).fill_nonzero();
+            page.content.restore_state();
+            page.text_centered(REGULAR_FONT, 8.0, x + slot / 2.0, chart_bottom + height + 4.0, &photos.to_string());
+        }
+        let label = NaiveDate::from_ymd_opt(year, month, 1).map(|date| date.format("%b").to_string()).unwrap_or_default();
+        page.text_centered(REGULAR_FONT, 9.0, x + slot / 2.0, chart_bottom - 14.0, &label);
+    }
+    page
+}
+
+/// Geotagged photo, with the month it was taken in
+struct LocatedPhoto {
+    latitude: f64,
+    longitude: f64,
+    month: u32,
+}
+
+/// Places where photos were taken, close ones merged
+struct Place {
+    latitude: f64,
+    longitude: f64,
+    photos: usize,
+    /// Month most of the photos were taken in, giving the color of the place
+    busiest_month: u32,
+    months: BTreeSet<u32>,
+}
+
+fn map_page(located: &[LocatedPhoto]) -> Page {
+    let mut page = Page::default();
+    let mut y = PAGE_HEIGHT - MARGIN - 22.0;
+    page.text(BOLD_FONT, 22.0, MARGIN, y, "Where the photos were taken");
+
+    let min_lat = located.iter().map(|photo| photo.latitude).fold(f64::MAX, f64::min);
+    let max_lat = located.iter().map(|photo| photo.latitude).fold(f64::MIN, f64::max);
+    let min_lon = located.iter().map(|photo| photo.longitude).fold(f64::MAX, f64::min);
+    let max_lon = located.iter().map(|photo| photo.longitude).fold(f64::MIN, f64::max);
+    // Padded, a single place is shown with a few kilometers around it
+    let span = (max_lat - min_lat).max(max_lon - min_lon).max(0.05);
+    let (min_lat, max_lat) = ((min_lat - span * 0.1).max(-90.0), (max_lat + span * 0.1).min(90.0));
+    let (min_lon, max_lon) = ((min_lon - span * 0.1).max(-180.0), (max_lon + span * 0.1).min(180.0));
+    let (min_lat, max_lat) = widen(min_lat, max_lat, 0.05);
+    let (min_lon, max_lon) = widen(min_lon, max_lon, 0.05);
+
+    // Equirectangular projection, longitudes shrunk by the cosine of the middle latitude
+    let lon_scale = ((min_lat + max_lat) / 2.0).to_radians().cos().max(0.1);
+    let (area_width, area_height) = (CONTENT_WIDTH as f64, 440.0);
+    let scale = (area_width / ((max_lon - min_lon) * lon_scale)).min(area_height / (max_lat - min_lat));
+    let (map_width, map_height) = ((max_lon - min_lon) * lon_scale * scale, (max_lat - min_lat) * scale);
+    let map_x = MARGIN as f64 + (area_width - map_width) / 2.0;
+    let map_top = y as f64 - 24.0;
+    let map_y = map_top - area_height + (area_height - map_height) / 2.0;
+    let project = |lat: f64, lon: f64| ((map_x + (lon - min_lon) * lon_scale * scale) as f32, (map_y + (lat - min_lat) * scale) as f32);
+
+    page.content.save_state();
+    page.content.set_fill_rgb(0.93, 0.95, 0.97).rect(map_x as f32, map_y as f32, map_width as f32, map_height as f32).fill_nonzero();
+    page.content.set_stroke_gray(0.8).set_line_width(0.4);
+    let step = grid_step((max_lat - min_lat).max(max_lon - min_lon) / 5.0);
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let mut grid_labels = Vec::new();
+    let mut lat = (min_lat / step).ceil() * step;
+    while lat <= max_lat {
+        let (x0, y0) = project(lat, min_lon);
+        let (x1, _) = project(lat, max_lon);
+        page.content.move_to(x0, y0).line_to(x1, y0).stroke();
+        grid_labels.push((x0 - 4.0, y0 - 3.0, format_coordinate(lat, decimals, 'N', 'S'), true));
+        lat += step;
+    }
+    let mut lon = (min_lon / step).ceil() * step;
+    while lon <= max_lon {
+        let (x0, y0) = project(min_lat, lon);
+        let (_, y1) = project(max_lat, lon);
+        page.content.move_to(x0, y0).line_to(x0, y1).stroke();
+        grid_labels.push((x0, y0 - 12.0, format_coordinate(lon, decimals, 'E', 'W'), false));
+        lon += step;
+    }
+    page.content.set_stroke_gray(0.5).set_line_width(0.8);
+    page.content.rect(map_x as f32, map_y as f32, map_width as f32, map_height as f32).stroke();
+    page.content.restore_state();
+    for (x, y, label, right_aligned) in grid_labels {
+        match right_aligned {
+            true => page.text(REGULAR_FONT, 7.0, x - text_width(&label, 7.0), y, &label),
+            false => page.text_centered(REGULAR_FONT, 7.0, x, y, &label),
+        }
+    }
+
+    // Close photos are merged into a dot sized by their count, colored by the busiest month
+    let places = cluster_places(located, span / 80.0);
+    for place in places.iter().rev() {
+        let (x, y) = project(place.latitude, place.longitude);
+        let radius = (2.5 + (place.photos as f32).sqrt()).min(14.0);
+        let (r, g, b) = month_color(place.busiest_month);
+        page.content.save_state();
+        page.content.set_fill_rgb(r, g, b).set_stroke_gray(1.0).set_line_width(0.6);
+        circle(&mut page.content, x, y, radius);
+        page.content.fill_nonzero();
+        circle(&mut page.content, x, y, radius);
+        page.content.stroke();
+        page.content.restore_state();
+    }
+
+    // Legend of the month colors
+    y = map_top as f32 - 440.0 - 28.0;
+    let slot = CONTENT_WIDTH / 12.0;
+    for month in 1..=12 {
+        let x = MARGIN + slot * (month - 1) as f32;
+        let (r, g, b) = month_color(month);
+        page.content.save_state();
+        page.content.set_fill_rgb(r, g, b);
+        circle(&mut page.content, x + 6.0, y + 3.0, 4.0);
+        page.content.fill_nonzero();
+        page.content.restore_state();
+        let label = NaiveDate::from_ymd_opt(2000, month, 1).map(|date| date.format("%b").to_string()).unwrap_or_default();
+        page.text(REGULAR_FONT, 8.0, x + 13.0, y, &label);
+    }
+
+    y -= 34.0;
+    page.text(BOLD_FONT, 13.0, MARGIN, y, &format!("Most photographed places ({} geotagged photos)", located.len()));
+    y -= 20.0;
+    for place in places.iter().take(LISTED_PLACES) {
+        let months = place.months.iter()
+            .filter_map(|month| NaiveDate::from_ymd_opt(2000, *month, 1).map(|date| date.format("%b").to_string()))
+            .collect::<Vec<_>>();
+        let line = format!(
+            "{}, {} - {} photos in {}",
+            format_coordinate(place.latitude, 4, 'N', 'S'),
+            format_coordinate(place.longitude, 4, 'E', 'W'),
+            place.photos,
+            months.join(", "),
+        );
+        page.text(REGULAR_FONT, 10.0, MARGIN, y, &fit_text(&line, 10.0, CONTENT_WIDTH));
+        y -= 15.0;
+    }
+    page
+}
+
+fn month_pages(doc: &mut Document, catalog: &Catalog, year: i32, month: u32, entries: &[&CatalogEntry], photos_per_month: usize) {
+    let days = entries.iter().filter_map(|entry| entry.timestamp.map(|ts| ts.date())).collect::<BTreeSet<_>>();
+    let geotagged = entries.iter().filter(|entry| entry.exif.position().is_some()).count();
+    let title = month_name(year, month);
+    let summary = format!("{} photos on {} days, {geotagged} geotagged", entries.len(), days.len());
+
+    let cell_width = (CONTENT_WIDTH - GRID_GAP * (GRID_COLUMNS - 1) as f32) / GRID_COLUMNS as f32;
+    let cell_height = cell_width + CAPTION_HEIGHT;
+    let grid_top = PAGE_HEIGHT - MARGIN - 70.0;
+    let rows_per_page = (((grid_top - MARGIN - 20.0) + GRID_GAP) / (cell_height + GRID_GAP)).floor().max(1.0) as usize;
+
+    let mut photos = Vec::new();
+    for entry in representative_photos(entries, photos_per_month) {
+        match catalog.read_thumbnail(entry).and_then(|jpeg| doc.image(&jpeg)) {
+            Ok(image) => photos.push((entry, image)),
+            Err(err) => eprintln!("Error reading thumbnail of {} - {err}", entry.source_path.display()),
+        }
+    }
+
+    // Months without any thumbnail still get their page, with the counts only
+    let chunks = match photos.is_empty() {
+        true => vec![&photos[..]],
+        false => photos.chunks(rows_per_page * GRID_COLUMNS).collect(),
+    };
+    for (idx, chunk) in chunks.into_iter().enumerate() {
+        let mut page = Page::default();
+        let heading = if idx == 0 { title.clone() } else { format!("{title} (continued)") };
+        page.text(BOLD_FONT, 24.0, MARGIN, PAGE_HEIGHT - MARGIN - 24.0, &heading);
+        page.text(REGULAR_FONT, 11.0, MARGIN, PAGE_HEIGHT - MARGIN - 44.0, &summary);
+
+        for (pos, (entry, image)) in chunk.iter().enumerate() {
+            let (row, column) = (pos / GRID_COLUMNS, pos % GRID_COLUMNS);
+            let x = MARGIN + column as f32 * (cell_width + GRID_GAP);
+            let top = grid_top - row as f32 * (cell_height + GRID_GAP);
+            let box_y = top - cell_width;
+            page.image(image, x, box_y, cell_width, cell_width);
+
+            let date = entry.timestamp.map(|ts| ts.format("%a %-d %b, %H:%M").to_string()).unwrap_or_default();
+            let marks = match (entry.favorite, entry.rating) {
+                (true, _) => String::from(" - favorite"),
+                (false, Some(rating)) => format!(" - {rating}/5"),
+                (false, None) => String::new(),
+            };
+            page.text(REGULAR_FONT, 8.5, x, box_y - 11.0, &fit_text(&format!("{date}{marks}"), 8.5, cell_width));
+            let caption = entry.caption.clone().unwrap_or_else(|| entry.file_name());
+            page.with_gray(0.4, |page| page.text(REGULAR_FONT, 8.0, x, box_y - 22.0, &fit_text(&caption, 8.0, cell_width)));
+        }
+        doc.add_page(page);
+    }
+}
+
+/// Photos shown for a month: its favorites and best rated ones first, up to half of them, then
+/// the others spread over the month, in capture order
+fn representative_photos<'a>(entries: &[&'a CatalogEntry], count: usize) -> Vec<&'a CatalogEntry> {
+    let candidates = entries.iter().copied().filter(|entry| entry.thumbnail_path.is_some()).collect::<Vec<_>>();
+    let mut highlights = candidates.iter().copied()
+        .filter(|entry| entry.favorite || entry.rating.is_some_and(|rating| rating >= 4))
+        .collect::<Vec<_>>();
+    highlights.sort_by_key(|entry| Reverse((entry.favorite, entry.rating)));
+    let mut picks = highlights.into_iter().take(count / 2).collect::<Vec<_>>();
+
+    let others = candidates.into_iter().filter(|entry| !picks.iter().any(|pick| std::ptr::eq(*pick, *entry))).collect::<Vec<_>>();
+    let missing = count.saturating_sub(picks.len()).min(others.len());
+    // The middle photo of as many equal slices of the month
+    picks.extend((0..missing).map(|slice| others[(2 * slice + 1) * others.len() / (2 * missing)]));
+    picks.sort_by_key(|entry| entry.timestamp);
+    picks
+}
+
+fn cluster_places(located: &[LocatedPhoto], cell: f64) -> Vec<Place> {
+    let mut cells: HashMap<(i64, i64), Vec<&LocatedPhoto>> = HashMap::new();
+    for photo in located {
+        cells.entry(((photo.latitude / cell).floor() as i64, (photo.longitude / cell).floor() as i64)).or_default().push(photo);
+    }
+    let mut places = cells.into_values()
+        .map(|points| {
+            let mut months: BTreeMap<u32, usize> = BTreeMap::new();
+            for photo in points.iter() {
+                *months.entry(photo.month).or_default() += 1;
+            }
+            Place {
+                latitude: points.iter().map(|photo| photo.latitude).sum::<f64>() / points.len() as f64,
+                longitude: points.iter().map(|photo| photo.longitude).sum::<f64>() / points.len() as f64,
+                photos: points.len(),
+                busiest_month: months.iter().max_by_key(|(month, photos)| (**photos, Reverse(**month))).map_or(1, |(month, _)| *month),
+                months: months.into_keys().collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+    places.sort_by(|a, b| b.photos.cmp(&a.photos).then(a.latitude.total_cmp(&b.latitude)));
+    places
+}
+
+/// Thumbnail embedded in the document
+struct PageImage {
+    id: Ref,
+    width: f32,
+    height: f32,
+    /// EXIF orientation copied from the photo, applied when drawing
+    orientation: u32,
+}
+
+/// Pages of a document being laid out
+struct Document {
+    pdf: Pdf,
+    title: String,
+    next_id: i32,
+    pages: Vec<Ref>,
+}
+
+const CATALOG_ID: Ref = Ref::new(1);
+const PAGE_TREE_ID: Ref = Ref::new(2);
+const REGULAR_FONT_ID: Ref = Ref::new(3);
+const BOLD_FONT_ID: Ref = Ref::new(4);
+const INFO_ID: Ref = Ref::new(5);
+
+impl Document {
+    fn new(title: &str) -> Self {
+        Self { pdf: Pdf::new(), title: String::from(title), next_id: 6, pages: Vec::new() }
+    }
+
+    fn next_ref(&mut self) -> Ref {
+        let id = Ref::new(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Embed a JPEG as is, PDF viewers decode it themselves
+    fn image(&mut self, jpeg: &[u8]) -> anyhow::Result<PageImage> {
+        let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(jpeg));
+        decoder.read_info()?;
+        let info = decoder.info().ok_or_else(|| anyhow::anyhow!("Missing JPEG header"))?;
+        let orientation = exif::Reader::new().read_from_container(&mut Cursor::new(jpeg)).ok()
+            .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY).and_then(|field| field.value.get_uint(0)))
+            .unwrap_or(1);
+
+        let id = self.next_ref();
+        let mut image = self.pdf.image_xobject(id, jpeg);
+        image.filter(Filter::DctDecode);
+        image.width(info.width as i32);
+        image.height(info.height as i32);
+        image.bits_per_component(8);
+        match info.pixel_format {
+            PixelFormat::L8 => image.color_space().device_gray(),
+            PixelFormat::RGB24 => image.color_space().device_rgb(),
+            PixelFormat::CMYK32 => {
+                image.color_space().device_cmyk();
+                // Adobe CMYK JPEGs are stored inverted
+                image.decode([1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+            }
+            PixelFormat::L16 => anyhow::bail!("16 bit JPEGs are not supported"),
+        }
+        image.finish();
+        Ok(PageImage { id, width: info.width as f32, height: info.height as f32, orientation })
+    }
+
+    fn add_page(&mut self, mut page: Page) {
+        let number = self.pages.len() + 1;
+        page.with_gray(0.5, |page| {
+            page.text(REGULAR_FONT, 8.0, MARGIN, MARGIN / 2.0, &self.title);
+            let number = number.to_string();
+            page.text(REGULAR_FONT, 8.0, PAGE_WIDTH - MARGIN - text_width(&number, 8.0), MARGIN / 2.0, &number);
+        });
+
+        let page_id = self.next_ref();
+        let content_id = self.next_ref();
+        let names = (0..page.images.len()).map(|idx| format!("Im{idx}")).collect::<Vec<_>>();
+        let mut pdf_page = self.pdf.page(page_id);
+        pdf_page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        pdf_page.parent(PAGE_TREE_ID);
+        pdf_page.contents(content_id);
+        let mut resources = pdf_page.resources();
+        resources.fonts().pair(REGULAR_FONT, REGULAR_FONT_ID).pair(BOLD_FONT, BOLD_FONT_ID);
+        let mut x_objects = resources.x_objects();
+        for (name, id) in names.iter().zip(page.images.iter()) {
+            x_objects.pair(Name(name.as_bytes()), *id);
+        }
+        x_objects.finish();
+        resources.finish();
+        pdf_page.finish();
+        self.pdf.stream(content_id, &page.content.finish());
+        self.pages.push(page_id);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.pdf.catalog(CATALOG_ID).pages(PAGE_TREE_ID);
+        self.pdf.pages(PAGE_TREE_ID).kids(self.pages.iter().copied()).count(self.pages.len() as i32);
+        for (id, font) in [(REGULAR_FONT_ID, Name(b"Helvetica")), (BOLD_FONT_ID, Name(b"Helvetica-Bold"))] {
+            self.pdf.type1_font(id).base_font(font).encoding_predefined(Name(b"WinAnsiEncoding"));
+        }
+        self.pdf.document_info(INFO_ID).title(TextStr(&self.title)).producer(TextStr("photo-archive"));
+        self.pdf.finish()
+    }
+}
+
+/// Content of a page, with the images it draws
+struct Page {
+    content: Content,
+    images: Vec<Ref>,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self { content: Content::new(), images: Vec::new() }
+    }
+}
+
+impl Page {
+    fn text(&mut self, font: Name, size: f32, x: f32, y: f32, text: &str) {
+        let encoded = win_ansi(text);
+        self.content.begin_text();
+        self.content.set_font(font, size);
+        self.content.next_line(x, y);
+        self.content.show(Str(&encoded));
+        self.content.end_text();
+    }
+
+    fn text_centered(&mut self, font: Name, size: f32, x: f32, y: f32, text: &str) {
+        self.text(font, size, x - text_width(text, size) / 2.0, y, text);
+    }
+
+    fn with_gray(&mut self, gray: f32, draw: impl FnOnce(&mut Self)) {
+        self.content.save_state();
+        self.content.set_fill_gray(gray);
+        draw(self);
+        self.content.restore_state();
+    }
+
+    /// Draw the image as large as it fits in the box, centered and upright
+    fn image(&mut self, image: &PageImage, x: f32, y: f32, width: f32, height: f32) {
+        let rotated = image.orientation >= 5;
+        let (image_width, image_height) = if rotated { (image.height, image.width) } else { (image.width, image.height) };
+        let scale = (width / image_width).min(height / image_height);
+        let (w, h) = (image_width * scale, image_height * scale);
+        let (x, y) = (x + (width - w) / 2.0, y + (height - h) / 2.0);
+        // Maps the unit square of the stored image to the upright one
+        let [a, b, c, d, e, f] = match image.orientation {
+            2 => [-1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+            3 => [-1.0, 0.0, 0.0, -1.0, 1.0, 1.0],
+            4 => [1.0, 0.0, 0.0, -1.0, 0.0, 1.0],
+            5 => [0.0, -1.0, -1.0, 0.0, 1.0, 1.0],
+            6 => [0.0, -1.0, 1.0, 0.0, 0.0, 1.0],
+            7 => [0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            8 => [0.0, 1.0, -1.0, 0.0, 1.0, 0.0],
+            _ => [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+        };
+        let name = format!("Im{}", self.images.len());
+        self.images.push(image.id);
+        self.content.save_state();
+        self.content.transform([w * a, h * b, w * c, h * d, w * e + x, h * f + y]);
+        self.content.x_object(Name(name.as_bytes()));
+        self.content.restore_state();
+    }
+}
+
+/// Add a circle to the current path
+fn circle(content: &mut Content, x: f32, y: f32, radius: f32) {
+    // Control points of the cubic Bézier curves closest to a quarter of circle
+    let k = radius * 0.552_284_8;
+    content.move_to(x + radius, y);
+    content.cubic_to(x + radius, y + k, x + k, y + radius, x, y + radius);
+    content.cubic_to(x - k, y + radius, x - radius, y + k, x - radius, y);
+    content.cubic_to(x - radius, y - k, x - k, y - radius, x, y - radius);
+    content.cubic_to(x + k, y - radius, x + radius, y - k, x + radius, y);
+    content.close_path();
+}
+
+/// Text in the encoding of the standard fonts, characters it lacks are replaced by `?`
+fn win_ansi(text: &str) -> Vec<u8> {
+    // Windows-1252 matches Latin-1 for printable characters but the 0x80-0x9F range
+    text.chars()
+        .map(|c| match c as u32 {
+            code @ (0x20..=0x7E | 0xA0..=0xFF) => code as u8,
+            0x2013 => 0x96,
+            0x2014 => 0x97,
+            0x2019 => 0x92,
+            0x201C => 0x93,
+            0x201D => 0x94,
+            0x2026 => 0x85,
+            0x20AC => 0x80,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Width of the text in Helvetica, approximated from the average width of character classes
+fn text_width(text: &str, size: f32) -> f32 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '!' | '\'' | '|' => 0.25,
+            ' ' | 'f' | 't' | 'r' | 'I' | '(' | ')' | '-' | '/' => 0.32,
+            'm' | 'w' | 'M' | 'W' => 0.84,
+            c if c.is_uppercase() => 0.68,
+            _ => 0.556,
+        })
+        .sum::<f32>()
+        * size
+}
+
+/// The text shortened with an ellipsis to fit the width
+fn fit_text(text: &str, size: f32, max_width: f32) -> String {
+    if text_width(text, size) <= max_width {
+        return String::from(text);
+    }
+    let mut fitted = String::new();
+    for c in text.chars() {
+        if text_width(&fitted, size) + text_width(&format!("{c}..."), size) > max_width {
+            break;
+        }
+        fitted.push(c);
+    }
+    format!("{}...", fitted.trim_end())
+}
+
+fn month_name(year: i32, month: u32) -> String {
+    NaiveDate::from_ymd_opt(year, month, 1).map(|date| date.format("%B %Y").to_string()).unwrap_or_default()
+}
+
+/// Color of the photos of the month, hues going around the color wheel through the year
+fn month_color(month: u32) -> (f32, f32, f32) {
+    let hue = (month.saturating_sub(1) % 12) as f32 / 12.0 * 6.0;
+    let (saturation, value) = (0.65, 0.85);
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    (r + m, g + m, b + m)
+}
+
+/// Round step of the map grid, 1, 2 or 5 times a power of ten degrees
+fn grid_step(raw: f64) -> f64 {
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+fn format_coordinate(value: f64, decimals: usize, positive: char, negative: char) -> String {
+    let hemisphere = if value < 0.0 { negative } else { positive };
+    format!("{:.decimals$}° {hemisphere}", value.abs())
+}
+
+/// Bounds at least `min_span` apart, around their middle
+fn widen(min: f64, max: f64, min_span: f64) -> (f64, f64) {
+    if max - min >= min_span {
+        return (min, max);
+    }
+    let middle = (min + max) / 2.0;
+    (middle - min_span / 2.0, middle + min_span / 2.0)
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::{is_index_file_name, PhotoArchiveRecordsStore};
+use crate::repository::sources::SourcesRepo;
+
+/// Overview of an archive, to find one's way in an archive left untouched for a long time
+#[derive(Debug)]
+pub struct ArchiveInfo {
+    /// Default settings for archives created before the manifest was introduced
+    pub manifest: ArchiveManifest,
+    pub has_manifest: bool,
+    pub sources: usize,
+    /// Unix timestamp of the end of the most recent sync of any source
+    pub last_sync: Option<i64>,
+    /// Why the index was not read, encrypted archives need their secret
+    pub index: Result<IndexHealth, String>,
+    pub disk_usage: DiskUsage,
+}
+
+#[derive(Debug, Default)]
+pub struct IndexHealth {
+    pub index_files: usize,
+    pub records: usize,
+    /// Records of photos whose source file was deleted
+    pub deleted: usize,
+    pub unreadable_lines: usize,
+    /// Records of sources missing from sources.ndjson
+    pub unknown_source_records: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct DiskUsage {
+    /// Size of the files of the archive, hard linked ones counted once
+    pub bytes: u64,
+    pub index_bytes: u64,
+    pub files: usize,
+}
+
+/// Describe the archive without changing it, reading its manifest, sources and index rows
+pub fn describe_archive(target: &Path, secret: Option<&ArchiveSecret>) -> anyhow::Result<ArchiveInfo> {
+    let sources_repo = SourcesRepo::new(target.to_path_buf());
+    let (manifest, has_manifest) = match ArchiveManifest::load(target)? {
+        Some(manifest) => (manifest, true),
+        None if sources_repo.exists() => (ArchiveManifest::default(), false),
+        None => anyhow::bail!("{target:?} is not a photo archive"),
+    };
+    let sources = sources_repo.all()?;
+    let source_ids = sources.iter().map(|source| source.id.as_str()).collect::<HashSet<_>>();
+
+    let index = manifest.cipher(secret)
+        .map_err(|err| err.to_string())
+        .and_then(|cipher| {
+            let mut health = IndexHealth::default();
+            let mut index_files = HashSet::new();
+            PhotoArchiveRecordsStore::with_cipher(target, cipher).for_each_line(|index_path, _, row| {
+                if !index_files.contains(index_path) {
+                    index_files.insert(index_path.to_path_buf());
+                }
+                match row {
+                    Ok(row) => {
+                        health.records += 1;
+                        if row.deleted_at().is_some() {
+                            health.deleted += 1;
+                        }
+                        if !source_ids.contains(row.source_id()) {
+                            health.unknown_source_records += 1;
+                        }
+                    }
+                    Err(_) => health.unreadable_lines += 1,
+                }
+            }).map_err(|err| err.to_string())?;
+            health.index_files = index_files.len();
+            Ok(health)
+        });
+
+    Ok(ArchiveInfo {
+        has_manifest,
+        last_sync: sources.iter().filter_map(|source| source.last_sync).max(),
+        sources: sources.len(),
+        manifest,
+        index,
+        disk_usage: disk_usage(target)?,
+    })
+}
+
+/// Size of the files under `dir`, links and their targets outside the archive are not counted
+fn disk_usage(dir: &Path) -> anyhow::Result<DiskUsage> {
+    let mut usage = DiskUsage::default();
+    let mut seen_inodes = HashSet::new();
+    let mut dirs: Vec<PathBuf> = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+                continue;
+            }
+            if !metadata.is_file() || (metadata.nlink() > 1 && !seen_inodes.insert((metadata.dev(), metadata.ino()))) {
+                continue;
+            }
+            usage.bytes += metadata.len();
+            usage.files += 1;
+            if entry.file_name().to_str().is_some_and(is_index_file_name) {
+                usage.index_bytes += metadata.len();
+            }
+        }
+    }
+    Ok(usage)
+}
@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::source_records::IndexedFile;
+
+const KNOWN_FILES_DIR: &str = "bloom";
+const MAGIC: &[u8; 4] = b"PAB1";
+/// About 1% of false positives
+const BITS_PER_FILE: usize = 10;
+const HASHES: u32 = 7;
+
+/// Bloom filter of the files of a source found archived, with their link, by the last sync.
+///
+/// Re-syncs consult it before extracting the metadata of a file or looking for its link in the
+/// archive, an unchanged file found in the filter is skipped with a single stat of the source.
+/// A false positive only means that a missing link is not recreated, the file being indexed
+/// already; full checks ignore the filter.
+///
+/// Keys are hashed with the names key of encrypted archives, so that the filter discloses
+/// nothing about their content.
+pub struct KnownFiles {
+    filter: Option<BloomFilter>,
+    hasher: blake3::Hasher,
+    /// Keys of the files found archived by the running sync, making up the next filter
+    found: Mutex<Vec<FileKey>>,
+}
+
+/// Hash of a file name, size, modification time and digest
+#[derive(Clone, Copy)]
+pub struct FileKey(u64, u64);
+
+impl KnownFiles {
+    /// Filter written by the last sync of the source, a missing or unreadable one knows no file
+    pub fn load(target_base_dir: &Path, source_id: &str, cipher: Option<&ArchiveCipher>) -> Self {
+        let filter = fs::read(filter_path(target_base_dir, source_id)).ok()
+            .and_then(|bytes| BloomFilter::from_bytes(&bytes));
+        Self {
+            filter,
+            hasher: cipher.map(ArchiveCipher::names_hasher).unwrap_or_default(),
+            found: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Key of a source file, `link_dir` is part of it since renaming a source moves its links
+    pub fn key(&self, link_dir: &Path, source_path: &Path, indexed: &IndexedFile) -> FileKey {
+        let mut hasher = self.hasher.clone();
+        for path in [link_dir, source_path] {
+            hasher.update(path.as_os_str().as_encoded_bytes());
+            hasher.update(b"\0");
+        }
+        let file_ts = indexed.file_ts.duration_since(std::time::UNIX_EPOCH).map_or(0, |ts| ts.as_secs());
+        hasher.update(&indexed.size.to_le_bytes());
+        hasher.update(&file_ts.to_le_bytes());
+        hasher.update(indexed.digest.to_string().as_bytes());
+        let hash = hasher.finalize();
+        let bytes = hash.as_bytes();
+        FileKey(
+            u64::from_le_bytes(bytes[..8].try_into().expect("Hash too short")),
+            u64::from_le_bytes(bytes[8..16].try_into().expect("Hash too short")),
+        )
+    }
+
+    /// Whether the file was found archived by the last sync, possibly wrongly
+    pub fn contains(&self, key: FileKey) -> bool {
+        self.filter.as_ref().is_some_and(|filter| filter.contains(key))
+    }
+
+    /// Remember that the file is archived, for the next sync
+    pub fn found(&self, key: FileKey) {
+        self.found.lock().expect("Known files lock poisoned").push(key);
+    }
+
+    /// Replace the filter of the source with the files found archived by this sync, the ones of
+    /// the previous filter are kept with `keep_previous` as long as it has room for the new ones
+    pub fn store(&self, target_base_dir: &Path, source_id: &str, keep_previous: bool) -> anyhow::Result<()> {
+        let found = self.found.lock().expect("Known files lock poisoned");
+        let mut filter = BloomFilter::with_capacity(found.len());
+        if let Some(previous) = self.filter.as_ref().filter(|previous| keep_previous && previous.words.len() >= filter.words.len()) {
+            filter.words.clone_from(&previous.words);
+        }
+        for key in found.iter() {
+            filter.insert(*key);
+        }
+
+        let path = filter_path(target_base_dir, source_id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, filter.to_bytes())?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+fn filter_path(target_base_dir: &Path, source_id: &str) -> PathBuf {
+    target_base_dir.join(KNOWN_FILES_DIR).join(format!("{source_id}.bloom"))
+}
+
+struct BloomFilter {
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    fn with_capacity(files: usize) -> Self {
+        let bits = (files * BITS_PER_FILE).max(64);
+        Self { words: vec![0; bits.div_ceil(64)] }
+    }
+
+    fn bits(&self) -> u64 {
+        self.words.len() as u64 * 64
+    }
+
+    /// Bit positions of the key, by double hashing
+    fn positions(&self, key: FileKey) -> impl Iterator<Item=u64> {
+        let bits = self.bits();
+        (0..HASHES).map(move |idx| key.0.wrapping_add(u64::from(idx).wrapping_mul(key.1)) % bits)
+    }
+
+    fn insert(&mut self, key: FileKey) {
+        for position in self.positions(key).collect::<Vec<_>>() {
+            self.words[(position / 64) as usize] |= 1 << (position % 64);
+        }
+    }
+
+    fn contains(&self, key: FileKey) -> bool {
+        self.positions(key).all(|position| self.words[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + self.words.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let words = bytes.strip_prefix(MAGIC.as_slice())?;
+        if words.is_empty() || words.len() % 8 != 0 {
+            return None;
+        }
+        let words = words.chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().expect("Chunk of 8 bytes")))
+            .collect();
+        Some(Self { words })
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt::{Display, Formatter};
+use std::os::unix::prelude::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::sync::CASTAGNOLI;
+use crate::archive::template::{sanitize_component, PathTemplate, TemplateField};
+
+const UNDATED_DIR: &str = "no-date";
+const FLAT_DIR: &str = "all";
+
+/// How dated photos are grouped into directories, fixed when the archive is created.
+///
+/// Each date directory holds an `img` folder with the thumbnails and the per-source link folders,
+/// coarser layouts keep the missing date parts in the thumbnail names instead.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ArchiveLayout {
+    /// `YYYY/MM.DD`, the historical layout
+    #[default]
+    Day,
+    /// `YYYY/MM`
+    Month,
+    /// `YYYY`
+    Year,
+    /// A single `all` directory for every dated photo
+    Flat,
+    /// Custom directories built from the `{year}`, `{month}` and `{day}` fields
+    Template(PathTemplate),
+}
+
+impl Display for ArchiveLayout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveLayout::Day => write!(f, "day"),
+            ArchiveLayout::Month => write!(f, "month"),
+            ArchiveLayout::Year => write!(f, "year"),
+            ArchiveLayout::Flat => write!(f, "flat"),
+            ArchiveLayout::Template(template) => write!(f, "{template}"),
+        }
+    }
+}
+
+impl FromStr for ArchiveLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "day" => Ok(ArchiveLayout::Day),
+            "month" => Ok(ArchiveLayout::Month),
+            "year" => Ok(ArchiveLayout::Year),
+            "flat" => Ok(ArchiveLayout::Flat),
+            _ if s.contains('{') => {
+                let template = PathTemplate::from_str(s)?;
+                template.check_fields(TemplateField::DATE)?;
+                Ok(ArchiveLayout::Template(template))
+            }
+            other => anyhow::bail!("Unknown archive layout '{other}', expected one of day, month, year, flat or a template"),
+        }
+    }
+}
+
+impl TryFrom<String> for ArchiveLayout {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+impl From<ArchiveLayout> for String {
+    fn from(value: ArchiveLayout) -> Self {
+        value.to_string()
+    }
+}
+
+impl ArchiveLayout {
+    /// Directory of the photo relative to the archive root, undated photos always go to `no-date`
+    pub fn date_dir(&self, photo_timestamp: Option<&NaiveDateTime>) -> PathBuf {
+        let Some(datetime) = photo_timestamp else {
+            return PathBuf::from(UNDATED_DIR);
+        };
+        match self {
+            ArchiveLayout::Day => PathBuf::from(datetime.year().to_string()).join(datetime.format("%m.%d").to_string()),
+            ArchiveLayout::Month => PathBuf::from(datetime.year().to_string()).join(datetime.format("%m").to_string()),
+            ArchiveLayout::Year => PathBuf::from(datetime.year().to_string()),
+            ArchiveLayout::Flat => PathBuf::from(FLAT_DIR),
+            ArchiveLayout::Template(template) => template.render(|field| match field {
+                TemplateField::Year => datetime.format("%Y").to_string(),
+                TemplateField::Month => datetime.format("%m").to_string(),
+                TemplateField::Day => datetime.format("%d").to_string(),
+                _ => String::new(),
+            }),
+        }
+    }
+
+    /// Timestamp prefix of the thumbnail names of dated photos
+    pub fn time_format(&self) -> &'static str {
+        match self {
+            ArchiveLayout::Day => "%H%M%S",
+            ArchiveLayout::Month => "%d-%H%M%S",
+            ArchiveLayout::Year => "%m%d-%H%M%S",
+            ArchiveLayout::Flat | ArchiveLayout::Template(_) => "%Y%m%d-%H%M%S",
+        }
+    }
+}
+
+/// How the per-source link directories inside the date directories are named
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LinkDirNaming {
+    /// `<source crc>.<dir crc>.<dir name>`, the historical naming, immune to name collisions
+    #[default]
+    Crc,
+    /// `<source name>/<source dir>`, mirroring the source tree under each date directory
+    Readable,
+    /// Custom names built from the source fields, e.g. `{source_name}/{orig_dir}`
+    Template(PathTemplate),
+}
+
+impl Display for LinkDirNaming {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkDirNaming::Crc => write!(f, "crc"),
+            LinkDirNaming::Readable => write!(f, "readable"),
+            LinkDirNaming::Template(template) => write!(f, "{template}"),
+        }
+    }
+}
+
+impl FromStr for LinkDirNaming {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "crc" => Ok(LinkDirNaming::Crc),
+            "readable" => Ok(LinkDirNaming::Readable),
+            _ if s.contains('{') => {
+                let template = PathTemplate::from_str(s)?;
+                template.check_fields(TemplateField::SOURCE)?;
+                Ok(LinkDirNaming::Template(template))
+            }
+            other => anyhow::bail!("Unknown link directory naming '{other}', expected crc, readable or a template"),
+        }
+    }
+}
+
+impl TryFrom<String> for LinkDirNaming {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+impl From<LinkDirNaming> for String {
+    fn from(value: LinkDirNaming) -> Self {
+        value.to_string()
+    }
+}
+
+impl LinkDirNaming {
+    /// Whether the names cannot be derived from the index rows alone and must be stored in them
+    pub fn is_stored(&self) -> bool {
+        !matches!(self, LinkDirNaming::Crc)
+    }
+
+    /// Whether the link directories of two sources sharing the same name would collide
+    pub fn uses_source_name(&self) -> bool {
+        match self {
+            LinkDirNaming::Crc => false,
+            LinkDirNaming::Readable => true,
+            LinkDirNaming::Template(template) => template.uses(TemplateField::SourceName),
+        }
+    }
+
+    /// Link directory, relative to the date directory, of a photo of the given source
+    pub fn link_dir(&self, source_id: &str, source_name: &str, source_relative_path: &Path) -> PathBuf {
+        match self {
+            LinkDirNaming::Crc => crc_link_dir(source_id, source_relative_path),
+            LinkDirNaming::Readable => {
+                let source_dir = source_relative_path.parent().unwrap_or(Path::new(""));
+                let source_name = Some(sanitize_component(&source_name.replace('/', "_")))
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| sanitize_component(&source_id.replace('/', "_")));
+                std::iter::once(source_name)
+                    .chain(source_dir.iter().map(|component| sanitize_component(&component.to_string_lossy())))
+                    .filter(|component| !component.is_empty())
+                    .collect()
+            }
+            LinkDirNaming::Template(template) => {
+                let source_dir = source_relative_path.parent().unwrap_or(Path::new(""));
+                template.render(|field| match field {
+                    TemplateField::SourceId => String::from(source_id),
+                    TemplateField::SourceName => String::from(source_name),
+                    TemplateField::SourceCrc => format!("{:08X}", CASTAGNOLI.checksum(source_id.as_bytes())),
+                    TemplateField::DirCrc => format!("{:08X}", CASTAGNOLI.checksum(source_dir.as_os_str().as_bytes())),
+                    TemplateField::DirName => source_dir_name(source_dir),
+                    TemplateField::OrigDir => source_dir.to_string_lossy().into_owned(),
+                    _ => String::new(),
+                })
+            }
+        }
+    }
+}
+
+fn source_dir_name(source_dir: &Path) -> String {
+    source_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(String::from)
+        .unwrap_or_else(|| String::from("ROOT"))
+}
+
+/// Historical link directory name, derived from the source id and the photo directory
+pub fn crc_link_dir(source_id: &str, source_relative_path: &Path) -> PathBuf {
+    let source_dir = source_relative_path.parent().expect("No source dir found");
+    PathBuf::from(format!(
+        "{:08X}.{:08X}.{}",
+        CASTAGNOLI.checksum(source_id.as_bytes()),
+        CASTAGNOLI.checksum(source_dir.as_os_str().as_bytes()),
+        source_dir_name(source_dir),
+    ))
+}
@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::common::ArchivedPhotoPaths;
+
+/// How the per-source link directories point to the thumbnails stored in the `img` directories.
+///
+/// Symlinks are the most compact option but are not supported by every filesystem (exFAT, NTFS
+/// through some drivers, Windows without developer mode), the other strategies are fallbacks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStrategy {
+    /// Relative symbolic link to the thumbnail
+    #[default]
+    Symlink,
+    /// Hard link to the thumbnail, requires link and thumbnail to be on the same filesystem
+    Hardlink,
+    /// Full copy of the thumbnail
+    Copy,
+    /// Small `.ref` text file containing the relative path of the thumbnail
+    Ref,
+}
+
+const REF_EXTENSION: &str = "ref";
+
+impl Display for LinkStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkStrategy::Symlink => write!(f, "symlink"),
+            LinkStrategy::Hardlink => write!(f, "hardlink"),
+            LinkStrategy::Copy => write!(f, "copy"),
+            LinkStrategy::Ref => write!(f, "ref"),
+        }
+    }
+}
+
+impl FromStr for LinkStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "symlink" => Ok(LinkStrategy::Symlink),
+            "hardlink" => Ok(LinkStrategy::Hardlink),
+            "copy" => Ok(LinkStrategy::Copy),
+            "ref" => Ok(LinkStrategy::Ref),
+            other => anyhow::bail!("Unknown link strategy '{other}', expected one of symlink, hardlink, copy, ref"),
+        }
+    }
+}
+
+impl LinkStrategy {
+    /// Whether the thumbnail must already be on disk when the link is created
+    pub fn requires_thumbnail(&self) -> bool {
+        matches!(self, LinkStrategy::Hardlink | LinkStrategy::Copy)
+    }
+
+    /// Path of the link entry for the given link file path, `.ref` files get their own extension
+    pub fn link_path(&self, link_file_path: &Path) -> PathBuf {
+        match self {
+            LinkStrategy::Ref => {
+                let mut file_name = link_file_path.file_name().unwrap_or_default().to_os_string();
+                file_name.push(".");
+                file_name.push(REF_EXTENSION);
+                link_file_path.with_file_name(file_name)
+            }
+            _ => link_file_path.to_path_buf(),
+        }
+    }
+}
+
+/// Where symlinks (and `.ref` files) point to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkStyle {
+    /// `../img/<thumbnail>`, compact but tied to the link directory sitting next to `img`
+    #[default]
+    Relative,
+    /// Absolute path of the thumbnail, survives reorganized date folders but not a moved archive
+    Absolute,
+    /// Relative path going up to the archive root and down to the thumbnail, survives a moved archive
+    RootRelative,
+}
+
+impl Display for SymlinkStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SymlinkStyle::Relative => write!(f, "relative"),
+            SymlinkStyle::Absolute => write!(f, "absolute"),
+            SymlinkStyle::RootRelative => write!(f, "root-relative"),
+        }
+    }
+}
+
+impl FromStr for SymlinkStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "relative" => Ok(SymlinkStyle::Relative),
+            "absolute" => Ok(SymlinkStyle::Absolute),
+            "root-relative" => Ok(SymlinkStyle::RootRelative),
+            other => anyhow::bail!("Unknown symlink style '{other}', expected one of relative, absolute, root-relative"),
+        }
+    }
+}
+
+/// Create, check and remove the link entries of an archive according to its manifest
+#[derive(Clone, Debug)]
+pub struct ArchiveLinker {
+    strategy: LinkStrategy,
+    style: SymlinkStyle,
+    archive_root: PathBuf,
+}
+
+impl ArchiveLinker {
+    pub fn new(archive_root: &Path, strategy: LinkStrategy, style: SymlinkStyle) -> anyhow::Result<Self> {
+        Ok(Self {
+            strategy,
+            style,
+            archive_root: std::path::absolute(archive_root)?,
+        })
+    }
+
+    pub fn strategy(&self) -> LinkStrategy {
+        self.strategy
+    }
+
+    /// Path the link entry of `paths` should point to
+    pub fn link_target(&self, paths: &ArchivedPhotoPaths, thumbnail_name: &str) -> anyhow::Result<PathBuf> {
+        let target = match self.style {
+            SymlinkStyle::Relative => {
+                // Link directories are usually right next to `img`, templated ones can be nested deeper
+                let depth = paths.link_dir_path.strip_prefix(&paths.date_path)?.components().count();
+                (0..depth).map(|_| Path::new(".."))
+                    .collect::<PathBuf>()
+                    .join("img")
+                    .join(thumbnail_name)
+            }
+            SymlinkStyle::Absolute => std::path::absolute(paths.img_path.join(thumbnail_name))?,
+            SymlinkStyle::RootRelative => {
+                let link_dir = std::path::absolute(&paths.link_dir_path)?;
+                let thumbnail = std::path::absolute(paths.img_path.join(thumbnail_name))?;
+                let depth = link_dir.strip_prefix(&self.archive_root)?.components().count();
+                (0..depth).map(|_| Path::new(".."))
+                    .collect::<PathBuf>()
+                    .join(thumbnail.strip_prefix(&self.archive_root)?)
+            }
+        };
+        Ok(target)
+    }
+
+    /// Link the entry of `paths` to the thumbnail `thumbnail_name` stored in its `img` directory
+    pub fn create_link(&self, paths: &ArchivedPhotoPaths, thumbnail_name: &str) -> anyhow::Result<()> {
+        let link_path = self.strategy.link_path(&paths.link_file_path);
+        match self.strategy {
+            LinkStrategy::Symlink => std::os::unix::fs::symlink(self.link_target(paths, thumbnail_name)?, link_path)?,
+            LinkStrategy::Hardlink => fs::hard_link(paths.img_path.join(thumbnail_name), link_path)?,
+            LinkStrategy::Copy => {
+                fs::copy(paths.img_path.join(thumbnail_name), link_path)?;
+            }
+            LinkStrategy::Ref => fs::write(link_path, format!("{}\n", self.link_target(paths, thumbnail_name)?.display()))?,
+        }
+        Ok(())
+    }
+
+    /// Whether a link entry exists for `paths`, dangling ones included
+    pub fn link_exists(&self, paths: &ArchivedPhotoPaths) -> bool {
+        fs::symlink_metadata(self.strategy.link_path(&paths.link_file_path)).is_ok()
+    }
+
+    /// Same as [`Self::link_exists`], answered from `existing` when it listed the link directory
+    pub fn link_exists_in(&self, paths: &ArchivedPhotoPaths, existing: &ExistingLinks) -> bool {
+        let link_path = self.strategy.link_path(&paths.link_file_path);
+        existing.contains(&link_path).unwrap_or_else(|| fs::symlink_metadata(&link_path).is_ok())
+    }
+
+    pub fn remove_link(&self, paths: &ArchivedPhotoPaths) -> anyhow::Result<()> {
+        fs::remove_file(self.strategy.link_path(&paths.link_file_path))?;
+        Ok(())
+    }
+
+    /// Check that the link entry of `paths` exists and points to the expected thumbnail in the configured style
+    pub fn verify_link(&self, paths: &ArchivedPhotoPaths, thumbnail_name: &str) -> anyhow::Result<bool> {
+        let link_path = self.strategy.link_path(&paths.link_file_path);
+        let thumbnail_path = paths.img_path.join(thumbnail_name);
+        let valid = match self.strategy {
+            LinkStrategy::Symlink => fs::read_link(&link_path)? == self.link_target(paths, thumbnail_name)? && thumbnail_path.exists(),
+            LinkStrategy::Ref => Path::new(fs::read_to_string(&link_path)?.trim_end()) == self.link_target(paths, thumbnail_name)? && thumbnail_path.exists(),
+            LinkStrategy::Hardlink => same_file(&link_path, &thumbnail_path)?,
+            LinkStrategy::Copy => fs::read(&link_path)? == fs::read(&thumbnail_path)?,
+        };
+        Ok(valid)
+    }
+}
+
+pub(crate) fn same_file(a: &Path, b: &Path) -> anyhow::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+/// Entries of link directories listed once, so that checking many links costs a directory read
+/// instead of a stat per link, which is slow on external or network targets.
+///
+/// Entries created after the listing are not seen, it is meant for the link directories of a
+/// source while it is being synchronized, each of its links being checked by a single worker.
+#[derive(Default)]
+pub struct ExistingLinks {
+    /// Entry names of each listed directory, `None` for the missing ones
+    dirs: HashMap<PathBuf, Option<HashSet<OsString>>>,
+}
+
+impl ExistingLinks {
+    pub fn list(dirs: impl IntoIterator<Item=PathBuf>) -> anyhow::Result<Self> {
+        let mut listed = HashMap::new();
+        for dir in dirs {
+            if listed.contains_key(&dir) {
+                continue;
+            }
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => Some(entries.map(|entry| entry.map(|entry| entry.file_name())).collect::<Result<HashSet<_>, _>>()?),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err.into()),
+            };
+            listed.insert(dir, entries);
+        }
+        Ok(Self { dirs: listed })
+    }
+
+    /// Whether `path` existed when its directory was listed, `None` when it was not
+    pub fn contains(&self, path: &Path) -> Option<bool> {
+        let entries = self.dirs.get(path.parent()?)?;
+        Some(entries.as_ref().is_some_and(|entries| path.file_name().is_some_and(|name| entries.contains(name))))
+    }
+
+    /// Whether the directory existed when it was listed, `None` when it was not
+    pub fn dir_exists(&self, dir: &Path) -> Option<bool> {
+        self.dirs.get(dir).map(Option::is_some)
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::digest::DigestAlgorithm;
+use crate::archive::encryption::{ArchiveCipher, ArchiveSecret, EncryptionManifest};
+use crate::archive::layout::{ArchiveLayout, LinkDirNaming};
+use crate::archive::originals::{OriginalsCompression, OriginalsMode, OriginalsStore};
+use crate::archive::records_store::IndexSharding;
+use crate::archive::link::{ArchiveLinker, LinkStrategy, SymlinkStyle};
+use crate::archive::thumbnail::{ResizeFilter, ThumbnailOpts, THUMBNAIL_SIZE};
+
+pub const DEFAULT_MIN_DIMENSION: u32 = 300;
+/// Manifest format written by this release, archives with a newer one cannot be opened
+pub const MANIFEST_VERSION: u32 = 1;
+
+fn default_min_dimension() -> u32 {
+    DEFAULT_MIN_DIMENSION
+}
+
+fn default_version() -> u32 {
+    MANIFEST_VERSION
+}
+
+/// Archive-wide settings that must stay consistent across syncs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// Unix timestamp of the archive creation, unknown for archives created before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub digest: DigestAlgorithm,
+    #[serde(default)]
+    pub link: LinkStrategy,
+    #[serde(default)]
+    pub symlink_style: SymlinkStyle,
+    #[serde(default)]
+    pub layout: ArchiveLayout,
+    /// Split of the rows among index files, changing it only affects the rows appended afterwards
+    #[serde(default)]
+    pub index_sharding: IndexSharding,
+    #[serde(default)]
+    pub link_dirs: LinkDirNaming,
+    /// Photos without EXIF timestamp are dated by their file modification time instead of going to `no-date`
+    #[serde(default)]
+    pub undated_by_mtime: bool,
+    /// Images whose smaller side is below this size are ignored unless their source overrides it, 0 keeps them all
+    #[serde(default = "default_min_dimension")]
+    pub min_dimension: u32,
+    #[serde(default)]
+    pub originals: OriginalsMode,
+    #[serde(default)]
+    pub originals_compression: OriginalsCompression,
+    /// Thumbnail settings of the sync creating the archive, later syncs may choose other ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnails: Option<ThumbnailSettings>,
+    /// Set on encrypted archives, kept last since it is serialized as a table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionManifest>,
+}
+
+impl Default for ArchiveManifest {
+    fn default() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            created_at: None,
+            digest: DigestAlgorithm::default(),
+            link: LinkStrategy::default(),
+            symlink_style: SymlinkStyle::default(),
+            layout: ArchiveLayout::default(),
+            index_sharding: IndexSharding::default(),
+            link_dirs: LinkDirNaming::default(),
+            undated_by_mtime: false,
+            min_dimension: DEFAULT_MIN_DIMENSION,
+            originals: OriginalsMode::default(),
+            originals_compression: OriginalsCompression::default(),
+            thumbnails: None,
+            encryption: None,
+        }
+    }
+}
+
+/// How the thumbnails of an archive were generated
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThumbnailSettings {
+    /// Size in pixels of the longest side
+    pub size: u32,
+    pub filter: ResizeFilter,
+    /// Thumbnails carry the whole EXIF metadata of the photos, not only their orientation
+    #[serde(default)]
+    pub keep_exif: bool,
+    /// Pixel digests of fast decoded images differ from the full decode ones
+    pub fast_decode: bool,
+    /// Thumbnails were generated from the EXIF previews when large enough
+    #[serde(default)]
+    pub exif_preview: bool,
+}
+
+impl From<&ThumbnailOpts> for ThumbnailSettings {
+    fn from(opts: &ThumbnailOpts) -> Self {
+        Self {
+            size: THUMBNAIL_SIZE,
+            filter: opts.filter,
+            keep_exif: opts.keep_exif,
+            fast_decode: opts.fast_decode,
+            exif_preview: opts.exif_preview,
+        }
+    }
+}
+
+/// Choices made when creating an archive, the unset ones take their default
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveSettings {
+    pub digest: Option<DigestAlgorithm>,
+    pub link_strategy: Option<LinkStrategy>,
+    pub symlink_style: Option<SymlinkStyle>,
+    pub layout: Option<ArchiveLayout>,
+    pub index_sharding: Option<IndexSharding>,
+    pub link_dirs: Option<LinkDirNaming>,
+    /// Date photos without EXIF timestamp by their file modification time
+    pub undated_by_mtime: bool,
+    pub originals: Option<OriginalsMode>,
+    pub originals_compression: Option<OriginalsCompression>,
+    pub thumbnail: ThumbnailOpts,
+}
+
+impl ArchiveManifest {
+    /// Write the manifest of a new archive, encrypted when a secret is given.
+    ///
+    /// `legacy` archives were populated before manifests existed: they keep their historical
+    /// defaults and cannot be encrypted, their files would stay in clear.
+    pub fn create(archive_dir: &Path, settings: &ArchiveSettings, secret: Option<&ArchiveSecret>, legacy: bool) -> anyhow::Result<(Self, Option<Arc<ArchiveCipher>>)> {
+        let default_digest = if legacy { DigestAlgorithm::Crc32 } else { DigestAlgorithm::Xxh64 };
+        // New archives get browsable link directories, older and encrypted ones keep the CRC names
+        let default_link_dirs = if legacy || secret.is_some() { LinkDirNaming::Crc } else { LinkDirNaming::Readable };
+        let (encryption, cipher) = match secret {
+            Some(_) if legacy => anyhow::bail!("Existing archives cannot be encrypted"),
+            // Their names would disclose the source tree the encryption is meant to hide
+            Some(_) if settings.link_dirs.as_ref().is_some_and(|link_dirs| *link_dirs != LinkDirNaming::Crc) => {
+                anyhow::bail!("Encrypted archives need crc link directories")
+            }
+            Some(_) if settings.originals == Some(OriginalsMode::PerSource) => {
+                anyhow::bail!("Encrypted archives cannot store per-source originals, use content-addressed ones")
+            }
+            Some(secret) => {
+                let (encryption, cipher) = EncryptionManifest::create(secret)?;
+                (Some(encryption), Some(Arc::new(cipher)))
+            }
+            None => (None, None),
+        };
+        let manifest = ArchiveManifest {
+            version: MANIFEST_VERSION,
+            created_at: Some(Utc::now().timestamp()),
+            digest: settings.digest.unwrap_or(default_digest),
+            link: settings.link_strategy.unwrap_or_default(),
+            symlink_style: settings.symlink_style.unwrap_or_default(),
+            layout: settings.layout.clone().unwrap_or_default(),
+            index_sharding: settings.index_sharding.unwrap_or_default(),
+            link_dirs: settings.link_dirs.clone().unwrap_or(default_link_dirs),
+            undated_by_mtime: settings.undated_by_mtime,
+            min_dimension: DEFAULT_MIN_DIMENSION,
+            originals: settings.originals.unwrap_or_default(),
+            originals_compression: settings.originals_compression.unwrap_or_default(),
+            thumbnails: Some(ThumbnailSettings::from(&settings.thumbnail)),
+            encryption,
+        };
+        manifest.store(archive_dir)?;
+        Ok((manifest, cipher))
+    }
+
+    fn manifest_path(archive_dir: &Path) -> PathBuf {
+        archive_dir.join("manifest.toml")
+    }
+
+    /// Whether `archive_dir` has a manifest, without reading it
+    pub fn exists(archive_dir: &Path) -> bool {
+        Self::manifest_path(archive_dir).is_file()
+    }
+
+    pub fn load(archive_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let manifest_path = Self::manifest_path(archive_dir);
+        if manifest_path.is_file() {
+            let manifest: Self = toml::from_str(&std::fs::read_to_string(manifest_path)?)?;
+            if manifest.version > MANIFEST_VERSION {
+                anyhow::bail!("Archive manifest version {} is not supported, this release reads up to version {MANIFEST_VERSION}", manifest.version);
+            }
+            Ok(Some(manifest))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Load the manifest of the archive, archives created before manifests existed get the default one
+    pub fn load_or_default(archive_dir: &Path) -> anyhow::Result<Self> {
+        Ok(Self::load(archive_dir)?.unwrap_or_default())
+    }
+
+    pub fn linker(&self, archive_dir: &Path) -> anyhow::Result<ArchiveLinker> {
+        ArchiveLinker::new(archive_dir, self.link, self.symlink_style)
+    }
+
+    pub fn originals_store(&self, archive_dir: &Path, cipher: Option<Arc<ArchiveCipher>>) -> OriginalsStore {
+        OriginalsStore::new(archive_dir, self.originals, self.originals_compression, cipher)
+    }
+
+    /// Unlock the archive cipher, a secret is required by encrypted archives and rejected by plain ones
+    pub fn cipher(&self, secret: Option<&ArchiveSecret>) -> anyhow::Result<Option<Arc<ArchiveCipher>>> {
+        match (&self.encryption, secret) {
+            (Some(encryption), Some(secret)) => Ok(Some(Arc::new(encryption.unlock(secret)?))),
+            (Some(_), None) => anyhow::bail!("Archive is encrypted, a passphrase or key file is required"),
+            (None, Some(_)) => anyhow::bail!("Archive is not encrypted"),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn store(&self, archive_dir: &Path) -> anyhow::Result<()> {
+        std::fs::write(Self::manifest_path(archive_dir), toml::to_string(self)?)?;
+        Ok(())
+    }
+}
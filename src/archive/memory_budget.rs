@@ -0,0 +1,53 @@
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+/// Bytes per decoded pixel, an upper bound covering RGBA buffers.
+const BYTES_PER_PIXEL: u64 = 4;
+
+/// Limit the amount of memory used by concurrent image decodes.
+///
+/// Images whose estimated size exceeds the whole budget are not rejected, they wait until no
+/// other decode is running and are then processed alone.
+pub struct MemoryBudget {
+    capacity: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+pub struct MemoryPermit<'a> {
+    budget: &'a MemoryBudget,
+    amount: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self, amount: u64) -> MemoryPermit<'_> {
+        let mut used = self.used.lock().expect("Memory budget lock poisoned");
+        while *used > 0 && *used + amount > self.capacity {
+            used = self.released.wait(used).expect("Memory budget lock poisoned");
+        }
+        *used += amount;
+        MemoryPermit { budget: self, amount }
+    }
+}
+
+impl Drop for MemoryPermit<'_> {
+    fn drop(&mut self) {
+        let mut used = self.budget.used.lock().expect("Memory budget lock poisoned");
+        *used -= self.amount;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Estimate the memory needed to decode an image reading only its header.
+pub fn estimate_decoded_size(image_path: &Path) -> anyhow::Result<u64> {
+    let (width, height) = image::image_dimensions(image_path)?;
+    Ok(width as u64 * height as u64 * BYTES_PER_PIXEL)
+}
@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use std::path::{Component, Path, PathBuf};
+
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::is_index_file_name;
+use crate::archive::albums::ALBUMS_FILE;
+use crate::archive::sync_history::SYNC_HISTORY_FILE;
+use crate::archive::user_metadata::USER_METADATA_FILE;
+use crate::repository::sources::SourcesRepo;
+
+const ZSTD_LEVEL: i32 = 9;
+const MANIFEST_FILE: &str = "manifest.toml";
+const SOURCES_FILE: &str = "sources.ndjson";
+
+#[derive(Debug, Default)]
+pub struct MetadataBackupStats {
+    pub files: usize,
+    pub bytes: u64,
+    /// Index files of the archive missing from the restored snapshot, removed by the restore
+    pub removed: usize,
+}
+
+/// Metadata files of the archive, relative to its root: manifest, sources, user metadata, albums, sync history and index files
+fn metadata_files(target: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = [MANIFEST_FILE, SOURCES_FILE, USER_METADATA_FILE, ALBUMS_FILE, SYNC_HISTORY_FILE]
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|file| target.join(file).is_file())
+        .collect::<Vec<_>>();
+    for entry in fs::read_dir(target)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        // Yearly and monthly index files
+        for index_entry in fs::read_dir(entry.path())? {
+            let index_entry = index_entry?;
+            let is_index = index_entry.file_name().to_str().is_some_and(is_index_file_name);
+            if is_index && index_entry.file_type()?.is_file() {
+                files.push(PathBuf::from(entry.file_name()).join(index_entry.file_name()));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether a snapshot entry is one of the metadata files, anything else is rejected on restore
+fn is_metadata_file(path: &Path) -> bool {
+    let components = path.components().collect::<Vec<_>>();
+    match components.as_slice() {
+        [Component::Normal(name)] => [MANIFEST_FILE, SOURCES_FILE, USER_METADATA_FILE, ALBUMS_FILE, SYNC_HISTORY_FILE].iter().any(|file| name == file),
+        [Component::Normal(_), Component::Normal(name)] => name.to_str().is_some_and(is_index_file_name),
+        _ => false,
+    }
+}
+
+/// Write the manifest, the sources and the index files of the archive into a zstd compressed
+/// tarball, a cheap backup of what cannot be rebuilt from the thumbnails alone.
+///
+/// Encrypted archives are exported as they are, the snapshot is then encrypted too.
+pub fn export_metadata(target: &Path, snapshot: &Path) -> anyhow::Result<MetadataBackupStats> {
+    if ArchiveManifest::load(target)?.is_none() && !SourcesRepo::new(target.to_path_buf()).exists() {
+        anyhow::bail!("{target:?} is not a photo archive");
+    }
+
+    let mut stats = MetadataBackupStats::default();
+    let temp_path = snapshot.with_extension("tmp");
+    let encoder = zstd::Encoder::new(BufWriter::new(File::create(&temp_path)?), ZSTD_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+    for file in metadata_files(target)? {
+        builder.append_path_with_name(target.join(&file), &file)?;
+        stats.files += 1;
+        stats.bytes += fs::metadata(target.join(&file))?.len();
+    }
+    builder.into_inner()?.finish()?.into_inner()?.sync_all()?;
+    fs::rename(&temp_path, snapshot)?;
+    Ok(stats)
+}
+
+/// Restore the metadata files of a snapshot created by [`export_metadata`] into the archive.
+///
+/// Existing metadata is only replaced when `overwrite` is set, metadata files missing from the
+/// snapshot are then removed so that the archive ends up with exactly the snapshot metadata.
+pub fn restore_metadata(target: &Path, snapshot: &Path, overwrite: bool) -> anyhow::Result<MetadataBackupStats> {
+    if !overwrite && !metadata_files(target)?.is_empty() {
+        anyhow::bail!("Archive already has metadata, it must be explicitly overwritten");
+    }
+
+    // Extracted into memory first, a corrupt snapshot must not leave the archive half restored
+    let mut entries = Vec::new();
+    let mut archive = tar::Archive::new(zstd::Decoder::new(File::open(snapshot)?)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !entry.header().entry_type().is_file() || !is_metadata_file(&path) {
+            anyhow::bail!("Unexpected entry {path:?} in metadata snapshot");
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        entries.push((path, content));
+    }
+    if !entries.iter().any(|(path, _)| path == Path::new(MANIFEST_FILE) || path == Path::new(SOURCES_FILE)) {
+        anyhow::bail!("Snapshot holds no archive metadata");
+    }
+
+    let mut stats = MetadataBackupStats::default();
+    let restored = entries.iter().map(|(path, _)| path.clone()).collect::<HashSet<_>>();
+    for (path, content) in entries {
+        let file_path = target.join(&path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = file_path.with_extension("tmp");
+        fs::write(&temp_path, &content)?;
+        fs::rename(&temp_path, &file_path)?;
+        stats.files += 1;
+        stats.bytes += content.len() as u64;
+    }
+    for stale in metadata_files(target)?.into_iter().filter(|file| !restored.contains(file)) {
+        let stale_path = target.join(stale);
+        fs::remove_file(&stale_path)?;
+        stats.removed += 1;
+        // Year directories holding nothing but the index are dropped with it
+        if let Some(dir) = stale_path.parent().filter(|dir| *dir != target) {
+            if fs::read_dir(dir)?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+    }
+    Ok(stats)
+}
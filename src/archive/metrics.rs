@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::sync_history::{load_sync_history, SyncRun};
+use crate::repository::sources::{SourceJsonRow, SourcesRepo};
+
+/// Scrapes not sent or read within this delay are dropped, a stuck client would block the others
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause after a failed accept, the usual causes (out of file descriptors) last a moment
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Metrics of the archive in the Prometheus text format, read from its files at each call.
+/// The runs of encrypted archives are only exported given their `cipher`.
+pub fn render_metrics(target: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<String> {
+    let sources = SourcesRepo::new(target.to_path_buf()).all()?;
+    let mut out = String::new();
+    metric_header(&mut out, "photo_archive_sources", "gauge", "Sources registered in the archive");
+    let _ = writeln!(out, "photo_archive_sources {}", sources.len());
+
+    type SourceGauge = (&'static str, &'static str, fn(&SourceJsonRow) -> Option<f64>);
+    let gauges: [SourceGauge; 5] = [
+        ("photo_archive_source_photos", "Photos of the source in the index", |source| source.stats.as_ref().map(|stats| stats.photos as f64)),
+        ("photo_archive_source_undated_photos", "Photos of the source without a capture date", |source| source.stats.as_ref().map(|stats| stats.undated as f64)),
+        ("photo_archive_source_original_bytes", "Size of the photo files on the source", |source| source.stats.as_ref().map(|stats| stats.original_bytes as f64)),
+        ("photo_archive_source_thumbnail_bytes", "Size of the thumbnails of the source stored in the archive", |source| source.stats.as_ref().map(|stats| stats.thumbnail_bytes as f64)),
+        ("photo_archive_source_last_sync_timestamp_seconds", "End of the last sync of the source", |source| source.last_sync.map(|ts| ts as f64)),
+    ];
+    for (name, help, value) in gauges {
+        metric_header(&mut out, name, "gauge", help);
+        for source in sources.iter() {
+            if let Some(value) = value(source) {
+                let _ = writeln!(
+                    out,
+                    "{name}{{source=\"{}\",name=\"{}\",group=\"{}\"}} {value}",
+                    escape_label(&source.id),
+                    escape_label(&source.name),
+                    escape_label(&source.group),
+                );
+            }
+        }
+    }
+
+    let encrypted = ArchiveManifest::load_or_default(target)?.encryption.is_some();
+    if !encrypted || cipher.is_some() {
+        render_last_runs(&mut out, &sources, &load_sync_history(target, cipher)?);
+    }
+    Ok(out)
+}
+
+/// Outcome of the last sync of each source, from the sync history
+fn render_last_runs(out: &mut String, sources: &[SourceJsonRow], runs: &[SyncRun]) {
+    let mut last_runs = HashMap::new();
+    for run in runs {
+        last_runs.insert(run.source_id.as_str(), run);
+    }
+    let last_runs = sources.iter()
+        .filter_map(|source| last_runs.get(source.id.as_str()).map(|run| (source, *run)))
+        .collect::<Vec<_>>();
+    let labels = |source: &SourceJsonRow| format!(
+        "source=\"{}\",name=\"{}\",group=\"{}\"",
+        escape_label(&source.id),
+        escape_label(&source.name),
+        escape_label(&source.group),
+    );
+
+    metric_header(out, "photo_archive_source_last_run_files", "gauge", "Files of the source handled by its last sync, by outcome");
+    for (source, run) in &last_runs {
+        let outcomes = [("stored", run.stored), ("skipped", run.skipped), ("moved", run.moved), ("ignored", run.ignored), ("errored", run.errored), ("pruned", run.pruned)];
+        for (outcome, count) in outcomes {
+            let _ = writeln!(out, "photo_archive_source_last_run_files{{{},outcome=\"{outcome}\"}} {count}", labels(source));
+        }
+    }
+    metric_header(out, "photo_archive_source_last_run_errors", "gauge", "Files and directories the last sync of the source failed to read or archive");
+    for (source, run) in &last_runs {
+        let _ = writeln!(out, "photo_archive_source_last_run_errors{{{}}} {}", labels(source), run.errored + run.scan_errors);
+    }
+    metric_header(out, "photo_archive_source_last_run_duration_seconds", "gauge", "Duration of the last sync of the source");
+    for (source, run) in &last_runs {
+        let _ = writeln!(out, "photo_archive_source_last_run_duration_seconds{{{}}} {}", labels(source), (run.ended_at - run.started_at).max(0));
+    }
+    metric_header(out, "photo_archive_source_last_run_stored_files_per_second", "gauge", "Files stored per second by the last sync of the source");
+    for (source, run) in &last_runs {
+        // Runs are timed to the second, shorter ones count as one
+        let duration = (run.ended_at - run.started_at).max(1);
+        let _ = writeln!(out, "photo_archive_source_last_run_stored_files_per_second{{{}}} {}", labels(source), run.stored as f64 / duration as f64);
+    }
+    metric_header(out, "photo_archive_source_last_run_scan_errors", "gauge", "Directories the last sync of the source failed to read");
+    for (source, run) in &last_runs {
+        let _ = writeln!(out, "photo_archive_source_last_run_scan_errors{{{}}} {}", labels(source), run.scan_errors);
+    }
+    metric_header(out, "photo_archive_source_last_run_workers", "gauge", "Workers archiving the files of the last sync of the source");
+    for (source, run) in &last_runs {
+        let _ = writeln!(out, "photo_archive_source_last_run_workers{{{}}} {}", labels(source), run.options.max_workers);
+    }
+}
+
+/// Answer the scrapes of `/metrics` until the listener fails, other paths get a 404.
+///
+/// Syncs run in their own processes: what they write to the archive shows at the next scrape.
+pub fn serve_metrics(target: &Path, listener: TcpListener, cipher: Option<&ArchiveCipher>) -> anyhow::Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Error accepting metrics request - {err}");
+                thread::sleep(ACCEPT_RETRY_DELAY);
+                continue;
+            }
+        };
+        if let Err(err) = answer_scrape(target, stream, cipher) {
+            eprintln!("Error answering metrics request - {err}");
+        }
+    }
+    Ok(())
+}
+
+fn answer_scrape(target: &Path, mut stream: TcpStream, cipher: Option<&ArchiveCipher>) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = match path.split('?').next() {
+        Some("/metrics") => match render_metrics(target, cipher) {
+            Ok(body) => ("200 OK", body),
+            Err(err) => ("500 Internal Server Error", format!("{err}\n")),
+        },
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    )?;
+    Ok(())
+}
+
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::fs::{File, Metadata};
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use crate::archive::digest::{digest_file, DigestAlgorithm};
+use crate::archive::manifest::ArchiveManifest;
+use crate::repository::sources::SourcesRepo;
+
+#[derive(Clone, Debug, Default)]
+pub struct MirrorOpts {
+    /// Hash the files already on the mirror too, copying again the ones whose content differs
+    pub verify_existing: bool,
+    /// Remove the files of the mirror that are no longer in the archive
+    pub delete: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MirrorStats {
+    pub copied: usize,
+    pub repaired: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+    pub errors: usize,
+    pub bytes: u64,
+}
+
+/// Replicate the whole archive (thumbnails, links, originals, index, sources and manifest) into `mirror`.
+///
+/// Files are copied only when missing or when their size or modification time changed, every
+/// copy is hashed against the archive file before replacing the previous one. Symlinks are
+/// replicated as they are and hard links are preserved, encrypted archives are copied sealed.
+pub fn mirror_archive(archive: &Path, mirror: &Path, opts: &MirrorOpts) -> anyhow::Result<MirrorStats> {
+    if ArchiveManifest::load(archive)?.is_none() && !SourcesRepo::new(archive.to_path_buf()).exists() {
+        anyhow::bail!("{archive:?} is not a photo archive");
+    }
+    let created = !mirror.exists();
+    fs::create_dir_all(mirror)?;
+    let archive = fs::canonicalize(archive)?;
+    let mirror = fs::canonicalize(mirror)?;
+    if mirror.starts_with(&archive) || archive.starts_with(&mirror) {
+        if created {
+            fs::remove_dir(&mirror)?;
+        }
+        anyhow::bail!("Mirror and archive directories cannot be nested");
+    }
+
+    let mut mirroring = Mirroring {
+        opts,
+        stats: MirrorStats::default(),
+        hard_links: HashMap::new(),
+    };
+    mirroring.mirror_dir(&archive, &mirror)?;
+    Ok(mirroring.stats)
+}
+
+struct Mirroring<'a> {
+    opts: &'a MirrorOpts,
+    stats: MirrorStats,
+    /// Mirror path of the archive files having several hard links, keyed by device and inode
+    hard_links: HashMap<(u64, u64), PathBuf>,
+}
+
+impl Mirroring<'_> {
+    fn mirror_dir(&mut self, src_dir: &Path, dst_dir: &Path) -> anyhow::Result<()> {
+        if !dst_dir.is_dir() {
+            fs::create_dir_all(dst_dir)?;
+        }
+
+        let mut mirrored = HashSet::new();
+        for entry in fs::read_dir(src_dir)? {
+            let entry = entry?;
+            let src_path = entry.path();
+            let dst_path = dst_dir.join(entry.file_name());
+            mirrored.insert(entry.file_name());
+
+            let out = entry.metadata().map_err(anyhow::Error::from).and_then(|metadata| {
+                if metadata.is_symlink() {
+                    self.mirror_symlink(&src_path, &dst_path)
+                } else if metadata.is_dir() {
+                    self.mirror_dir(&src_path, &dst_path)
+                } else {
+                    self.mirror_file(&src_path, &dst_path, &metadata)
+                }
+            });
+            if let Err(err) = out {
+                eprintln!("Error mirroring {} - {err}", src_path.display());
+                self.stats.errors += 1;
+            }
+        }
+
+        if self.opts.delete {
+            self.delete_stale(dst_dir, &mirrored)?;
+        }
+        Ok(())
+    }
+
+    fn mirror_symlink(&mut self, src_path: &Path, dst_path: &Path) -> anyhow::Result<()> {
+        let target = fs::read_link(src_path)?;
+        if fs::read_link(dst_path).is_ok_and(|existing| existing == target) {
+            self.stats.unchanged += 1;
+            return Ok(());
+        }
+        remove_entry(dst_path)?;
+        symlink(&target, dst_path)?;
+        self.stats.copied += 1;
+        Ok(())
+    }
+
+    fn mirror_file(&mut self, src_path: &Path, dst_path: &Path, metadata: &Metadata) -> anyhow::Result<()> {
+        if metadata.nlink() > 1 {
+            let inode = (metadata.dev(), metadata.ino());
+            if let Some(first_copy) = self.hard_links.get(&inode) {
+                let same_inode = fs::symlink_metadata(first_copy)
+                    .and_then(|first| fs::symlink_metadata(dst_path).map(|dst| (first.dev(), first.ino()) == (dst.dev(), dst.ino())))
+                    .unwrap_or(false);
+                if same_inode {
+                    self.stats.unchanged += 1;
+                } else {
+                    remove_entry(dst_path)?;
+                    fs::hard_link(first_copy, dst_path)?;
+                    self.stats.copied += 1;
+                }
+                return Ok(());
+            }
+            self.hard_links.insert(inode, dst_path.to_path_buf());
+        }
+
+        let existing = fs::symlink_metadata(dst_path).ok().filter(Metadata::is_file);
+        let up_to_date = existing.as_ref().is_some_and(|existing| {
+            existing.len() == metadata.len() && existing.modified().ok() == metadata.modified().ok()
+        });
+        if up_to_date && !self.opts.verify_existing {
+            self.stats.unchanged += 1;
+            return Ok(());
+        }
+
+        let src_digest = digest_file(src_path, DigestAlgorithm::Blake3)?;
+        if up_to_date {
+            if digest_file(dst_path, DigestAlgorithm::Blake3)? == src_digest {
+                self.stats.unchanged += 1;
+                return Ok(());
+            }
+            eprintln!("Mirror copy of {} is corrupted, copying it again", src_path.display());
+        }
+
+        // Copied besides the destination, so that an interrupted mirror never leaves a truncated file
+        let mut temp_name = OsString::from(".");
+        temp_name.push(dst_path.file_name().unwrap_or_default());
+        temp_name.push(".mirror-tmp");
+        let temp_path = dst_path.with_file_name(temp_name);
+        fs::copy(src_path, &temp_path)?;
+        File::options().write(true).open(&temp_path)?.set_modified(metadata.modified()?)?;
+        if digest_file(&temp_path, DigestAlgorithm::Blake3)? != src_digest {
+            fs::remove_file(&temp_path)?;
+            anyhow::bail!("Copied file digest does not match");
+        }
+        if existing.is_none() {
+            remove_entry(dst_path)?;
+        }
+        fs::rename(&temp_path, dst_path)?;
+
+        if up_to_date {
+            self.stats.repaired += 1;
+        } else {
+            self.stats.copied += 1;
+        }
+        self.stats.bytes += metadata.len();
+        Ok(())
+    }
+
+    fn delete_stale(&mut self, dst_dir: &Path, mirrored: &HashSet<OsString>) -> anyhow::Result<()> {
+        for entry in fs::read_dir(dst_dir)? {
+            let entry = entry?;
+            if mirrored.contains(&entry.file_name()) {
+                continue;
+            }
+            match remove_entry(&entry.path()) {
+                Ok(()) => self.stats.deleted += 1,
+                Err(err) => {
+                    eprintln!("Error removing {} - {err}", entry.path().display());
+                    self.stats.errors += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove whatever is at `path`, if anything
+fn remove_entry(path: &Path) -> anyhow::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
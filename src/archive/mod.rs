@@ -1,4 +1,47 @@
 pub mod sync;
+pub mod sync_history;
+pub mod sync_report;
+pub mod codec;
 pub mod records_store;
+pub mod exif_fields;
+pub mod embedded_metadata;
+pub mod motion_photo;
+pub mod user_metadata;
+pub mod albums;
 pub mod remove;
-pub mod common;
\ No newline at end of file
+pub mod common;
+pub mod thumbnail;
+pub mod memory_budget;
+pub mod digest;
+pub mod manifest;
+pub mod thumbnail_registry;
+pub mod thumbnail_dedupe;
+pub mod link;
+pub mod relink;
+pub mod originals;
+pub mod encryption;
+pub mod source_records;
+pub mod known_files;
+pub mod dir_times;
+pub mod source_snapshot;
+pub mod layout;
+pub mod template;
+pub mod progress;
+pub mod priority;
+pub mod retry;
+pub mod mirror;
+pub mod replication;
+pub mod metadata_backup;
+pub mod verify;
+pub mod doctor;
+pub mod info;
+pub mod stats;
+pub mod timeline;
+pub mod catalog;
+pub mod duplicates;
+pub mod sources_status;
+pub mod metrics;
+pub mod notifications;
+pub mod remote;
+pub mod export;
+pub mod photo_archive;
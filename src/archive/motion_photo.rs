@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+use serde::{Deserialize, Serialize};
+
+const MP4_FILE_TYPE: &[u8; 4] = b"ftyp";
+/// End of the trailer Samsung phones append to their files, preceded by the size of its directory
+const SEF_TRAILER: &[u8; 4] = b"SEFT";
+const SEF_HEADER: &[u8; 4] = b"SEFH";
+const SEF_MOTION_PHOTO: &[u8] = b"MotionPhoto_Data";
+/// Larger directories are not Samsung trailers
+const MAX_SEF_DIRECTORY: u64 = 64 * 1024;
+
+/// Video appended after the still image by motion photos (Google Motion Photo and MicroVideo,
+/// Samsung Motion Photo). Image decoders only read the still, the file size includes the video.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MotionClip {
+    /// Position of the MP4 video in the file
+    #[serde(rename = "off")]
+    pub offset: u64,
+    #[serde(rename = "len")]
+    pub length: u64,
+    /// Size of the JPEG still, without the video and the other data appended to it
+    #[serde(rename = "stl")]
+    pub still_length: u64,
+}
+
+/// Layout of a motion photo declared by its XMP
+#[derive(Clone, Copy, Debug)]
+pub struct DeclaredMotionClip {
+    /// Length of the video, at the end of the file
+    pub length: u64,
+    /// Length of everything appended to the still (video, gain map, depth map, ...)
+    pub appended_length: u64,
+}
+
+/// Video of a JPEG motion photo, found at the end of the file when its XMP declares it,
+/// else in the Samsung trailer. Only the MP4 header of the video is read.
+pub fn locate_motion_clip(file: &mut File, declared: Option<DeclaredMotionClip>) -> anyhow::Result<Option<MotionClip>> {
+    let file_length = file.metadata()?.len();
+    let mut soi = [0u8; 2];
+    file.seek(SeekFrom::Start(0))?;
+    if file.read_exact(&mut soi).is_err() || soi != [0xFF, 0xD8] {
+        return Ok(None);
+    }
+
+    if let Some(declared) = declared.filter(|declared| declared.length > 0 && declared.length < file_length) {
+        let clip = MotionClip {
+            offset: file_length - declared.length,
+            length: declared.length,
+            still_length: file_length.saturating_sub(declared.appended_length.max(declared.length)),
+        };
+        if is_mp4(file, clip.offset)? {
+            return Ok(Some(clip));
+        }
+    }
+    // Samsung files end with their trailer, after the video the XMP length points to
+    samsung_motion_clip(file, file_length)
+}
+
+/// Video of the motion photo within its content, checked to still be an MP4
+pub fn extract_motion_clip<'a>(content: &'a [u8], clip: &MotionClip) -> anyhow::Result<&'a [u8]> {
+    let end = clip.offset.checked_add(clip.length).ok_or_else(|| anyhow::anyhow!("Invalid motion clip position"))?;
+    let video = usize::try_from(clip.offset).ok()
+        .zip(usize::try_from(end).ok())
+        .and_then(|(start, end)| content.get(start..end))
+        .filter(|video| video.get(4..8) == Some(MP4_FILE_TYPE))
+        .ok_or_else(|| anyhow::anyhow!("No motion clip at {}, the file changed since it was archived", clip.offset))?;
+    Ok(video)
+}
+
+fn is_mp4(file: &mut File, offset: u64) -> anyhow::Result<bool> {
+    let mut header = [0u8; 8];
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file.read_exact(&mut header).is_ok() && &header[4..] == MP4_FILE_TYPE)
+}
+
+/// Video listed in the directory of the Samsung trailer, whose entries are located backwards from
+/// the start of the directory. Each entry holds its type, the length of its name, its name and its data.
+fn samsung_motion_clip(file: &mut File, file_length: u64) -> anyhow::Result<Option<MotionClip>> {
+    let mut trailer = [0u8; 8];
+    if file_length < 8 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-8))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[4..] != SEF_TRAILER {
+        return Ok(None);
+    }
+    let directory_length = u64::from(u32::from_le_bytes(trailer[..4].try_into().expect("Slice of 4 bytes")));
+    let Some(directory_offset) = file_length.checked_sub(8 + directory_length).filter(|_| directory_length <= MAX_SEF_DIRECTORY) else {
+        return Ok(None);
+    };
+    let mut directory = vec![0u8; directory_length as usize];
+    file.seek(SeekFrom::Start(directory_offset))?;
+    file.read_exact(&mut directory)?;
+    if directory.len() < 12 || !directory.starts_with(SEF_HEADER) {
+        return Ok(None);
+    }
+
+    let le_u64 = |bytes: &[u8]| u64::from(u32::from_le_bytes(bytes.try_into().expect("Slice of 4 bytes")));
+    let count = le_u64(&directory[8..12]) as usize;
+    let entries = directory[12..].chunks_exact(12).take(count);
+    // The still ends where the data of the first entry starts
+    let still_length = entries.clone()
+        .filter_map(|entry| directory_offset.checked_sub(le_u64(&entry[4..8])))
+        .min()
+        .unwrap_or(directory_offset);
+    for entry in entries {
+        let (distance, size) = (le_u64(&entry[4..8]), le_u64(&entry[8..12]));
+        let Some(entry_offset) = directory_offset.checked_sub(distance) else {
+            continue;
+        };
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(entry_offset))?;
+        if file.read_exact(&mut header).is_err() || le_u64(&header[4..]) != SEF_MOTION_PHOTO.len() as u64 {
+            continue;
+        }
+        let mut name = [0u8; SEF_MOTION_PHOTO.len()];
+        if file.read_exact(&mut name).is_err() || name != SEF_MOTION_PHOTO {
+            continue;
+        }
+        let offset = entry_offset + 8 + name.len() as u64;
+        let Some(length) = size.checked_sub(8 + name.len() as u64).filter(|length| *length > 0) else {
+            continue;
+        };
+        if is_mp4(file, offset)? {
+            return Ok(Some(MotionClip { offset, length, still_length }));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use crate::archive::remote::api::api_error;
+use crate::archive::sync_history::SyncRun;
+
+/// Time given to a hook to answer, a slow one must not hold the next sync
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Service told about each completed sync
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SyncHook {
+    /// POST of the record of the run as JSON, as listed by `history show`
+    Webhook { url: String },
+    /// Message of a Gotify server, `url` being the one of the server
+    Gotify {
+        url: String,
+        /// Token of the Gotify application the messages are sent as
+        token: String,
+        #[serde(default)]
+        priority: Option<u8>,
+    },
+    /// Message published on a ntfy topic, `url` including the topic e.g. `https://ntfy.sh/photos`
+    Ntfy {
+        url: String,
+        /// Access token of protected topics
+        #[serde(default)]
+        token: Option<String>,
+        #[serde(default)]
+        priority: Option<u8>,
+    },
+}
+
+impl SyncHook {
+    pub fn url(&self) -> &str {
+        match self {
+            SyncHook::Webhook { url } | SyncHook::Gotify { url, .. } | SyncHook::Ntfy { url, .. } => url,
+        }
+    }
+}
+
+/// Title and body of the messages about the run
+pub fn sync_run_summary(run: &SyncRun) -> (String, String) {
+    let failed = run.errored + run.scan_errors;
+    let title = match failed {
+        0 => format!("Sync of {} completed", run.source_name),
+        failed => format!("Sync of {} completed with {failed} errors", run.source_name),
+    };
+    let mut message = format!(
+        "stored {}, skipped {}, moved {}, ignored {}, errored {} in {}s",
+        run.stored,
+        run.skipped,
+        run.moved,
+        run.ignored,
+        run.errored,
+        run.ended_at - run.started_at,
+    );
+    if run.scan_errors > 0 {
+        message.push_str(&format!(", {} unreadable directories", run.scan_errors));
+    }
+    if let Some(failure) = run.failures.first() {
+        message.push_str(&format!("\nfirst failure: {} - {}", failure.path.display(), failure.cause));
+    }
+    (title, message)
+}
+
+/// Tell the service about the run
+pub fn send_sync_hook(hook: &SyncHook, run: &SyncRun) -> anyhow::Result<()> {
+    let agent = ureq::AgentBuilder::new().timeout(HOOK_TIMEOUT).build();
+    let (title, message) = sync_run_summary(run);
+    let res = match hook {
+        SyncHook::Webhook { url } => agent.post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&serde_json::to_string(run)?),
+        SyncHook::Gotify { url, token, priority } => agent.post(&format!("{}/message", url.trim_end_matches('/')))
+            .set("X-Gotify-Key", token)
+            .set("Content-Type", "application/json")
+            .send_string(&json!({ "title": title, "message": message, "priority": priority.unwrap_or(5) }).to_string()),
+        SyncHook::Ntfy { url, token, priority } => {
+            // Published as JSON to the server, headers could not hold non-ASCII titles
+            let mut server = Url::parse(url)?;
+            let topic = server.path_segments().and_then(|mut segments| segments.next_back()).map(String::from).unwrap_or_default();
+            if topic.is_empty() {
+                anyhow::bail!("Missing topic in ntfy url {url}");
+            }
+            if let Ok(mut segments) = server.path_segments_mut() {
+                segments.pop();
+            }
+            let tags = if run.errored + run.scan_errors > 0 { "warning" } else { "white_check_mark" };
+            let mut request = agent.request_url("POST", &server).set("Content-Type", "application/json");
+            if let Some(token) = token {
+                request = request.set("Authorization", &format!("Bearer {token}"));
+            }
+            request.send_string(&json!({ "topic": topic, "title": title, "message": message, "tags": [tags], "priority": priority.unwrap_or(3) }).to_string())
+        }
+    };
+    res.map_err(|err| api_error(&format!("Error notifying {}", hook.url()), err))?;
+    Ok(())
+}
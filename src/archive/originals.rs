@@ -0,0 +1,194 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::digest::{digest_file, DigestAlgorithm};
+
+const ORIGINALS_DIR: &str = "originals";
+const OBJECTS_DIR: &str = "objects";
+
+static TEMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a path relative to the archive root lies in one of the originals directories
+pub fn is_original_path(relative_path: &Path) -> bool {
+    relative_path.components().count() > 1
+        && (relative_path.starts_with(ORIGINALS_DIR) || relative_path.starts_with(OBJECTS_DIR))
+}
+
+/// Whether, besides the thumbnail, the original file is stored in the archive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OriginalsMode {
+    /// Only thumbnails and index rows are stored
+    #[default]
+    None,
+    /// Originals are copied under `originals/<source id>/<source path>`
+    PerSource,
+    /// Originals are stored once under `objects/<ab>/<cdef…>`, keyed by their BLAKE3 hash, so that
+    /// identical files coming from several sources share the same object
+    ContentAddressed,
+}
+
+impl Display for OriginalsMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OriginalsMode::None => write!(f, "none"),
+            OriginalsMode::PerSource => write!(f, "per-source"),
+            OriginalsMode::ContentAddressed => write!(f, "content-addressed"),
+        }
+    }
+}
+
+impl FromStr for OriginalsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(OriginalsMode::None),
+            "per-source" => Ok(OriginalsMode::PerSource),
+            "content-addressed" => Ok(OriginalsMode::ContentAddressed),
+            other => anyhow::bail!("Unknown originals mode '{other}', expected one of none, per-source, content-addressed"),
+        }
+    }
+}
+
+/// Object names of encrypted archives use a keyed hash, so that they do not disclose which files are stored
+fn object_path(source_file: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<PathBuf> {
+    let hash = match cipher {
+        Some(cipher) => {
+            let mut hasher = cipher.names_hasher();
+            hasher.update_reader(File::open(source_file)?)?;
+            hasher.finalize().to_hex().to_string()
+        }
+        None => digest_file(source_file, DigestAlgorithm::Blake3)?.to_string().to_lowercase(),
+    };
+    let (prefix, rest) = hash.split_at(2);
+    Ok(PathBuf::from(OBJECTS_DIR).join(prefix).join(rest))
+}
+
+/// Compression applied to newly stored originals, each file keeps its own `.zst` suffix so
+/// that compressed and plain originals can coexist in the same archive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OriginalsCompression {
+    #[default]
+    None,
+    Zstd,
+}
+
+const ZSTD_EXTENSION: &str = "zst";
+const ZSTD_LEVEL: i32 = 9;
+
+impl Display for OriginalsCompression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OriginalsCompression::None => write!(f, "none"),
+            OriginalsCompression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl FromStr for OriginalsCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(OriginalsCompression::None),
+            "zstd" => Ok(OriginalsCompression::Zstd),
+            other => anyhow::bail!("Unknown originals compression '{other}', expected one of none, zstd"),
+        }
+    }
+}
+
+/// Originals storage of an archive, configured from its manifest
+#[derive(Clone)]
+pub struct OriginalsStore {
+    mode: OriginalsMode,
+    compression: OriginalsCompression,
+    target_base_dir: PathBuf,
+    cipher: Option<Arc<ArchiveCipher>>,
+}
+
+impl OriginalsStore {
+    pub fn new(target_base_dir: &Path, mode: OriginalsMode, compression: OriginalsCompression, cipher: Option<Arc<ArchiveCipher>>) -> Self {
+        Self {
+            mode,
+            compression,
+            target_base_dir: target_base_dir.to_path_buf(),
+            cipher,
+        }
+    }
+
+    /// Copy the original file into the archive, returning its path relative to the archive root.
+    ///
+    /// The file is written to a temporary sibling and renamed once complete, so an interrupted copy
+    /// never leaves a truncated original behind. Content-addressed objects already present in the
+    /// archive are not copied again, per-source originals are only replaced when `replace` is set.
+    pub fn store(&self, source_id: &str, source_relative_path: &Path, source_file: &Path, replace: bool) -> anyhow::Result<Option<PathBuf>> {
+        let plain_path = match self.mode {
+            OriginalsMode::None => return Ok(None),
+            OriginalsMode::PerSource => PathBuf::from(ORIGINALS_DIR).join(source_id).join(source_relative_path),
+            OriginalsMode::ContentAddressed => object_path(source_file, self.cipher.as_deref())?,
+        };
+        let mut compressed_path = plain_path.clone();
+        compressed_path.as_mut_os_string().push(format!(".{ZSTD_EXTENSION}"));
+        // Objects stored before the compression was toggled hold the same content
+        if self.mode == OriginalsMode::ContentAddressed {
+            if let Some(existing) = [&plain_path, &compressed_path].into_iter().find(|path| self.target_base_dir.join(path).exists()) {
+                return Ok(Some(existing.clone()));
+            }
+        }
+        let relative_path = match self.compression {
+            OriginalsCompression::None => plain_path,
+            OriginalsCompression::Zstd => compressed_path,
+        };
+
+        let original_path = self.target_base_dir.join(&relative_path);
+        if replace || !original_path.exists() {
+            let parent = original_path.parent().expect("Error extracting original parent");
+            fs::create_dir_all(parent)?;
+
+            // Workers may copy the same object concurrently, each one needs its own temporary file
+            let mut temp_name = original_path.file_name().unwrap_or_default().to_os_string();
+            temp_name.push(format!(".{}.part", TEMP_SEQ.fetch_add(1, Ordering::Relaxed)));
+            let temp_path = original_path.with_file_name(temp_name);
+            let reader = BufReader::new(File::open(source_file)?);
+            let mut reader: Box<dyn Read> = match self.compression {
+                OriginalsCompression::None => Box::new(reader),
+                OriginalsCompression::Zstd => Box::new(zstd::stream::read::Encoder::new(reader, ZSTD_LEVEL)?),
+            };
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            match &self.cipher {
+                Some(cipher) => cipher.encrypt_stream(reader, &mut writer)?,
+                None => {
+                    io::copy(&mut reader, &mut writer)?;
+                }
+            }
+            writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+            fs::rename(&temp_path, &original_path)?;
+        }
+
+        Ok(Some(relative_path))
+    }
+
+    /// Open a stored original, transparently decrypting and decompressing it
+    pub fn open(&self, relative_path: &Path) -> anyhow::Result<Box<dyn Read>> {
+        let file = BufReader::new(File::open(self.target_base_dir.join(relative_path))?);
+        let file: Box<dyn Read> = match &self.cipher {
+            Some(cipher) => Box::new(cipher.decrypt_stream(file)?),
+            None => Box::new(file),
+        };
+        if relative_path.extension().is_some_and(|ext| ext == ZSTD_EXTENSION) {
+            Ok(Box::new(zstd::Decoder::new(file)?))
+        } else {
+            Ok(Box::new(file))
+        }
+    }
+}
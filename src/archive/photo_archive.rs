@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::archive::albums::Albums;
+use crate::archive::catalog::{Catalog, CatalogEntry, CatalogFilter};
+use crate::archive::digest::Digest;
+use crate::archive::encryption::{ArchiveCipher, ArchiveSecret};
+use crate::archive::manifest::{ArchiveManifest, ArchiveSettings};
+use crate::archive::records_store::{PageToken, PhotoArchiveJsonRow, PhotoArchiveRecordsStore, RowsPage};
+use crate::archive::remove::{remove_by_source, retain_images};
+use crate::archive::sync::{synchronize_source, SyncOpts, SyncrhonizationTask};
+use crate::archive::sync_history::{load_sync_history, SyncRun};
+use crate::archive::user_metadata::{PhotoUserMetadata, UserMetadata};
+use crate::repository::sources::SourcesRepo;
+
+/// Archive directory opened for reading and writing: its manifest, registered sources and index.
+///
+/// Entry point of library users, who do not have to know the on-disk layout of archives.
+pub struct PhotoArchive {
+    root: PathBuf,
+    manifest: ArchiveManifest,
+    secret: Option<ArchiveSecret>,
+    sources: SourcesRepo,
+    records: PhotoArchiveRecordsStore,
+}
+
+impl PhotoArchive {
+    /// Open an existing archive, `secret` is required by encrypted ones
+    pub fn open(root: &Path, secret: Option<ArchiveSecret>) -> anyhow::Result<Self> {
+        let sources = SourcesRepo::new(root.to_path_buf());
+        let manifest = match ArchiveManifest::load(root)? {
+            Some(manifest) => manifest,
+            // Archives populated before manifests existed
+            None if sources.exists() => ArchiveManifest::default(),
+            None => anyhow::bail!("{} is not a photo archive", root.display()),
+        };
+        let cipher = manifest.cipher(secret.as_ref())?;
+        Ok(Self::assemble(root, manifest, secret, cipher))
+    }
+
+    /// Create an empty archive in `root`, encrypted when a secret is given
+    pub fn create(root: &Path, settings: &ArchiveSettings, secret: Option<ArchiveSecret>) -> anyhow::Result<Self> {
+        let sources = SourcesRepo::new(root.to_path_buf());
+        if ArchiveManifest::load(root)?.is_some() || sources.exists() {
+            anyhow::bail!("{} already holds an archive", root.display());
+        }
+        std::fs::create_dir_all(root)?;
+        let (manifest, cipher) = ArchiveManifest::create(root, settings, secret.as_ref(), false)?;
+        Ok(Self::assemble(root, manifest, secret, cipher))
+    }
+
+    /// Root of the archive holding `dir`, found by walking up its ancestors until one has a
+    /// manifest or a sources file, as archives populated before manifests existed
+    pub fn find_root(dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let dir = std::path::absolute(dir)?;
+        Ok(dir.ancestors()
+            .find(|ancestor| ArchiveManifest::exists(ancestor) || SourcesRepo::new(ancestor.to_path_buf()).exists())
+            .map(Path::to_path_buf))
+    }
+
+    fn assemble(root: &Path, manifest: ArchiveManifest, secret: Option<ArchiveSecret>, cipher: Option<Arc<ArchiveCipher>>) -> Self {
+        let records = PhotoArchiveRecordsStore::with_cipher(root, cipher).with_sharding(manifest.index_sharding);
+        Self {
+            root: root.to_path_buf(),
+            manifest,
+            secret,
+            sources: SourcesRepo::new(root.to_path_buf()),
+            records,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn manifest(&self) -> &ArchiveManifest {
+        &self.manifest
+    }
+
+    pub fn sources(&self) -> &SourcesRepo {
+        &self.sources
+    }
+
+    /// Index rows, decrypted with the archive secret
+    pub fn records(&self) -> &PhotoArchiveRecordsStore {
+        &self.records
+    }
+
+    /// Synchronize a source into the archive, the secret of the archive is used when `opts` has none
+    pub fn sync(&self, mut opts: SyncOpts) -> anyhow::Result<SyncrhonizationTask> {
+        if opts.secret.is_none() {
+            opts.secret = self.secret.clone();
+        }
+        synchronize_source(opts, &self.root)
+    }
+
+    /// Photos of the archive, with the mounted state of their sources
+    pub fn catalog(&self) -> anyhow::Result<Catalog> {
+        Catalog::load(&self.root, self.secret.as_ref())
+    }
+
+    /// Photos matching `filter`, sorted by capture date
+    pub fn query(&self, filter: &CatalogFilter) -> anyhow::Result<Vec<CatalogEntry>> {
+        let catalog = match &filter.digest {
+            Some(digest) => Catalog::load_digest(&self.root, self.secret.as_ref(), digest)?,
+            None => self.catalog()?,
+        };
+        Ok(catalog.filter(filter).cloned().collect())
+    }
+
+    /// Page of index rows ordered by timestamp, see [`PhotoArchiveRecordsStore::page`]
+    pub fn page(&self, after: Option<&PageToken>, limit: usize) -> anyhow::Result<RowsPage> {
+        self.records.page(after, limit)
+    }
+
+    /// Drop every photo of the source from the archive, the source stays registered
+    pub fn remove_source(&self, source_id: &str) -> anyhow::Result<()> {
+        remove_by_source(self.root.clone(), source_id, self.secret.as_ref())
+    }
+
+    /// Set or clear the caption of a photo, shared by all its copies and kept across syncs
+    pub fn set_caption(&self, digest: &Digest, caption: Option<String>) -> anyhow::Result<()> {
+        self.update_user_metadata(digest, |metadata| metadata.caption = caption)
+    }
+
+    /// Change what the user set on a photo, see [`UserMetadata::update`]
+    pub fn update_user_metadata(&self, digest: &Digest, f: impl FnOnce(&mut PhotoUserMetadata)) -> anyhow::Result<()> {
+        let cipher = self.manifest.cipher(self.secret.as_ref())?;
+        let mut user_metadata = UserMetadata::load(&self.root, cipher.as_deref())?;
+        user_metadata.update(digest, f);
+        user_metadata.store(&self.root, cipher.as_deref())
+    }
+
+    pub fn albums(&self) -> anyhow::Result<Albums> {
+        let cipher = self.manifest.cipher(self.secret.as_ref())?;
+        Albums::load(&self.root, cipher.as_deref())
+    }
+
+    /// Change the albums of the archive, they are saved when `f` succeeds
+    pub fn update_albums<T>(&self, f: impl FnOnce(&mut Albums) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let cipher = self.manifest.cipher(self.secret.as_ref())?;
+        let mut albums = Albums::load(&self.root, cipher.as_deref())?;
+        let res = f(&mut albums)?;
+        albums.store(&self.root, cipher.as_deref())?;
+        Ok(res)
+    }
+
+    /// Recorded sync runs, oldest first
+    pub fn sync_history(&self) -> anyhow::Result<Vec<SyncRun>> {
+        let cipher = self.manifest.cipher(self.secret.as_ref())?;
+        load_sync_history(&self.root, cipher.as_deref())
+    }
+
+    /// Drop the index rows not matching `condition`, with the files only they reference
+    pub fn retain(&self, condition: impl FnMut(&PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
+        retain_images(self.root.clone(), self.secret.as_ref(), condition)
+    }
+}
@@ -0,0 +1,36 @@
+/// Niceness given to background threads, the lowest CPU priority
+const BACKGROUND_NICENESS: libc::c_int = 19;
+
+/// Let the calling thread run only when the machine has nothing better to do: lowest CPU
+/// priority and, on Linux, idle I/O scheduling class (as `nice -n 19 ionice -c 3`).
+///
+/// The threads it spawns afterwards inherit both settings. The idle class is only honored by
+/// the I/O schedulers supporting priorities (BFQ, CFQ), others ignore it.
+#[cfg(target_os = "linux")]
+pub fn lower_thread_priority() -> anyhow::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+    // Linux applies niceness and I/O priority to the thread whose id is given
+    let tid = unsafe { libc::gettid() };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, BACKGROUND_NICENESS) } != 0 {
+        return Err(anyhow::anyhow!("Error lowering CPU priority - {}", std::io::Error::last_os_error()));
+    }
+    let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, tid as libc::c_long, ioprio) } != 0 {
+        return Err(anyhow::anyhow!("Error setting idle I/O priority - {}", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Let the process run only when the machine has nothing better to do: lowest CPU priority.
+///
+/// Other systems do not set the priority of single threads, the whole process is affected.
+#[cfg(not(target_os = "linux"))]
+pub fn lower_thread_priority() -> anyhow::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, BACKGROUND_NICENESS) } != 0 {
+        return Err(anyhow::anyhow!("Error lowering CPU priority - {}", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
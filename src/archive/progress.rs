@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Processing progress of a sync, with the throughput measured over the last few seconds so
+/// that the estimate follows the pace of the current files (thumbnails to generate vs skipped)
+pub struct ProgressTracker {
+    processed: u64,
+    scanned: u64,
+    scan_completed: bool,
+    samples: VecDeque<(Instant, u64)>,
+    last_report: Option<Instant>,
+}
+
+/// Snapshot of the progress, see [`ProgressTracker::report`]
+pub struct ProgressReport {
+    pub processed: u64,
+    pub total: u64,
+    pub scan_completed: bool,
+    pub throughput: f64,
+    pub eta: Option<Duration>,
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self {
+            processed: 0,
+            scanned: 0,
+            scan_completed: false,
+            samples: VecDeque::from([(Instant::now(), 0)]),
+            last_report: None,
+        }
+    }
+}
+
+impl ProgressTracker {
+    pub fn processed(&mut self) {
+        self.processed += 1;
+    }
+
+    pub fn scanned(&mut self, count: u64, completed: bool) {
+        self.scanned = count;
+        self.scan_completed = completed;
+    }
+
+    /// Progress snapshot, at most one per second
+    pub fn report(&mut self) -> Option<ProgressReport> {
+        let now = Instant::now();
+        if self.last_report.is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL) {
+            return None;
+        }
+        self.last_report = Some(now);
+
+        self.samples.push_back((now, self.processed));
+        while self.samples.len() > 2 && self.samples.front().is_some_and(|(ts, _)| now.duration_since(*ts) > THROUGHPUT_WINDOW) {
+            self.samples.pop_front();
+        }
+        let (oldest_ts, oldest_processed) = self.samples.front().copied().unwrap_or((now, self.processed));
+        let elapsed = now.duration_since(oldest_ts).as_secs_f64();
+        let throughput = if elapsed > 0.0 {
+            (self.processed - oldest_processed) as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        // The total is only known once the scan is over, earlier estimates would be too optimistic
+        let total = self.scanned.max(self.processed);
+        let eta = (self.scan_completed && throughput > 0.0)
+            .then(|| Duration::from_secs_f64((total - self.processed) as f64 / throughput));
+
+        Some(ProgressReport {
+            processed: self.processed,
+            total,
+            scan_completed: self.scan_completed,
+            throughput,
+            eta,
+        })
+    }
+}
@@ -1,128 +1,761 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::num::NonZeroUsize;
 use std::ops::Add;
+use std::fmt::{Display, Formatter};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, SystemTime};
 
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+
 use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
 use exif::Exif;
+use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
 
+use crate::archive::common::build_filename;
+use crate::archive::digest::Digest;
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::exif_fields::ExifFields;
+use crate::archive::layout::ArchiveLayout;
+use crate::archive::motion_photo::MotionClip;
+
+/// Index lines parsed at once, then handed over in order
+const PARSE_BATCH_LINES: usize = 16 * 1024;
+/// Smaller batches are not worth spreading over threads
+const PARSE_PARALLEL_MIN_LINES: usize = 1024;
+
+/// Sidecar of the index files telling which of them hold the rows of each digest and of each source
+const LOOKUP_FILE: &str = "lookup.ndjson";
+
 pub struct PhotoArchiveRow {
     pub photo_ts: Option<NaiveDateTime>,
+    /// `photo_ts` was estimated from the file modification time
+    pub date_estimated: bool,
     pub file_ts: SystemTime,
     pub source_id: String,
     pub source_path: PathBuf,
     pub exif: Option<Exif>,
+    /// Description embedded in the photo (XMP, IPTC or EXIF)
+    pub description: Option<String>,
+    /// Stars embedded in the photo (XMP or EXIF)
+    pub rating: Option<u8>,
+    /// Video of motion photos, counted in `size`
+    pub motion_clip: Option<MotionClip>,
     pub size: u64,
     pub height: u32,
     pub width: u32,
-    pub digest: u32,
+    pub digest: Digest,
+    /// Thumbnail file name, set only when it differs from the one derived by `build_filename`
+    pub thumbnail_name: Option<String>,
+    /// Path of the stored original relative to the archive root, if originals are kept
+    pub original: Option<PathBuf>,
+    /// Link directory relative to the date directory, set only when it is not derived from the source
+    pub link_dir: Option<PathBuf>,
+    /// The image is only indexed, it has neither a thumbnail nor a link
+    pub no_thumbnail: bool,
+}
+
+/// How the rows of an archive are split among index files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexSharding {
+    /// One `<year>/index.json` file per year
+    #[default]
+    Yearly,
+    /// One `<year>/index.<month>.json` file per month, for years too large to be read at once
+    Monthly,
+}
+
+impl Display for IndexSharding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexSharding::Yearly => write!(f, "yearly"),
+            IndexSharding::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+impl FromStr for IndexSharding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "yearly" => Ok(IndexSharding::Yearly),
+            "monthly" => Ok(IndexSharding::Monthly),
+            other => anyhow::bail!("Unknown index sharding '{other}', expected one of yearly, monthly"),
+        }
+    }
 }
 
 pub struct PhotoArchiveRecordsStore {
     base_dir: PathBuf,
+    cipher: Option<Arc<ArchiveCipher>>,
+    sharding: IndexSharding,
+    /// Whether the lookup file is complete and can be appended to, checked on the first append
+    lookup_current: Mutex<Option<bool>>,
+}
+
+/// Line of the lookup file, one per index row
+#[derive(Deserialize, Serialize)]
+struct LookupEntry {
+    #[serde(rename = "crc")]
+    digest: Digest,
+    #[serde(rename = "src")]
+    source: String,
+    /// Directory of the index file, relative to the archive root
+    #[serde(rename = "shr")]
+    shard: String,
+}
+
+impl LookupEntry {
+    fn of(row: &PhotoArchiveJsonRow, shard: &str) -> Self {
+        Self {
+            digest: row.crc.clone(),
+            source: row.source.clone(),
+            shard: String::from(shard),
+        }
+    }
 }
 
 impl PhotoArchiveRecordsStore {
     pub fn new(base_dir: &Path) -> Self {
+        Self::with_cipher(base_dir, None)
+    }
+
+    /// Store of an encrypted archive, each index row is sealed on its own so that rows can still be appended
+    pub fn with_cipher(base_dir: &Path, cipher: Option<Arc<ArchiveCipher>>) -> Self {
         Self {
             base_dir: base_dir.to_path_buf(),
+            cipher,
+            sharding: IndexSharding::default(),
+            lookup_current: Mutex::new(None),
+        }
+    }
+
+    /// Rows appended by the store go to index files split as `sharding` tells. Files of any
+    /// sharding are read, so that archives keep working after their sharding changed.
+    pub fn with_sharding(mut self, sharding: IndexSharding) -> Self {
+        self.sharding = sharding;
+        self
+    }
+
+    fn encode_line(&self, line: String) -> anyhow::Result<String> {
+        match &self.cipher {
+            Some(cipher) => cipher.seal_line(&line),
+            None => Ok(line),
+        }
+    }
+
+    fn decode_line(&self, line: &str) -> anyhow::Result<String> {
+        match &self.cipher {
+            Some(cipher) => cipher.open_line(line),
+            None => Ok(String::from(line)),
         }
     }
 
     pub fn write(&self, row: PhotoArchiveRow) {
-        let frame = serde_json::to_string(&PhotoArchiveJsonRow {
-            timestamp: row.photo_ts.map(|ts| ts.timestamp()),
-            file_ts: row.file_ts.duration_since(SystemTime::UNIX_EPOCH)
-                .expect("Ts is before unix epoch")
-                .as_secs(),
-            source: row.source_id,
-            path: row.source_path.as_os_str().to_str().map(ToString::to_string).unwrap_or_default(),
-            exif: row.exif
-                .map(|exif| Vec::from(exif.buf()))
-                .unwrap_or_default(),
-            size: row.size,
-            height: row.height,
-            width: row.width,
-            crc: row.digest,
-        }).unwrap();
+        self.append(&PhotoArchiveJsonRow::from(row)).expect("Error writing index row");
+    }
+
+    /// Append an already built row to the index file of its year
+    pub fn append(&self, row: &PhotoArchiveJsonRow) -> anyhow::Result<()> {
+        let frame = self.encode_line(serde_json::to_string(row)?)?;
 
+        // The directory may not exist yet with layouts other than the daily one
+        let index_path = self.index_path(row.timestamp().as_ref());
+        if let Some(index_dir) = index_path.parent() {
+            fs::create_dir_all(index_dir)?;
+        }
+        // Checked before the index file changes, which would make a stale lookup file look current
+        let mut lookup_current = self.lookup_current.lock().expect("Poisoned lookup state");
+        if lookup_current.is_none() {
+            *lookup_current = Some(self.is_lookup_current()?);
+        }
         let mut file = std::fs::File::options()
             .read(true)
             .append(true)
             .create(true)
-            .open(self.base_dir.join(row.photo_ts.map(|ts| ts.year().to_string()).unwrap_or_else(|| String::from("no-date"))).join("index.json")).unwrap();
+            .open(&index_path)?;
+
+        file.write_all(frame.as_bytes())?;
+        file.write_all(b"\n")?;
+
+        if *lookup_current == Some(true) {
+            let entry = LookupEntry::of(row, &shard_name(&index_path));
+            let mut file = File::options()
+                .append(true)
+                .create(true)
+                .open(self.base_dir.join(LOOKUP_FILE))?;
+            file.write_all(self.encode_line(serde_json::to_string(&entry)?)?.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Rows holding a photo with `digest` (compared case-insensitively with its printed form),
+    /// only the index files listed for it by the lookup file are read
+    pub fn find_by_digest(&self, digest: &str) -> anyhow::Result<Vec<PhotoArchiveJsonRow>> {
+        let shards = self.lookup_shards(|entry| digest.eq_ignore_ascii_case(&entry.digest.to_string()))?;
+        let mut rows = Vec::new();
+        self.for_each_in(&shards, |row| {
+            if digest.eq_ignore_ascii_case(&row.crc.to_string()) {
+                rows.push(row);
+            }
+        })?;
+        Ok(rows)
+    }
+
+    /// Index shards, named by their directory, holding rows of photos with each of the digests
+    pub fn digests_shards(&self, digests: &HashSet<Digest>) -> anyhow::Result<HashMap<Digest, BTreeSet<String>>> {
+        if !self.is_lookup_current()? {
+            self.rebuild_lookup()?;
+        }
+        let mut shards = HashMap::<Digest, BTreeSet<String>>::new();
+        for entry in self.lookup_entries()? {
+            if digests.contains(&entry.digest) {
+                shards.entry(entry.digest).or_default().insert(entry.shard);
+            }
+        }
+        Ok(shards)
+    }
+
+    /// Index shards, named by their directory, holding rows of the source
+    pub fn source_shards(&self, source_id: &str) -> anyhow::Result<BTreeSet<String>> {
+        self.lookup_shards(|entry| entry.source == source_id)
+    }
+
+    /// Visit the rows of the source, only the index files listed for it by the lookup file are read
+    pub fn for_each_of_source(&self, source_id: &str, mut f: impl FnMut(PhotoArchiveJsonRow)) -> anyhow::Result<()> {
+        self.for_each_in(&self.source_shards(source_id)?, |row| {
+            if row.source == source_id {
+                f(row);
+            }
+        })
+    }
+
+    /// Shards of the lookup entries matching `f`.
+    ///
+    /// The lookup file is rebuilt first when it is missing or older than an index file, as left
+    /// by releases which did not maintain it.
+    fn lookup_shards(&self, mut f: impl FnMut(&LookupEntry) -> bool) -> anyhow::Result<BTreeSet<String>> {
+        if !self.is_lookup_current()? {
+            self.rebuild_lookup()?;
+        }
+        let mut shards = BTreeSet::new();
+        for entry in self.lookup_entries()? {
+            if f(&entry) {
+                shards.insert(entry.shard);
+            }
+        }
+        Ok(shards)
+    }
+
+    fn lookup_entries(&self) -> anyhow::Result<Vec<LookupEntry>> {
+        let reader = BufReader::new(File::open(self.base_dir.join(LOOKUP_FILE))?);
+        reader.lines()
+            .map(|res_line| Ok(serde_json::from_str::<LookupEntry>(&self.decode_line(&res_line?)?)?))
+            .collect()
+    }
+
+    fn is_lookup_current(&self) -> anyhow::Result<bool> {
+        let Ok(metadata) = fs::metadata(self.base_dir.join(LOOKUP_FILE)) else {
+            return Ok(false);
+        };
+        let indexed_at = metadata.modified()?;
+        for index_path in self.indexes_list()? {
+            if fs::metadata(&index_path)?.modified()? > indexed_at {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write the lookup file again from every index file
+    pub fn rebuild_lookup(&self) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+        for index_path in self.indexes_list()? {
+            let shard = shard_name(&index_path);
+            self.read_index_file(&index_path, |_, row| {
+                entries.push(LookupEntry::of(&row, &shard));
+                Ok(())
+            })?;
+        }
+        self.write_lookup(entries)
+    }
+
+    fn write_lookup(&self, entries: Vec<LookupEntry>) -> anyhow::Result<()> {
+        let lookup_path = self.base_dir.join(LOOKUP_FILE);
+        let temp_path = lookup_path.with_extension("ndjson.tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        for entry in entries {
+            writer.write_all(self.encode_line(serde_json::to_string(&entry)?)?.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &lookup_path)?;
+        *self.lookup_current.lock().expect("Poisoned lookup state") = Some(true);
+        Ok(())
+    }
+
+    /// Index file holding the rows of photos taken at `photo_ts`, index files are sharded by year
+    /// or month whatever the layout
+    pub fn index_path(&self, photo_ts: Option<&NaiveDateTime>) -> PathBuf {
+        let shard = match (photo_ts, self.sharding) {
+            (Some(ts), IndexSharding::Yearly) => ts.year().to_string(),
+            (Some(ts), IndexSharding::Monthly) => format!("{}-{:02}", ts.year(), ts.month()),
+            (None, _) => String::from("no-date"),
+        };
+        shard_index_path(&self.base_dir, &shard)
+    }
+
+    pub fn for_each(&self, mut f: impl FnMut(PhotoArchiveJsonRow)) -> anyhow::Result<()> {
+        for index_path in self.indexes_list()? {
+            self.read_index_file(&index_path, |_, row| {
+                f(row);
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Visit the rows of the given index shards only
+    pub fn for_each_in(&self, shards: &BTreeSet<String>, mut f: impl FnMut(PhotoArchiveJsonRow)) -> anyhow::Result<()> {
+        for shard in shards {
+            // Shards emptied by a removal are only dropped from the lookup file by the next rewrite
+            let index_path = shard_index_path(&self.base_dir, shard);
+            if !index_path.is_file() {
+                continue;
+            }
+            self.read_index_file(&index_path, |_, row| {
+                f(row);
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Visit the rows of an index file with their stored line, in file order.
+    ///
+    /// The file is memory-mapped and its lines are parsed in place, by batches spread over the
+    /// available cores, sparing the line buffers of a `BufReader` on full index scans.
+    fn read_index_file(&self, index_path: &Path, mut f: impl FnMut(&str, PhotoArchiveJsonRow) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let file = File::open(index_path)?;
+        // Empty files cannot be mapped on every platform
+        if file.metadata()?.len() == 0 {
+            return Ok(());
+        }
+        // SAFETY: index files are replaced through renames and only grow in place, by appends
+        // which stay beyond the mapped length, so the mapped bytes never change while read
+        let mmap = unsafe { Mmap::map(&file)? };
+        let lines = mmap.split(|byte| *byte == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        for batch in lines.chunks(PARSE_BATCH_LINES) {
+            let parsed = if threads > 1 && batch.len() >= PARSE_PARALLEL_MIN_LINES {
+                thread::scope(|scope| {
+                    let parsers = batch.chunks(batch.len().div_ceil(threads))
+                        .map(|chunk| scope.spawn(move || chunk.iter().map(|line| self.parse_line(line)).collect::<Vec<_>>()))
+                        .collect::<Vec<_>>();
+                    parsers.into_iter()
+                        .flat_map(|parser| parser.join().expect("Index parser panicked"))
+                        .collect::<Vec<_>>()
+                })
+            } else {
+                batch.iter().map(|line| self.parse_line(line)).collect()
+            };
+            for res_row in parsed {
+                let (line, row) = res_row.map_err(|err| anyhow::anyhow!("Error reading {} - {err}", index_path.display()))?;
+                f(line, row)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_line<'a>(&self, line: &'a [u8]) -> anyhow::Result<(&'a str, PhotoArchiveJsonRow)> {
+        let line = std::str::from_utf8(line)?;
+        let row = match &self.cipher {
+            Some(cipher) => serde_json::from_str(&cipher.open_line(line)?)?,
+            None => serde_json::from_str(line)?,
+        };
+        Ok((line, row))
+    }
+
+    /// Visit every line of the index files, unreadable ones included, with its file and line number (1-based)
+    pub fn for_each_line(&self, mut f: impl FnMut(&Path, usize, anyhow::Result<PhotoArchiveJsonRow>)) -> anyhow::Result<()> {
+        for index_path in self.indexes_list()? {
+            let reader = BufReader::new(File::open(&index_path)?);
+            for (idx, res_line) in reader.lines().enumerate() {
+                let row = res_line.map_err(anyhow::Error::from)
+                    .and_then(|line| self.decode_line(&line))
+                    .and_then(|line| Ok(serde_json::from_str::<PhotoArchiveJsonRow>(&line)?));
+                f(&index_path, idx + 1, row);
+            }
+        }
+        Ok(())
+    }
 
-        file.write(frame.as_bytes()).unwrap();
-        file.write(b"\n").unwrap();
+    /// Up to `limit` rows ordered by timestamp (undated ones last), starting after the row of `after`.
+    ///
+    /// Only one index file is loaded at a time, so pages can be read from archives of any size.
+    /// Rows appended before an already read position are not returned by the following pages.
+    pub fn page(&self, after: Option<&PageToken>, limit: usize) -> anyhow::Result<RowsPage> {
+        if limit == 0 {
+            anyhow::bail!("Page limit must be positive");
+        }
+        let spans = self.sorted_spans()?;
+        let mut rows = Vec::new();
+        for (idx, (span, index_paths)) in spans.iter().enumerate() {
+            if after.is_some_and(|token| token.is_after_span(span)) {
+                continue;
+            }
+            let mut shard_rows = Vec::new();
+            for index_path in index_paths {
+                self.read_index_file(index_path, |_, row| {
+                    if after.is_none_or(|token| PageToken::of(&row) > *token) {
+                        shard_rows.push(row);
+                    }
+                    Ok(())
+                })?;
+            }
+            shard_rows.sort_by_cached_key(PageToken::of);
+
+            let remaining = limit - rows.len();
+            let more_in_shard = shard_rows.len() > remaining;
+            rows.extend(shard_rows.into_iter().take(remaining));
+            if rows.len() == limit {
+                let more = more_in_shard || idx + 1 < spans.len();
+                let next = rows.last().filter(|_| more).map(PageToken::of);
+                return Ok(RowsPage { rows, next });
+            }
+        }
+        Ok(RowsPage { rows, next: None })
+    }
+
+    /// Index files grouped by the time span they cover, in timestamp order with the undated ones
+    /// last. The monthly files of a year which also has a yearly file are part of the year span.
+    fn sorted_spans(&self) -> anyhow::Result<Vec<(ShardSpan, Vec<PathBuf>)>> {
+        let mut indexes = self.indexes_list()?
+            .map(|index_path| (ShardSpan::of(&shard_name(&index_path)), index_path))
+            .collect::<Vec<_>>();
+        indexes.sort();
+
+        let mut spans: Vec<(ShardSpan, Vec<PathBuf>)> = Vec::new();
+        for (span, index_path) in indexes {
+            match spans.last_mut() {
+                Some((last, paths)) if last.month.is_none() && last.year.is_some() && last.year == span.year => paths.push(index_path),
+                _ => spans.push((span, vec![index_path])),
+            }
+        }
+        Ok(spans)
     }
 
     fn indexes_list(&self) -> anyhow::Result<impl Iterator<Item=PathBuf>> {
         let iter = fs::read_dir(&self.base_dir)?
-            .into_iter()
             .filter_map(|entry| entry.ok())
-            .filter_map(|entry| Some(entry.path().join("index.json")).filter(|p| p.is_file()));
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+            .flat_map(|entry| {
+                // Monthly index files only exist in year directories, the others are not listed
+                let is_year = entry.file_name().to_str().is_some_and(|name| name.parse::<i32>().is_ok());
+                let mut files = vec![entry.path().join("index.json")];
+                if is_year {
+                    files.extend(fs::read_dir(entry.path()).into_iter()
+                        .flatten()
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| entry.file_name().to_str().and_then(index_month).is_some())
+                        .map(|entry| entry.path()));
+                }
+                files.into_iter().filter(|path| path.is_file())
+            });
         Ok(iter)
     }
 
-    pub fn retain(&self, mut f: impl FnMut(&PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
-        for index_path in self.indexes_list()? {
-            let file = File::open(&index_path)?;
-            let reader = BufReader::new(file);
+    pub fn retain(&self, f: impl FnMut(&PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
+        self.retain_in(None, f)
+    }
 
+    /// Like [`Self::retain`], only the given index shards are rewritten when set
+    pub fn retain_in(&self, shards: Option<&BTreeSet<String>>, mut f: impl FnMut(&PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
+        self.rewrite(shards, |line, row| Ok(f(&row).then(|| String::from(line))))
+    }
+
+    /// Update rows in place, `f` returns whether the row was changed and has to be written back
+    pub fn update(&self, f: impl FnMut(&mut PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
+        self.update_in(None, f)
+    }
+
+    /// Like [`Self::update`], only the given index shards are rewritten when set
+    pub fn update_in(&self, shards: Option<&BTreeSet<String>>, mut f: impl FnMut(&mut PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
+        self.rewrite(shards, |line, mut row| {
+            if f(&mut row) {
+                Ok(Some(self.encode_line(serde_json::to_string(&row)?)?))
+            } else {
+                Ok(Some(String::from(line)))
+            }
+        })
+    }
+
+    /// Rewrite the index files, all of them unless `shards` is set, `f` maps each stored line and
+    /// its decoded row to the line to keep, if any
+    fn rewrite(
+        &self,
+        shards: Option<&BTreeSet<String>>,
+        mut f: impl FnMut(&str, PhotoArchiveJsonRow) -> anyhow::Result<Option<String>>,
+    ) -> anyhow::Result<()> {
+        // Rows keep their digest and source when rewritten, the lookup file is rebuilt along the
+        // way from the kept rows and the entries of the untouched shards
+        let mut lookup_entries = match shards {
+            Some(shards) => {
+                if !self.is_lookup_current()? {
+                    self.rebuild_lookup()?;
+                }
+                self.lookup_entries()?.into_iter().filter(|entry| !shards.contains(&entry.shard)).collect()
+            }
+            None => Vec::new(),
+        };
+        for index_path in self.indexes_list()? {
+            let shard = shard_name(&index_path);
+            if shards.is_some_and(|shards| !shards.contains(&shard)) {
+                continue;
+            }
             let temp_path = index_path.parent()
                 .expect("Error extracting index parent")
-                .join(format!("index.{}.{}.json", index_path.parent().unwrap().file_name().and_then(|name| name.to_str()).unwrap_or("-"), Utc::now().format("%Y%m%d-%H%M%S")));
+                .join(format!("index.{}.{}.json", shard, Utc::now().format("%Y%m%d-%H%M%S")));
             let temp_file = File::create(&temp_path)?;
             let mut writer = BufWriter::new(temp_file);
 
-            for res_line in reader.lines() {
-                let line = res_line?;
-                let row = serde_json::from_str::<PhotoArchiveJsonRow>(&line)?;
-                if f(&row) {
-                    writer.write(line.as_bytes())?;
+            // The index file is unmapped before being replaced, which some platforms require
+            self.read_index_file(&index_path, |line, row| {
+                let entry = LookupEntry::of(&row, &shard);
+                if let Some(out_line) = f(line, row)? {
+                    writer.write_all(out_line.as_bytes())?;
+                    writer.write_all(b"\n")?;
+                    lookup_entries.push(entry);
                 }
-            }
+                Ok(())
+            })?;
             writer.flush()?;
             drop(writer);
 
             std::fs::rename(&temp_path, &index_path)?;
         }
-        Ok(())
+        self.write_lookup(lookup_entries)
     }
 }
 
-#[derive(Deserialize, Serialize)]
+/// Name of an index file as recorded by the lookup file: its directory, followed by the month
+/// for monthly files (e.g. `2021` or `2021-05`)
+fn shard_name(index_path: &Path) -> String {
+    let dir = index_path.parent()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match index_path.file_name().and_then(|name| name.to_str()).and_then(index_month) {
+        Some(month) => format!("{dir}-{month:02}"),
+        None => dir,
+    }
+}
+
+/// Path of the index file of a shard named by [`shard_name`]
+fn shard_index_path(base_dir: &Path, shard: &str) -> PathBuf {
+    let monthly = shard.split_once('-')
+        .filter(|(year, _)| year.parse::<i32>().is_ok())
+        .and_then(|(year, month)| Some(year).zip(month.parse::<u32>().ok()));
+    match monthly {
+        Some((year, month)) => base_dir.join(year).join(format!("index.{month:02}.json")),
+        None => base_dir.join(shard).join("index.json"),
+    }
+}
+
+/// Month of a monthly index file name, `index.<month>.json`
+fn index_month(file_name: &str) -> Option<u32> {
+    file_name.strip_prefix("index.")
+        .and_then(|name| name.strip_suffix(".json"))
+        .filter(|month| month.len() == 2)
+        .and_then(|month| month.parse::<u32>().ok())
+        .filter(|month| (1..=12).contains(month))
+}
+
+/// Whether the file name is the one of an index file, yearly or monthly
+pub fn is_index_file_name(file_name: &str) -> bool {
+    file_name == "index.json" || index_month(file_name).is_some()
+}
+
+/// Time span covered by an index file, `year` is unset for the undated rows and `month` for
+/// yearly files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ShardSpan {
+    // Field order is the timestamp order
+    undated: bool,
+    year: Option<i32>,
+    month: Option<u32>,
+}
+
+impl ShardSpan {
+    fn of(shard: &str) -> Self {
+        let (year, month) = match shard.split_once('-') {
+            Some((year, month)) => (year.parse::<i32>().ok(), month.parse::<u32>().ok()),
+            None => (shard.parse::<i32>().ok(), None),
+        };
+        Self { undated: year.is_none(), year, month: month.filter(|_| year.is_some()) }
+    }
+}
+
+/// Rows of the index read by [`PhotoArchiveRecordsStore::page`]
+pub struct RowsPage {
+    pub rows: Vec<PhotoArchiveJsonRow>,
+    /// Position to read the next page from, missing once the last row was read
+    pub next: Option<PageToken>,
+}
+
+/// Position in the timestamp ordered index, printed as an opaque string for the clients of the
+/// library which have to hand it back (e.g. over HTTP)
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PageToken {
+    // Field order is the row order
+    undated: bool,
+    ts: Option<i64>,
+    source: String,
+    path: String,
+    digest: String,
+}
+
+impl PageToken {
+    fn of(row: &PhotoArchiveJsonRow) -> Self {
+        Self {
+            undated: row.timestamp.is_none(),
+            ts: row.timestamp,
+            source: row.source.clone(),
+            path: row.path.clone(),
+            digest: row.crc.to_string(),
+        }
+    }
+
+    /// Whether every row of the index files covering `span` comes before the token
+    fn is_after_span(&self, span: &ShardSpan) -> bool {
+        let token_date = self.ts.and_then(|ts| DateTime::from_timestamp(ts, 0)).map(|ts| (ts.year(), ts.month()));
+        match (span.year, span.month, token_date) {
+            (Some(year), Some(month), Some(token_date)) => (year, month) < token_date,
+            (Some(year), None, Some((token_year, _))) => year < token_year,
+            (Some(_), _, None) => true,
+            (None, _, _) => false,
+        }
+    }
+}
+
+impl Display for PageToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", URL_SAFE_NO_PAD.encode(json))
+    }
+}
+
+impl FromStr for PageToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json = URL_SAFE_NO_PAD.decode(s).map_err(|_| anyhow::anyhow!("Invalid page token"))?;
+        serde_json::from_slice(&json).map_err(|_| anyhow::anyhow!("Invalid page token"))
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct PhotoArchiveJsonRow {
     #[serde(rename = "ts")]
     timestamp: Option<i64>,
+    /// The timestamp is not from the EXIF data but from the file modification time
+    #[serde(rename = "par", default, skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
     #[serde(rename = "fts")]
     file_ts: u64,
     #[serde(rename = "src")]
     source: String,
     #[serde(rename = "pth")]
     path: String,
-    #[serde(rename = "exf", with = "base64")]
-    exif: Vec<u8>,
+    /// Raw EXIF data, kept base64 encoded as stored since most readers do not need it
+    #[serde(rename = "exf")]
+    exif: String,
+    /// Shooting details parsed from the EXIF data, missing in rows written by older versions
+    #[serde(rename = "exd", default, skip_serializing_if = "Option::is_none")]
+    exif_fields: Option<ExifFields>,
+    #[serde(rename = "dsc", default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(rename = "rat", default, skip_serializing_if = "Option::is_none")]
+    rating: Option<u8>,
+    #[serde(rename = "mvc", default, skip_serializing_if = "Option::is_none")]
+    motion_clip: Option<MotionClip>,
     #[serde(rename = "siz")]
     size: u64,
     #[serde(rename = "hgh")]
     height: u32,
     #[serde(rename = "wdt")]
     width: u32,
-    crc: u32,
+    crc: Digest,
+    #[serde(rename = "thm", default, skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<String>,
+    #[serde(rename = "org", default, skip_serializing_if = "Option::is_none")]
+    original: Option<PathBuf>,
+    #[serde(rename = "lnk", default, skip_serializing_if = "Option::is_none")]
+    link_dir: Option<PathBuf>,
+    /// Image below the size threshold, recorded without thumbnail and link
+    #[serde(rename = "nth", default, skip_serializing_if = "std::ops::Not::not")]
+    no_thumbnail: bool,
+    /// Tombstone, unix timestamp of the sync that found the source file deleted
+    #[serde(rename = "del", default, skip_serializing_if = "Option::is_none")]
+    deleted: Option<i64>,
+}
+
+impl From<PhotoArchiveRow> for PhotoArchiveJsonRow {
+    fn from(row: PhotoArchiveRow) -> Self {
+        Self {
+            timestamp: row.photo_ts.map(|ts| ts.and_utc().timestamp()),
+            partial: row.date_estimated,
+            file_ts: row.file_ts.duration_since(SystemTime::UNIX_EPOCH)
+                .expect("Ts is before unix epoch")
+                .as_secs(),
+            source: row.source_id,
+            path: row.source_path.as_os_str().to_str().map(ToString::to_string).unwrap_or_default(),
+            exif_fields: row.exif.as_ref().map(ExifFields::from_exif).filter(|fields| !fields.is_empty()),
+            exif: row.exif
+                .map(|exif| STANDARD.encode(exif.buf()))
+                .unwrap_or_default(),
+            description: row.description,
+            rating: row.rating,
+            motion_clip: row.motion_clip,
+            size: row.size,
+            height: row.height,
+            width: row.width,
+            crc: row.digest,
+            thumbnail: row.thumbnail_name,
+            original: row.original,
+            link_dir: row.link_dir,
+            no_thumbnail: row.no_thumbnail,
+            deleted: None,
+        }
+    }
 }
 
 impl PhotoArchiveJsonRow {
     pub fn timestamp(&self) -> Option<NaiveDateTime> {
-        self.timestamp.and_then(|ts| NaiveDateTime::from_timestamp_opt(ts, 0))
+        self.timestamp.and_then(|ts| DateTime::from_timestamp(ts, 0)).map(|ts| ts.naive_utc())
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.partial
     }
 
     pub fn file_timestamp(&self) -> SystemTime {
-        SystemTime::UNIX_EPOCH.add(Duration::from_secs(self.file_ts as u64))
+        SystemTime::UNIX_EPOCH.add(Duration::from_secs(self.file_ts))
     }
 
     pub fn source_id(&self) -> &str {
@@ -133,26 +766,94 @@ impl PhotoArchiveJsonRow {
         PathBuf::from(&self.path)
     }
 
-    pub fn digest(&self) -> u32 {
-        self.crc
+    /// Move the record to another file of its source, e.g. after the file was renamed
+    pub fn set_source_path(&mut self, source_path: &Path, link_dir: Option<PathBuf>) {
+        self.path = source_path.as_os_str().to_str().map(ToString::to_string).unwrap_or_default();
+        self.link_dir = link_dir;
     }
-}
 
-mod base64 {
-    use serde::{Serialize, Deserialize};
-    use serde::{Deserializer, Serializer};
-    use base64::engine::general_purpose::STANDARD;
-    use base64::Engine;
+    pub fn digest(&self) -> &Digest {
+        &self.crc
+    }
 
-    pub fn serialize<S: Serializer>(v: &Vec<u8>, s: S) -> Result<S::Ok, S::Error> {
-        let base64 = STANDARD.encode(v);
-        String::serialize(&base64, s)
+    /// Shooting details of the photo, parsed from the raw EXIF data for rows stored without them
+    pub fn exif_fields(&self) -> ExifFields {
+        if let Some(fields) = &self.exif_fields {
+            return fields.clone();
+        }
+        STANDARD.decode(&self.exif).ok()
+            .filter(|buf| !buf.is_empty())
+            .and_then(|buf| exif::Reader::new().read_raw(buf).ok())
+            .map(|exif| ExifFields::from_exif(&exif))
+            .unwrap_or_default()
     }
 
-    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
-        let base64 = String::deserialize(d)?;
-        STANDARD.decode(base64.as_bytes())
-            .map_err(|e| serde::de::Error::custom(e))
+    /// Description embedded in the photo, user captions are kept apart in the user metadata
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
     }
-}
 
+    /// Stars embedded in the photo, user ratings are kept apart in the user metadata
+    pub fn rating(&self) -> Option<u8> {
+        self.rating
+    }
+
+    /// Video appended to the still image, set on motion photos
+    pub fn motion_clip(&self) -> Option<MotionClip> {
+        self.motion_clip
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn original(&self) -> Option<&Path> {
+        self.original.as_deref()
+    }
+
+    pub fn set_original(&mut self, original: Option<PathBuf>) {
+        self.original = original;
+    }
+
+    pub fn link_dir(&self) -> Option<&Path> {
+        self.link_dir.as_deref()
+    }
+
+    pub fn no_thumbnail(&self) -> bool {
+        self.no_thumbnail
+    }
+
+    /// When the source file was found deleted, the photo then survives only in the archive
+    pub fn deleted_at(&self) -> Option<NaiveDateTime> {
+        self.deleted.and_then(|ts| DateTime::from_timestamp(ts, 0)).map(|ts| ts.naive_utc())
+    }
+
+    pub fn set_deleted_at(&mut self, deleted_at: Option<NaiveDateTime>) {
+        self.deleted = deleted_at.map(|ts| ts.and_utc().timestamp());
+    }
+
+    /// Store an explicit thumbnail name, e.g. after it had to be disambiguated
+    pub fn set_thumbnail_name(&mut self, thumbnail_name: String) {
+        self.thumbnail = Some(thumbnail_name);
+    }
+
+    /// Thumbnail name stored in the row, only set when it differs from the derived one
+    pub fn stored_thumbnail_name(&self) -> Option<&str> {
+        self.thumbnail.as_deref()
+    }
+
+    pub fn thumbnail_name(&self, layout: &ArchiveLayout) -> anyhow::Result<String> {
+        match &self.thumbnail {
+            Some(name) => Ok(name.clone()),
+            None => build_filename(layout, self.timestamp().as_ref(), self.file_timestamp(), &self.crc),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive::common::build_record_paths;
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::link::SymlinkStyle;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::PhotoArchiveRecordsStore;
+
+#[derive(Debug, Default)]
+pub struct RelinkStats {
+    pub relinked: usize,
+    pub unchanged: usize,
+    pub errors: usize,
+}
+
+/// Rewrite the existing links of the archive so that they follow the configured style.
+///
+/// When `style` is provided it replaces the one stored in the manifest before relinking.
+pub fn relink_archive(target: &Path, style: Option<SymlinkStyle>, secret: Option<&ArchiveSecret>) -> anyhow::Result<RelinkStats> {
+    let mut manifest = ArchiveManifest::load_or_default(target)?;
+    let cipher = manifest.cipher(secret)?;
+    if let Some(style) = style {
+        manifest.symlink_style = style;
+        manifest.store(target)?;
+    }
+    let linker = manifest.linker(target)?;
+
+    let mut stats = RelinkStats::default();
+    PhotoArchiveRecordsStore::with_cipher(target, cipher).for_each(|row| {
+        let out = build_record_paths(&manifest.layout, target, &row).and_then(|paths| {
+            if !linker.link_exists(&paths) {
+                return Ok(false);
+            }
+            let thumbnail_name = row.thumbnail_name(&manifest.layout)?;
+            if linker.verify_link(&paths, &thumbnail_name).unwrap_or(false) {
+                return Ok(false);
+            }
+            linker.remove_link(&paths)?;
+            linker.create_link(&paths, &thumbnail_name)?;
+            Ok(true)
+        });
+
+        match out {
+            Ok(true) => stats.relinked += 1,
+            Ok(false) => stats.unchanged += 1,
+            Err(err) => {
+                eprintln!("Error relinking {} - {err}", row.source_path().display());
+                stats.errors += 1;
+            }
+        }
+    })?;
+
+    Ok(stats)
+}
+
+#[derive(Debug, Default)]
+pub struct RepairLinksStats {
+    /// Links created again, with the paths of their link entries
+    pub recreated: Vec<PathBuf>,
+    pub unchanged: usize,
+    /// Link entries that cannot be recreated because their thumbnail is missing too
+    pub missing_thumbnail: Vec<PathBuf>,
+    pub errors: usize,
+}
+
+/// Recreate the link directories and link entries of the archive from its index, for archives
+/// whose thumbnails survived but whose links were lost (e.g. copied to a filesystem dropping
+/// symlinks, or an interrupted sync). Existing link entries are left untouched.
+pub fn repair_links(target: &Path, secret: Option<&ArchiveSecret>) -> anyhow::Result<RepairLinksStats> {
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let linker = manifest.linker(target)?;
+
+    // Superseded rows share the link of the row replacing them, the latest one wins
+    let mut links = HashMap::new();
+    PhotoArchiveRecordsStore::with_cipher(target, manifest.cipher(secret)?).for_each(|row| {
+        if row.no_thumbnail() {
+            return;
+        }
+        match build_record_paths(&manifest.layout, target, &row) {
+            Ok(paths) => {
+                links.insert(paths.link_file_path.clone(), (paths, row));
+            }
+            Err(err) => eprintln!("Error building paths of {} - {err}", row.source_path().display()),
+        }
+    })?;
+
+    let mut stats = RepairLinksStats::default();
+    let mut links = links.into_values().collect::<Vec<_>>();
+    links.sort_by(|(a, _), (b, _)| a.link_file_path.cmp(&b.link_file_path));
+    for (paths, row) in links {
+        if linker.link_exists(&paths) {
+            stats.unchanged += 1;
+            continue;
+        }
+        let out = row.thumbnail_name(&manifest.layout).and_then(|thumbnail_name| {
+            if !paths.img_path.join(&thumbnail_name).exists() {
+                return Ok(false);
+            }
+            fs::create_dir_all(&paths.link_dir_path)?;
+            linker.create_link(&paths, &thumbnail_name)?;
+            Ok(true)
+        });
+
+        match out {
+            Ok(true) => stats.recreated.push(paths.link_file_path),
+            Ok(false) => stats.missing_thumbnail.push(paths.link_file_path),
+            Err(err) => {
+                eprintln!("Error recreating link {} - {err}", paths.link_file_path.display());
+                stats.errors += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
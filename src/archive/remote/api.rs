@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use url::Url;
+
+use crate::archive::remote::config::ApiAccount;
+
+/// Bearer tokens of an API source: either a fixed access token, or short-lived ones obtained from
+/// a refresh token when the previous one expires
+pub(crate) struct AccessTokens {
+    grant: TokenGrant,
+    cached: RefCell<Option<AccessToken>>,
+}
+
+enum TokenGrant {
+    Fixed(String),
+    Refresh {
+        token_url: &'static str,
+        params: Vec<(&'static str, String)>,
+    },
+}
+
+struct AccessToken {
+    value: String,
+    expires_at: Instant,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TokenResponse {
+    pub access_token: String,
+    pub expires_in: Option<u64>,
+    pub refresh_token: Option<String>,
+}
+
+impl AccessTokens {
+    pub fn fixed(access_token: String) -> Self {
+        Self { grant: TokenGrant::Fixed(access_token), cached: RefCell::new(None) }
+    }
+
+    /// Tokens refreshed at `token_url`, `params` are sent along with the refresh token (client
+    /// id and secret, scope, ...)
+    pub fn refreshed(token_url: &'static str, refresh_token: &str, params: Vec<(&'static str, String)>) -> Self {
+        let mut params = params;
+        params.push(("grant_type", String::from("refresh_token")));
+        params.push(("refresh_token", String::from(refresh_token)));
+        Self { grant: TokenGrant::Refresh { token_url, params }, cached: RefCell::new(None) }
+    }
+
+    /// Tokens of a configured account, `params` are sent along with its refresh token
+    pub fn of_account(account: &ApiAccount, token_url: &'static str, params: Vec<(&'static str, String)>) -> anyhow::Result<Self> {
+        match (&account.access_token, &account.refresh_token, &account.client_id) {
+            (_, Some(refresh_token), Some(client_id)) => {
+                let mut params = params;
+                params.push(("client_id", client_id.clone()));
+                if let Some(client_secret) = &account.client_secret {
+                    params.push(("client_secret", client_secret.clone()));
+                }
+                Ok(Self::refreshed(token_url, refresh_token, params))
+            }
+            (Some(access_token), _, _) => Ok(Self::fixed(access_token.clone())),
+            _ => anyhow::bail!("Account needs either an access_token, or a refresh_token and a client_id"),
+        }
+    }
+
+    /// Current access token, refreshed when expired
+    pub fn get(&self, agent: &ureq::Agent) -> anyhow::Result<String> {
+        let (token_url, params) = match &self.grant {
+            TokenGrant::Fixed(access_token) => return Ok(access_token.clone()),
+            TokenGrant::Refresh { token_url, params } => (token_url, params),
+        };
+        let mut cached = self.cached.borrow_mut();
+        if let Some(token) = cached.as_ref().filter(|token| token.expires_at > Instant::now()) {
+            return Ok(token.value.clone());
+        }
+
+        let form = params.iter().map(|(key, value)| (*key, value.as_str())).collect::<Vec<_>>();
+        let response = agent.post(token_url)
+            .send_form(&form)
+            .map_err(|err| api_error("Error refreshing the access token", err))?;
+        let response: TokenResponse = serde_json::from_reader(response.into_reader())?;
+        // Refreshed a minute early, not to send a token expiring in flight
+        let lifetime = Duration::from_secs(response.expires_in.unwrap_or(3600).saturating_sub(60));
+        *cached = Some(AccessToken { value: response.access_token.clone(), expires_at: Instant::now() + lifetime });
+        Ok(response.access_token)
+    }
+}
+
+/// Error of an API call, with the body of error responses which usually tells what went wrong
+pub(crate) fn api_error(context: &str, err: ureq::Error) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            anyhow::anyhow!("{context} - status {status} {}", body.trim())
+        }
+        other => anyhow::anyhow!("{context} - {other}"),
+    }
+}
+
+pub(crate) fn get_json<T: DeserializeOwned>(agent: &ureq::Agent, url: &str, access_token: &str) -> anyhow::Result<T> {
+    let response = agent.get(url)
+        .set("Authorization", &format!("Bearer {access_token}"))
+        .call()
+        .map_err(|err| api_error(&format!("Error reading {url}"), err))?;
+    Ok(serde_json::from_reader(response.into_reader())?)
+}
+
+pub(crate) fn post_json<T: DeserializeOwned>(agent: &ureq::Agent, url: &str, access_token: &str, body: &serde_json::Value) -> anyhow::Result<T> {
+    let response = agent.post(url)
+        .set("Authorization", &format!("Bearer {access_token}"))
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+        .map_err(|err| api_error(&format!("Error reading {url}"), err))?;
+    Ok(serde_json::from_reader(response.into_reader())?)
+}
+
+/// Account name and decoded folder segments of urls like `dropbox://<account>/<folder>`
+pub(crate) fn parse_account_url(url: &str) -> anyhow::Result<(String, Vec<String>)> {
+    let parsed = Url::parse(url)?;
+    let account = parsed.host_str()
+        .filter(|account| !account.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Missing account in {url}, expected {}://<account>/<folder>", parsed.scheme()))?;
+    let segments = parsed.path_segments()
+        .map(|segments| segments
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+            .collect())
+        .unwrap_or_default();
+    Ok((String::from(account), segments))
+}
+
+/// Source id made of the provider name, the account and the folder, with only filename-safe chars
+pub(crate) fn api_source_id(provider: &str, account: &str, folder: &[String]) -> String {
+    let location = std::iter::once(account).chain(folder.iter().map(String::as_str)).collect::<Vec<_>>().join("_");
+    let location = location.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect::<String>();
+    format!("{provider}-{location}")
+}
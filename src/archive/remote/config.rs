@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::remote::google_photos::GooglePhotosAccount;
+
+/// Accounts of the API sources, stored outside of the archives (`remotes.toml` of the user
+/// configuration directory) as they hold the tokens giving access to the user storage
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RemotesConfig {
+    /// Google Photos accounts by name, addressed as `gphotos://<name>`
+    #[serde(default)]
+    pub gphotos: BTreeMap<String, GooglePhotosAccount>,
+    /// Dropbox accounts by name, addressed as `dropbox://<name>/<folder>`
+    #[serde(default)]
+    pub dropbox: BTreeMap<String, ApiAccount>,
+    /// OneDrive accounts by name, addressed as `onedrive://<name>/<folder>`
+    #[serde(default)]
+    pub onedrive: BTreeMap<String, ApiAccount>,
+}
+
+/// Account of a storage API, written by hand from the tokens issued by the app console of the
+/// provider: either a long-lived access token, or a refresh token and the client it was issued to
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ApiAccount {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Not needed by public clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+/// OAuth client registered by the user with the storage provider
+#[derive(Clone, Debug)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+impl RemotesConfig {
+    /// Read the configuration, an empty one when the file does not exist yet
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if path.is_file() {
+            Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn store(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // Tokens are as sensitive as passwords, they are never readable by others, not even
+        // before the permissions of an existing file are restricted
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(toml::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use serde::Deserialize;
+
+use crate::archive::remote::api::{api_error, api_source_id, parse_account_url, post_json, AccessTokens};
+use crate::archive::remote::config::ApiAccount;
+use crate::archive::remote::{RemoteFile, RemoteSource};
+
+const TOKEN_URL: &str = "https://api.dropbox.com/oauth2/token";
+const API_URL: &str = "https://api.dropboxapi.com/2";
+const CONTENT_URL: &str = "https://content.dropboxapi.com/2";
+
+/// Folder of a Dropbox account, addressed as `dropbox://<account>/<folder>`
+pub struct DropboxSource {
+    account_name: String,
+    folder: Vec<String>,
+    agent: ureq::Agent,
+    tokens: AccessTokens,
+}
+
+#[derive(Deserialize)]
+struct FolderPage {
+    entries: Vec<FolderEntry>,
+    cursor: String,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct FolderEntry {
+    #[serde(rename = ".tag")]
+    tag: String,
+    id: Option<String>,
+    path_display: Option<String>,
+    size: Option<u64>,
+    /// Modification time reported by the device which uploaded the file
+    client_modified: Option<String>,
+}
+
+impl DropboxSource {
+    pub fn new(url: &str, accounts: &BTreeMap<String, ApiAccount>) -> anyhow::Result<Self> {
+        let (account_name, folder) = parse_account_url(url)?;
+        let account = accounts.get(&account_name)
+            .ok_or_else(|| anyhow::anyhow!("Dropbox account '{account_name}' is not configured, add its tokens to [dropbox.{account_name}] of the remotes configuration"))?;
+        Ok(Self {
+            tokens: AccessTokens::of_account(account, TOKEN_URL, vec![])?,
+            account_name,
+            folder,
+            agent: ureq::AgentBuilder::new().build(),
+        })
+    }
+
+    /// Folder path as expected by the API, the empty string being the root of the account
+    fn folder_path(&self) -> String {
+        self.folder.iter().map(|segment| format!("/{segment}")).collect()
+    }
+
+    fn list_page(&self, cursor: Option<&str>) -> anyhow::Result<FolderPage> {
+        let access_token = self.tokens.get(&self.agent)?;
+        match cursor {
+            Some(cursor) => post_json(&self.agent, &format!("{API_URL}/files/list_folder/continue"), &access_token, &serde_json::json!({ "cursor": cursor })),
+            None => post_json(&self.agent, &format!("{API_URL}/files/list_folder"), &access_token, &serde_json::json!({ "path": self.folder_path(), "recursive": true })),
+        }
+    }
+}
+
+impl RemoteSource for DropboxSource {
+    fn source_id(&self) -> String {
+        api_source_id("dropbox", &self.account_name, &self.folder)
+    }
+
+    fn kind(&self) -> &'static str {
+        "dropbox"
+    }
+
+    fn location(&self) -> String {
+        format!("dropbox://{}{}", self.account_name, self.folder_path())
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<RemoteFile>> {
+        let mut files = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let page = self.list_page(cursor.as_deref())?;
+            for entry in page.entries.into_iter().filter(|entry| entry.tag == "file") {
+                let Some(path_display) = entry.path_display else {
+                    continue;
+                };
+                // Only the last component of `path_display` is sure to have the right case, the
+                // folder prefix is thus skipped by component count
+                let path = path_display.split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .skip(self.folder.len())
+                    .collect::<PathBuf>();
+                files.push(RemoteFile {
+                    path,
+                    size: entry.size,
+                    modified: entry.client_modified
+                        .and_then(|modified| DateTime::parse_from_rfc3339(&modified).ok())
+                        .map(SystemTime::from),
+                    content_id: entry.id,
+                });
+            }
+            if !page.has_more {
+                return Ok(files);
+            }
+            cursor = Some(page.cursor);
+        }
+    }
+
+    fn download(&self, file: &RemoteFile, out: &mut dyn Write) -> anyhow::Result<()> {
+        // Ids are plain ASCII, unlike paths which would need escaping in the header
+        let target = match &file.content_id {
+            Some(id) => String::from(id),
+            None => format!("{}/{}", self.folder_path(), file.path.to_string_lossy()),
+        };
+        let response = self.agent.post(&format!("{CONTENT_URL}/files/download"))
+            .set("Authorization", &format!("Bearer {}", self.tokens.get(&self.agent)?))
+            .set("Dropbox-API-Arg", &serde_json::json!({ "path": target }).to_string())
+            .call()
+            .map_err(|err| api_error(&format!("Error downloading {}", file.path.display()), err))?;
+        std::io::copy(&mut response.into_reader(), out)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Local, Utc};
+use img_parts::jpeg::Jpeg;
+use img_parts::{Bytes, ImageEXIF};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::archive::encryption::rand_bytes;
+use crate::archive::remote::api::{api_error, get_json, post_json, AccessTokens, TokenResponse};
+use crate::archive::remote::config::OAuthClient;
+use crate::archive::remote::{RemoteFile, RemoteSource};
+
+const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const API_URL: &str = "https://photospicker.googleapis.com/v1";
+/// The library can no longer be listed by third party applications, only the photos the user picks
+const SCOPE: &str = "https://www.googleapis.com/auth/photospicker.mediaitems.readonly";
+const MEDIA_PAGE_SIZE: u32 = 100;
+/// Used when the session tells no polling interval or timeout
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_PICK_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Google Photos account authorized through [`authorize`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GooglePhotosAccount {
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+}
+
+/// Photos picked from a Google Photos account, addressed as `gphotos://<account>`.
+///
+/// Every listing opens a picking session: `on_url` is called with the page where the user picks
+/// the photos to import, the listing returns once the selection is done. The photos picked in
+/// earlier sessions stay staged, each photo is staged once as `<name>~<end of its id>.<ext>`.
+///
+/// Originals are downloaded unless the url asks for renditions, e.g. `gphotos://me?size=2048`
+/// for JPEGs whose longest side is at most 2048 pixels (which also makes HEIC photos readable).
+///
+/// Album names are not imported as tags: the picker only tells the id, creation time, type and
+/// file of the picked items, the albums holding them are no longer exposed to applications.
+pub struct GooglePhotosSource {
+    account_name: String,
+    rendition_size: Option<u32>,
+    agent: ureq::Agent,
+    tokens: AccessTokens,
+    on_url: Box<dyn Fn(&str)>,
+    /// Items of the last listing by id, their base urls are only returned by the session
+    picked: RefCell<HashMap<String, PickedMediaItem>>,
+    /// Session of the last listing, deleted once done
+    session_id: RefCell<Option<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PickingSession {
+    id: String,
+    picker_uri: String,
+    #[serde(default)]
+    media_items_set: bool,
+    polling_config: Option<PollingConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PollingConfig {
+    /// Duration in seconds, e.g. `5s`
+    poll_interval: Option<String>,
+    timeout_in: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Page<T> {
+    #[serde(default = "Vec::new", alias = "mediaItems")]
+    items: Vec<T>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PickedMediaItem {
+    id: String,
+    /// RFC 3339 timestamp
+    create_time: Option<String>,
+    /// `PHOTO` or `VIDEO`
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    media_file: MediaFile,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MediaFile {
+    base_url: String,
+    filename: String,
+}
+
+impl PickedMediaItem {
+    fn created(&self) -> Option<DateTime<Utc>> {
+        let create_time = self.create_time.as_deref()?;
+        DateTime::parse_from_rfc3339(create_time).ok().map(|created| created.with_timezone(&Utc))
+    }
+}
+
+/// Durations of the API, in seconds with an `s` suffix
+fn parse_duration(duration: Option<&str>) -> Option<Duration> {
+    duration?.strip_suffix('s')?.parse::<f64>().ok().filter(|secs| secs.is_finite() && *secs >= 0.0).map(Duration::from_secs_f64)
+}
+
+/// Run the OAuth flow of installed applications: `on_url` is called with the page the user has to
+/// open to grant access to the photos they pick, Google then redirects the browser to a local port.
+pub fn authorize(client: &OAuthClient, on_url: impl FnOnce(&str)) -> anyhow::Result<GooglePhotosAccount> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let redirect_uri = format!("http://127.0.0.1:{}", listener.local_addr()?.port());
+    let state = rand_bytes::<16>().iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+
+    let mut auth_url = Url::parse(AUTH_URL)?;
+    auth_url.query_pairs_mut()
+        .append_pair("client_id", &client.client_id)
+        .append_pair("redirect_uri", &redirect_uri)
+        .append_pair("response_type", "code")
+        .append_pair("scope", SCOPE)
+        .append_pair("access_type", "offline")
+        .append_pair("prompt", "consent")
+        .append_pair("state", &state);
+    on_url(auth_url.as_str());
+
+    let code = wait_authorization_code(&listener, &state)?;
+    let response = ureq::post(TOKEN_URL)
+        .send_form(&[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("client_id", &client.client_id),
+            ("client_secret", &client.client_secret),
+            ("redirect_uri", &redirect_uri),
+        ])
+        .map_err(|err| api_error("Error exchanging the authorization code", err))?;
+    let token: TokenResponse = serde_json::from_reader(response.into_reader())?;
+    Ok(GooglePhotosAccount {
+        client_id: client.client_id.clone(),
+        client_secret: client.client_secret.clone(),
+        refresh_token: token.refresh_token.ok_or_else(|| anyhow::anyhow!("Google returned no refresh token"))?,
+    })
+}
+
+/// Serve the redirect of the browser, answering any other request (favicon, ...) with a 404
+fn wait_authorization_code(listener: &TcpListener, state: &str) -> anyhow::Result<String> {
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+        let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let params = Url::parse(&format!("http://127.0.0.1{target}"))?
+            .query_pairs()
+            .into_owned()
+            .collect::<HashMap<_, _>>();
+
+        let outcome = match (params.get("state"), params.get("code"), params.get("error")) {
+            (None, _, None) => {
+                write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+                continue;
+            }
+            (_, _, Some(error)) => Err(anyhow::anyhow!("Authorization denied - {error}")),
+            (Some(received), Some(code), None) if received == state => Ok(code.clone()),
+            _ => Err(anyhow::anyhow!("Invalid authorization redirect")),
+        };
+        let message = match &outcome {
+            Ok(_) => String::from("photo-archive is authorized, this window can be closed."),
+            Err(err) => format!("photo-archive authorization failed: {err}"),
+        };
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{message}", message.len())?;
+        return outcome;
+    }
+    anyhow::bail!("Authorization listener closed")
+}
+
+impl GooglePhotosSource {
+    /// `on_url` is called with the page where the user picks the photos, at every listing
+    pub fn new(url: &str, accounts: &BTreeMap<String, GooglePhotosAccount>, on_url: Box<dyn Fn(&str)>) -> anyhow::Result<Self> {
+        let parsed = Url::parse(url)?;
+        let account_name = parsed.host_str()
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing account in {url}, expected gphotos://<account>"))?;
+        let account = accounts.get(account_name)
+            .ok_or_else(|| anyhow::anyhow!("Google Photos account '{account_name}' is not configured, authorize it with `sources login gphotos://{account_name}`"))?;
+        let rendition_size = parsed.query_pairs()
+            .find(|(key, _)| key == "size")
+            .map(|(_, size)| size.parse::<u32>().map_err(|_| anyhow::anyhow!("Invalid rendition size '{size}'")))
+            .transpose()?;
+
+        Ok(Self {
+            account_name: String::from(account_name),
+            rendition_size,
+            agent: ureq::AgentBuilder::new().build(),
+            tokens: AccessTokens::refreshed(TOKEN_URL, &account.refresh_token, vec![
+                ("client_id", account.client_id.clone()),
+                ("client_secret", account.client_secret.clone()),
+            ]),
+            on_url,
+            picked: RefCell::new(HashMap::new()),
+            session_id: RefCell::new(None),
+        })
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
+        get_json(&self.agent, url, &self.tokens.get(&self.agent)?)
+    }
+
+    fn post_json<T: DeserializeOwned>(&self, url: &str, body: &serde_json::Value) -> anyhow::Result<T> {
+        post_json(&self.agent, url, &self.tokens.get(&self.agent)?, body)
+    }
+
+    /// Open a picking session and wait for the user to pick the photos
+    fn pick(&self) -> anyhow::Result<PickingSession> {
+        let session: PickingSession = self.post_json(&format!("{API_URL}/sessions"), &serde_json::json!({}))?;
+        *self.session_id.borrow_mut() = Some(session.id.clone());
+        (self.on_url)(&session.picker_uri);
+
+        let polling = session.polling_config.as_ref();
+        let interval = parse_duration(polling.and_then(|polling| polling.poll_interval.as_deref())).unwrap_or(DEFAULT_POLL_INTERVAL);
+        let timeout = parse_duration(polling.and_then(|polling| polling.timeout_in.as_deref())).unwrap_or(DEFAULT_PICK_TIMEOUT);
+        let deadline = Instant::now() + timeout;
+        let mut session = session;
+        while !session.media_items_set {
+            if Instant::now() >= deadline {
+                anyhow::bail!("No photos picked before the session expired");
+            }
+            thread::sleep(interval.max(Duration::from_secs(1)));
+            session = self.get_json(&format!("{API_URL}/sessions/{}", session.id))?;
+        }
+        Ok(session)
+    }
+
+    /// Name of the staged file, renditions are always JPEGs
+    fn file_name(&self, item: &PickedMediaItem) -> String {
+        let file_name = match self.rendition_size {
+            Some(_) => Path::new(&item.media_file.filename).with_extension("jpg").to_string_lossy().into_owned(),
+            None => item.media_file.filename.clone(),
+        };
+        disambiguated_name(&file_name, &item.id)
+    }
+}
+
+impl Drop for GooglePhotosSource {
+    fn drop(&mut self) {
+        // Sessions expire anyway, deleting them frees the picked items right away
+        if let Some(session_id) = self.session_id.get_mut().take() {
+            if let Ok(token) = self.tokens.get(&self.agent) {
+                let _ = self.agent.delete(&format!("{API_URL}/sessions/{session_id}"))
+                    .set("Authorization", &format!("Bearer {token}"))
+                    .call();
+            }
+        }
+    }
+}
+
+fn collect_pages<T>(mut fetch: impl FnMut(Option<&str>) -> anyhow::Result<Page<T>>) -> anyhow::Result<Vec<T>> {
+    let mut items = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let page = fetch(page_token.as_deref())?;
+        items.extend(page.items);
+        match page.next_page_token.filter(|token| !token.is_empty()) {
+            Some(token) => page_token = Some(token),
+            None => return Ok(items),
+        }
+    }
+}
+
+/// File name made unique by the end of the media item id, photos picked in different sessions
+/// can share their name
+fn disambiguated_name(file_name: &str, item_id: &str) -> String {
+    let path = Path::new(file_name);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let suffix = &item_id[item_id.len().saturating_sub(8)..];
+    match path.extension() {
+        Some(extension) => format!("{stem}~{suffix}.{}", extension.to_string_lossy()),
+        None => format!("{stem}~{suffix}"),
+    }
+}
+
+/// Write the creation date of the library as capture date of the JPEGs carrying no EXIF, such as
+/// renditions, so that they are filed under the day they were taken
+fn with_capture_date(content: Vec<u8>, created: DateTime<Utc>) -> Vec<u8> {
+    let bytes = Bytes::from(content);
+    let Ok(mut jpeg) = Jpeg::from_bytes(bytes.clone()) else {
+        return bytes.to_vec();
+    };
+    if jpeg.exif().is_some() || jpeg.segments().len() < 3 {
+        return bytes.to_vec();
+    }
+
+    // EXIF dates have no time zone, photos are assumed to be taken in the local one
+    let date = created.with_timezone(&Local).format("%Y:%m:%d %H:%M:%S").to_string();
+    let field = exif::Field {
+        tag: exif::Tag::DateTimeOriginal,
+        ifd_num: exif::In::PRIMARY,
+        value: exif::Value::Ascii(vec![date.into_bytes()]),
+    };
+    let mut writer = exif::experimental::Writer::new();
+    writer.push_field(&field);
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    if writer.write(&mut tiff, false).is_err() {
+        return bytes.to_vec();
+    }
+    jpeg.set_exif(Some(Bytes::from(tiff.into_inner())));
+
+    let mut out = Vec::new();
+    match jpeg.encoder().write_to(&mut out) {
+        Ok(_) => out,
+        Err(_) => bytes.to_vec(),
+    }
+}
+
+impl RemoteSource for GooglePhotosSource {
+    fn source_id(&self) -> String {
+        let account = self.account_name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect::<String>();
+        format!("gphotos-{account}")
+    }
+
+    fn kind(&self) -> &'static str {
+        "gphotos"
+    }
+
+    fn location(&self) -> String {
+        match self.rendition_size {
+            Some(size) => format!("gphotos://{}?size={size}", self.account_name),
+            None => format!("gphotos://{}", self.account_name),
+        }
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<RemoteFile>> {
+        let session = self.pick()?;
+        let items: Vec<PickedMediaItem> = collect_pages(|page_token| {
+            let mut url = format!("{API_URL}/mediaItems?sessionId={}&pageSize={MEDIA_PAGE_SIZE}", session.id);
+            if let Some(page_token) = page_token {
+                url.push_str(&format!("&pageToken={page_token}"));
+            }
+            self.get_json(&url)
+        })?;
+
+        let mut picked = self.picked.borrow_mut();
+        picked.clear();
+        let mut files = Vec::new();
+        for item in items.into_iter().filter(|item| item.kind.as_deref() != Some("VIDEO")) {
+            files.push(RemoteFile {
+                path: PathBuf::from(self.file_name(&item)),
+                size: None,
+                modified: item.created().map(SystemTime::from),
+                content_id: Some(item.id.clone()),
+            });
+            picked.insert(item.id.clone(), item);
+        }
+        Ok(files)
+    }
+
+    fn partial_listing(&self) -> bool {
+        true
+    }
+
+    fn download(&self, file: &RemoteFile, out: &mut dyn Write) -> anyhow::Result<()> {
+        let item = file.content_id.as_deref()
+            .and_then(|id| self.picked.borrow().get(id).cloned())
+            .ok_or_else(|| anyhow::anyhow!("{} was not picked", file.path.display()))?;
+        let variant = match self.rendition_size {
+            Some(size) => format!("=w{size}-h{size}"),
+            None => String::from("=d"),
+        };
+        // Unlike the library ones, the base urls of picked items need the access token
+        let response = self.agent.get(&format!("{}{variant}", item.media_file.base_url))
+            .set("Authorization", &format!("Bearer {}", self.tokens.get(&self.agent)?))
+            .call()
+            .map_err(|err| api_error(&format!("Error downloading {}", item.media_file.filename), err))?;
+        let mut content = Vec::new();
+        response.into_reader().read_to_end(&mut content)?;
+        if let Some(created) = item.created() {
+            content = with_capture_date(content, created);
+        }
+        out.write_all(&content)?;
+        Ok(())
+    }
+}
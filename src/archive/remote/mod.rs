@@ -0,0 +1,234 @@
+pub mod adb;
+pub(crate) mod api;
+pub mod config;
+pub mod dropbox;
+pub mod google_photos;
+pub mod onedrive;
+pub mod rclone;
+pub mod webdav;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::archive::remote::config::{OAuthClient, RemotesConfig};
+use crate::archive::sync::is_supported_image;
+use crate::common::fs::model::{MountedPartitionInfo, PartitionDetails, PartitionInfo};
+
+/// File of a remote source
+#[derive(Clone, Debug)]
+pub struct RemoteFile {
+    /// Path relative to the source root
+    pub path: PathBuf,
+    /// Unknown for the APIs not telling it before the download
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    /// Identifier of the content on the remote, files sharing it are downloaded once
+    pub content_id: Option<String>,
+}
+
+/// Storage whose photos are read through an API instead of a mounted partition.
+///
+/// Remote sources are staged: their images are downloaded into a local directory mirroring the
+/// remote tree, which is then synchronized like a mounted source. Later stagings only download
+/// the files whose size or modification time changed.
+pub trait RemoteSource {
+    /// Id registering the source in the archive, the same at every sync
+    fn source_id(&self) -> String;
+    /// Kind of storage, shown as the filesystem type of the staged source
+    fn kind(&self) -> &'static str;
+    /// Location of the source, without credentials
+    fn location(&self) -> String;
+    /// Every file below the source root
+    fn list(&self) -> anyhow::Result<Vec<RemoteFile>>;
+    /// Whether `list` only returns some of the files, e.g. the ones picked by the user: the staged
+    /// files it misses are then kept instead of being removed
+    fn partial_listing(&self) -> bool {
+        false
+    }
+    /// Write the content of `file` into `out`
+    fn download(&self, file: &RemoteFile, out: &mut dyn Write) -> anyhow::Result<()>;
+}
+
+/// Open the remote source addressed by `url`, e.g. `davs://cloud.example.com/remote.php/dav/files/me/Photos`.
+/// `on_url` is called with the pages the user has to open, where Google Photos asks which photos to import.
+pub fn open_remote_source(url: &str, credentials: &RemoteCredentials, on_url: impl Fn(&str) + 'static) -> anyhow::Result<Box<dyn RemoteSource>> {
+    match url_scheme(url) {
+        "dav" | "davs" => Ok(Box::new(webdav::WebDavSource::new(url, credentials.password.clone())?)),
+        "gphotos" => Ok(Box::new(google_photos::GooglePhotosSource::new(url, &credentials.config.gphotos, Box::new(on_url))?)),
+        "dropbox" => Ok(Box::new(dropbox::DropboxSource::new(url, &credentials.config.dropbox)?)),
+        "onedrive" => Ok(Box::new(onedrive::OneDriveSource::new(url, &credentials.config.onedrive)?)),
+        "rclone" => Ok(Box::new(rclone::RcloneSource::new(url)?)),
+        "adb" => Ok(Box::new(adb::AdbSource::new(url)?)),
+        other => anyhow::bail!("Unsupported source url scheme '{other}', expected one of dav, davs, gphotos, dropbox, onedrive, rclone, adb"),
+    }
+}
+
+/// Authorize access to the account of the remote source addressed by `url`, storing its tokens in
+/// `config`. `on_url` is called with the page the user has to open to grant the access.
+pub fn login_remote_source(url: &str, client: &OAuthClient, config: &mut RemotesConfig, on_url: impl FnOnce(&str)) -> anyhow::Result<()> {
+    match url_scheme(url) {
+        "gphotos" => {
+            let account = url::Url::parse(url)?
+                .host_str()
+                .map(String::from)
+                .filter(|account| !account.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("Missing account in {url}, expected gphotos://<account>"))?;
+            let authorized = google_photos::authorize(client, on_url)?;
+            config.gphotos.insert(account, authorized);
+            Ok(())
+        }
+        "dropbox" | "onedrive" => anyhow::bail!("Add the tokens of {url} to the remotes configuration, as issued by the app console of the provider"),
+        other => anyhow::bail!("Sources with url scheme '{other}' need no login"),
+    }
+}
+
+fn url_scheme(url: &str) -> &str {
+    url.split_once("://").map(|(scheme, _)| scheme).unwrap_or_default()
+}
+
+/// Secrets of the remote sources, not part of their url
+#[derive(Clone, Debug, Default)]
+pub struct RemoteCredentials {
+    pub password: Option<String>,
+    /// Accounts of the API sources
+    pub config: RemotesConfig,
+}
+
+#[derive(Debug, Default)]
+pub struct StagingStats {
+    pub downloaded: usize,
+    pub unchanged: usize,
+    /// Staged files no longer on the remote source
+    pub removed: usize,
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+/// Mirror the images of `source` into `staging_dir`, returning the staged directory as a mounted
+/// partition the sync can read. `on_download` is called before each download.
+pub fn stage_remote_source(
+    source: &dyn RemoteSource,
+    staging_dir: &Path,
+    mut on_download: impl FnMut(&RemoteFile),
+) -> anyhow::Result<(MountedPartitionInfo, StagingStats)> {
+    fs::create_dir_all(staging_dir)?;
+    let mut stats = StagingStats::default();
+    let files = source.list()?
+        .into_iter()
+        .filter(|file| is_supported_image(&file.path))
+        .collect::<Vec<_>>();
+
+    // Staged copy of each content, linked instead of downloaded again by the files sharing it
+    let mut staged_contents: HashMap<&str, PathBuf> = HashMap::new();
+    for file in &files {
+        // Names come from the remote side, they must not lead out of the staging directory
+        if !file.path.components().all(|component| matches!(component, Component::Normal(_))) {
+            stats.errors.push((file.path.clone(), String::from("Invalid remote path")));
+            continue;
+        }
+        let local_path = staging_dir.join(&file.path);
+        if is_staged(&local_path, file) {
+            stats.unchanged += 1;
+        } else if let Some(staged) = file.content_id.as_deref().and_then(|content_id| staged_contents.get(content_id)) {
+            match link_staged(staged, &local_path) {
+                Ok(()) => stats.downloaded += 1,
+                Err(err) => stats.errors.push((file.path.clone(), err.to_string())),
+            }
+        } else {
+            on_download(file);
+            match download_file(source, file, &local_path) {
+                Ok(()) => stats.downloaded += 1,
+                Err(err) => {
+                    stats.errors.push((file.path.clone(), err.to_string()));
+                    continue;
+                }
+            }
+        }
+        if let Some(content_id) = file.content_id.as_deref() {
+            staged_contents.entry(content_id).or_insert(local_path);
+        }
+    }
+
+    // Files removed from the source are removed from the stage too, so that syncs see them deleted
+    if !source.partial_listing() {
+        let remote_paths = files.iter().map(|file| staging_dir.join(&file.path)).collect::<HashSet<_>>();
+        remove_unlisted(staging_dir, &remote_paths, &mut stats);
+    }
+
+    let partition = MountedPartitionInfo {
+        mount_point: staging_dir.to_path_buf(),
+        fs_type: String::from(source.kind()),
+        info: PartitionInfo {
+            device_path: PathBuf::from(source.location()),
+            partition_id: source.source_id(),
+        },
+        details: PartitionDetails::default(),
+        fingerprint: None,
+    };
+    Ok((partition, stats))
+}
+
+/// Directory name of a source below the staging root
+pub fn staging_dir_name(source_id: &str) -> String {
+    source_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' }).collect()
+}
+
+fn is_staged(local_path: &Path, file: &RemoteFile) -> bool {
+    let Ok(metadata) = fs::metadata(local_path) else {
+        return false;
+    };
+    file.size.is_none_or(|size| metadata.len() == size) && file.modified.is_some_and(|modified| metadata.modified().is_ok_and(|local| local == modified))
+}
+
+fn download_file(source: &dyn RemoteSource, file: &RemoteFile, local_path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Interrupted downloads never leave a truncated image behind
+    let partial_path = local_path.with_extension("part");
+    let mut out = fs::File::create(&partial_path)?;
+    source.download(file, &mut out)?;
+    if let Some(modified) = file.modified {
+        out.set_modified(modified)?;
+    }
+    drop(out);
+    fs::rename(partial_path, local_path)?;
+    Ok(())
+}
+
+fn link_staged(staged: &Path, local_path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if local_path.exists() {
+        fs::remove_file(local_path)?;
+    }
+    if fs::hard_link(staged, local_path).is_err() {
+        fs::copy(staged, local_path)?;
+        fs::File::options().write(true).open(local_path)?.set_modified(fs::metadata(staged)?.modified()?)?;
+    }
+    Ok(())
+}
+
+fn remove_unlisted(dir: &Path, remote_paths: &HashSet<PathBuf>, stats: &mut StagingStats) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            stats.errors.push((dir.to_path_buf(), format!("Error reading staged dir - {err}")));
+            return;
+        }
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            remove_unlisted(&path, remote_paths, stats);
+        } else if !remote_paths.contains(&path) {
+            match fs::remove_file(&path) {
+                Ok(()) => stats.removed += 1,
+                Err(err) => stats.errors.push((path, format!("Error removing staged file - {err}"))),
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+use crate::archive::remote::api::{api_error, api_source_id, get_json, parse_account_url, AccessTokens};
+use crate::archive::remote::config::ApiAccount;
+use crate::archive::remote::{RemoteFile, RemoteSource};
+
+const TOKEN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
+const SCOPE: &str = "Files.Read offline_access";
+const API_URL: &str = "https://graph.microsoft.com/v1.0/me/drive";
+
+/// Folder of a OneDrive account, addressed as `onedrive://<account>/<folder>`
+pub struct OneDriveSource {
+    account_name: String,
+    folder: Vec<String>,
+    agent: ureq::Agent,
+    tokens: AccessTokens,
+}
+
+#[derive(Deserialize)]
+struct DriveItem {
+    id: String,
+    name: String,
+    size: Option<u64>,
+    folder: Option<serde_json::Value>,
+    file: Option<serde_json::Value>,
+    #[serde(rename = "fileSystemInfo")]
+    file_system_info: Option<FileSystemInfo>,
+}
+
+#[derive(Deserialize)]
+struct FileSystemInfo {
+    /// Modification time reported by the device which uploaded the file
+    #[serde(rename = "lastModifiedDateTime")]
+    last_modified: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChildrenPage {
+    #[serde(default)]
+    value: Vec<DriveItem>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+impl OneDriveSource {
+    pub fn new(url: &str, accounts: &BTreeMap<String, ApiAccount>) -> anyhow::Result<Self> {
+        let (account_name, folder) = parse_account_url(url)?;
+        let account = accounts.get(&account_name)
+            .ok_or_else(|| anyhow::anyhow!("OneDrive account '{account_name}' is not configured, add its tokens to [onedrive.{account_name}] of the remotes configuration"))?;
+        Ok(Self {
+            tokens: AccessTokens::of_account(account, TOKEN_URL, vec![("scope", String::from(SCOPE))])?,
+            account_name,
+            folder,
+            agent: ureq::AgentBuilder::new().build(),
+        })
+    }
+
+    fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
+        get_json(&self.agent, url, &self.tokens.get(&self.agent)?)
+    }
+
+    /// Item of the source folder
+    fn root_item(&self) -> anyhow::Result<DriveItem> {
+        if self.folder.is_empty() {
+            return self.get_json(&format!("{API_URL}/root"));
+        }
+        let path = self.folder.iter()
+            .map(|segment| utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.get_json(&format!("{API_URL}/root:/{path}"))
+    }
+
+    fn children(&self, item_id: &str) -> anyhow::Result<Vec<DriveItem>> {
+        let mut children = Vec::new();
+        let mut next_url = Some(format!("{API_URL}/items/{item_id}/children?$top=1000"));
+        while let Some(url) = next_url {
+            let page: ChildrenPage = self.get_json(&url)?;
+            children.extend(page.value);
+            next_url = page.next_link;
+        }
+        Ok(children)
+    }
+}
+
+impl RemoteSource for OneDriveSource {
+    fn source_id(&self) -> String {
+        api_source_id("onedrive", &self.account_name, &self.folder)
+    }
+
+    fn kind(&self) -> &'static str {
+        "onedrive"
+    }
+
+    fn location(&self) -> String {
+        let folder = self.folder.iter().map(|segment| format!("/{segment}")).collect::<String>();
+        format!("onedrive://{}{folder}", self.account_name)
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<RemoteFile>> {
+        let root = self.root_item()?;
+        if root.folder.is_none() {
+            anyhow::bail!("{} is not a folder", self.location());
+        }
+
+        let mut files = Vec::new();
+        let mut pending = VecDeque::from([(root.id, PathBuf::new())]);
+        while let Some((folder_id, folder_path)) = pending.pop_front() {
+            for item in self.children(&folder_id)? {
+                let path = folder_path.join(&item.name);
+                if item.folder.is_some() {
+                    pending.push_back((item.id, path));
+                } else if item.file.is_some() {
+                    files.push(RemoteFile {
+                        path,
+                        size: item.size,
+                        modified: item.file_system_info
+                            .and_then(|info| info.last_modified)
+                            .and_then(|modified| DateTime::parse_from_rfc3339(&modified).ok())
+                            .map(SystemTime::from),
+                        content_id: Some(item.id),
+                    });
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn download(&self, file: &RemoteFile, out: &mut dyn Write) -> anyhow::Result<()> {
+        let id = file.content_id.as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Missing drive item id of {}", file.path.display()))?;
+        // The content is served by a redirect to a pre-authenticated url
+        let response = self.agent.get(&format!("{API_URL}/items/{id}/content"))
+            .set("Authorization", &format!("Bearer {}", self.tokens.get(&self.agent)?))
+            .call()
+            .map_err(|err| api_error(&format!("Error downloading {}", file.path.display()), err))?;
+        std::io::copy(&mut response.into_reader(), out)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use serde::Deserialize;
+
+use crate::archive::remote::api::api_source_id;
+use crate::archive::remote::{RemoteFile, RemoteSource};
+
+/// Path of an rclone remote, addressed as `rclone://<remote>:<path>`.
+///
+/// Listing and downloads shell out to the `rclone` command, the remote being one of its
+/// configuration (`rclone config`): any storage backend of rclone can then be a source.
+pub struct RcloneSource {
+    remote: String,
+    path: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsEntry {
+    path: String,
+    /// -1 when the backend does not know it
+    size: i64,
+    mod_time: Option<String>,
+    #[serde(rename = "ID")]
+    id: Option<String>,
+}
+
+impl RcloneSource {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let target = url.strip_prefix("rclone://").unwrap_or(url);
+        let (remote, path) = target.split_once(':')
+            .filter(|(remote, _)| !remote.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Invalid rclone url {url}, expected rclone://<remote>:<path>"))?;
+        Ok(Self {
+            remote: String::from(remote),
+            path: String::from(path.trim_end_matches('/')),
+        })
+    }
+
+    /// Source path as understood by rclone
+    fn target(&self) -> String {
+        format!("{}:{}", self.remote, self.path)
+    }
+
+    fn file_target(&self, file: &RemoteFile) -> String {
+        let relative = file.path.iter().map(|segment| segment.to_string_lossy()).collect::<Vec<_>>().join("/");
+        match self.path.as_str() {
+            "" => format!("{}:{relative}", self.remote),
+            path => format!("{}:{path}/{relative}", self.remote),
+        }
+    }
+}
+
+fn rclone_error(args: &[&str], stderr: &[u8]) -> anyhow::Error {
+    anyhow::anyhow!("rclone {} failed - {}", args.join(" "), String::from_utf8_lossy(stderr).trim())
+}
+
+impl RemoteSource for RcloneSource {
+    fn source_id(&self) -> String {
+        let folder = self.path.split('/').filter(|segment| !segment.is_empty()).map(String::from).collect::<Vec<_>>();
+        api_source_id("rclone", &self.remote, &folder)
+    }
+
+    fn kind(&self) -> &'static str {
+        "rclone"
+    }
+
+    fn location(&self) -> String {
+        format!("rclone://{}", self.target())
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<RemoteFile>> {
+        let target = self.target();
+        let args = ["lsjson", "--recursive", "--files-only", "--no-mimetype", target.as_str()];
+        let output = Command::new("rclone")
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| anyhow::anyhow!("Error running rclone - {err}"))?;
+        if !output.status.success() {
+            return Err(rclone_error(&args, &output.stderr));
+        }
+
+        let entries: Vec<LsEntry> = serde_json::from_slice(&output.stdout)?;
+        Ok(entries.into_iter()
+            .map(|entry| RemoteFile {
+                path: entry.path.split('/').collect::<PathBuf>(),
+                size: u64::try_from(entry.size).ok(),
+                modified: entry.mod_time
+                    .and_then(|modified| DateTime::parse_from_rfc3339(&modified).ok())
+                    .map(SystemTime::from),
+                content_id: entry.id,
+            })
+            .collect())
+    }
+
+    fn download(&self, file: &RemoteFile, out: &mut dyn Write) -> anyhow::Result<()> {
+        let file_target = self.file_target(file);
+        let args = ["cat", file_target.as_str()];
+        let mut child = Command::new("rclone")
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow::anyhow!("Error running rclone - {err}"))?;
+        let mut stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("Missing rclone stdout"))?;
+        let copied = std::io::copy(&mut stdout, out);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(rclone_error(&args, &output.stderr));
+        }
+        copied?;
+        Ok(())
+    }
+}
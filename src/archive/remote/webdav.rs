@@ -0,0 +1,182 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::DateTime;
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::archive::remote::{RemoteFile, RemoteSource};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getcontentlength/><d:getlastmodified/></d:prop></d:propfind>"#;
+const DAV_NAMESPACE: &str = "DAV:";
+
+/// Folder of a WebDAV server (Nextcloud, ownCloud, Apache mod_dav, ...), addressed as
+/// `dav://host/path` or, over TLS, `davs://host/path`
+pub struct WebDavSource {
+    /// Http url of the folder, ending with a slash and without credentials
+    root: Url,
+    username: Option<String>,
+    password: Option<String>,
+    agent: ureq::Agent,
+}
+
+struct DavEntry {
+    url: Url,
+    collection: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl WebDavSource {
+    /// Credentials can be part of the url (`davs://user@host/path`), an explicit password
+    /// replaces the one of the url
+    pub fn new(url: &str, password: Option<String>) -> anyhow::Result<Self> {
+        let (scheme, rest) = url.split_once("://")
+            .ok_or_else(|| anyhow::anyhow!("Invalid WebDAV url {url}"))?;
+        let http_scheme = match scheme {
+            "dav" => "http",
+            "davs" => "https",
+            other => anyhow::bail!("Unsupported WebDAV url scheme '{other}', expected dav or davs"),
+        };
+        let mut root = Url::parse(&format!("{http_scheme}://{rest}"))?;
+        let decode = |value: &str| percent_decode_str(value).decode_utf8_lossy().into_owned();
+        let username = Some(decode(root.username())).filter(|username| !username.is_empty());
+        let password = password.or_else(|| root.password().map(decode));
+        root.set_username("").map_err(|_| anyhow::anyhow!("Invalid WebDAV url {url}"))?;
+        root.set_password(None).map_err(|_| anyhow::anyhow!("Invalid WebDAV url {url}"))?;
+        if !root.path().ends_with('/') {
+            root.set_path(&format!("{}/", root.path()));
+        }
+
+        Ok(Self {
+            root,
+            username,
+            password,
+            agent: ureq::AgentBuilder::new().build(),
+        })
+    }
+
+    fn request(&self, method: &str, url: &Url) -> ureq::Request {
+        let request = self.agent.request_url(method, url);
+        match &self.username {
+            Some(username) => {
+                let credentials = format!("{username}:{}", self.password.as_deref().unwrap_or_default());
+                request.set("Authorization", &format!("Basic {}", STANDARD.encode(credentials)))
+            }
+            None => request,
+        }
+    }
+
+    /// Entries of the collection at `url`, the collection itself excluded
+    fn propfind(&self, url: &Url) -> anyhow::Result<Vec<DavEntry>> {
+        let response = self.request("PROPFIND", url)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY)
+            .map_err(|err| anyhow::anyhow!("Error listing {url} - {err}"))?;
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body)?;
+
+        let document = roxmltree::Document::parse(&body)?;
+        let dav_element = |node: &roxmltree::Node, name: &str| node.tag_name().namespace() == Some(DAV_NAMESPACE) && node.tag_name().name() == name;
+        let entries = document.descendants()
+            .filter(|node| dav_element(node, "response"))
+            .filter_map(|response| {
+                let href = response.descendants().find(|node| dav_element(node, "href"))?.text()?;
+                let entry_url = url.join(href.trim()).ok()?;
+                let prop_text = |name: &str| response.descendants().find(|node| dav_element(node, name)).and_then(|node| node.text());
+                Some(DavEntry {
+                    collection: response.descendants().any(|node| dav_element(&node, "collection")),
+                    size: prop_text("getcontentlength").and_then(|size| size.trim().parse().ok()).unwrap_or(0),
+                    modified: prop_text("getlastmodified")
+                        .and_then(|modified| DateTime::parse_from_rfc2822(modified.trim()).ok())
+                        .map(SystemTime::from),
+                    url: entry_url,
+                })
+            })
+            .filter(|entry| entry.url.path().trim_end_matches('/') != url.path().trim_end_matches('/'))
+            .collect();
+        Ok(entries)
+    }
+
+    /// Whether `url` is on the server of the source and below its root. Hrefs come from the
+    /// server, following others would send the credentials elsewhere or crawl the whole server.
+    fn within_root(&self, url: &Url) -> bool {
+        url.scheme() == self.root.scheme()
+            && url.host_str() == self.root.host_str()
+            && url.port_or_known_default() == self.root.port_or_known_default()
+            && url.path().starts_with(self.root.path())
+    }
+
+    /// Path of `url` relative to the source root, decoded. Segments decoding to a separator,
+    /// `.` or `..` are refused, they would lead out of the staging directory.
+    fn relative_path(&self, url: &Url) -> Option<PathBuf> {
+        let relative = url.path().strip_prefix(self.root.path())?;
+        relative.split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+            .map(|segment| Some(segment).filter(|segment| segment != "." && segment != ".." && !segment.contains(['/', '\\'])))
+            .collect()
+    }
+
+    fn file_url(&self, path: &Path) -> Url {
+        let mut url = self.root.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().extend(path.iter().map(|segment| segment.to_string_lossy()));
+        }
+        url
+    }
+}
+
+impl RemoteSource for WebDavSource {
+    fn source_id(&self) -> String {
+        let location = format!("{}{}", self.root.host_str().unwrap_or_default(), self.root.path().trim_end_matches('/'));
+        let location = location.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect::<String>();
+        format!("dav-{location}")
+    }
+
+    fn kind(&self) -> &'static str {
+        "webdav"
+    }
+
+    fn location(&self) -> String {
+        self.root.to_string()
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<RemoteFile>> {
+        let mut files = Vec::new();
+        let mut pending = VecDeque::from([self.root.clone()]);
+        let mut visited = HashSet::from([String::from(self.root.path())]);
+        // Servers often refuse `Depth: infinity`, collections are listed one level at a time
+        while let Some(collection) = pending.pop_front() {
+            for entry in self.propfind(&collection)?.into_iter().filter(|entry| self.within_root(&entry.url)) {
+                if entry.collection {
+                    let mut url = entry.url;
+                    if !url.path().ends_with('/') {
+                        url.set_path(&format!("{}/", url.path()));
+                    }
+                    if visited.insert(String::from(url.path())) {
+                        pending.push_back(url);
+                    }
+                } else if let Some(path) = self.relative_path(&entry.url) {
+                    files.push(RemoteFile { path, size: Some(entry.size), modified: entry.modified, content_id: None });
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn download(&self, file: &RemoteFile, out: &mut dyn Write) -> anyhow::Result<()> {
+        let url = self.file_url(&file.path);
+        let response = self.request("GET", &url)
+            .call()
+            .map_err(|err| anyhow::anyhow!("Error downloading {url} - {err}"))?;
+        std::io::copy(&mut response.into_reader(), out)?;
+        Ok(())
+    }
+}
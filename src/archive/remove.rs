@@ -1,68 +1,250 @@
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-use crate::archive::common::{build_filename, build_paths};
+use crate::archive::common::{build_record_paths, ArchivedPhotoPaths};
+use crate::archive::digest::Digest;
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::layout::ArchiveLayout;
+use crate::archive::link::ArchiveLinker;
+use crate::archive::manifest::ArchiveManifest;
 use crate::archive::records_store::{PhotoArchiveJsonRow, PhotoArchiveRecordsStore};
-use crate::archive::sync::CASTAGNOLI;
+use crate::archive::sync::{find_mount_info, find_registered_source, SourceCoordinates};
+use crate::repository::sources::{SourceStats, SourcesRepo};
 
-pub fn remove_by_source(target: PathBuf, source: &str) -> anyhow::Result<()> {
-    retain_images(target, |row| row.source_id().ne(source))
+pub fn remove_by_source(target: PathBuf, source: &str, secret: Option<&ArchiveSecret>) -> anyhow::Result<()> {
+    let manifest = ArchiveManifest::load_or_default(&target)?;
+    let store = PhotoArchiveRecordsStore::with_cipher(&target, manifest.cipher(secret)?);
+    // Only the index files holding rows of the source are rewritten
+    let shards = store.source_shards(source)?;
+    retain_records(&target, &manifest.layout, &store, &manifest.linker(&target)?, Some(&shards), |row| row.source_id().ne(source))?;
+
+    // The source stays registered, with nothing archived anymore
+    let repo = SourcesRepo::new(target);
+    if let Some(mut entry) = repo.find_by_id(source)? {
+        entry.stats = Some(SourceStats::default());
+        repo.update_entry(&entry)?;
+    }
+    Ok(())
+}
+
+pub fn retain_images(target: PathBuf, secret: Option<&ArchiveSecret>, condition: impl FnMut(&PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
+    let manifest = ArchiveManifest::load_or_default(&target)?;
+    let store = PhotoArchiveRecordsStore::with_cipher(&target, manifest.cipher(secret)?);
+    retain_records(&target, &manifest.layout, &store, &manifest.linker(&target)?, None, condition)
+}
+
+/// What dropping index rows takes away from the archive
+#[derive(Clone, Debug, Default)]
+pub struct RemovalSummary {
+    /// Index rows dropped
+    pub records: usize,
+    /// Thumbnails and originals no retained row references anymore
+    pub files: usize,
+    /// Size of those files
+    pub bytes: u64,
+    pub links: usize,
+}
+
+/// Files of a source which a sync with `prune` drops from the archive
+#[derive(Clone, Debug, Default)]
+pub struct PrunePreview {
+    /// Source files of the records no longer found in the source
+    pub vanished: Vec<PathBuf>,
+    pub removal: RemovalSummary,
 }
 
-pub fn retain_images(target: PathBuf, mut condition: impl FnMut(&PhotoArchiveJsonRow) -> bool) -> anyhow::Result<()> {
-    let store = PhotoArchiveRecordsStore::new(&target);
+/// What [`remove_by_source`] would remove, the archive is left untouched
+pub fn preview_remove_by_source(target: &Path, source: &str, secret: Option<&ArchiveSecret>) -> anyhow::Result<RemovalSummary> {
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let store = PhotoArchiveRecordsStore::with_cipher(target, manifest.cipher(secret)?);
+    let shards = store.source_shards(source)?;
+    preview_records(target, &manifest.layout, &store, Some(&shards), |row| row.source_id().ne(source))
+}
 
-    let mut thumbnail_with_link = HashSet::new();
-    let mut thumbnail_to_remove = HashSet::new();
+/// What [`retain_images`] would remove, the archive is left untouched
+pub fn preview_retain_images(target: &Path, secret: Option<&ArchiveSecret>, condition: impl FnMut(&PhotoArchiveJsonRow) -> bool) -> anyhow::Result<RemovalSummary> {
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let store = PhotoArchiveRecordsStore::with_cipher(target, manifest.cipher(secret)?);
+    preview_records(target, &manifest.layout, &store, None, condition)
+}
 
-    store.retain(|row| {
+/// Records of the registered source a sync with `prune` would drop, looking at the files
+/// currently missing from the source. Files found moved by the sync are followed instead.
+pub fn preview_prune(target: &Path, coord: &SourceCoordinates, secret: Option<&ArchiveSecret>) -> anyhow::Result<PrunePreview> {
+    let mount_info = find_mount_info(coord)?;
+    let entry = find_registered_source(&SourcesRepo::new(target.to_path_buf()), &mount_info)?;
+    if !mount_info.mount_point.is_dir() {
+        anyhow::bail!("Source dir {:?} is not available", mount_info.mount_point);
+    }
+
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let store = PhotoArchiveRecordsStore::with_cipher(target, manifest.cipher(secret)?);
+    let shards = store.source_shards(&entry.id)?;
+    let mut vanished = Vec::new();
+    let removal = preview_records(target, &manifest.layout, &store, Some(&shards), |row| {
+        if row.source_id() != entry.id {
+            return true;
+        }
+        let source_file = mount_info.mount_point.join(row.source_path());
+        let exists = source_file.exists();
+        if !exists {
+            vanished.push(source_file);
+        }
+        exists
+    })?;
+    Ok(PrunePreview { vanished, removal })
+}
+
+/// Drop the index rows not matching `condition` together with their links, thumbnails and originals.
+///
+/// Only the rows of the given index shards are visited when `shards` is set.
+pub(crate) fn retain_records(
+    target: &Path,
+    layout: &ArchiveLayout,
+    store: &PhotoArchiveRecordsStore,
+    linker: &ArchiveLinker,
+    shards: Option<&BTreeSet<String>>,
+    mut condition: impl FnMut(&PhotoArchiveJsonRow) -> bool,
+) -> anyhow::Result<()> {
+    let mut plan = RemovalPlan::default();
+    store.retain_in(shards, |row| {
         let retain = condition(row);
+        plan.visit(target, layout, row, retain);
+        retain
+    })?;
+    plan.keep_shared_originals(store, shards)?;
+    plan.execute(linker);
+    Ok(())
+}
 
-        let photo_timestamp = row.timestamp();
-        let file_timestamp = row.file_timestamp();
+fn preview_records(
+    target: &Path,
+    layout: &ArchiveLayout,
+    store: &PhotoArchiveRecordsStore,
+    shards: Option<&BTreeSet<String>>,
+    mut condition: impl FnMut(&PhotoArchiveJsonRow) -> bool,
+) -> anyhow::Result<RemovalSummary> {
+    let mut plan = RemovalPlan::default();
+    let visit = |row: PhotoArchiveJsonRow| {
+        let retain = condition(&row);
+        plan.visit(target, layout, &row, retain);
+    };
+    match shards {
+        Some(shards) => store.for_each_in(shards, visit)?,
+        None => store.for_each(visit)?,
+    }
+    plan.keep_shared_originals(store, shards)?;
+    Ok(plan.summary())
+}
+
+/// Files and links of the dropped rows, collected while visiting the index
+#[derive(Default)]
+struct RemovalPlan {
+    records: usize,
+    // Links, thumbnails and originals can be shared by several rows (e.g. a superseded record and
+    // the one replacing it), they are removed once no retained row references them
+    files_in_use: HashSet<PathBuf>,
+    files_to_remove: HashSet<PathBuf>,
+    links_in_use: HashSet<PathBuf>,
+    links_to_remove: HashMap<PathBuf, ArchivedPhotoPaths>,
+    // Digests of the dropped originals, which may be shared with rows of the shards not visited
+    dropped_originals: HashMap<PathBuf, Digest>,
+}
 
-        let archive_paths = build_paths(
-            CASTAGNOLI.checksum(row.source_id().as_bytes()),
-            &target,
-            &row.source_path(),
-            photo_timestamp.as_ref(),
-        ).expect("Error building paths");
+impl RemovalPlan {
+    fn visit(&mut self, target: &Path, layout: &ArchiveLayout, row: &PhotoArchiveJsonRow, retain: bool) {
+        let archive_paths = build_record_paths(layout, target, row).expect("Error building paths");
 
-        let thumbnail_path = archive_paths.img_path.join(build_filename(
-            photo_timestamp.as_ref(),
-            file_timestamp,
-            row.digest(),
-        ).expect("Error building filename"));
+        // Rows of images indexed without thumbnail own no thumbnail nor link
+        let has_thumbnail = !row.no_thumbnail();
+        let thumbnail_path = has_thumbnail
+            .then(|| archive_paths.img_path.join(row.thumbnail_name(layout).expect("Error building filename")));
+        let row_files = thumbnail_path.into_iter()
+            .chain(row.original().map(|original| target.join(original)));
 
         if retain {
-            thumbnail_to_remove.remove(&thumbnail_path);
-            thumbnail_with_link.insert(thumbnail_path);
+            for file in row_files {
+                self.files_to_remove.remove(&file);
+                self.files_in_use.insert(file);
+            }
+            if has_thumbnail {
+                self.links_to_remove.remove(&archive_paths.link_file_path);
+                self.links_in_use.insert(archive_paths.link_file_path);
+            }
         } else {
-            if !thumbnail_with_link.contains(&thumbnail_path) {
-                thumbnail_to_remove.insert(thumbnail_path);
+            self.records += 1;
+            if let Some(original) = row.original() {
+                self.dropped_originals.insert(target.join(original), row.digest().clone());
+            }
+            for file in row_files {
+                if !self.files_in_use.contains(&file) {
+                    self.files_to_remove.insert(file);
+                }
             }
+            if has_thumbnail && !self.links_in_use.contains(&archive_paths.link_file_path) {
+                self.links_to_remove.insert(archive_paths.link_file_path.clone(), archive_paths);
+            }
+        }
+    }
+
+    /// Thumbnails and links are named after the date of the photo, only content-addressed
+    /// originals can be referenced from another shard
+    fn keep_shared_originals(&mut self, store: &PhotoArchiveRecordsStore, shards: Option<&BTreeSet<String>>) -> anyhow::Result<()> {
+        let Some(shards) = shards else {
+            return Ok(());
+        };
+        let digests = self.files_to_remove.iter()
+            .filter_map(|file| self.dropped_originals.get(file).cloned())
+            .collect::<HashSet<_>>();
+        if !digests.is_empty() {
+            let digests_shards = store.digests_shards(&digests)?;
+            self.files_to_remove.retain(|file| {
+                self.dropped_originals.get(file)
+                    .and_then(|digest| digests_shards.get(digest))
+                    .is_none_or(|digest_shards| digest_shards.is_subset(shards))
+            });
+        }
+        Ok(())
+    }
+
+    fn summary(&self) -> RemovalSummary {
+        let existing_files = self.files_to_remove.iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .collect::<Vec<_>>();
+        RemovalSummary {
+            records: self.records,
+            files: existing_files.len(),
+            bytes: existing_files.iter().map(|metadata| metadata.len()).sum(),
+            links: self.links_to_remove.len(),
+        }
+    }
 
-            if archive_paths.link_file_path.exists() {
-                std::fs::remove_file(archive_paths.link_file_path)
-                    .expect("Error removing symlink file");
+    fn execute(self, linker: &ArchiveLinker) {
+        for archive_paths in self.links_to_remove.into_values() {
+            if linker.link_exists(&archive_paths) {
+                linker.remove_link(&archive_paths)
+                    .expect("Error removing link file");
             }
 
-            if archive_paths.link_dir_path.exists() && archive_paths.link_dir_path.read_dir().expect("Error reading dir").next().is_none() {
-                std::fs::remove_dir(archive_paths.link_dir_path)
+            // Templated link directories can be nested, empty parents are dropped up to the date directory
+            let mut link_dir = Some(archive_paths.link_dir_path.as_path());
+            while let Some(dir) = link_dir.filter(|dir| *dir != archive_paths.date_path) {
+                if !dir.exists() || dir.read_dir().expect("Error reading dir").next().is_some() {
+                    break;
+                }
+                std::fs::remove_dir(dir)
                     .expect("Error removing symlink dir");
+                link_dir = dir.parent();
             }
         }
-        retain
-    })?;
 
-    for f in thumbnail_to_remove {
-        let remove_out = std::fs::remove_file(&f);
-        if let Err(err) = remove_out {
-            eprintln!("Error removing file {f:?} - {err}")
-        } else {
-            println!("Removed file {f:?}");
+        for f in self.files_to_remove {
+            let remove_out = std::fs::remove_file(&f);
+            if let Err(err) = remove_out {
+                eprintln!("Error removing file {f:?} - {err}")
+            } else {
+                println!("Removed file {f:?}");
+            }
         }
     }
-
-    Ok(())
-}
\ No newline at end of file
+}
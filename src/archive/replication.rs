@@ -0,0 +1,566 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::common::{build_record_paths, disambiguate_filename};
+use crate::archive::digest::{digest_file, Digest, DigestAlgorithm};
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::originals::{is_original_path, OriginalsMode};
+use crate::archive::records_store::{PhotoArchiveJsonRow, PhotoArchiveRecordsStore};
+use crate::repository::sources::{SourceJsonRow, SourcesRepo};
+
+/// Largest file exchanged with a replica, a corrupted length would otherwise be allocated at once
+const MAX_PAYLOAD: u64 = 1 << 30;
+
+/// Which way the photos flow between the local archive and the remote one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplicationDirection {
+    /// Copy the remote photos missing from the local archive
+    Pull,
+    /// Copy the local photos missing from the remote archive
+    Push,
+    /// Pull, then push
+    #[default]
+    Both,
+}
+
+impl Display for ReplicationDirection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplicationDirection::Pull => write!(f, "pull"),
+            ReplicationDirection::Push => write!(f, "push"),
+            ReplicationDirection::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl FromStr for ReplicationDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pull" => Ok(ReplicationDirection::Pull),
+            "push" => Ok(ReplicationDirection::Push),
+            "both" => Ok(ReplicationDirection::Both),
+            other => anyhow::bail!("Unknown replication direction '{other}', expected one of pull, push, both"),
+        }
+    }
+}
+
+/// Settings and sources of an archive taking part in a replication
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArchiveDescription {
+    /// Whether the archive holds anything, empty ones take the manifest of the other side
+    pub initialized: bool,
+    pub manifest: ArchiveManifest,
+    pub sources: Vec<SourceJsonRow>,
+}
+
+/// Archive taking part in a replication, opened locally or reached through a `replica-serve` process
+pub trait ReplicaEndpoint {
+    fn describe(&mut self) -> anyhow::Result<ArchiveDescription>;
+    /// Create the archive with the given manifest, it must not be initialized yet
+    fn init(&mut self, manifest: &ArchiveManifest) -> anyhow::Result<()>;
+    fn rows(&mut self) -> anyhow::Result<Vec<PhotoArchiveJsonRow>>;
+    /// BLAKE3 digest of a file, relative to the archive root, `None` if it does not exist
+    fn file_digest(&mut self, path: &Path) -> anyhow::Result<Option<Digest>>;
+    fn read_file(&mut self, path: &Path) -> anyhow::Result<Vec<u8>>;
+    fn add_source(&mut self, source: &SourceJsonRow) -> anyhow::Result<()>;
+    /// Add a row to the index and link it, `thumbnail` and `original` are the contents of the
+    /// files it references, missing when the archive already holds an identical copy
+    fn import(&mut self, row: PhotoArchiveJsonRow, thumbnail: Option<Vec<u8>>, original: Option<Vec<u8>>) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct ReplicationStats {
+    pub sources: usize,
+    pub rows: usize,
+    pub files: usize,
+    pub bytes: u64,
+    pub errors: usize,
+}
+
+/// Copy into `to` the index rows of `from` it misses, identified by source, path and digest,
+/// together with the thumbnails and originals whose identical copy is not there yet.
+///
+/// Rows are only added: tombstones and rows pruned or superseded on one side are not propagated.
+pub fn replicate(from: &mut dyn ReplicaEndpoint, to: &mut dyn ReplicaEndpoint) -> anyhow::Result<ReplicationStats> {
+    let from_description = from.describe()?;
+    let mut to_description = to.describe()?;
+    if !from_description.initialized {
+        anyhow::bail!("Archive to replicate is empty");
+    }
+    if !to_description.initialized {
+        to.init(&from_description.manifest)?;
+        to_description = to.describe()?;
+    }
+    check_compatible(&from_description.manifest, &to_description.manifest)?;
+
+    let mut stats = ReplicationStats::default();
+    for source in &from_description.sources {
+        if !to_description.sources.iter().any(|existing| existing.id == source.id) {
+            to.add_source(source)?;
+            stats.sources += 1;
+        }
+    }
+
+    let known_rows = to.rows()?.iter().map(row_key).collect::<HashSet<_>>();
+    let keep_originals = to_description.manifest.originals != OriginalsMode::None;
+    for row in from.rows()? {
+        if known_rows.contains(&row_key(&row)) {
+            continue;
+        }
+        let source_path = row.source_path();
+        match transfer_row(from, to, &from_description.manifest, keep_originals, row, &mut stats) {
+            Ok(()) => stats.rows += 1,
+            Err(err) => {
+                eprintln!("Error replicating {} - {err}", source_path.display());
+                stats.errors += 1;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+fn check_compatible(from: &ArchiveManifest, to: &ArchiveManifest) -> anyhow::Result<()> {
+    if from.digest != to.digest {
+        anyhow::bail!("Archives use different digest algorithms ({} and {})", from.digest, to.digest);
+    }
+    if from.layout != to.layout {
+        anyhow::bail!("Archives use different layouts ({} and {})", from.layout, to.layout);
+    }
+    if from.link_dirs != to.link_dirs {
+        anyhow::bail!("Archives use different link directory namings ({} and {})", from.link_dirs, to.link_dirs);
+    }
+    Ok(())
+}
+
+fn row_key(row: &PhotoArchiveJsonRow) -> (String, PathBuf, Digest) {
+    (String::from(row.source_id()), row.source_path(), row.digest().clone())
+}
+
+fn transfer_row(
+    from: &mut dyn ReplicaEndpoint,
+    to: &mut dyn ReplicaEndpoint,
+    manifest: &ArchiveManifest,
+    keep_originals: bool,
+    mut row: PhotoArchiveJsonRow,
+    stats: &mut ReplicationStats,
+) -> anyhow::Result<()> {
+    let thumbnail = if row.no_thumbnail() {
+        None
+    } else {
+        let paths = build_record_paths(&manifest.layout, Path::new(""), &row)?;
+        transfer_file(from, to, &paths.img_path.join(row.thumbnail_name(&manifest.layout)?), stats)?
+    };
+    let original = match row.original().map(Path::to_path_buf) {
+        Some(original) if keep_originals => transfer_file(from, to, &original, stats)?,
+        _ => {
+            row.set_original(None);
+            None
+        }
+    };
+    to.import(row, thumbnail, original)
+}
+
+/// Content of the file when `to` has no identical copy of it
+fn transfer_file(from: &mut dyn ReplicaEndpoint, to: &mut dyn ReplicaEndpoint, path: &Path, stats: &mut ReplicationStats) -> anyhow::Result<Option<Vec<u8>>> {
+    let digest = from.file_digest(path)?
+        .ok_or_else(|| anyhow::anyhow!("File {} is missing", path.display()))?;
+    if to.file_digest(path)?.is_some_and(|existing| existing == digest) {
+        return Ok(None);
+    }
+    let content = from.read_file(path)?;
+    stats.files += 1;
+    stats.bytes += content.len() as u64;
+    Ok(Some(content))
+}
+
+/// Archive on the local filesystem
+pub struct LocalReplica {
+    target: PathBuf,
+    manifest: ArchiveManifest,
+    /// Sources whose stored totals were already dropped, replicated rows make them outdated
+    outdated_stats: HashSet<String>,
+}
+
+impl LocalReplica {
+    pub fn open(target: &Path) -> anyhow::Result<Self> {
+        let manifest = ArchiveManifest::load_or_default(target)?;
+        if manifest.encryption.is_some() {
+            anyhow::bail!("Encrypted archives cannot be replicated");
+        }
+        Ok(Self {
+            target: target.to_path_buf(),
+            manifest,
+            outdated_stats: HashSet::new(),
+        })
+    }
+
+    /// Drop the stored totals of the source, the next sync or `stats` computes them from the index
+    fn invalidate_stats(&mut self, source_id: &str) -> anyhow::Result<()> {
+        if !self.outdated_stats.insert(String::from(source_id)) {
+            return Ok(());
+        }
+        let repo = SourcesRepo::new(self.target.clone());
+        if let Some(mut entry) = repo.find_by_id(source_id)?.filter(|entry| entry.stats.is_some()) {
+            entry.stats = None;
+            repo.update_entry(&entry)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve a path received from the other side, which must stay inside the archive
+    fn archive_path(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        check_relative_path(path)?;
+        Ok(self.target.join(path))
+    }
+
+    /// Name of the thumbnail in `img_path`, disambiguated if another photo already owns `file_name`
+    fn store_thumbnail(&self, img_path: &Path, file_name: &str, content: &[u8]) -> anyhow::Result<String> {
+        let digest = Digest::Hex(blake3::hash(content).to_hex().to_uppercase());
+        let mut seq = 0;
+        loop {
+            let candidate = disambiguate_filename(file_name, seq);
+            let candidate_path = img_path.join(&candidate);
+            if !candidate_path.exists() {
+                fs::create_dir_all(img_path)?;
+                write_file(&candidate_path, content)?;
+                return Ok(candidate);
+            }
+            if digest_file(&candidate_path, DigestAlgorithm::Blake3)? == digest {
+                return Ok(candidate);
+            }
+            seq += 1;
+        }
+    }
+}
+
+impl ReplicaEndpoint for LocalReplica {
+    fn describe(&mut self) -> anyhow::Result<ArchiveDescription> {
+        let sources = SourcesRepo::new(self.target.clone());
+        Ok(ArchiveDescription {
+            initialized: ArchiveManifest::load(&self.target)?.is_some() || sources.exists(),
+            manifest: self.manifest.clone(),
+            sources: sources.all()?,
+        })
+    }
+
+    fn init(&mut self, manifest: &ArchiveManifest) -> anyhow::Result<()> {
+        if self.describe()?.initialized {
+            anyhow::bail!("Archive is already initialized");
+        }
+        if manifest.encryption.is_some() {
+            anyhow::bail!("Encrypted archives cannot be replicated");
+        }
+        fs::create_dir_all(&self.target)?;
+        manifest.store(&self.target)?;
+        self.manifest = manifest.clone();
+        Ok(())
+    }
+
+    fn rows(&mut self) -> anyhow::Result<Vec<PhotoArchiveJsonRow>> {
+        let mut rows = Vec::new();
+        if self.target.is_dir() {
+            PhotoArchiveRecordsStore::new(&self.target).for_each(|row| rows.push(row))?;
+        }
+        Ok(rows)
+    }
+
+    fn file_digest(&mut self, path: &Path) -> anyhow::Result<Option<Digest>> {
+        let path = self.archive_path(path)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(digest_file(&path, DigestAlgorithm::Blake3)?))
+    }
+
+    fn read_file(&mut self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        Ok(fs::read(self.archive_path(path)?)?)
+    }
+
+    fn add_source(&mut self, source: &SourceJsonRow) -> anyhow::Result<()> {
+        // Source ids name the files of the bloom, scans, dir times and snapshots directories
+        let safe_id = source.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if source.id.is_empty() || source.id == "." || source.id == ".." || !safe_id {
+            anyhow::bail!("Invalid source id {:?}", source.id);
+        }
+        SourcesRepo::new(self.target.clone()).write_entry(SourceJsonRow {
+            stats: None,
+            ..source.clone()
+        })
+    }
+
+    fn import(&mut self, mut row: PhotoArchiveJsonRow, thumbnail: Option<Vec<u8>>, original: Option<Vec<u8>>) -> anyhow::Result<()> {
+        // Link and thumbnail paths are built from these, they must not lead out of the archive
+        check_relative_path(&row.source_path())?;
+        if let Some(link_dir) = row.link_dir() {
+            check_relative_path(link_dir)?;
+        }
+        if let Some(name) = row.stored_thumbnail_name() {
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                anyhow::bail!("Invalid thumbnail name {name:?}");
+            }
+        }
+        if let Some(original_path) = row.original() {
+            // Index, manifest and sources files are out of reach of the other side
+            if !is_original_path(original_path) {
+                anyhow::bail!("Invalid original path {}", original_path.display());
+            }
+            if let Some(content) = original {
+                let original_path = self.archive_path(original_path)?;
+                if !original_path.exists() {
+                    if let Some(parent) = original_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    write_file(&original_path, &content)?;
+                } else if digest_file(&original_path, DigestAlgorithm::Blake3)? != Digest::Hex(blake3::hash(&content).to_hex().to_uppercase()) {
+                    anyhow::bail!("Original {} already exists with another content", original_path.display());
+                }
+            }
+        }
+
+        if !row.no_thumbnail() {
+            let layout = &self.manifest.layout;
+            let paths = build_record_paths(layout, &self.target, &row)?;
+            let mut thumbnail_name = row.thumbnail_name(layout)?;
+            if let Some(content) = thumbnail {
+                let stored_name = self.store_thumbnail(&paths.img_path, &thumbnail_name, &content)?;
+                if stored_name != thumbnail_name {
+                    row.set_thumbnail_name(stored_name.clone());
+                    thumbnail_name = stored_name;
+                }
+            }
+
+            let linker = self.manifest.linker(&self.target)?;
+            if !linker.link_exists(&paths) {
+                fs::create_dir_all(&paths.link_dir_path)?;
+                linker.create_link(&paths, &thumbnail_name)?;
+            }
+        }
+
+        self.invalidate_stats(row.source_id())?;
+        PhotoArchiveRecordsStore::new(&self.target)
+            .with_sharding(self.manifest.index_sharding)
+            .append(&row)
+    }
+}
+
+/// Paths received from the other side are relative and only made of plain names, no `..`
+fn check_relative_path(path: &Path) -> anyhow::Result<()> {
+    if path.as_os_str().is_empty() || !path.components().all(|component| matches!(component, Component::Normal(_))) {
+        anyhow::bail!("Invalid archive path {}", path.display());
+    }
+    Ok(())
+}
+
+/// Written next to the destination then renamed, so that an interrupted transfer leaves no truncated file
+fn write_file(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let temp_path = path.with_extension("replica-tmp");
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Requests of the replication protocol, one JSON line each followed by the raw file contents they announce
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+enum ReplicaRequest {
+    Describe,
+    Init { manifest: ArchiveManifest },
+    Rows,
+    FileDigest { path: PathBuf },
+    ReadFile { path: PathBuf },
+    AddSource { source: SourceJsonRow },
+    Import { row: Box<PhotoArchiveJsonRow>, thumbnail: Option<u64>, original: Option<u64> },
+}
+
+/// Response line, `data` is the length of the raw bytes following it
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ReplicaResponse {
+    Ok { value: serde_json::Value, data: Option<u64> },
+    Error { cause: String },
+}
+
+/// Archive reached through a `replica-serve` process, usually started on another host through ssh
+pub struct RemoteReplica {
+    child: Child,
+    reader: BufReader<ChildStdout>,
+    /// Taken on drop, closing stdin ends the serve loop
+    writer: Option<BufWriter<ChildStdin>>,
+}
+
+impl RemoteReplica {
+    /// Run `remote_command replica-serve` on `host` with the system ssh client
+    pub fn connect_ssh(host: &str, remote_command: &str, remote_path: &Path) -> anyhow::Result<Self> {
+        let remote_path = remote_path.to_str()
+            .ok_or_else(|| anyhow::anyhow!("Remote path is not valid UTF-8"))?;
+        let mut command = Command::new("ssh");
+        command
+            .arg(host)
+            .arg("--")
+            .arg(format!("{remote_command} replica-serve -t '{}'", remote_path.replace('\'', r"'\''")));
+        Self::spawn(command)
+    }
+
+    /// Speak the replication protocol with the `replica-serve` process started by `command`
+    pub fn spawn(mut command: Command) -> anyhow::Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let reader = BufReader::new(child.stdout.take().ok_or_else(|| anyhow::anyhow!("Missing replica stdout"))?);
+        let writer = BufWriter::new(child.stdin.take().ok_or_else(|| anyhow::anyhow!("Missing replica stdin"))?);
+        Ok(Self {
+            child,
+            reader,
+            writer: Some(writer),
+        })
+    }
+
+    fn call<T: DeserializeOwned>(&mut self, request: &ReplicaRequest, payloads: &[&[u8]]) -> anyhow::Result<(T, Vec<u8>)> {
+        let writer = self.writer.as_mut().ok_or_else(|| anyhow::anyhow!("Replica connection is closed"))?;
+        serde_json::to_writer(&mut *writer, request)?;
+        writer.write_all(b"\n")?;
+        for payload in payloads {
+            writer.write_all(payload)?;
+        }
+        writer.flush()?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            anyhow::bail!("Replica closed the connection");
+        }
+        match serde_json::from_str(&line)? {
+            ReplicaResponse::Ok { value, data } => {
+                let content = read_payload(&mut self.reader, data.unwrap_or(0))?;
+                Ok((serde_json::from_value(value)?, content))
+            }
+            ReplicaResponse::Error { cause } => anyhow::bail!("Replica error - {cause}"),
+        }
+    }
+}
+
+impl Drop for RemoteReplica {
+    fn drop(&mut self) {
+        drop(self.writer.take());
+        if let Err(err) = self.child.wait() {
+            eprintln!("Error waiting for replica process - {err}");
+        }
+    }
+}
+
+impl ReplicaEndpoint for RemoteReplica {
+    fn describe(&mut self) -> anyhow::Result<ArchiveDescription> {
+        Ok(self.call(&ReplicaRequest::Describe, &[])?.0)
+    }
+
+    fn init(&mut self, manifest: &ArchiveManifest) -> anyhow::Result<()> {
+        self.call::<()>(&ReplicaRequest::Init { manifest: manifest.clone() }, &[])?;
+        Ok(())
+    }
+
+    fn rows(&mut self) -> anyhow::Result<Vec<PhotoArchiveJsonRow>> {
+        Ok(self.call(&ReplicaRequest::Rows, &[])?.0)
+    }
+
+    fn file_digest(&mut self, path: &Path) -> anyhow::Result<Option<Digest>> {
+        Ok(self.call(&ReplicaRequest::FileDigest { path: path.to_path_buf() }, &[])?.0)
+    }
+
+    fn read_file(&mut self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let ((), content) = self.call(&ReplicaRequest::ReadFile { path: path.to_path_buf() }, &[])?;
+        Ok(content)
+    }
+
+    fn add_source(&mut self, source: &SourceJsonRow) -> anyhow::Result<()> {
+        self.call::<()>(&ReplicaRequest::AddSource { source: source.clone() }, &[])?;
+        Ok(())
+    }
+
+    fn import(&mut self, row: PhotoArchiveJsonRow, thumbnail: Option<Vec<u8>>, original: Option<Vec<u8>>) -> anyhow::Result<()> {
+        let request = ReplicaRequest::Import {
+            row: Box::new(row),
+            thumbnail: thumbnail.as_ref().map(|content| content.len() as u64),
+            original: original.as_ref().map(|content| content.len() as u64),
+        };
+        let payloads = [thumbnail.as_deref(), original.as_deref()].into_iter().flatten().collect::<Vec<_>>();
+        self.call::<()>(&request, &payloads)?;
+        Ok(())
+    }
+}
+
+/// Serve the replication protocol for the archive at `target` until `input` is closed
+pub fn serve_replica(target: &Path, input: impl Read, output: impl Write) -> anyhow::Result<()> {
+    let mut replica = LocalReplica::open(target).map_err(|err| err.to_string());
+    let mut reader = BufReader::new(input);
+    let mut writer = BufWriter::new(output);
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let request = serde_json::from_str::<ReplicaRequest>(&line)?;
+
+        // Payloads are read even when the request fails, to keep the stream in sync
+        let (thumbnail, original) = match &request {
+            ReplicaRequest::Import { thumbnail, original, .. } => (
+                thumbnail.map(|len| read_payload(&mut reader, len)).transpose()?,
+                original.map(|len| read_payload(&mut reader, len)).transpose()?,
+            ),
+            _ => (None, None),
+        };
+
+        let out = match &mut replica {
+            Ok(replica) => handle_request(replica, request, thumbnail, original),
+            Err(cause) => Err(anyhow::anyhow!("{cause}")),
+        };
+        let (response, content) = match out {
+            Ok((value, content)) => (ReplicaResponse::Ok { value, data: content.as_ref().map(|content| content.len() as u64) }, content),
+            Err(err) => (ReplicaResponse::Error { cause: err.to_string() }, None),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+        if let Some(content) = content {
+            writer.write_all(&content)?;
+        }
+        writer.flush()?;
+    }
+}
+
+fn read_payload(reader: &mut impl Read, len: u64) -> anyhow::Result<Vec<u8>> {
+    if len > MAX_PAYLOAD {
+        anyhow::bail!("Payload of {len} bytes exceeds the {MAX_PAYLOAD} bytes limit");
+    }
+    let mut content = vec![0; len as usize];
+    reader.read_exact(&mut content)?;
+    Ok(content)
+}
+
+fn handle_request(
+    replica: &mut LocalReplica,
+    request: ReplicaRequest,
+    thumbnail: Option<Vec<u8>>,
+    original: Option<Vec<u8>>,
+) -> anyhow::Result<(serde_json::Value, Option<Vec<u8>>)> {
+    let value = match request {
+        ReplicaRequest::Describe => serde_json::to_value(replica.describe()?)?,
+        ReplicaRequest::Init { manifest } => serde_json::to_value(replica.init(&manifest)?)?,
+        ReplicaRequest::Rows => serde_json::to_value(replica.rows()?)?,
+        ReplicaRequest::FileDigest { path } => serde_json::to_value(replica.file_digest(&path)?)?,
+        ReplicaRequest::ReadFile { path } => return Ok((serde_json::Value::Null, Some(replica.read_file(&path)?))),
+        ReplicaRequest::AddSource { source } => serde_json::to_value(replica.add_source(&source)?)?,
+        ReplicaRequest::Import { row, .. } => serde_json::to_value(replica.import(*row, thumbnail, original)?)?,
+    };
+    Ok((value, None))
+}
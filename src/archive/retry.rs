@@ -0,0 +1,58 @@
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+use image::ImageError;
+
+/// How the sync workers retry reading a photo after an I/O error.
+///
+/// Removable disks (USB, SD readers) occasionally fail a read that succeeds when repeated, only
+/// such errors are retried: missing files, denied access and corrupt data fail right away.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts made after the first failed one
+    pub retries: u32,
+    /// Delay before the first retry, doubled at each following one
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `op` until it succeeds, fails with a permanent error or runs out of retries.
+    ///
+    /// `retries` is increased for every retry performed, so that callers can report it.
+    pub fn run<T>(&self, retries: &mut u32, mut op: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    thread::sleep(self.backoff.saturating_mul(1 << attempt.min(16)));
+                    attempt += 1;
+                    *retries += 1;
+                }
+                out => return out,
+            }
+        }
+    }
+}
+
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let io_err = cause.downcast_ref::<std::io::Error>().or_else(|| match cause.downcast_ref::<ImageError>() {
+            Some(ImageError::IoError(io_err)) => Some(io_err),
+            _ => None,
+        });
+        io_err.is_some_and(|io_err| !matches!(
+            io_err.kind(),
+            ErrorKind::NotFound | ErrorKind::PermissionDenied | ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::UnexpectedEof
+        ))
+    })
+}
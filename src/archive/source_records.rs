@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::archive::common::build_filename;
+use crate::archive::digest::{digest_file, Digest, DigestAlgorithm};
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::layout::ArchiveLayout;
+use crate::archive::link::ArchiveLinker;
+use crate::archive::remove::retain_records;
+use crate::archive::records_store::{PhotoArchiveJsonRow, PhotoArchiveRecordsStore};
+
+/// Source file state at the time it was archived
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedFile {
+    pub timestamp: Option<NaiveDateTime>,
+    pub file_ts: SystemTime,
+    pub size: u64,
+    pub digest: Digest,
+    pub height: u32,
+    pub width: u32,
+    /// The file was only indexed, being below the size threshold
+    pub no_thumbnail: bool,
+    /// Thumbnail name stored in the record, when it differs from the derived one
+    pub thumbnail: Option<String>,
+}
+
+impl IndexedFile {
+    pub(crate) fn from_row(row: &PhotoArchiveJsonRow) -> Self {
+        Self {
+            timestamp: row.timestamp(),
+            file_ts: row.file_timestamp(),
+            size: row.size(),
+            digest: row.digest().clone(),
+            height: row.height(),
+            width: row.width(),
+            no_thumbnail: row.no_thumbnail(),
+            thumbnail: row.stored_thumbnail_name().map(String::from),
+        }
+    }
+
+    pub fn thumbnail_name(&self, layout: &ArchiveLayout) -> anyhow::Result<String> {
+        match &self.thumbnail {
+            Some(name) => Ok(name.clone()),
+            None => build_filename(layout, self.timestamp.as_ref(), self.file_ts, &self.digest),
+        }
+    }
+
+    /// Whether the file still has the size and modification time it was archived with
+    pub fn matches_metadata(&self, metadata: &fs::Metadata) -> anyhow::Result<bool> {
+        let file_ts = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+        let indexed_ts = self.file_ts.duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(metadata.len() == self.size && file_ts == indexed_ts)
+    }
+
+    fn matches_row(&self, row: &PhotoArchiveJsonRow) -> bool {
+        *self == Self::from_row(row)
+    }
+
+    /// Whether the source file changed since it was archived.
+    ///
+    /// Size and modification time are checked first, when they differ and the archive uses file
+    /// digests the content is hashed, so that merely touched or copied files are not re-processed.
+    pub fn is_modified(&self, path: &Path, digest_algorithm: DigestAlgorithm) -> anyhow::Result<bool> {
+        if self.matches_metadata(&fs::metadata(path)?)? {
+            return Ok(false);
+        }
+        if digest_algorithm.is_file_based() {
+            return Ok(digest_file(path, digest_algorithm)? != self.digest);
+        }
+        Ok(true)
+    }
+}
+
+/// Records of the source being synchronized, keyed by path relative to the source root
+pub struct SourceRecords {
+    source_id: String,
+    files: HashMap<PathBuf, IndexedFile>,
+    superseded: Mutex<HashMap<PathBuf, IndexedFile>>,
+    /// Files moved within the source during this sync, by their previous path
+    moved: Mutex<HashMap<PathBuf, MovedFile>>,
+}
+
+struct MovedFile {
+    source_path: PathBuf,
+    link_dir: Option<PathBuf>,
+    indexed: IndexedFile,
+}
+
+impl SourceRecords {
+    pub fn load(target_base_dir: &Path, cipher: Option<Arc<ArchiveCipher>>, source_id: &str) -> anyhow::Result<Self> {
+        let mut files = HashMap::new();
+        PhotoArchiveRecordsStore::with_cipher(target_base_dir, cipher).for_each_of_source(source_id, |row| {
+            files.insert(row.source_path(), IndexedFile::from_row(&row));
+        })?;
+
+        Ok(Self {
+            source_id: String::from(source_id),
+            files,
+            superseded: Mutex::new(HashMap::new()),
+            moved: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether the source had no record before this sync
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn get(&self, source_relative_path: &Path) -> Option<&IndexedFile> {
+        self.files.get(source_relative_path)
+    }
+
+    /// Indexed files with their path relative to the source root
+    pub fn iter(&self) -> impl Iterator<Item=(&Path, &IndexedFile)> {
+        self.files.iter().map(|(path, indexed)| (path.as_path(), indexed))
+    }
+
+    /// Mark the current record of the file as replaced by a newer one
+    pub fn supersede(&self, source_relative_path: &Path) {
+        if let Some(indexed) = self.files.get(source_relative_path) {
+            self.superseded.lock()
+                .expect("Source records lock poisoned")
+                .insert(source_relative_path.to_path_buf(), indexed.clone());
+        }
+    }
+
+    pub fn has_superseded(&self) -> bool {
+        !self.superseded.lock().expect("Source records lock poisoned").is_empty()
+    }
+
+    /// Whether the row is one of the records replaced during this sync
+    pub fn is_superseded(&self, row: &PhotoArchiveJsonRow) -> bool {
+        row.source_id() == self.source_id && self.superseded.lock()
+            .expect("Source records lock poisoned")
+            .get(&row.source_path())
+            .is_some_and(|indexed| indexed.matches_row(row))
+    }
+
+    /// Record that the file of `previous_path` is now `source_path`, the record is renamed by [`Self::apply_moves`]
+    pub fn record_move(&self, previous_path: &Path, source_path: &Path, link_dir: Option<PathBuf>) {
+        if let Some(indexed) = self.files.get(previous_path) {
+            self.moved.lock()
+                .expect("Source records lock poisoned")
+                .insert(previous_path.to_path_buf(), MovedFile { source_path: source_path.to_path_buf(), link_dir, indexed: indexed.clone() });
+        }
+    }
+
+    /// Rename the records of the files moved during this sync
+    pub fn apply_moves(&self, store: &PhotoArchiveRecordsStore) -> anyhow::Result<()> {
+        let moved = self.moved.lock().expect("Source records lock poisoned");
+        if moved.is_empty() {
+            return Ok(());
+        }
+        store.update_in(Some(&store.source_shards(&self.source_id)?), |row| {
+            let Some(moved_file) = moved.get(&row.source_path()).filter(|moved_file| row.source_id() == self.source_id && moved_file.indexed.matches_row(row)) else {
+                return false;
+            };
+            row.set_source_path(&moved_file.source_path, moved_file.link_dir.clone());
+            row.set_deleted_at(None);
+            true
+        })
+    }
+
+    /// Mark the records whose file disappeared from the source with a tombstone, and clear it from
+    /// the ones whose file came back. Thumbnails and originals are kept untouched.
+    pub fn update_tombstones(&self, store: &PhotoArchiveRecordsStore, source_base_dir: &Path) -> anyhow::Result<()> {
+        // An unmounted source would otherwise look like every file was deleted
+        if !source_base_dir.is_dir() {
+            anyhow::bail!("Source dir {source_base_dir:?} is not available");
+        }
+
+        let now = Utc::now().naive_utc();
+        store.update_in(Some(&store.source_shards(&self.source_id)?), |row| {
+            if row.source_id() != self.source_id {
+                return false;
+            }
+            let exists = source_base_dir.join(row.source_path()).exists();
+            match (row.deleted_at(), exists) {
+                (None, false) => row.set_deleted_at(Some(now)),
+                (Some(_), true) => row.set_deleted_at(None),
+                _ => return false,
+            }
+            true
+        })
+    }
+
+    /// Drop the records whose file disappeared from the source, with their links and the
+    /// thumbnails and originals no other record references. `on_pruned` receives each vanished file.
+    pub fn prune_vanished(
+        &self,
+        target_base_dir: &Path,
+        layout: &ArchiveLayout,
+        store: &PhotoArchiveRecordsStore,
+        linker: &ArchiveLinker,
+        source_base_dir: &Path,
+        mut on_pruned: impl FnMut(PathBuf),
+    ) -> anyhow::Result<()> {
+        if !source_base_dir.is_dir() {
+            anyhow::bail!("Source dir {source_base_dir:?} is not available");
+        }
+
+        let shards = store.source_shards(&self.source_id)?;
+        retain_records(target_base_dir, layout, store, linker, Some(&shards), |row| {
+            if row.source_id() != self.source_id {
+                return true;
+            }
+            let source_file = source_base_dir.join(row.source_path());
+            let exists = source_file.exists();
+            if !exists {
+                on_pruned(source_file);
+            }
+            exists
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::digest::Digest;
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::records_store::PhotoArchiveRecordsStore;
+
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Source file as seen by the last sync
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    #[serde(rename = "pth")]
+    pub path: PathBuf,
+    #[serde(rename = "siz")]
+    pub size: u64,
+    /// Modification time, unix timestamp
+    #[serde(rename = "mtm")]
+    pub file_ts: u64,
+    pub crc: Digest,
+}
+
+/// Files of a source left archived by its last sync, with their size, modification time and digest.
+///
+/// Syncs look for the new files of the source among the ones of the snapshot that are gone: a
+/// file with the same size, modification time and digest was moved or renamed, its record is
+/// then updated instead of archiving the file again and marking the old record deleted.
+pub struct SourceSnapshot {
+    by_content: HashMap<(u64, u64), Vec<SnapshotEntry>>,
+    /// Paths already taken as the origin of a move by the running sync
+    claimed: Mutex<HashSet<PathBuf>>,
+}
+
+impl SourceSnapshot {
+    /// Snapshot written by the last sync of the source, empty when there is none
+    pub fn load(target_base_dir: &Path, source_id: &str, cipher: Option<&ArchiveCipher>) -> anyhow::Result<Self> {
+        let mut by_content: HashMap<_, Vec<_>> = HashMap::new();
+        match File::open(snapshot_path(target_base_dir, source_id)) {
+            Ok(file) => for res_line in BufReader::new(file).lines() {
+                let line = res_line?;
+                let line = match cipher {
+                    Some(cipher) => cipher.open_line(&line)?,
+                    None => line,
+                };
+                let entry: SnapshotEntry = serde_json::from_str(&line)?;
+                by_content.entry((entry.size, entry.file_ts)).or_default().push(entry);
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        Ok(Self { by_content, claimed: Mutex::new(HashSet::new()) })
+    }
+
+    /// Files of the snapshot with the size and modification time of `metadata` which are no
+    /// longer in the source, the possible origins of a move
+    pub fn vanished_like(&self, source_base_dir: &Path, metadata: &fs::Metadata) -> anyhow::Result<Vec<&SnapshotEntry>> {
+        let file_ts = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(self.by_content.get(&(metadata.len(), file_ts))
+            .map(|entries| entries.iter().filter(|entry| !source_base_dir.join(&entry.path).exists()).collect())
+            .unwrap_or_default())
+    }
+
+    /// Take `path` as the origin of a move, false when another file of the source already did
+    pub fn claim(&self, path: &Path) -> bool {
+        self.claimed.lock().expect("Snapshot lock poisoned").insert(path.to_path_buf())
+    }
+
+    /// Replace the snapshot of the source with its records not marked deleted
+    pub fn store(target_base_dir: &Path, source_id: &str, store: &PhotoArchiveRecordsStore, cipher: Option<&ArchiveCipher>) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+        store.for_each_of_source(source_id, |row| {
+            if row.deleted_at().is_none() {
+                entries.push(SnapshotEntry {
+                    path: row.source_path(),
+                    size: row.size(),
+                    file_ts: row.file_timestamp().duration_since(UNIX_EPOCH).map_or(0, |ts| ts.as_secs()),
+                    crc: row.digest().clone(),
+                });
+            }
+        })?;
+
+        let path = snapshot_path(target_base_dir, source_id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("ndjson.tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        for entry in entries {
+            let line = serde_json::to_string(&entry)?;
+            let line = match cipher {
+                Some(cipher) => cipher.seal_line(&line)?,
+                None => line,
+            };
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+fn snapshot_path(target_base_dir: &Path, source_id: &str) -> PathBuf {
+    target_base_dir.join(SNAPSHOTS_DIR).join(format!("{source_id}.ndjson"))
+}
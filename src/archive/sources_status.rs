@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+
+use crate::common::fs::{list_mounted_partitions, partition_device_by_id};
+use crate::repository::sources::{SourceJsonRow, SourcesRepo};
+
+/// Where a registered source currently is
+pub enum SourceLocation {
+    Mounted {
+        mount_point: PathBuf,
+        fs_type: String,
+        /// Space available to the current user, unknown when the filesystem cannot be queried
+        free_bytes: Option<u64>,
+    },
+    /// The partition is attached but not mounted
+    Unmounted { device_path: PathBuf },
+    /// No attached partition has the id of the source: the disk is missing, or was renamed or reformatted
+    Unresolved,
+    /// Several mounted partitions share the id of the source
+    Ambiguous { mount_points: Vec<PathBuf> },
+}
+
+pub struct SourceStatus {
+    pub source: SourceJsonRow,
+    pub location: SourceLocation,
+}
+
+/// Locate every registered source of the archive among the partitions of the machine
+pub fn sources_status(target: &Path) -> anyhow::Result<Vec<SourceStatus>> {
+    // Unsupported platforms or an unreadable mount table only mean that no source is mounted
+    let mounted = list_mounted_partitions().unwrap_or_default();
+
+    let status = SourcesRepo::new(target.to_path_buf()).all()?
+        .into_iter()
+        .map(|source| {
+            let mut mounts = mounted.iter()
+                .filter(|partition| source.identified_by(&partition.info.partition_id))
+                .collect::<Vec<_>>();
+            let location = match mounts.len() {
+                0 => match source.partition_ids().find_map(partition_device_by_id) {
+                    Some(device_path) => SourceLocation::Unmounted { device_path },
+                    None => SourceLocation::Unresolved,
+                },
+                1 => {
+                    let partition = mounts.remove(0);
+                    SourceLocation::Mounted {
+                        mount_point: partition.mount_point.clone(),
+                        fs_type: partition.fs_type.clone(),
+                        free_bytes: fs2::available_space(&partition.mount_point).ok(),
+                    }
+                }
+                _ => SourceLocation::Ambiguous {
+                    mount_points: mounts.into_iter().map(|partition| partition.mount_point.clone()).collect(),
+                },
+            };
+            SourceStatus { source, location }
+        })
+        .collect();
+    Ok(status)
+}
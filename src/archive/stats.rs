@@ -0,0 +1,80 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::archive::common::build_record_paths;
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::layout::ArchiveLayout;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::{PhotoArchiveJsonRow, PhotoArchiveRecordsStore};
+use crate::repository::sources::{SourceJsonRow, SourceStats, SourcesRepo};
+
+/// Account for an index row in the totals of its source
+pub(crate) fn add_row(stats: &mut SourceStats, layout: &ArchiveLayout, target: &Path, row: &PhotoArchiveJsonRow) {
+    let thumbnail_bytes = if row.no_thumbnail() {
+        0
+    } else {
+        build_record_paths(layout, target, row).ok()
+            .zip(row.thumbnail_name(layout).ok())
+            .and_then(|(paths, thumbnail_name)| fs::metadata(paths.img_path.join(thumbnail_name)).ok())
+            .map_or(0, |metadata| metadata.len())
+    };
+    stats.add(row.timestamp().map(|ts| ts.and_utc().timestamp()), row.size(), thumbnail_bytes);
+}
+
+/// Compute from scratch the totals of the given sources, reading the index files holding their rows
+pub(crate) fn compute_sources_stats(
+    target: &Path,
+    layout: &ArchiveLayout,
+    store: &PhotoArchiveRecordsStore,
+    source_ids: &[&str],
+) -> anyhow::Result<HashMap<String, SourceStats>> {
+    let mut stats = source_ids.iter()
+        .map(|source_id| (String::from(*source_id), SourceStats::default()))
+        .collect::<HashMap<_, _>>();
+    let mut shards = BTreeSet::new();
+    for source_id in source_ids {
+        shards.extend(store.source_shards(source_id)?);
+    }
+    store.for_each_in(&shards, |row| {
+        if let Some(source_stats) = stats.get_mut(row.source_id()) {
+            add_row(source_stats, layout, target, &row);
+        }
+    })?;
+    Ok(stats)
+}
+
+/// Totals of the registered sources, or of `source_id` only.
+///
+/// Totals are read from the sources entries, the ones of sources never synced since they were
+/// introduced are computed from the index without being stored.
+pub fn source_stats(target: &Path, source_id: Option<&str>, secret: Option<&ArchiveSecret>) -> anyhow::Result<Vec<(SourceJsonRow, SourceStats)>> {
+    let mut sources = SourcesRepo::new(target.to_path_buf()).all()?;
+    if let Some(source_id) = source_id {
+        sources.retain(|source| source.id == source_id);
+        if sources.is_empty() {
+            anyhow::bail!("Source {source_id} is not registered in the archive");
+        }
+    }
+
+    let missing = sources.iter()
+        .filter(|source| source.stats.is_none())
+        .map(|source| source.id.as_str())
+        .collect::<Vec<_>>();
+    let mut computed = if missing.is_empty() {
+        HashMap::new()
+    } else {
+        let manifest = ArchiveManifest::load_or_default(target)?;
+        let store = PhotoArchiveRecordsStore::with_cipher(target, manifest.cipher(secret)?);
+        compute_sources_stats(target, &manifest.layout, &store, &missing)?
+    };
+
+    Ok(sources.into_iter()
+        .map(|source| {
+            let stats = source.stats.clone()
+                .or_else(|| computed.remove(&source.id))
+                .unwrap_or_default();
+            (source, stats)
+        })
+        .collect())
+}
@@ -1,34 +1,222 @@
-use std::fmt::format;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::num::NonZeroUsize;
 use std::ops::Add;
-use std::os::unix::prelude::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use std::{fs, thread};
 
 use anyhow::{anyhow, Context};
-use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime, Utc};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
 use crc::{Crc, CRC_32_ISCSI};
 use crossbeam::channel::{Receiver, Sender};
 use exif::{Exif, Tag};
-use image::imageops::FilterType;
-use image::{DynamicImage, ImageFormat};
-use crate::archive::common::{build_filename, build_paths};
+use crate::archive::codec::{decode_image, decode_image_scaled, DecodedImage};
+use crate::archive::common::{build_filename, build_paths, ArchivedPhotoPaths};
+use crate::archive::embedded_metadata::{read_embedded_metadata, EmbeddedMetadata};
+use crate::archive::digest::{digest_file, digest_pixels, Digest, DigestAlgorithm};
+use crate::archive::encryption::{ArchiveCipher, ArchiveSecret};
+use crate::archive::layout::{ArchiveLayout, LinkDirNaming};
+use crate::archive::manifest::{ArchiveManifest, ArchiveSettings};
+use crate::archive::memory_budget::{estimate_decoded_size, MemoryBudget, MemoryPermit};
+use crate::archive::priority::lower_thread_priority;
 
-use crate::archive::records_store::{PhotoArchiveRecordsStore, PhotoArchiveRow};
+use crate::archive::records_store::{IndexSharding, PhotoArchiveJsonRow, PhotoArchiveRecordsStore, PhotoArchiveRow};
+use crate::archive::remove::retain_records;
+use crate::archive::source_records::SourceRecords;
+use crate::archive::dir_times::DirTimes;
+use crate::archive::known_files::KnownFiles;
+use crate::archive::source_snapshot::SourceSnapshot;
+use crate::archive::stats::{add_row, compute_sources_stats};
+use crate::archive::thumbnail::{exif_preview, extract_icc_profile, generate_thumb, ThumbnailOpts, THUMBNAIL_SIZE};
+use crate::archive::thumbnail_dedupe::{link_thumbnail, same_thumbnail};
+use crate::archive::thumbnail_registry::{ThumbnailClaim, ThumbnailFingerprint, ThumbnailRegistry};
+use crate::archive::originals::{OriginalsCompression, OriginalsMode, OriginalsStore};
+use crate::archive::progress::ProgressTracker;
+use crate::archive::sync_history::{record_sync_run, RunBreakdown, SyncRun, SyncRunOptions};
+use crate::archive::retry::RetryPolicy;
+use crate::archive::link::{ArchiveLinker, ExistingLinks, LinkStrategy, SymlinkStyle};
 use crate::common::fs::model::MountedPartitionInfo;
-use crate::repository::sources::{SourceJsonRow, SourcesRepo};
+use crate::repository::sources::{SourceJsonRow, SourceStats, SourcesRepo};
 
 pub struct SyncOpts {
     pub count_images: bool,
     pub source: SyncSource,
+    pub thumbnail: ThumbnailOpts,
+    pub parallelism: ParallelismOpts,
+    pub retry: RetryPolicy,
+    pub scan: ScanOpts,
+    pub filter: FilterOpts,
+    /// Maximum amount of bytes used by concurrent image decodes, unlimited if not set
+    pub memory_budget: Option<u64>,
+    /// Digest algorithm of a newly created archive, must match the manifest of existing ones
+    pub digest: Option<DigestAlgorithm>,
+    /// Link strategy of a newly created archive, must match the manifest of existing ones
+    pub link_strategy: Option<LinkStrategy>,
+    /// Symlink style of a newly created archive, existing ones must be converted with `relink`
+    pub symlink_style: Option<SymlinkStyle>,
+    /// Date directories layout of a newly created archive, must match the manifest of existing ones
+    pub layout: Option<ArchiveLayout>,
+    /// Index files sharding of a newly created archive, existing ones keep the one of their manifest
+    pub index_sharding: Option<IndexSharding>,
+    /// Link directory naming of a newly created archive, must match the manifest of existing ones
+    pub link_dirs: Option<LinkDirNaming>,
+    /// Date photos without EXIF timestamp by their file modification time, only when creating an archive
+    pub undated_by_mtime: bool,
+    /// Store original files besides thumbnails, can be enabled on existing archives
+    pub originals: Option<OriginalsMode>,
+    /// Compression of newly stored originals, can be changed at any time
+    pub originals_compression: Option<OriginalsCompression>,
+    /// Secret of encrypted archives, providing it when creating an archive enables encryption
+    pub secret: Option<ArchiveSecret>,
+    /// Drop the records of files deleted from the source instead of marking them with a tombstone
+    pub prune: bool,
+    /// Create the archive when the target is not one yet, syncs into other directories are refused
+    pub init: bool,
+    /// Look for the link of every unchanged file, instead of trusting the files the last sync
+    /// found archived. Implies a full scan.
+    pub full_check: bool,
+    /// Read every directory of the source, instead of the ones changed since the last sync
+    pub full_scan: bool,
+    /// Process again the files that failed in a previous sync instead of scanning the source
+    pub rerun: Option<Rerun>,
+}
+
+/// Files of a previous sync handed again to the workers, e.g. after fixing their permissions
+#[derive(Clone, Debug)]
+pub struct Rerun {
+    /// Id of the sync in the history
+    pub run_id: u64,
+    /// Files and unreadable directories, relative to the source root; directories are read whole
+    pub paths: Vec<PathBuf>,
+}
+
+impl SyncOpts {
+    fn archive_settings(&self) -> ArchiveSettings {
+        ArchiveSettings {
+            digest: self.digest,
+            link_strategy: self.link_strategy,
+            symlink_style: self.symlink_style,
+            layout: self.layout.clone(),
+            index_sharding: self.index_sharding,
+            link_dirs: self.link_dirs.clone(),
+            undated_by_mtime: self.undated_by_mtime,
+            originals: self.originals,
+            originals_compression: self.originals_compression,
+            thumbnail: self.thumbnail.clone(),
+        }
+    }
+}
+
+/// Which directories of the source are scanned
+#[derive(Clone, Debug)]
+pub struct ScanOpts {
+    /// Scan hidden directories and the ones known to hold only system files and generated
+    /// thumbnails (`@eaDir`, `$RECYCLE.BIN`, ...), skipped by default
+    pub include_junk_dirs: bool,
+    pub follow_symlinks: SymlinkPolicy,
+    /// Directories read concurrently, sources made of many small directories enumerate faster
+    /// with several
+    pub threads: NonZeroUsize,
+}
+
+impl Default for ScanOpts {
+    fn default() -> Self {
+        Self {
+            include_junk_dirs: false,
+            follow_symlinks: SymlinkPolicy::default(),
+            threads: NonZeroUsize::new(4).expect("Non zero scan threads"),
+        }
+    }
+}
+
+/// Digest of the options deciding which files are scanned and archived, the concurrency left out
+fn options_digest(scan: &ScanOpts, filter: &FilterOpts, min_dimension: u32) -> u32 {
+    let options = format!(
+        "{}:{:?}:{:?}:{min_dimension}:{}",
+        scan.include_junk_dirs, scan.follow_symlinks, filter.max_file_size, filter.index_small,
+    );
+    CASTAGNOLI.checksum(options.as_bytes())
+}
+
+/// Which symlinked directories the scanner descends into, symlinked files are always scanned
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    #[default]
+    Never,
+    /// Only links pointing to a directory of the source itself
+    WithinSource,
+    /// Every link, directories reached twice (e.g. through a link to a parent) are scanned once
+    All,
+}
+
+impl Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SymlinkPolicy::Never => write!(f, "never"),
+            SymlinkPolicy::WithinSource => write!(f, "within-source"),
+            SymlinkPolicy::All => write!(f, "all"),
+        }
+    }
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "never" => Ok(SymlinkPolicy::Never),
+            "within-source" => Ok(SymlinkPolicy::WithinSource),
+            "all" => Ok(SymlinkPolicy::All),
+            other => anyhow::bail!("Unknown symlink policy '{other}', expected one of never, within-source, all"),
+        }
+    }
+}
+
+/// Which of the scanned images get archived
+#[derive(Clone, Debug, Default)]
+pub struct FilterOpts {
+    /// Files larger than this amount of bytes are ignored, huge scans or corrupt files would stall a worker
+    pub max_file_size: Option<u64>,
+    /// Minimum size of the smaller side of the images of the source, 0 disables the check.
+    ///
+    /// Stored in the source entry, the archive one is used when neither is set.
+    pub min_dimension: Option<u32>,
+    /// Record the images below the minimum dimension in the index, without thumbnail nor link
+    pub index_small: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParallelismOpts {
+    /// Number of processing workers kept alive even when the pipeline is starving
+    pub min_workers: usize,
+    /// Maximum number of processing workers spawned while the pipeline is saturated
+    pub max_workers: usize,
+    /// Scan and process with the lowest CPU and I/O priority, so that a background sync does
+    /// not slow down the desktop
+    pub nice: bool,
+}
+
+impl Default for ParallelismOpts {
+    fn default() -> Self {
+        Self {
+            min_workers: 1,
+            max_workers: thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(4),
+            nice: false,
+        }
+    }
 }
 
 pub enum SourceCoordinates {
     Id(String),
     Path(PathBuf),
+    /// Partition already resolved, e.g. chosen among the mounts sharing an id
+    Mounted(MountedPartitionInfo),
 }
 
 pub enum SyncSource {
@@ -46,6 +234,15 @@ pub enum SyncSource {
 pub enum SynchronizationEvent {
     ScanProgress {
         count: u64,
+        /// Directory being walked when the event was emitted
+        current_dir: PathBuf,
+        /// Images found per second since the scan started
+        files_per_sec: f64,
+    },
+    /// Directory (or entry) that could not be read, its content is not synchronized
+    ScanError {
+        path: PathBuf,
+        cause: String,
     },
     ScanCompleted {
         count: u64,
@@ -55,11 +252,22 @@ pub enum SynchronizationEvent {
         dst: PathBuf,
         generated: bool,
         partial: bool,
+        /// Capture time, estimated from the file modification time when `partial`
+        taken_at: Option<NaiveDateTime>,
+        /// Size of the source file
+        bytes: u64,
+        /// Reads retried after transient I/O errors
+        retries: u32,
     },
     Skipped {
         src: PathBuf,
         existing: PathBuf,
     },
+    /// File archived by a previous sync under another path of the source, its record and link follow it
+    Moved {
+        src: PathBuf,
+        previous: PathBuf,
+    },
     Ignored {
         src: PathBuf,
         cause: String,
@@ -67,6 +275,41 @@ pub enum SynchronizationEvent {
     Errored {
         src: PathBuf,
         cause: String,
+        /// Reads retried after transient I/O errors before giving up
+        retries: u32,
+    },
+    PipelineStats {
+        workers: usize,
+        queued_images: usize,
+        queued_records: usize,
+    },
+    /// Record dropped by `prune` since its source file no longer exists
+    Pruned {
+        src: PathBuf,
+    },
+    /// Periodic processing progress, sent at most once per second
+    Progress {
+        processed: u64,
+        /// Images found so far, final once `scan_completed` is set
+        total: u64,
+        scan_completed: bool,
+        /// Images processed per second over the last seconds
+        throughput: f64,
+        /// Estimated time to process the remaining images, only known once the scan is completed
+        eta: Option<Duration>,
+    },
+    /// Last event of the stream, sent once every worker is done
+    SyncCompleted {
+        stored: u64,
+        skipped: u64,
+        moved: u64,
+        ignored: u64,
+        errored: u64,
+        /// Size of the stored source files
+        bytes: u64,
+        duration: Duration,
+        /// Record of the run, its id is 0 when it could not be added to the sync history
+        run: Box<SyncRun>,
     },
 }
 
@@ -91,16 +334,94 @@ impl SyncrhonizationTask {
     }
 }
 
-fn find_mount_info(coord: &SourceCoordinates) -> anyhow::Result<MountedPartitionInfo> {
+pub(crate) fn find_mount_info(coord: &SourceCoordinates) -> anyhow::Result<MountedPartitionInfo> {
     match coord {
-        SourceCoordinates::Id(id) => crate::common::fs::partition_by_id(&id),
-        SourceCoordinates::Path(path) => crate::common::fs::common::partition_by_path(&path),
+        SourceCoordinates::Id(id) => Ok(crate::common::fs::partition_by_id(id)?),
+        SourceCoordinates::Path(path) => crate::common::fs::common::partition_by_path(path),
+        SourceCoordinates::Mounted(partition) => Ok(partition.clone()),
+    }
+}
+
+/// Registered source held by the mounted partition, pointing to the migration of the source when
+/// the partition is unknown but looks like the medium of a registered one
+pub(crate) fn find_registered_source(repo: &SourcesRepo, mount_info: &MountedPartitionInfo) -> anyhow::Result<SourceJsonRow> {
+    if let Some(entry) = repo.find_by_partition_id(&mount_info.info.partition_id)? {
+        return Ok(entry);
+    }
+    let candidates = match &mount_info.fingerprint {
+        Some(fingerprint) => repo.find_same_medium(fingerprint)?,
+        None => Vec::new(),
+    };
+    match &candidates[..] {
+        [] => anyhow::bail!("Source {} is not currently registered", mount_info.info.partition_id),
+        [candidate] => anyhow::bail!(
+            "Source {} is not currently registered, its volume looks like the one of source {} ('{}'): migrate that source if its medium was reformatted",
+            mount_info.info.partition_id,
+            candidate.id,
+            candidate.name,
+        ),
+        _ => anyhow::bail!(
+            "Source {} is not currently registered, its volume looks like the ones of sources {}: migrate the right one if its medium was reformatted",
+            mount_info.info.partition_id,
+            candidates.iter().map(|candidate| candidate.id.as_str()).collect::<Vec<_>>().join(", "),
+        ),
+    }
+}
+
+fn load_or_create_manifest(target: &Path, repo: &SourcesRepo, opts: &SyncOpts) -> anyhow::Result<(ArchiveManifest, Option<Arc<ArchiveCipher>>)> {
+    match ArchiveManifest::load(target)? {
+        Some(mut manifest) => {
+            if let Some(requested) = opts.digest.filter(|requested| *requested != manifest.digest) {
+                anyhow::bail!("Archive uses {} digests, cannot switch to {requested}", manifest.digest);
+            }
+            if let Some(requested) = opts.link_strategy.filter(|requested| *requested != manifest.link) {
+                anyhow::bail!("Archive uses {} links, cannot switch to {requested}", manifest.link);
+            }
+            if let Some(requested) = opts.layout.as_ref().filter(|requested| **requested != manifest.layout) {
+                anyhow::bail!("Archive uses the {} layout, cannot switch to {requested}", manifest.layout);
+            }
+            if opts.undated_by_mtime && !manifest.undated_by_mtime {
+                anyhow::bail!("Archive keeps undated photos in no-date, cannot switch to file modification dates");
+            }
+            if let Some(requested) = opts.link_dirs.as_ref().filter(|requested| **requested != manifest.link_dirs) {
+                anyhow::bail!("Archive uses {} link directories, cannot switch to {requested}", manifest.link_dirs);
+            }
+            if let Some(requested) = opts.symlink_style.filter(|requested| *requested != manifest.symlink_style) {
+                anyhow::bail!("Archive uses {} symlinks, run relink to switch to {requested}", manifest.symlink_style);
+            }
+            // Unlocked before any change to the manifest, so that a wrong secret leaves it untouched
+            let cipher = manifest.cipher(opts.secret.as_ref())?;
+            if let Some(requested) = opts.originals.filter(|requested| *requested != manifest.originals) {
+                if manifest.originals != OriginalsMode::None {
+                    anyhow::bail!("Archive stores {} originals, cannot switch to {requested}", manifest.originals);
+                }
+                if manifest.encryption.is_some() && requested == OriginalsMode::PerSource {
+                    anyhow::bail!("Encrypted archives cannot store per-source originals, use content-addressed ones");
+                }
+                manifest.originals = requested;
+                manifest.store(target)?;
+            }
+            if let Some(requested) = opts.originals_compression.filter(|requested| *requested != manifest.originals_compression) {
+                manifest.originals_compression = requested;
+                manifest.store(target)?;
+            }
+            Ok((manifest, cipher))
+        }
+        // Archives populated before manifests existed keep their historical defaults
+        None if repo.exists() => ArchiveManifest::create(target, &opts.archive_settings(), opts.secret.as_ref(), true),
+        None if opts.init => ArchiveManifest::create(target, &opts.archive_settings(), opts.secret.as_ref(), false),
+        None => anyhow::bail!("{} is not a photo archive, pass --init to create one", target.display()),
     }
 }
 
 pub fn synchronize_source(opts: SyncOpts, target: &Path) -> anyhow::Result<SyncrhonizationTask> {
+    if opts.prune && opts.rerun.is_some() {
+        anyhow::bail!("Pruning needs the whole source to be scanned, it cannot be combined with a rerun of failed files")
+    }
     let repo = SourcesRepo::new(target.to_path_buf());
-    let (source, source_id) = match opts.source {
+    let (manifest, cipher) = load_or_create_manifest(target, &repo, &opts)?;
+    let thumbnails = Arc::new(ThumbnailRegistry::load(target, &manifest.layout, cipher.clone()).context("Error loading archived thumbnails")?);
+    let (source, source_id, source_name, source_min_dimension, source_stats) = match opts.source {
         SyncSource::New {
             coord: id,
             name,
@@ -108,202 +429,699 @@ pub fn synchronize_source(opts: SyncOpts, target: &Path) -> anyhow::Result<Syncr
             tags,
         } => {
             let mount_info = find_mount_info(&id)?;
+            if manifest.link_dirs.uses_source_name() {
+                let homonym = repo.all()?
+                    .into_iter()
+                    .find(|entry| entry.name == name && entry.id != mount_info.info.partition_id);
+                if let Some(homonym) = homonym {
+                    anyhow::bail!("Source name '{name}' is already used by source {}, their link directories would collide", homonym.id);
+                }
+            }
             repo.write_entry(SourceJsonRow {
                 id: mount_info.info.partition_id.clone(),
-                name,
+                name: name.clone(),
                 group,
                 tags,
+                min_dimension: opts.filter.min_dimension,
+                stats: None,
+                last_sync: None,
+                fingerprint: mount_info.fingerprint.clone(),
+                aliases: Vec::new(),
             })?;
-            (mount_info.mount_point, mount_info.info.partition_id)
+            (mount_info.mount_point, mount_info.info.partition_id, name, opts.filter.min_dimension, None)
         }
         SyncSource::Existing { coord: id } => {
             let mount_info = find_mount_info(&id)?;
-            repo.find_by_id(&mount_info.info.partition_id)?
-                .ok_or_else(|| anyhow::anyhow!("Source {} is not currently registered", mount_info.info.partition_id))?;
+            let mut entry = find_registered_source(&repo, &mount_info)?;
+            let mut changed = false;
+            if opts.filter.min_dimension.is_some() && opts.filter.min_dimension != entry.min_dimension {
+                entry.min_dimension = opts.filter.min_dimension;
+                changed = true;
+            }
+            if mount_info.fingerprint.is_some() && mount_info.fingerprint != entry.fingerprint {
+                entry.fingerprint = mount_info.fingerprint.clone();
+                changed = true;
+            }
+            if changed {
+                repo.update_entry(&entry)?;
+            }
 
-            (mount_info.mount_point, mount_info.info.partition_id)
+            (mount_info.mount_point, entry.id, entry.name, entry.min_dimension, entry.stats)
         }
     };
+    let min_dimension = source_min_dimension.unwrap_or(manifest.min_dimension);
+    let source_records = Arc::new(SourceRecords::load(target, cipher.clone(), &source_id).context("Error loading source records")?);
+    let known_files = Arc::new(KnownFiles::load(target, &source_id, cipher.as_deref()));
+    let dir_times = Arc::new(
+        DirTimes::load(target, &source_id, cipher.as_deref(), opts.full_scan || opts.full_check, options_digest(&opts.scan, &opts.filter, min_dimension))
+            .context("Error loading directory times")?
+    );
+    let snapshot = Arc::new(SourceSnapshot::load(target, &source_id, cipher.as_deref()).context("Error loading source snapshot")?);
+    let existing_links = Arc::new(
+        preload_links(&manifest.layout, &manifest.link_dirs, target, &source_id, &source_name, &source_records)
+            .context("Error listing link directories")?
+    );
+    // Totals are updated with the rows written by the sync, they are computed again when unknown
+    let source_stats = if source_records.is_empty() { Some(SourceStats::default()) } else { source_stats };
+    let linker = manifest.linker(target)?;
 
-    let (image_path_sender, image_path_receiver) = crossbeam::channel::bounded(100);
-    let (record_sender, record_receiver) = crossbeam::channel::bounded(100);
+    let (image_path_sender, image_path_receiver) = crossbeam::channel::bounded(QUEUE_CAPACITY);
+    let (record_sender, record_receiver) = crossbeam::channel::bounded(QUEUE_CAPACITY);
     let (events_sender, events_receiver) = crossbeam::channel::unbounded();
     let (logged_events_sender, logged_events_receiver) = crossbeam::channel::unbounded();
 
-    if opts.count_images {
-        thread::spawn({
-            let owned_source = source.to_path_buf();
-            let owned_events_sender = events_sender.clone();
-            move || count_images(owned_source, &owned_events_sender)
-        });
-    }
-
     let owned_source = source.to_path_buf();
     let owned_target = target.to_path_buf();
-    let scanner_hndl = thread::spawn(move || scan_for_images(owned_source, &image_path_sender));
+    let scanner_hndl = thread::spawn({
+        let events_sender = events_sender.clone();
+        let scan_opts = opts.scan.clone();
+        let count_images = opts.count_images;
+        let dir_times = dir_times.clone();
+        let rerun = opts.rerun.clone();
+        let nice = opts.parallelism.nice;
+        move || {
+            if nice {
+                run_in_background();
+            }
+            match rerun {
+                Some(rerun) => feed_paths(owned_source, rerun.paths, &scan_opts, &image_path_sender, &events_sender),
+                None => scan_for_images(owned_source, &scan_opts, &dir_times, count_images, &image_path_sender, &events_sender),
+            }
+        }
+    });
     let logger_hndl = thread::spawn({
         let owned_target = owned_target.clone();
-        let source_id = String::from(&source_id);
+        let cipher = cipher.clone();
+        let run = SyncRun {
+            source_id: String::from(&source_id),
+            source_name: String::from(&source_name),
+            rerun_of: opts.rerun.as_ref().map(|rerun| rerun.run_id),
+            options: SyncRunOptions {
+                full_scan: opts.full_scan,
+                full_check: opts.full_check,
+                prune: opts.prune,
+                min_dimension,
+                max_file_size: opts.filter.max_file_size,
+                include_junk_dirs: opts.scan.include_junk_dirs,
+                max_workers: opts.parallelism.max_workers,
+                nice: opts.parallelism.nice,
+                retries: opts.retry.retries,
+            },
+            ..SyncRun::default()
+        };
+        let source_path = source.to_path_buf();
         move || {
             logger_worker(
                 owned_target,
-                source_id,
+                source_path,
+                cipher,
+                run,
                 events_receiver,
                 logged_events_sender,
             )
         }
     });
-    let writer_hndl = thread::spawn(move || process_record_store(owned_target, record_receiver));
-    let workers_hdnl = (0..4)
-        .into_iter()
-        .map(|idx| {
-            let receiver = image_path_receiver.clone();
-            let record_sender = record_sender.clone();
-            let events_sender = events_sender.clone();
-            let owned_target = target.to_path_buf();
-            let owned_source = source.to_path_buf();
-            let partition_id = String::from(&source_id);
-            thread::spawn(move || {
-                process_images(
-                    WorkerContext {
-                        worker_id: idx,
-                        partition_id,
-                        source_base_dir: owned_source,
-                        target_base_dir: owned_target,
-                    },
-                    events_sender,
-                    record_sender,
-                    receiver,
-                )
-            })
-        })
-        .collect::<Vec<_>>();
+    let writer_hndl = thread::spawn({
+        let writer_ctx = RecordStoreContext {
+            target_base_dir: owned_target,
+            source_base_dir: source.to_path_buf(),
+            source_id: String::from(&source_id),
+            source_stats,
+            layout: manifest.layout.clone(),
+            index_sharding: manifest.index_sharding,
+            cipher: cipher.clone(),
+            linker: linker.clone(),
+            source_records: source_records.clone(),
+            known_files: known_files.clone(),
+            dir_times: dir_times.clone(),
+            prune: opts.prune,
+            partial: opts.rerun.is_some(),
+        };
+        let events_sender = events_sender.clone();
+        let nice = opts.parallelism.nice;
+        move || {
+            if nice {
+                run_in_background();
+            }
+            process_record_store(writer_ctx, events_sender, record_receiver)
+        }
+    });
+    let worker_ctx = WorkerContext {
+        partition_id: String::from(&source_id),
+        source_name,
+        source_base_dir: source.to_path_buf(),
+        target_base_dir: target.to_path_buf(),
+        thumbnail_opts: opts.thumbnail,
+        memory_budget: opts.memory_budget.map(|capacity| Arc::new(MemoryBudget::new(capacity))),
+        retry: opts.retry,
+        filter: opts.filter,
+        min_dimension,
+        digest_algorithm: manifest.digest,
+        layout: manifest.layout.clone(),
+        index_sharding: manifest.index_sharding,
+        link_dirs: manifest.link_dirs.clone(),
+        undated_by_mtime: manifest.undated_by_mtime,
+        linker,
+        originals: manifest.originals_store(target, cipher.clone()),
+        cipher,
+        thumbnails,
+        source_records,
+        known_files,
+        existing_links,
+        snapshot,
+        dir_times,
+        full_check: opts.full_check,
+    };
+    let supervisor_hndl = thread::spawn(move || {
+        supervise_workers(
+            opts.parallelism,
+            worker_ctx,
+            events_sender,
+            record_sender,
+            image_path_receiver,
+        )
+    });
 
     Ok(SyncrhonizationTask {
         events_stream: logged_events_receiver,
-        handlers: [scanner_hndl, writer_hndl, logger_hndl]
-            .into_iter()
-            .chain(workers_hdnl)
-            .collect(),
+        handlers: vec![scanner_hndl, writer_hndl, logger_hndl, supervisor_hndl],
     })
 }
 
+const QUEUE_CAPACITY: usize = 100;
+
+/// Lower the priority of the calling pipeline thread, a failure only costs responsiveness
+fn run_in_background() {
+    if let Err(err) = lower_thread_priority() {
+        eprintln!("{err}");
+    }
+}
+const SUPERVISOR_SAMPLING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Keep the processing workers in line with the pipeline load.
+///
+/// When the images queue is almost full the workers are the bottleneck (CPU bound) and a new one
+/// is spawned, when it is almost empty the scanner is (I/O bound) and an idle worker is retired.
+fn supervise_workers(
+    opts: ParallelismOpts,
+    ctx: WorkerContext,
+    events_sender: Sender<SynchronizationEvent>,
+    record_sender: Sender<PhotoArchiveRow>,
+    receiver: Receiver<PathBuf>,
+) {
+    // Spawned workers inherit the priority of the supervisor
+    if opts.nice {
+        run_in_background();
+    }
+    let min_workers = opts.min_workers.max(1);
+    let max_workers = opts.max_workers.max(min_workers);
+    let (retire_sender, retire_receiver) = crossbeam::channel::unbounded();
+
+    let spawn_worker = || {
+        let ctx = ctx.clone();
+        let events_sender = events_sender.clone();
+        let record_sender = record_sender.clone();
+        let receiver = receiver.clone();
+        let retire_receiver = retire_receiver.clone();
+        thread::spawn(move || process_images(ctx, events_sender, record_sender, receiver, retire_receiver))
+    };
+
+    let mut workers = (0..min_workers).map(|_| spawn_worker()).collect::<Vec<_>>();
+
+    while !workers.is_empty() {
+        thread::sleep(SUPERVISOR_SAMPLING_INTERVAL);
+
+        let (finished, running): (Vec<_>, Vec<_>) = workers.into_iter().partition(|w| w.is_finished());
+        workers = running;
+        for worker in finished {
+            if let Err(err) = worker.join() {
+                eprintln!("Error joining worker thread - {err:?}");
+            }
+        }
+
+        let active_workers = workers.len().saturating_sub(retire_sender.len());
+        let queued_images = receiver.len();
+        if active_workers > 0 && queued_images * 10 >= QUEUE_CAPACITY * 8 && active_workers < max_workers {
+            workers.push(spawn_worker());
+        } else if queued_images * 10 <= QUEUE_CAPACITY && active_workers > min_workers && retire_sender.is_empty() {
+            send_or_log(&retire_sender, ());
+        }
+
+        send_or_log(&events_sender, SynchronizationEvent::PipelineStats {
+            workers: workers.len().saturating_sub(retire_sender.len()),
+            queued_images,
+            queued_records: record_sender.len(),
+        });
+    }
+}
+
+/// Forward the events of the pipeline adding progress reports, and record the run in the sync
+/// history once every other sender is gone
 fn logger_worker(
     archive_path: PathBuf,
-    source_id: String,
+    source_path: PathBuf,
+    cipher: Option<Arc<ArchiveCipher>>,
+    mut run: SyncRun,
     evt_receiver: Receiver<SynchronizationEvent>,
     evt_sender: Sender<SynchronizationEvent>,
 ) {
-    let now = Utc::now();
-    let ignored_log_path = archive_path.join(format!(
-        "{}_{}_IGN.log",
-        now.format("%Y%m%d-%H%M"),
-        source_id
-    ));
-    let errored_log_path = archive_path.join(format!(
-        "{}_{}_ERR.log",
-        now.format("%Y%m%d-%H%M"),
-        source_id
-    ));
-    let completed_log_path = archive_path.join(format!(
-        "{}_{}_CMP.log",
-        now.format("%Y%m%d-%H%M"),
-        source_id
-    ));
-
-    let mut ignored_f =
-        BufWriter::new(File::create(ignored_log_path).expect("Error creating skipped log file"));
-    let mut errored_f =
-        BufWriter::new(File::create(errored_log_path).expect("Error creating skipped log file"));
-    let mut completed_f =
-        BufWriter::new(File::create(completed_log_path).expect("Error creating skipped log file"));
+    let started_at = Instant::now();
+    run.started_at = Utc::now().timestamp();
+    let mut progress = ProgressTracker::default();
+    let mut breakdown = RunBreakdown::default();
+    let relative = |path: &Path| path.strip_prefix(&source_path).unwrap_or(path).to_path_buf();
+    let dir_of = |file: &Path| file.parent().map(relative).unwrap_or_default();
 
     while let Ok(evt) = evt_receiver.recv() {
-        let out = match &evt {
-            SynchronizationEvent::Stored {
-                src,
-                dst,
-                generated,
-                partial,
-            } => completed_f
-                .write(format!("src: {src:?} dst: {dst:?} gen: {generated} par: {partial}\n").as_bytes()),
-            SynchronizationEvent::Skipped { src, existing } => {
-                ignored_f.write(format!("src: {src:?} cause: file already exists {existing:?}\n").as_bytes())
-            }SynchronizationEvent::Ignored { src, cause } => {
-                ignored_f.write(format!("src: {src:?} cause: {cause}\n").as_bytes())
+        match &evt {
+            SynchronizationEvent::ScanProgress { count, .. } => progress.scanned(*count, false),
+            SynchronizationEvent::ScanCompleted { count } => progress.scanned(*count, true),
+            SynchronizationEvent::Stored { .. }
+            | SynchronizationEvent::Skipped { .. }
+            | SynchronizationEvent::Moved { .. }
+            | SynchronizationEvent::Ignored { .. }
+            | SynchronizationEvent::Errored { .. } => progress.processed(),
+            _ => {}
+        }
+        match &evt {
+            SynchronizationEvent::Stored { src, bytes, taken_at, .. } => {
+                run.stored += 1;
+                run.bytes += bytes;
+                breakdown.directory(dir_of(src)).stored += 1;
+                breakdown.stored_on(taken_at.map(|ts| ts.date()));
+            }
+            SynchronizationEvent::Skipped { src, .. } => {
+                run.skipped += 1;
+                breakdown.directory(dir_of(src)).skipped += 1;
+            }
+            SynchronizationEvent::Moved { src, .. } => {
+                run.moved += 1;
+                breakdown.directory(dir_of(src)).moved += 1;
             }
-            SynchronizationEvent::Errored { src, cause } => {
-                errored_f.write(format!("src: {src:?} cause: '{cause}'\n").as_bytes())
+            SynchronizationEvent::Ignored { src, .. } => {
+                run.ignored += 1;
+                breakdown.directory(dir_of(src)).ignored += 1;
             }
+            SynchronizationEvent::Errored { src, cause, .. } => {
+                run.errored += 1;
+                run.record_failure(relative(src), cause);
+                breakdown.directory(dir_of(src)).errored += 1;
+            }
+            SynchronizationEvent::ScanError { path, cause } => {
+                run.scan_errors += 1;
+                run.record_failure(relative(path), cause);
+                breakdown.directory(relative(path)).errored += 1;
+            }
+            SynchronizationEvent::Pruned { .. } => run.pruned += 1,
             SynchronizationEvent::ScanProgress { .. }
-            | SynchronizationEvent::ScanCompleted { .. } => Ok(0),
-        };
-        if let Err(err) = out {
-            eprintln!("Error writing log - {err}");
+            | SynchronizationEvent::ScanCompleted { .. }
+            | SynchronizationEvent::PipelineStats { .. }
+            | SynchronizationEvent::Progress { .. }
+            | SynchronizationEvent::SyncCompleted { .. } => {}
         }
         send_or_log(&evt_sender, evt);
+
+        if let Some(report) = progress.report() {
+            send_or_log(&evt_sender, SynchronizationEvent::Progress {
+                processed: report.processed,
+                total: report.total,
+                scan_completed: report.scan_completed,
+                throughput: report.throughput,
+                eta: report.eta,
+            });
+        }
     }
-}
 
-fn scan_for_images(source: PathBuf, sender: &Sender<PathBuf>) {
-    scan_for_images_with_callback(source, &mut |entry| {
-        sender.send(entry).expect("Error sending path")
+    // Every other sender is gone, the pipeline is drained
+    let duration = started_at.elapsed();
+    run.ended_at = Utc::now().timestamp();
+    breakdown.apply(&mut run);
+    let run = match record_sync_run(&archive_path, cipher.as_deref(), run.clone()) {
+        Ok(recorded) => recorded,
+        Err(err) => {
+            eprintln!("Error recording sync history - {err}");
+            run
+        }
+    };
+    send_or_log(&evt_sender, SynchronizationEvent::SyncCompleted {
+        stored: run.stored,
+        skipped: run.skipped,
+        moved: run.moved,
+        ignored: run.ignored,
+        errored: run.errored,
+        bytes: run.bytes,
+        duration,
+        run: Box::new(run),
     });
 }
 
-fn count_images(source: PathBuf, sender: &Sender<SynchronizationEvent>) {
-    let mut count = 0;
+pub(crate) enum ScanItem {
+    Directory(PathBuf),
+    Image(PathBuf),
+    Error { path: PathBuf, cause: String },
+}
+
+/// Paths kept by the counting walk while the workers are busy, a few tens of MiB
+const MAX_SCAN_BACKLOG: usize = 200_000;
+
+/// Walk the source once, feeding the workers and, when `count` is set, the progress counter.
+///
+/// While counting, the walk does not wait for the workers: the images they cannot take yet are
+/// kept in a backlog, so that the total is known early without scanning the source twice. Once
+/// the backlog is full the walk waits again, huge sources are then counted at the workers pace.
+fn scan_for_images(source: PathBuf, opts: &ScanOpts, dir_times: &DirTimes, count: bool, sender: &Sender<PathBuf>, events_sender: &Sender<SynchronizationEvent>) {
+    let mut found = 0;
+    let mut backlog = VecDeque::new();
+    let started_at = Instant::now();
+    let mut current_dir = source.clone();
     let mut last_evt_sent_ts = SystemTime::now();
-    let mut callback = |_entry| {
-        count += 1;
-        if last_evt_sent_ts.add(Duration::from_millis(1000)) < SystemTime::now() {
-            let out = sender.send(SynchronizationEvent::ScanProgress { count });
-            last_evt_sent_ts = SystemTime::now();
-            if let Err(err) = out {
-                eprintln!("Error updating img count - {err}");
+    scan_for_images_with_callback(source, opts, Some(dir_times), &mut |item| match item {
+        ScanItem::Image(entry) if !count => sender.send(entry).expect("Error sending path"),
+        ScanItem::Image(entry) => {
+            found += 1;
+            backlog.push_back(entry);
+            while let Some(entry) = backlog.pop_front() {
+                if let Err(err) = sender.try_send(entry) {
+                    backlog.push_front(err.into_inner());
+                    break;
+                }
+            }
+            if backlog.len() > MAX_SCAN_BACKLOG {
+                let entry = backlog.pop_front().expect("Backlog is not empty");
+                sender.send(entry).expect("Error sending path");
+            }
+            if last_evt_sent_ts.add(Duration::from_millis(1000)) < SystemTime::now() {
+                send_or_log(events_sender, SynchronizationEvent::ScanProgress {
+                    count: found,
+                    current_dir: current_dir.clone(),
+                    files_per_sec: found as f64 / started_at.elapsed().as_secs_f64(),
+                });
+                last_evt_sent_ts = SystemTime::now();
             }
         }
-    };
-    scan_for_images_with_callback(source, &mut callback);
+        ScanItem::Error { path, cause } => send_or_log(events_sender, SynchronizationEvent::ScanError { path, cause }),
+        ScanItem::Directory(dir) => current_dir = dir,
+    });
 
-    let out = sender.send(SynchronizationEvent::ScanCompleted { count });
-    if let Err(err) = out {
-        eprintln!("Error updating img count - {err}");
+    if count {
+        send_or_log(events_sender, SynchronizationEvent::ScanCompleted { count: found });
     }
+    for entry in backlog {
+        sender.send(entry).expect("Error sending path");
+    }
+}
+
+/// Hand the given files of the source to the workers, directories being read whole
+fn feed_paths(source: PathBuf, paths: Vec<PathBuf>, opts: &ScanOpts, sender: &Sender<PathBuf>, events_sender: &Sender<SynchronizationEvent>) {
+    let mut found = 0;
+    for path in paths.into_iter().map(|path| source.join(path)) {
+        if path.is_dir() {
+            scan_for_images_with_callback(path, opts, None, &mut |item| match item {
+                ScanItem::Image(entry) => {
+                    found += 1;
+                    sender.send(entry).expect("Error sending path");
+                }
+                ScanItem::Error { path, cause } => send_or_log(events_sender, SynchronizationEvent::ScanError { path, cause }),
+                ScanItem::Directory(_) => {}
+            });
+        } else if path.is_file() {
+            found += 1;
+            sender.send(path).expect("Error sending path");
+        } else {
+            found += 1;
+            send_or_log(events_sender, SynchronizationEvent::Ignored { src: path, cause: String::from("No longer in the source") });
+        }
+    }
+    send_or_log(events_sender, SynchronizationEvent::ScanCompleted { count: found });
 }
 
-fn scan_for_images_with_callback(source: PathBuf, callback: &mut impl FnMut(PathBuf)) {
-    for entry_res in fs::read_dir(&source).expect("Error reading dir") {
-        match entry_res {
-            Ok(entry) => {
-                let entry_path = entry.path();
+/// Directories created by NAS indexers and operating systems, full of thumbnails and deleted files
+const JUNK_DIRS: [&str; 3] = ["@eaDir", "$RECYCLE.BIN", "System Volume Information"];
 
-                if entry_path.is_dir() && !entry_path.is_symlink() {
-                    scan_for_images_with_callback(entry_path, callback)
-                } else if entry_path.is_file() {
-                    let ext = entry_path
-                        .extension()
-                        .and_then(|ext| ext.to_str())
-                        .map(ToString::to_string)
-                        .unwrap_or_default()
-                        .to_lowercase();
+/// Whether the directory is hidden (`.thumbnails`, `.Trash-1000`, ...) or a well-known junk one
+fn is_junk_dir(dir: &Path) -> bool {
+    dir.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.') || JUNK_DIRS.iter().any(|junk| junk.eq_ignore_ascii_case(name)))
+}
+
+/// Whether the file has the extension of an image format the archive handles
+pub(crate) fn is_supported_image(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(ToString::to_string)
+        .unwrap_or_default()
+        .to_lowercase();
+    ["jpg", "jpeg"].contains(&&ext[..])
+}
 
-                    let supported_format = ["jpg", "jpeg"].contains(&&ext[..]);
-                    if supported_format {
-                        callback(entry_path);
+/// Walk the source with `opts.threads` threads, `callback` receives the items on the calling one
+/// in no particular order. The directories unchanged according to `dir_times` are not read.
+pub(crate) fn scan_for_images_with_callback(source: PathBuf, opts: &ScanOpts, dir_times: Option<&DirTimes>, callback: &mut impl FnMut(ScanItem)) {
+    let source_root = match fs::canonicalize(&source) {
+        Ok(source_root) => source_root,
+        Err(err) => {
+            callback(ScanItem::Error { path: source, cause: format!("Error resolving dir - {err}") });
+            return;
+        }
+    };
+    let scanner = SourceScanner {
+        opts,
+        source_dir: source.clone(),
+        source_root,
+        visited: Mutex::new(HashSet::new()),
+        dir_times,
+    };
+
+    // Directories left to read, `None` tells the walkers that there is none anymore
+    let (dirs_sender, dirs_receiver) = crossbeam::channel::unbounded::<Option<PathBuf>>();
+    let (items_sender, items_receiver) = crossbeam::channel::bounded(QUEUE_CAPACITY);
+    // Directories queued or being read
+    let pending = AtomicUsize::new(1);
+    dirs_sender.send(Some(source)).expect("Error queueing source dir");
+
+    thread::scope(|scope| {
+        for _ in 0..opts.threads.get() {
+            let (dirs_sender, dirs_receiver, items_sender) = (dirs_sender.clone(), dirs_receiver.clone(), items_sender.clone());
+            let (scanner, pending) = (&scanner, &pending);
+            scope.spawn(move || {
+                while let Ok(Some(dir)) = dirs_receiver.recv() {
+                    scanner.scan_dir(
+                        dir,
+                        // The items are dropped once the receiving side is gone, the walk then winds down
+                        &mut |item| { let _ = items_sender.send(item); },
+                        &mut |subdir| {
+                            pending.fetch_add(1, Ordering::SeqCst);
+                            dirs_sender.send(Some(subdir)).expect("Error queueing dir");
+                        },
+                    );
+                    if pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        for _ in 0..opts.threads.get() {
+                            dirs_sender.send(None).expect("Error stopping walkers");
+                        }
                     }
                 }
+            });
+        }
+        drop(items_sender);
+        for item in items_receiver {
+            callback(item);
+        }
+    });
+}
+
+struct SourceScanner<'a> {
+    opts: &'a ScanOpts,
+    source_dir: PathBuf,
+    /// Canonical path of the source, to tell whether links point inside it
+    source_root: PathBuf,
+    /// Device and inode of the scanned directories, only tracked when links are followed
+    visited: Mutex<HashSet<(u64, u64)>>,
+    dir_times: Option<&'a DirTimes>,
+}
+
+impl SourceScanner<'_> {
+    /// Report the content of `dir`, its subdirectories to scan are handed to `descend`
+    fn scan_dir(&self, dir: PathBuf, callback: &mut impl FnMut(ScanItem), descend: &mut impl FnMut(PathBuf)) {
+        let follow_symlinks = self.opts.follow_symlinks != SymlinkPolicy::Never;
+        let metadata = match (follow_symlinks || self.dir_times.is_some()).then(|| fs::metadata(&dir)).transpose() {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                callback(ScanItem::Error { path: dir, cause: format!("Error reading dir metadata - {err}") });
+                return;
+            }
+        };
+        // Links can point to a parent directory, each directory is scanned once to avoid loops
+        if let Some(metadata) = metadata.as_ref().filter(|_| follow_symlinks) {
+            if !self.visited.lock().expect("Visited dirs lock poisoned").insert((metadata.dev(), metadata.ino())) {
+                return;
+            }
+        }
+
+        let relative_dir = dir.strip_prefix(&self.source_dir).unwrap_or(Path::new("")).to_path_buf();
+        let dir_times = self.dir_times.zip(metadata.as_ref());
+        if let Some(subdirs) = dir_times.and_then(|(dir_times, metadata)| dir_times.unchanged(&relative_dir, metadata)) {
+            callback(ScanItem::Directory(dir.clone()));
+            for subdir in subdirs {
+                let subdir_path = dir.join(subdir);
+                if subdir_path.is_dir() && self.should_descend(&subdir_path) {
+                    descend(subdir_path);
+                }
             }
-            Err(err) => eprintln!("Error reading dir entry - {err}"),
+            return;
+        }
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                callback(ScanItem::Error { path: dir, cause: format!("Error reading dir - {err}") });
+                return;
+            }
+        };
+        callback(ScanItem::Directory(dir.clone()));
+        let mut subdirs = Vec::new();
+        let mut complete = true;
+        for entry_res in entries {
+            match entry_res {
+                Ok(entry) => {
+                    let entry_path = entry.path();
+
+                    if entry_path.is_dir() {
+                        // A lossy name would not lead back to the directory, which would then be skipped
+                        match entry.file_name().into_string() {
+                            Ok(name) => subdirs.push(name),
+                            Err(_) => complete = false,
+                        }
+                        if self.should_descend(&entry_path) {
+                            descend(entry_path)
+                        }
+                    } else if entry_path.is_file() && is_supported_image(&entry_path) {
+                        callback(ScanItem::Image(entry_path));
+                    }
+                }
+                Err(err) => {
+                    complete = false;
+                    callback(ScanItem::Error { path: dir.clone(), cause: format!("Error reading dir entry - {err}") });
+                }
+            }
+        }
+        if let Some((dir_times, metadata)) = dir_times.filter(|_| complete) {
+            dir_times.read(&relative_dir, metadata, subdirs);
+        }
+    }
+
+    fn should_descend(&self, dir: &Path) -> bool {
+        if !self.opts.include_junk_dirs && is_junk_dir(dir) {
+            return false;
+        }
+        if !dir.is_symlink() {
+            return true;
+        }
+        match self.opts.follow_symlinks {
+            SymlinkPolicy::Never => false,
+            SymlinkPolicy::WithinSource => fs::canonicalize(dir).is_ok_and(|target| target.starts_with(&self.source_root)),
+            SymlinkPolicy::All => true,
         }
     }
 }
 
+#[derive(Clone)]
 pub struct WorkerContext {
-    worker_id: u32,
     partition_id: String,
+    source_name: String,
     source_base_dir: PathBuf,
     target_base_dir: PathBuf,
+    thumbnail_opts: ThumbnailOpts,
+    memory_budget: Option<Arc<MemoryBudget>>,
+    retry: RetryPolicy,
+    filter: FilterOpts,
+    min_dimension: u32,
+    digest_algorithm: DigestAlgorithm,
+    layout: ArchiveLayout,
+    index_sharding: IndexSharding,
+    link_dirs: LinkDirNaming,
+    undated_by_mtime: bool,
+    linker: ArchiveLinker,
+    originals: OriginalsStore,
+    cipher: Option<Arc<ArchiveCipher>>,
+    thumbnails: Arc<ThumbnailRegistry>,
+    source_records: Arc<SourceRecords>,
+    known_files: Arc<KnownFiles>,
+    existing_links: Arc<ExistingLinks>,
+    snapshot: Arc<SourceSnapshot>,
+    dir_times: Arc<DirTimes>,
+    full_check: bool,
+}
+
+/// List the date and link directories of the files already indexed for the source, re-syncs then
+/// check their links in memory instead of with a stat per file
+fn preload_links(
+    layout: &ArchiveLayout,
+    link_dirs: &LinkDirNaming,
+    target: &Path,
+    source_id: &str,
+    source_name: &str,
+    source_records: &SourceRecords,
+) -> anyhow::Result<ExistingLinks> {
+    let mut dirs = HashSet::new();
+    for (source_path, indexed) in source_records.iter() {
+        let link_dir = link_dirs.link_dir(source_id, source_name, source_path);
+        let paths = build_paths(layout, target, &link_dir, source_path, indexed.timestamp.as_ref())?;
+        dirs.insert(paths.date_path);
+        dirs.insert(paths.link_dir_path);
+    }
+    ExistingLinks::list(dirs)
+}
+
+/// Share of the memory budget needed to decode `p`, held until the decoded image is dropped
+fn decode_permit<'a>(ctx: &'a WorkerContext, p: &Path) -> Option<MemoryPermit<'a>> {
+    ctx.memory_budget.as_ref().map(|budget| {
+        let estimated_size = estimate_decoded_size(p).unwrap_or_else(|err| {
+            eprintln!("Error estimating decoded size - {err}");
+            0
+        });
+        budget.acquire(estimated_size)
+    })
+}
+
+/// Move the link of a file of the last snapshot that vanished from the source, when `p` has the
+/// same content, and let the writer rename its record. Returns the previous path of the file.
+fn move_record(ctx: &WorkerContext, p: &Path, source_path: &Path, link_dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let candidates = ctx.snapshot.vanished_like(&ctx.source_base_dir, &fs::metadata(p)?)?
+        .into_iter()
+        .filter(|entry| ctx.source_records.get(&entry.path).is_some_and(|indexed| indexed.digest == entry.crc))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    let digest = if ctx.digest_algorithm.is_file_based() {
+        digest_file(p, ctx.digest_algorithm)?
+    } else {
+        let _memory_permit = decode_permit(ctx, p);
+        digest_pixels(&decode_image(p)?)
+    };
+    let Some(entry) = candidates.into_iter().find(|entry| entry.crc == digest && ctx.snapshot.claim(&entry.path)) else {
+        return Ok(None);
+    };
+    let indexed = ctx.source_records.get(&entry.path).expect("Candidate without record");
+
+    if !indexed.no_thumbnail {
+        let previous_link_dir = ctx.link_dirs.link_dir(&ctx.partition_id, &ctx.source_name, &entry.path);
+        let previous_paths = build_paths(&ctx.layout, &ctx.target_base_dir, &previous_link_dir, &entry.path, indexed.timestamp.as_ref())?;
+        let archive_paths = build_paths(&ctx.layout, &ctx.target_base_dir, link_dir, source_path, indexed.timestamp.as_ref())?;
+        if !ctx.linker.link_exists_in(&archive_paths, &ctx.existing_links) {
+            fs::create_dir_all(&archive_paths.link_dir_path)?;
+            ctx.linker.create_link(&archive_paths, &indexed.thumbnail_name(&ctx.layout)?)?;
+        }
+        if ctx.linker.link_exists_in(&previous_paths, &ctx.existing_links) {
+            ctx.linker.remove_link(&previous_paths)?;
+        }
+        ctx.known_files.found(ctx.known_files.key(link_dir, source_path, indexed));
+    }
+    let stored_link_dir = ctx.link_dirs.is_stored().then(|| link_dir.to_path_buf());
+    ctx.source_records.record_move(&entry.path, source_path, stored_link_dir);
+    Ok(Some(ctx.source_base_dir.join(&entry.path)))
 }
 
 fn send_or_log<T>(sender: &Sender<T>, msg: T) {
@@ -318,12 +1136,66 @@ fn process_images(
     events_sender: Sender<SynchronizationEvent>,
     record_sender: Sender<PhotoArchiveRow>,
     receiver: Receiver<PathBuf>,
+    retire_receiver: Receiver<()>,
 ) {
-    let partition_crc = CASTAGNOLI.checksum(ctx.partition_id.as_bytes());
-    let send_evt = |evt: SynchronizationEvent| send_or_log(&events_sender, evt);
+    let send_evt = |evt: SynchronizationEvent| {
+        // The directory is read again by the next sync, for the file to be retried
+        if let SynchronizationEvent::Errored { src, .. } = &evt {
+            let relative_dir = src.strip_prefix(&ctx.source_base_dir).ok().and_then(Path::parent);
+            ctx.dir_times.failed(relative_dir.unwrap_or(Path::new("")));
+        }
+        send_or_log(&events_sender, evt)
+    };
+
+    loop {
+        let p = crossbeam::select! {
+            recv(receiver) -> msg => match msg {
+                Ok(p) => p,
+                Err(_) => break,
+            },
+            recv(retire_receiver) -> _ => break,
+        };
+
+        let source_path = p.strip_prefix(&ctx.source_base_dir).expect("Error extracting base dir");
+        let link_dir = ctx.link_dirs.link_dir(&ctx.partition_id, &ctx.source_name, source_path);
+
+        // Files unchanged since the last sync found them archived are skipped before being read
+        let unchanged = ctx.source_records.get(source_path)
+            .filter(|indexed| !indexed.no_thumbnail)
+            .filter(|indexed| fs::metadata(&p).is_ok_and(|metadata| indexed.matches_metadata(&metadata).unwrap_or(false)))
+            .map(|indexed| (indexed, ctx.known_files.key(&link_dir, source_path, indexed)));
+        let skipped = unchanged.filter(|_| !ctx.full_check).and_then(|(indexed, key)| {
+            let archive_paths = build_paths(&ctx.layout, &ctx.target_base_dir, &link_dir, source_path, indexed.timestamp.as_ref())
+                .expect("Error building paths");
+            // Files missing from the filter (first sync after an upgrade, filter lost) are looked up
+            // in the links listed at start, without reading their metadata
+            let known = ctx.known_files.contains(key)
+                || ctx.existing_links.contains(&ctx.linker.strategy().link_path(&archive_paths.link_file_path)) == Some(true);
+            known.then_some((archive_paths, key))
+        });
+        if let Some((archive_paths, key)) = skipped {
+            ctx.known_files.found(key);
+            send_evt(SynchronizationEvent::Skipped {
+                src: p,
+                existing: ctx.linker.strategy().link_path(&archive_paths.link_file_path),
+            });
+            continue;
+        }
 
-    while let Ok(p) = receiver.recv() {
-        let (datetime, exif) = match extract_exif(&p)
+        // A new path may be a file archived by a previous sync and moved since then
+        if ctx.source_records.get(source_path).is_none() {
+            match move_record(&ctx, &p, source_path, &link_dir) {
+                Ok(Some(previous)) => {
+                    send_evt(SynchronizationEvent::Moved { src: p, previous });
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => eprintln!("Error looking for the previous path of {p:?} - {err}"),
+            }
+        }
+
+        let mut retries = 0;
+        let (datetime, exif) = match ctx.retry.run(&mut retries, || extract_exif(&p))
             .map(|maybe_exif| maybe_exif.map(|exif| (extract_timestamp(&exif), exif)))
         {
             Err(err) => {
@@ -334,102 +1206,316 @@ fn process_images(
             Ok(Some((None, exif))) => (None, Some(exif)),
             Ok(Some((Some(datetime), exif))) => (Some(datetime), Some(exif)),
         };
-
+        let (datetime, date_estimated) = match datetime {
+            None if ctx.undated_by_mtime => {
+                let file_datetime = file_timestamp(&p);
+                (file_datetime, file_datetime.is_some())
+            }
+            datetime => (datetime, false),
+        };
+        let embedded = read_embedded_metadata(&p, exif.as_ref()).unwrap_or_else(|err| {
+            eprintln!("Error reading the metadata embedded in {p:?} - {err}");
+            Default::default()
+        });
+        let metadata = ImageMetadata { datetime, date_estimated, exif, embedded };
 
         let archive_paths = build_paths(
-            partition_crc,
+            &ctx.layout,
             &ctx.target_base_dir,
-            &p.strip_prefix(&ctx.source_base_dir).expect("Error extracting base dir"),
-            datetime.as_ref(),
+            &link_dir,
+            source_path,
+            metadata.datetime.as_ref(),
         ).expect("Error building paths");
 
-        if !archive_paths.img_path.exists() {
+        if !ctx.existing_links.contains(&archive_paths.img_path).unwrap_or_else(|| archive_paths.img_path.exists()) {
             fs::create_dir_all(&archive_paths.img_path).expect("Error creating dir");
         }
 
-        if archive_paths.link_file_path.exists() {
+        let indexed = ctx.source_records.get(source_path);
+        let modified = indexed.filter(|indexed| {
+            indexed.is_modified(&p, ctx.digest_algorithm).unwrap_or_else(|err| {
+                eprintln!("Error checking source file changes - {err}");
+                false
+            })
+        });
+        // Images indexed without thumbnail are archived as soon as they reach the minimum dimension
+        let index_only = indexed.filter(|indexed| modified.is_none() && indexed.no_thumbnail);
+        let upgraded = index_only.filter(|indexed| indexed.height.min(indexed.width) >= ctx.min_dimension);
+
+        if let Some(indexed) = modified {
+            // The file was edited in place, its previous link is dropped so that the new record can take its place
+            let previous_paths = build_paths(&ctx.layout, &ctx.target_base_dir, &link_dir, source_path, indexed.timestamp.as_ref())
+                .expect("Error building paths");
+            if ctx.linker.link_exists_in(&previous_paths, &ctx.existing_links) {
+                if let Err(err) = ctx.linker.remove_link(&previous_paths) {
+                    send_evt(SynchronizationEvent::Errored {
+                        src: p,
+                        cause: format!("Error removing link of modified file - {err}"),
+                        retries,
+                    });
+                    continue;
+                }
+            }
+        }
+
+        if let Some(indexed) = index_only.filter(|_| upgraded.is_none()) {
             send_evt(SynchronizationEvent::Skipped {
                 src: p,
-                existing: archive_paths.link_file_path,
+                existing: PhotoArchiveRecordsStore::new(&ctx.target_base_dir)
+                    .with_sharding(ctx.index_sharding)
+                    .index_path(indexed.timestamp.as_ref()),
             });
             continue;
-        } else if !archive_paths.link_dir_path.exists() {
-            fs::create_dir_all(&archive_paths.link_dir_path).expect("Error creating dir");
         }
 
-        let out = image::open(p.as_path())
-            .map_err(anyhow::Error::from)
-            .and_then(|img| {
-                if img.height() < 300 || img.width() < 300 {
-                    return Ok(ImgProcessOutcome::Ignored { cause: format!("Image is too small {}x{}", img.width(), img.height()) })
-                }
-                let digest = CASTAGNOLI.checksum(img.as_bytes());
-                let file_name = build_filename(
-                    datetime.as_ref(),
-                    std::fs::metadata(&p)?.modified()?,
-                    digest,
-                )?;
-                let file_path = archive_paths.img_path.join(&file_name);
-                let generated = if !file_path.exists() {
-                    generate_thumb(&img, file_path.as_path())?;
-                    true
-                } else {
-                    false
-                };
-                if !archive_paths.link_file_path.exists() {
-                    std::os::unix::fs::symlink(
-                        PathBuf::from("../img").join(file_name),
-                        archive_paths.link_file_path,
-                    )?;
-
-                    record_sender
-                        .send(PhotoArchiveRow {
-                            photo_ts: datetime,
-                            file_ts: fs::metadata(&p)?.modified()?,
-                            source_id: ctx.partition_id.clone(),
-                            source_path: p
-                                .strip_prefix(&ctx.source_base_dir)
-                                .unwrap()
-                                .to_path_buf(),
-                            exif,
-                            size: fs::metadata(&p)
-                                .expect("Cannot extract file metadata")
-                                .len(),
-                            height: img.height(),
-                            width: img.width(),
-                            digest,
-                        })
-                        .expect("Error sending photo archive row");
-                }
-                Ok(ImgProcessOutcome::Completed { generated, partial: datetime.is_none(), dst_path: file_path })
+        let superseding = modified.is_some() || upgraded.is_some();
+        if !superseding && ctx.linker.link_exists_in(&archive_paths, &ctx.existing_links) {
+            if let Some((_, key)) = unchanged {
+                ctx.known_files.found(key);
+            }
+            send_evt(SynchronizationEvent::Skipped {
+                src: p,
+                existing: ctx.linker.strategy().link_path(&archive_paths.link_file_path),
             });
+            continue;
+        } else if !ctx.existing_links.dir_exists(&archive_paths.link_dir_path).unwrap_or_else(|| archive_paths.link_dir_path.exists()) {
+            fs::create_dir_all(&archive_paths.link_dir_path).expect("Error creating dir");
+        }
+
+        let out = archive_image(&ctx, &p, metadata, &archive_paths, superseding, &record_sender, &mut retries);
+        if superseding && matches!(out, Ok(ImgProcessOutcome::Completed { .. } | ImgProcessOutcome::Indexed { .. })) {
+            ctx.source_records.supersede(source_path);
+        }
 
         match out {
             Err(err) => send_evt(SynchronizationEvent::Errored {
                 src: p,
                 cause: format!("Error processing image - {err}"),
+                retries,
             }),
-            Ok(ImgProcessOutcome::Completed { generated, partial, dst_path }) => send_evt(SynchronizationEvent::Stored {
+            Ok(ImgProcessOutcome::Completed { generated, partial, taken_at, dst_path, bytes }) => send_evt(SynchronizationEvent::Stored {
                 src: p,
                 dst: dst_path,
                 generated,
                 partial,
+                taken_at,
+                bytes,
+                retries,
             }),
             Ok(ImgProcessOutcome::Ignored { cause }) => send_evt(SynchronizationEvent::Ignored {
                 src: p,
                 cause
             }),
+            Ok(ImgProcessOutcome::Indexed { cause }) => send_evt(SynchronizationEvent::Ignored {
+                src: p,
+                cause: format!("{cause}, indexed without thumbnail"),
+            }),
+        }
+    }
+}
+
+/// Metadata extracted from the photo before archiving it
+struct ImageMetadata {
+    datetime: Option<NaiveDateTime>,
+    /// `datetime` comes from the file modification time, not from the EXIF data
+    date_estimated: bool,
+    exif: Option<Exif>,
+    embedded: EmbeddedMetadata,
+}
+
+fn archive_image(
+    ctx: &WorkerContext,
+    p: &Path,
+    metadata: ImageMetadata,
+    archive_paths: &ArchivedPhotoPaths,
+    superseding: bool,
+    record_sender: &Sender<PhotoArchiveRow>,
+    retries: &mut u32,
+) -> anyhow::Result<ImgProcessOutcome> {
+    let ImageMetadata { datetime, date_estimated, exif, embedded } = metadata;
+    let file_metadata = fs::metadata(p)?;
+    if let Some(max_file_size) = ctx.filter.max_file_size.filter(|max_file_size| file_metadata.len() > *max_file_size) {
+        return Ok(ImgProcessOutcome::Ignored {
+            cause: format!("File is too large {} bytes, maximum file size is {max_file_size} bytes", file_metadata.len()),
+        });
+    }
+    let file_ts = file_metadata.modified()?;
+    let (width, height) = ctx.retry.run(retries, || Ok(image::image_dimensions(p)?))?;
+    if height.min(width) < ctx.min_dimension {
+        let cause = format!("Image is too small {width}x{height}, minimum dimension is {}", ctx.min_dimension);
+        if !ctx.filter.index_small {
+            return Ok(ImgProcessOutcome::Ignored { cause });
+        }
+
+        let digest = if ctx.digest_algorithm.is_file_based() {
+            ctx.retry.run(retries, || digest_file(p, ctx.digest_algorithm))?
+        } else {
+            let _memory_permit = decode_permit(ctx, p);
+            digest_pixels(&ctx.retry.run(retries, || decode_image(p))?)
+        };
+        record_sender
+            .send(PhotoArchiveRow {
+                photo_ts: datetime,
+                date_estimated,
+                file_ts,
+                source_id: ctx.partition_id.clone(),
+                source_path: p.strip_prefix(&ctx.source_base_dir)?.to_path_buf(),
+                exif,
+                description: embedded.description,
+                rating: embedded.rating,
+                motion_clip: embedded.motion_clip,
+                size: file_metadata.len(),
+                height,
+                width,
+                digest,
+                thumbnail_name: None,
+                original: None,
+                link_dir: None,
+                no_thumbnail: true,
+            })
+            .expect("Error sending photo archive row");
+        return Ok(ImgProcessOutcome::Indexed { cause });
+    }
+    let fingerprint = ThumbnailFingerprint { width, height };
+
+    let file_digest = ctx.digest_algorithm.is_file_based()
+        .then(|| ctx.retry.run(retries, || digest_file(p, ctx.digest_algorithm)))
+        .transpose()?;
+    let file_claim = file_digest.as_ref()
+        .map(|digest| anyhow::Ok(ctx.thumbnails.claim(&archive_paths.img_path, &thumbnail_file_name(ctx, datetime.as_ref(), file_ts, digest)?, digest, fingerprint)))
+        .transpose()?;
+
+    // Thumbnails claimed by another worker may still be in progress, links other than symlinks need them on disk
+    let needs_thumbnail = |claim: &ThumbnailClaim| {
+        !claim.existing || (ctx.linker.strategy().requires_thumbnail() && !archive_paths.img_path.join(&claim.file_name).exists())
+    };
+
+    let (digest, claim, generated) = match (file_digest, file_claim) {
+        (Some(digest), Some(claim)) if !needs_thumbnail(&claim) => {
+            // The thumbnail was already generated from an identical file, no need to decode it again
+            (digest, claim, false)
+        }
+        (Some(digest), Some(claim)) if claim.duplicate_of.as_ref().is_some_and(|duplicate| link_thumbnail(duplicate, &archive_paths.img_path.join(&claim.file_name)).is_ok()) => {
+            // An identical file was archived under another name, its thumbnail is shared instead
+            // of decoding the photo again. Without hard links the thumbnail is generated.
+            (digest, claim, false)
+        }
+        (file_digest, file_claim) => {
+            // With file digests the photo is decoded for its thumbnail only, the EXIF preview can stand in
+            let preview = exif.as_ref()
+                .filter(|_| file_digest.is_some() && ctx.thumbnail_opts.exif_preview)
+                .and_then(|exif| exif_preview(exif, width, height));
+            let _memory_permit = preview.is_none().then(|| decode_permit(ctx, p)).flatten();
+
+            let img = match preview {
+                Some(preview) => preview,
+                None => ctx.retry.run(retries, || if ctx.thumbnail_opts.fast_decode {
+                    decode_image_scaled(p, THUMBNAIL_SIZE)
+                } else {
+                    decode_image(p).map(DecodedImage::from)
+                })?.image,
+            };
+
+            let (digest, claim) = match (file_digest, file_claim) {
+                (Some(digest), Some(claim)) => (digest, claim),
+                _ => {
+                    let digest = digest_pixels(&img);
+                    let claim = ctx.thumbnails.claim(&archive_paths.img_path, &thumbnail_file_name(ctx, datetime.as_ref(), file_ts, &digest)?, &digest, fingerprint);
+                    (digest, claim)
+                }
+            };
+            let generated = if needs_thumbnail(&claim) {
+                let icc_profile = extract_icc_profile(p).unwrap_or_else(|err| {
+                    eprintln!("Error extracting icc profile - {err}");
+                    None
+                });
+                let thumbnail_path = archive_paths.img_path.join(&claim.file_name);
+                generate_thumb(&img, exif.as_ref(), icc_profile, &thumbnail_path, &ctx.thumbnail_opts, ctx.cipher.as_deref())?;
+                if let Some(duplicate) = claim.duplicate_of.as_ref().filter(|duplicate| duplicate.exists()) {
+                    share_thumbnail(duplicate, &thumbnail_path, ctx.cipher.as_deref());
+                }
+                true
+            } else {
+                false
+            };
+            (digest, claim, generated)
+        }
+    };
+
+    let file_name = claim.file_name;
+    let file_path = archive_paths.img_path.join(&file_name);
+    // Only names that had to be disambiguated are stored, the others are derived from the row
+    let thumbnail_name = (file_name != build_filename(&ctx.layout, datetime.as_ref(), file_ts, &digest)?).then(|| file_name.clone());
+    if !ctx.linker.link_exists_in(archive_paths, &ctx.existing_links) {
+        let source_path = p.strip_prefix(&ctx.source_base_dir)?.to_path_buf();
+        // Stored before linking, so that a failed copy is retried by the next sync
+        let original = ctx.originals.store(&ctx.partition_id, &source_path, p, superseding)?;
+        ctx.linker.create_link(archive_paths, &file_name)?;
+
+        record_sender
+            .send(PhotoArchiveRow {
+                photo_ts: datetime,
+                date_estimated,
+                file_ts,
+                source_id: ctx.partition_id.clone(),
+                source_path,
+                exif,
+                description: embedded.description,
+                rating: embedded.rating,
+                motion_clip: embedded.motion_clip,
+                size: file_metadata.len(),
+                height,
+                width,
+                digest,
+                thumbnail_name,
+                original,
+                link_dir: ctx.link_dirs.is_stored()
+                    .then(|| archive_paths.link_dir_path.strip_prefix(&archive_paths.date_path).map(Path::to_path_buf))
+                    .transpose()?,
+                no_thumbnail: false,
+            })
+            .expect("Error sending photo archive row");
+    }
+    Ok(ImgProcessOutcome::Completed {
+        generated,
+        partial: datetime.is_none() || date_estimated,
+        taken_at: datetime,
+        dst_path: file_path,
+        bytes: file_metadata.len(),
+    })
+}
+
+/// Thumbnail name of a photo, encrypted archives store it in the row as it cannot be derived
+fn thumbnail_file_name(ctx: &WorkerContext, datetime: Option<&NaiveDateTime>, file_ts: SystemTime, digest: &Digest) -> anyhow::Result<String> {
+    match &ctx.cipher {
+        Some(cipher) => Ok(cipher.thumbnail_name(digest)),
+        None => build_filename(&ctx.layout, datetime, file_ts, digest),
+    }
+}
+
+/// Replace the thumbnail just generated with a hard link to `duplicate`, generated for the same
+/// digest, when both hold the same image. Pixel digests ignore the metadata embedded in the
+/// thumbnails, so the two can differ.
+fn share_thumbnail(duplicate: &Path, thumbnail: &Path, cipher: Option<&ArchiveCipher>) {
+    match same_thumbnail(duplicate, thumbnail, cipher) {
+        // Kept as a copy without hard links
+        Ok(true) => {
+            let _ = link_thumbnail(duplicate, thumbnail);
         }
+        Ok(false) => {}
+        Err(err) => eprintln!("Error comparing thumbnail {} with {} - {err}", thumbnail.display(), duplicate.display()),
     }
 }
 
 enum ImgProcessOutcome {
-    Completed { generated: bool, partial: bool, dst_path: PathBuf },
+    Completed { generated: bool, partial: bool, taken_at: Option<NaiveDateTime>, dst_path: PathBuf, bytes: u64 },
     Ignored { cause: String },
+    /// Recorded in the index only, `cause` tells why no thumbnail was generated
+    Indexed { cause: String },
 }
 
 fn extract_exif(image_path: &Path) -> anyhow::Result<Option<Exif>> {
-    let file = std::fs::File::open(&image_path)?;
+    let file = std::fs::File::open(image_path)?;
     let mut bufreader = std::io::BufReader::new(&file);
     let exifreader = exif::Reader::new();
     let exif = exifreader.read_from_container(&mut bufreader).ok();
@@ -437,6 +1523,13 @@ fn extract_exif(image_path: &Path) -> anyhow::Result<Option<Exif>> {
     Ok(exif)
 }
 
+/// Local modification time of the file, unless missing or clearly bogus (unset clock, future date)
+fn file_timestamp(image_path: &Path) -> Option<NaiveDateTime> {
+    let modified = fs::metadata(image_path).and_then(|metadata| metadata.modified()).ok()?;
+    let usable = modified > SystemTime::UNIX_EPOCH.add(Duration::from_secs(24 * 60 * 60)) && modified <= SystemTime::now();
+    usable.then(|| DateTime::<Local>::from(modified).naive_local())
+}
+
 fn extract_timestamp(exif: &Exif) -> Option<NaiveDateTime> {
     let dt = exif
         .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
@@ -460,21 +1553,98 @@ fn extract_timestamp(exif: &Exif) -> Option<NaiveDateTime> {
 
 pub const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
 
-fn generate_thumb(img: &DynamicImage, target: &Path) -> anyhow::Result<()> {
-    let (nheight, nwidth) = if img.height() > img.width() {
-        (300, img.width() * 300 / img.height())
-    } else {
-        (img.height() * 300 / img.width(), 300)
-    };
-
-    let resized = img.resize(nwidth, nheight, FilterType::Nearest);
-    resized.save_with_format(target, ImageFormat::Jpeg)?;
-    Ok(())
+/// State of the records writer, which also finalizes the index once every worker is done
+struct RecordStoreContext {
+    target_base_dir: PathBuf,
+    source_base_dir: PathBuf,
+    source_id: String,
+    source_stats: Option<SourceStats>,
+    layout: ArchiveLayout,
+    index_sharding: IndexSharding,
+    cipher: Option<Arc<ArchiveCipher>>,
+    linker: ArchiveLinker,
+    source_records: Arc<SourceRecords>,
+    known_files: Arc<KnownFiles>,
+    dir_times: Arc<DirTimes>,
+    prune: bool,
+    /// Only some files of the source were handed to the workers, its scan state is kept
+    partial: bool,
 }
 
-fn process_record_store(target_base_dir: PathBuf, receiver: Receiver<PhotoArchiveRow>) {
-    let store = PhotoArchiveRecordsStore::new(target_base_dir.as_path());
+fn process_record_store(ctx: RecordStoreContext, events_sender: Sender<SynchronizationEvent>, receiver: Receiver<PhotoArchiveRow>) {
+    let store = PhotoArchiveRecordsStore::with_cipher(ctx.target_base_dir.as_path(), ctx.cipher.clone())
+        .with_sharding(ctx.index_sharding);
+    let mut source_stats = ctx.source_stats;
     while let Ok(row) = receiver.recv() {
-        store.write(row);
+        let json_row = PhotoArchiveJsonRow::from(row);
+        store.append(&json_row).expect("Error writing index row");
+        if let Some(stats) = source_stats.as_mut() {
+            add_row(stats, &ctx.layout, &ctx.target_base_dir, &json_row);
+        }
+    }
+
+    // All the workers are done, records of modified files can now be replaced by the new ones
+    if ctx.source_records.has_superseded() {
+        source_stats = None;
+        let out = store.source_shards(&ctx.source_id)
+            .and_then(|shards| retain_records(&ctx.target_base_dir, &ctx.layout, &store, &ctx.linker, Some(&shards), |row| !ctx.source_records.is_superseded(row)));
+        if let Err(err) = out {
+            eprintln!("Error dropping superseded records - {err}");
+        }
+    }
+
+    // Before looking for vanished files, which moved ones would otherwise look like
+    if let Err(err) = ctx.source_records.apply_moves(&store) {
+        eprintln!("Error renaming records of moved source files - {err}");
+    }
+
+    if ctx.prune {
+        let out = ctx.source_records.prune_vanished(&ctx.target_base_dir, &ctx.layout, &store, &ctx.linker, &ctx.source_base_dir, |src| {
+            source_stats = None;
+            send_or_log(&events_sender, SynchronizationEvent::Pruned { src });
+        });
+        if let Err(err) = out {
+            eprintln!("Error pruning records of deleted source files - {err}");
+        }
+    } else if let Err(err) = ctx.source_records.update_tombstones(&store, &ctx.source_base_dir) {
+        eprintln!("Error updating tombstones of deleted source files - {err}");
     }
+
+    if let Err(err) = store_source_stats(&ctx.target_base_dir, &ctx.layout, &store, &ctx.source_id, source_stats) {
+        eprintln!("Error updating source statistics - {err}");
+    }
+    // Files of the directories left unread were not looked at, the previous filter still knows them
+    if let Err(err) = ctx.known_files.store(&ctx.target_base_dir, &ctx.source_id, ctx.partial || ctx.dir_times.has_skipped()) {
+        eprintln!("Error storing the files found archived - {err}");
+    }
+    // Reruns do not read the directories, the times of the last scan are kept
+    if !ctx.partial {
+        if let Err(err) = ctx.dir_times.store(&ctx.target_base_dir, &ctx.source_id, ctx.cipher.as_deref()) {
+            eprintln!("Error storing the directory times - {err}");
+        }
+    }
+    if let Err(err) = SourceSnapshot::store(&ctx.target_base_dir, &ctx.source_id, &store, ctx.cipher.as_deref()) {
+        eprintln!("Error storing the source snapshot - {err}");
+    }
+}
+
+/// Save the totals of the synchronized source, computing them from the index when unknown,
+/// together with the time the sync ended
+fn store_source_stats(
+    target: &Path,
+    layout: &ArchiveLayout,
+    store: &PhotoArchiveRecordsStore,
+    source_id: &str,
+    stats: Option<SourceStats>,
+) -> anyhow::Result<()> {
+    let stats = match stats {
+        Some(stats) => stats,
+        None => compute_sources_stats(target, layout, store, &[source_id])?.remove(source_id).unwrap_or_default(),
+    };
+    let repo = SourcesRepo::new(target.to_path_buf());
+    let mut entry = repo.find_by_id(source_id)?
+        .ok_or_else(|| anyhow::anyhow!("Source {source_id} is no longer registered"))?;
+    entry.stats = Some(stats);
+    entry.last_sync = Some(Utc::now().timestamp());
+    repo.update_entry(&entry)
 }
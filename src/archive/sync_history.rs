@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::encryption::ArchiveCipher;
+
+/// File of the archive root recording the syncs, one line per run
+pub const SYNC_HISTORY_FILE: &str = "sync_history.ndjson";
+/// Failures kept in the record of a run, the following ones are only counted
+const MAX_RECORDED_FAILURES: usize = 1000;
+
+/// File or directory a sync could not read or archive
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncFailure {
+    /// Relative to the source root
+    pub path: PathBuf,
+    pub cause: String,
+}
+
+impl SyncFailure {
+    /// Kind of error used to group the failures: the cause without the details following its
+    /// first colon, which usually name the file or the byte that failed
+    pub fn kind(&self) -> &str {
+        self.cause.split_once(": ").map_or(self.cause.as_str(), |(kind, _)| kind)
+    }
+
+    /// Details of the cause left out of its kind
+    pub fn details(&self) -> Option<&str> {
+        self.cause.split_once(": ").map(|(_, details)| details)
+    }
+}
+
+/// Days in which photos stored by a sync were taken, formatted as `YYYY-MM-DD`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DateRange {
+    pub first: String,
+    pub last: String,
+    pub photos: u64,
+}
+
+/// What a sync did with the files of a directory of the source
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DirectoryCounts {
+    /// Relative to the source root
+    pub path: PathBuf,
+    pub stored: u64,
+    pub skipped: u64,
+    pub moved: u64,
+    pub ignored: u64,
+    /// Files that errored, or the directory itself when it could not be read
+    pub errored: u64,
+}
+
+/// Options a sync was run with, the ones changing what it archives or how long it takes
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SyncRunOptions {
+    pub full_scan: bool,
+    pub full_check: bool,
+    pub prune: bool,
+    /// Applied to the source, from the options, the source or the manifest
+    pub min_dimension: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    pub include_junk_dirs: bool,
+    pub max_workers: usize,
+    /// Run with the lowest CPU and I/O priority
+    #[serde(default)]
+    pub nice: bool,
+    pub retries: u32,
+}
+
+/// Record of a sync of a source, written when it ends
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SyncRun {
+    /// Sequence number of the run in the archive, starting at 1
+    pub id: u64,
+    pub source_id: String,
+    pub source_name: String,
+    /// Run whose failed files this one processed again, instead of scanning the source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerun_of: Option<u64>,
+    /// Unix timestamps
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub stored: u64,
+    pub skipped: u64,
+    pub moved: u64,
+    pub ignored: u64,
+    pub errored: u64,
+    pub pruned: u64,
+    /// Directories that could not be read
+    pub scan_errors: u64,
+    pub bytes: u64,
+    /// First failures of the run, files that errored and directories that could not be read
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<SyncFailure>,
+    /// Capture dates of the stored photos, consecutive days merged into one range
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stored_dates: Vec<DateRange>,
+    /// Directories where files were stored, moved, ignored or failed, the ones only holding
+    /// already archived files are left out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<DirectoryCounts>,
+    pub options: SyncRunOptions,
+}
+
+impl SyncRun {
+    pub fn record_failure(&mut self, path: PathBuf, cause: &str) {
+        if self.failures.len() < MAX_RECORDED_FAILURES {
+            self.failures.push(SyncFailure { path, cause: String::from(cause) });
+        }
+    }
+
+    /// Recorded failures grouped by kind, the most frequent kind first
+    pub fn failures_by_kind(&self) -> Vec<(&str, Vec<&SyncFailure>)> {
+        let mut groups: BTreeMap<&str, Vec<&SyncFailure>> = BTreeMap::new();
+        for failure in self.failures.iter() {
+            groups.entry(failure.kind()).or_default().push(failure);
+        }
+        let mut groups = groups.into_iter().collect::<Vec<_>>();
+        groups.sort_by_key(|(_, failures)| std::cmp::Reverse(failures.len()));
+        groups
+    }
+
+    /// Failures of the run left out of its record
+    pub fn unrecorded_failures(&self) -> u64 {
+        (self.errored + self.scan_errors).saturating_sub(self.failures.len() as u64)
+    }
+}
+
+/// Per-directory and per-day counts of a run, gathered while it goes
+#[derive(Default)]
+pub(crate) struct RunBreakdown {
+    days: BTreeMap<NaiveDate, u64>,
+    directories: BTreeMap<PathBuf, DirectoryCounts>,
+}
+
+impl RunBreakdown {
+    pub(crate) fn directory(&mut self, path: PathBuf) -> &mut DirectoryCounts {
+        self.directories.entry(path.clone()).or_insert_with(|| DirectoryCounts { path, ..DirectoryCounts::default() })
+    }
+
+    /// Count a stored photo, undated ones are only counted by the run
+    pub(crate) fn stored_on(&mut self, day: Option<NaiveDate>) {
+        if let Some(day) = day {
+            *self.days.entry(day).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn apply(self, run: &mut SyncRun) {
+        let mut ranges: Vec<(NaiveDate, NaiveDate, u64)> = Vec::new();
+        for (day, photos) in self.days {
+            match ranges.last_mut() {
+                Some((_, last, count)) if last.succ_opt() == Some(day) => {
+                    *last = day;
+                    *count += photos;
+                }
+                _ => ranges.push((day, day, photos)),
+            }
+        }
+        run.stored_dates = ranges.into_iter()
+            .map(|(first, last, photos)| DateRange { first: first.to_string(), last: last.to_string(), photos })
+            .collect();
+        run.directories = self.directories.into_values()
+            .filter(|dir| dir.stored + dir.moved + dir.ignored + dir.errored > 0)
+            .collect();
+    }
+}
+
+/// Runs recorded in the archive, oldest first
+pub fn load_sync_history(target_base_dir: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<Vec<SyncRun>> {
+    let file = match File::open(sync_history_path(target_base_dir)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut runs = Vec::new();
+    for res_line in BufReader::new(file).lines() {
+        let line = res_line?;
+        let line = match cipher {
+            Some(cipher) => cipher.open_line(&line)?,
+            None => line,
+        };
+        runs.push(serde_json::from_str(&line)?);
+    }
+    Ok(runs)
+}
+
+/// Append the run to the history, its id is set to the one following the last recorded run
+pub fn record_sync_run(target_base_dir: &Path, cipher: Option<&ArchiveCipher>, mut run: SyncRun) -> anyhow::Result<SyncRun> {
+    let path = sync_history_path(target_base_dir);
+    // Lines are counted without opening them, every run takes one
+    let recorded = match File::open(&path) {
+        Ok(file) => BufReader::new(file).lines().count(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(err) => return Err(err.into()),
+    };
+    run.id = recorded as u64 + 1;
+
+    let line = serde_json::to_string(&run)?;
+    let line = match cipher {
+        Some(cipher) => cipher.seal_line(&line)?,
+        None => line,
+    };
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(format!("{line}\n").as_bytes())?;
+    Ok(run)
+}
+
+fn sync_history_path(target_base_dir: &Path) -> PathBuf {
+    target_base_dir.join(SYNC_HISTORY_FILE)
+}
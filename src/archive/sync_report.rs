@@ -0,0 +1,181 @@
+use std::fmt::{Display, Formatter, Write as _};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::sync_history::SyncRun;
+
+/// Directory of the archive root holding the sync reports
+pub const REPORTS_DIR: &str = "reports";
+
+/// Format of the sync reports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Html => "html",
+        }
+    }
+}
+
+impl Display for ReportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportFormat::Markdown => write!(f, "markdown"),
+            ReportFormat::Html => write!(f, "html"),
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "html" => Ok(ReportFormat::Html),
+            other => anyhow::bail!("Unknown report format '{other}', expected markdown or html"),
+        }
+    }
+}
+
+/// Write the report of the run into the reports directory of the archive, replacing the one
+/// previously written for the same run, and return its path.
+///
+/// Reports name the files of the source in clear, they are refused on encrypted archives.
+pub fn write_sync_report(target: &Path, run: &SyncRun, format: ReportFormat) -> anyhow::Result<PathBuf> {
+    if ArchiveManifest::load_or_default(target)?.encryption.is_some() {
+        anyhow::bail!("Reports are not written into encrypted archives, they would expose the file names in clear")
+    }
+    let dir = target.join(REPORTS_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("sync-{:04}-{}.{}", run.id, run.source_id, format.extension()));
+    std::fs::write(&path, render_sync_report(run, format))?;
+    Ok(path)
+}
+
+/// Report of the run: counts, capture dates of the stored photos, directories and failures
+pub fn render_sync_report(run: &SyncRun, format: ReportFormat) -> String {
+    let title = format!("Sync {} of {}", run.id, run.source_name);
+    let options = &run.options;
+    let summary = [
+        ("Source", format!("{} ({})", run.source_name, run.source_id)),
+        ("Started", format_timestamp(run.started_at)),
+        ("Ended", format_timestamp(run.ended_at)),
+        ("Duration", format!("{}s", run.ended_at - run.started_at)),
+        ("Options", format!(
+            "full scan {}, full check {}, prune {}, junk dirs {}, min dimension {}px, {} workers, {} retries",
+            options.full_scan, options.full_check, options.prune, options.include_junk_dirs, options.min_dimension, options.max_workers, options.retries,
+        )),
+        ("Stored", format!("{} ({:.1} MiB)", run.stored, run.bytes as f64 / (1024.0 * 1024.0))),
+        ("Skipped", run.skipped.to_string()),
+        ("Moved", run.moved.to_string()),
+        ("Ignored", run.ignored.to_string()),
+        ("Pruned", run.pruned.to_string()),
+        ("Errored", run.errored.to_string()),
+        ("Unreadable directories", run.scan_errors.to_string()),
+    ].map(|(name, value)| vec![String::from(name), value]);
+
+    let dated = run.stored_dates.iter().map(|range| range.photos).sum::<u64>();
+    let mut dates = run.stored_dates.iter()
+        .map(|range| {
+            let days = if range.first == range.last { range.first.clone() } else { format!("{} to {}", range.first, range.last) };
+            vec![days, range.photos.to_string()]
+        })
+        .collect::<Vec<_>>();
+    if run.stored > dated {
+        dates.push(vec![String::from("undated"), (run.stored - dated).to_string()]);
+    }
+
+    let directories = run.directories.iter()
+        .map(|dir| {
+            let path = if dir.path.as_os_str().is_empty() { String::from(".") } else { dir.path.display().to_string() };
+            [path, dir.stored.to_string(), dir.skipped.to_string(), dir.moved.to_string(), dir.ignored.to_string(), dir.errored.to_string()].to_vec()
+        })
+        .collect::<Vec<_>>();
+
+    let mut failures = run.failures.iter()
+        .map(|failure| vec![failure.path.display().to_string(), failure.cause.clone()])
+        .collect::<Vec<_>>();
+    let unrecorded = run.unrecorded_failures();
+    if unrecorded > 0 {
+        failures.push(vec![format!("... and {unrecorded} more"), String::new()]);
+    }
+
+    let sections = [
+        ("Summary", vec!["", ""], summary.to_vec()),
+        ("Capture dates of the stored photos", vec!["Days", "Photos"], dates),
+        ("Directories", vec!["Directory", "Stored", "Skipped", "Moved", "Ignored", "Errored"], directories),
+        ("Failures", vec!["Path", "Cause"], failures),
+    ];
+    match format {
+        ReportFormat::Markdown => render_markdown(&title, &sections),
+        ReportFormat::Html => render_html(&title, &sections),
+    }
+}
+
+type Section<'a> = (&'a str, Vec<&'a str>, Vec<Vec<String>>);
+
+fn render_markdown(title: &str, sections: &[Section]) -> String {
+    let cell = |value: &str| value.replace('|', "\\|").replace('\n', " ");
+    let mut out = format!("# {}\n", cell(title));
+    for (name, header, rows) in sections {
+        let _ = write!(out, "\n## {name}\n\n");
+        if rows.is_empty() {
+            out.push_str("None\n");
+            continue;
+        }
+        let _ = writeln!(out, "| {} |", header.join(" | "));
+        let _ = writeln!(out, "|{}", "---|".repeat(header.len()));
+        for row in rows {
+            let _ = writeln!(out, "| {} |", row.iter().map(|value| cell(value)).collect::<Vec<_>>().join(" | "));
+        }
+    }
+    out
+}
+
+fn render_html(title: &str, sections: &[Section]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n<style>\
+        body {{ font-family: sans-serif; margin: 2em; }} table {{ border-collapse: collapse; }} \
+        th, td {{ border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }}\
+        </style>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        escape_html(title),
+    );
+    for (name, header, rows) in sections {
+        let _ = writeln!(out, "<h2>{}</h2>", escape_html(name));
+        if rows.is_empty() {
+            out.push_str("<p>None</p>\n");
+            continue;
+        }
+        out.push_str("<table>\n");
+        if header.iter().any(|column| !column.is_empty()) {
+            let _ = writeln!(out, "<tr>{}</tr>", header.iter().map(|column| format!("<th>{}</th>", escape_html(column))).collect::<String>());
+        }
+        for row in rows {
+            let _ = writeln!(out, "<tr>{}</tr>", row.iter().map(|value| format!("<td>{}</td>", escape_html(value))).collect::<String>());
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape_html(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn format_timestamp(ts: i64) -> String {
+    DateTime::from_timestamp(ts, 0)
+        .map(|ts| ts.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
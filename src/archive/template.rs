@@ -0,0 +1,171 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Value that can be referenced as `{name}` inside a path template
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemplateField {
+    Year,
+    Month,
+    Day,
+    SourceId,
+    SourceName,
+    /// CRC32 of the source id, the historical link directory prefix
+    SourceCrc,
+    /// CRC32 of the source directory of the photo
+    DirCrc,
+    /// Name of the source directory of the photo, `ROOT` for the source root
+    DirName,
+    /// Whole source directory of the photo, the only value that can span several path components
+    OrigDir,
+}
+
+impl TemplateField {
+    pub const DATE: &'static [TemplateField] = &[TemplateField::Year, TemplateField::Month, TemplateField::Day];
+    pub const SOURCE: &'static [TemplateField] = &[
+        TemplateField::SourceId,
+        TemplateField::SourceName,
+        TemplateField::SourceCrc,
+        TemplateField::DirCrc,
+        TemplateField::DirName,
+        TemplateField::OrigDir,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            TemplateField::Year => "year",
+            TemplateField::Month => "month",
+            TemplateField::Day => "day",
+            TemplateField::SourceId => "source_id",
+            TemplateField::SourceName => "source_name",
+            TemplateField::SourceCrc => "source_crc",
+            TemplateField::DirCrc => "dir_crc",
+            TemplateField::DirName => "dir_name",
+            TemplateField::OrigDir => "orig_dir",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::DATE.iter()
+            .chain(Self::SOURCE)
+            .copied()
+            .find(|field| field.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TemplateSegment {
+    Literal(String),
+    Field(TemplateField),
+}
+
+/// Relative path made of literal text and `{field}` placeholders, e.g. `{year}/{month}`.
+///
+/// Rendered values are sanitized so that they always yield a relative path made of valid file
+/// names: path separators, characters rejected by common filesystems and `..` never leak through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathTemplate {
+    source: String,
+    segments: Vec<TemplateSegment>,
+}
+
+impl Display for PathTemplate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            anyhow::bail!("Path template is empty");
+        }
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                anyhow::bail!("Unexpected '}}' in path template '{s}'");
+            }
+            let end = rest[start..].find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow::anyhow!("Unclosed '{{' in path template '{s}'"))?;
+            let name = &rest[start + 1..end];
+            let field = TemplateField::from_name(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown field '{{{name}}}' in path template '{s}'"))?;
+            if start > 0 {
+                segments.push(TemplateSegment::Literal(String::from(&rest[..start])));
+            }
+            segments.push(TemplateSegment::Field(field));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(TemplateSegment::Literal(String::from(rest)));
+        }
+
+        Ok(Self {
+            source: String::from(s),
+            segments,
+        })
+    }
+}
+
+impl PathTemplate {
+    /// Fail if the template references a field outside of `allowed`
+    pub fn check_fields(&self, allowed: &[TemplateField]) -> anyhow::Result<()> {
+        let unsupported = self.segments.iter()
+            .find_map(|segment| match segment {
+                TemplateSegment::Field(field) if !allowed.contains(field) => Some(field),
+                _ => None,
+            });
+        match unsupported {
+            Some(field) => anyhow::bail!(
+                "Field '{{{}}}' is not supported here, expected one of {}",
+                field.name(),
+                allowed.iter().map(|field| format!("{{{}}}", field.name())).collect::<Vec<_>>().join(", "),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    pub fn uses(&self, field: TemplateField) -> bool {
+        self.segments.contains(&TemplateSegment::Field(field))
+    }
+
+    /// Render the template, `value` provides the raw value of each referenced field.
+    ///
+    /// Empty components are dropped, a template rendering to nothing yields `ROOT`.
+    pub fn render(&self, mut value: impl FnMut(TemplateField) -> String) -> PathBuf {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                TemplateSegment::Literal(literal) => rendered.push_str(literal),
+                TemplateSegment::Field(TemplateField::OrigDir) => rendered.push_str(&value(TemplateField::OrigDir)),
+                TemplateSegment::Field(field) => rendered.push_str(&value(*field).replace('/', "_")),
+            }
+        }
+
+        let path = rendered.split('/')
+            .map(sanitize_component)
+            .filter(|component| !component.is_empty())
+            .collect::<PathBuf>();
+        if path.as_os_str().is_empty() {
+            PathBuf::from("ROOT")
+        } else {
+            path
+        }
+    }
+}
+
+/// Make `component` a valid file name on the filesystems archives are usually stored on
+pub fn sanitize_component(component: &str) -> String {
+    let sanitized = component
+        .chars()
+        .map(|c| if c.is_control() || "\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect::<String>();
+    match sanitized.trim() {
+        "." | ".." => String::from("_"),
+        trimmed => String::from(trimmed),
+    }
+}
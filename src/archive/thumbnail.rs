@@ -0,0 +1,207 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use exif::{Exif, In, Tag};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use img_parts::jpeg::Jpeg;
+use img_parts::{Bytes, ImageEXIF, ImageICC};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::embedded_metadata::jpeg_header_segments;
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::codec::encode_jpeg;
+
+/// Size in pixels of the longest side of generated thumbnails
+pub const THUMBNAIL_SIZE: u32 = 300;
+/// Signature of the APP2 segments holding a chunk of an ICC profile
+const ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+/// Color space of the profiles carried over, thumbnails are always encoded as RGB
+const ICC_RGB_SPACE: &[u8] = b"RGB ";
+/// Relative difference of proportions tolerated between a photo and its EXIF preview, larger
+/// ones come from cameras padding the preview with black bands
+const PREVIEW_RATIO_TOLERANCE: f64 = 0.02;
+
+#[derive(Clone, Debug, Default)]
+pub struct ThumbnailOpts {
+    /// Copy the whole EXIF metadata of the original photo (GPS position, camera serial, embedded
+    /// preview, ...) into the thumbnails, which otherwise only carry its orientation
+    pub keep_exif: bool,
+    /// Filter used to downscale the original photo
+    pub filter: ResizeFilter,
+    /// Decode JPEGs at a reduced DCT scale, just large enough for the thumbnail.
+    /// Pixel digests are then computed on the reduced image and differ from full decode ones.
+    pub fast_decode: bool,
+    /// Read back and decode every written thumbnail, catching silent write failures on flaky targets
+    pub verify: bool,
+    /// Generate thumbnails from the preview embedded in the EXIF data of the photos when it is
+    /// large enough, instead of decoding them. Only used with file digests, pixel digests need
+    /// the photo decoded anyway.
+    pub exif_preview: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    /// Fastest, produces visibly aliased thumbnails
+    Nearest,
+    /// Bilinear filtering, good quality/speed trade-off
+    #[default]
+    Triangle,
+    /// Sharpest result, most CPU intensive
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl Display for ResizeFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResizeFilter::Nearest => write!(f, "nearest"),
+            ResizeFilter::Triangle => write!(f, "triangle"),
+            ResizeFilter::Lanczos3 => write!(f, "lanczos3"),
+        }
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "triangle" => Ok(ResizeFilter::Triangle),
+            "lanczos3" => Ok(ResizeFilter::Lanczos3),
+            other => anyhow::bail!("Unknown resize filter '{other}', expected one of nearest, triangle, lanczos3"),
+        }
+    }
+}
+
+/// Extract the embedded ICC color profile of a JPEG file, if any.
+///
+/// Wide-gamut photos (Display P3, Adobe RGB) look washed out when the profile is dropped,
+/// so it is carried over to the generated thumbnail. Only the header segments are read, other
+/// formats have no profile, and CMYK or gray profiles do not apply to the RGB thumbnails.
+pub fn extract_icc_profile(image_path: &Path) -> anyhow::Result<Option<Bytes>> {
+    let segments = jpeg_header_segments(&mut File::open(image_path)?, &[0xE2])?;
+    // Large profiles are split in chunks, each one prefixed by its sequence number and the count
+    let mut chunks = segments.iter()
+        .filter_map(|(_, payload)| payload.strip_prefix(ICC_SIGNATURE))
+        .filter_map(|chunk| Some((*chunk.first()?, chunk.get(2..)?)))
+        .collect::<Vec<_>>();
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+    chunks.sort_by_key(|(sequence, _)| *sequence);
+    let profile = chunks.into_iter().flat_map(|(_, chunk)| chunk.iter().copied()).collect::<Vec<_>>();
+    Ok((profile.get(16..20) == Some(ICC_RGB_SPACE)).then(|| Bytes::from(profile)))
+}
+
+/// Preview JPEG embedded in the EXIF data of a `width`x`height` photo, when it is at least as
+/// large as the thumbnails and has the proportions of the photo
+pub fn exif_preview(exif: &Exif, width: u32, height: u32) -> Option<DynamicImage> {
+    let offset = exif.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    let length = exif.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    // Offsets are relative to the TIFF header, where the EXIF buffer starts
+    let jpeg = exif.buf().get(offset..offset.checked_add(length)?)?;
+    let preview = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg).ok()?;
+    if preview.width().max(preview.height()) < THUMBNAIL_SIZE || width == 0 || height == 0 {
+        return None;
+    }
+    let photo_ratio = width as f64 / height as f64;
+    let preview_ratio = preview.width() as f64 / preview.height() as f64;
+    ((photo_ratio / preview_ratio - 1.0).abs() <= PREVIEW_RATIO_TOLERANCE).then_some(preview)
+}
+
+pub fn generate_thumb(
+    img: &DynamicImage,
+    exif: Option<&Exif>,
+    icc_profile: Option<Bytes>,
+    target: &Path,
+    opts: &ThumbnailOpts,
+    cipher: Option<&ArchiveCipher>,
+) -> anyhow::Result<()> {
+    let (nheight, nwidth) = if img.height() > img.width() {
+        (THUMBNAIL_SIZE, img.width() * THUMBNAIL_SIZE / img.height())
+    } else {
+        (img.height() * THUMBNAIL_SIZE / img.width(), THUMBNAIL_SIZE)
+    };
+
+    let resized = img.resize(nwidth, nheight, opts.filter.into());
+
+    let encoded = encode_jpeg(&resized, 75)?;
+
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(encoded))?;
+    jpeg.set_icc_profile(icc_profile);
+    if opts.keep_exif {
+        jpeg.set_exif(exif.map(|exif| Bytes::copy_from_slice(exif.buf())));
+    } else {
+        jpeg.set_exif(exif.and_then(orientation_exif));
+    }
+
+    let mut encoded = Vec::new();
+    jpeg.encoder().write_to(&mut encoded)?;
+    let content = match cipher {
+        Some(cipher) => cipher.seal(&encoded)?,
+        None => encoded,
+    };
+
+    let mut file = File::create(target)?;
+    file.write_all(&content)?;
+    if opts.verify {
+        // Flushed to the device first, otherwise the read back could be served by the page cache
+        file.sync_all()?;
+        drop(file);
+        if let Err(err) = verify_thumb(target, &content, cipher) {
+            let _ = std::fs::remove_file(target);
+            anyhow::bail!("Thumbnail verification failed - {err}");
+        }
+    }
+    Ok(())
+}
+
+/// EXIF data holding only the orientation of the photo, the resized pixels are not rotated
+fn orientation_exif(exif: &Exif) -> Option<Bytes> {
+    let orientation = exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0)?;
+    let orientation = u16::try_from(orientation).ok().filter(|orientation| (2..=8).contains(orientation))?;
+    // Little endian TIFF header followed by an IFD with a single SHORT entry
+    let mut tiff = Vec::with_capacity(26);
+    tiff.extend_from_slice(b"II*\0");
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&Tag::Orientation.number().to_le_bytes());
+    tiff.extend_from_slice(&3u16.to_le_bytes());
+    tiff.extend_from_slice(&1u32.to_le_bytes());
+    tiff.extend_from_slice(&orientation.to_le_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    Some(Bytes::from(tiff))
+}
+
+fn verify_thumb(target: &Path, expected: &[u8], cipher: Option<&ArchiveCipher>) -> anyhow::Result<()> {
+    let written = std::fs::read(target)?;
+    if written.len() != expected.len() {
+        anyhow::bail!("size mismatch, expected {} bytes, found {}", expected.len(), written.len());
+    }
+    if blake3::hash(&written) != blake3::hash(expected) {
+        anyhow::bail!("content mismatch");
+    }
+
+    let jpeg = match cipher {
+        Some(cipher) => cipher.open(&written)?,
+        None => written,
+    };
+    image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg)?;
+    Ok(())
+}
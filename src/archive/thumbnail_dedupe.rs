@@ -0,0 +1,128 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive::common::{build_record_paths, ArchivedPhotoPaths};
+use crate::archive::digest::Digest;
+use crate::archive::encryption::{ArchiveCipher, ArchiveSecret};
+use crate::archive::link::{same_file, LinkStrategy};
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::PhotoArchiveRecordsStore;
+
+#[derive(Debug, Default)]
+pub struct DedupeThumbsStats {
+    /// Thumbnails replaced by a hard link to an identical one (to be replaced, on dry runs)
+    pub linked: Vec<PathBuf>,
+    /// Space given back by the replaced thumbnails
+    pub bytes: u64,
+    pub already_linked: usize,
+    /// Thumbnails of the same digest generated with other options or metadata, left untouched
+    pub different: usize,
+    pub errors: usize,
+}
+
+/// Whether the two thumbnails hold the same image, compared in clear on encrypted archives
+pub fn same_thumbnail(a: &Path, b: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<bool> {
+    // Sealing adds the same overhead to every file, lengths are comparable either way
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let (a, b) = (fs::read(a)?, fs::read(b)?);
+    match cipher {
+        _ if a == b => Ok(true),
+        Some(cipher) => Ok(cipher.open(&a)? == cipher.open(&b)?),
+        None => Ok(false),
+    }
+}
+
+/// Make `thumbnail` a hard link to `original`, replacing it atomically when it exists.
+///
+/// Fails on filesystems without hard links (exFAT, FAT32) and when the original is missing.
+pub fn link_thumbnail(original: &Path, thumbnail: &Path) -> anyhow::Result<()> {
+    let file_name = thumbnail.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = thumbnail.with_file_name(format!(".{file_name}.link-tmp"));
+    let _ = fs::remove_file(&temp_path);
+    fs::hard_link(original, &temp_path)?;
+    if let Err(err) = fs::rename(&temp_path, thumbnail) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Replace the byte-identical thumbnails of the same digest with hard links to a single file,
+/// for archives synchronized before duplicates were linked at sync time.
+///
+/// With the hardlink strategy the link entries of the replaced thumbnails are linked again,
+/// otherwise they would keep the old copies alive.
+pub fn dedupe_thumbnails(target: &Path, secret: Option<&ArchiveSecret>, dry_run: bool) -> anyhow::Result<DedupeThumbsStats> {
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let cipher = manifest.cipher(secret)?;
+    let linker = manifest.linker(target)?;
+    let relink = linker.strategy() == LinkStrategy::Hardlink;
+
+    let mut by_digest: HashMap<Digest, BTreeSet<PathBuf>> = HashMap::new();
+    // Link entries of each thumbnail, superseded rows share the entry of the row replacing them
+    let mut entries: HashMap<PathBuf, HashMap<PathBuf, (ArchivedPhotoPaths, String)>> = HashMap::new();
+    PhotoArchiveRecordsStore::with_cipher(target, cipher.clone()).for_each(|row| {
+        if row.no_thumbnail() {
+            return;
+        }
+        let paths = build_record_paths(&manifest.layout, target, &row)
+            .and_then(|paths| Ok((row.thumbnail_name(&manifest.layout)?, paths)));
+        match paths {
+            Ok((thumbnail_name, paths)) => {
+                let thumbnail_path = paths.img_path.join(&thumbnail_name);
+                by_digest.entry(row.digest().clone()).or_default().insert(thumbnail_path.clone());
+                if relink {
+                    entries.entry(thumbnail_path).or_default().insert(paths.link_file_path.clone(), (paths, thumbnail_name));
+                }
+            }
+            Err(err) => eprintln!("Error building paths of {} - {err}", row.source_path().display()),
+        }
+    })?;
+
+    let mut stats = DedupeThumbsStats::default();
+    let mut groups = by_digest.into_values().filter(|thumbnails| thumbnails.len() > 1).collect::<Vec<_>>();
+    groups.sort();
+    for thumbnails in groups {
+        let mut existing = thumbnails.into_iter().filter(|path| path.exists());
+        let Some(original) = existing.next() else {
+            continue;
+        };
+        for thumbnail in existing {
+            let out = same_file(&original, &thumbnail).and_then(|linked| {
+                if linked {
+                    return Ok(None);
+                }
+                if !same_thumbnail(&original, &thumbnail, cipher.as_deref())? {
+                    return Ok(Some(false));
+                }
+                let bytes = fs::metadata(&thumbnail)?.len();
+                if !dry_run {
+                    link_thumbnail(&original, &thumbnail)?;
+                    for (paths, thumbnail_name) in entries.get(&thumbnail).into_iter().flat_map(HashMap::values) {
+                        if linker.link_exists(paths) {
+                            linker.remove_link(paths)?;
+                            linker.create_link(paths, thumbnail_name)?;
+                        }
+                    }
+                }
+                stats.bytes += bytes;
+                Ok(Some(true))
+            });
+
+            match out {
+                Ok(Some(true)) => stats.linked.push(thumbnail),
+                Ok(Some(false)) => stats.different += 1,
+                Ok(None) => stats.already_linked += 1,
+                Err(err) => {
+                    eprintln!("Error linking thumbnail {} - {err}", thumbnail.display());
+                    stats.errors += 1;
+                }
+            }
+        }
+    }
+
+    Ok(stats)
+}
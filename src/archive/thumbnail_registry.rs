@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::archive::common::{build_record_paths, disambiguate_filename};
+use crate::archive::digest::Digest;
+use crate::archive::encryption::ArchiveCipher;
+use crate::archive::layout::ArchiveLayout;
+use crate::archive::records_store::PhotoArchiveRecordsStore;
+
+/// Dimensions of the photo a thumbnail was generated from, used to tell apart two different
+/// photos that ended up with the same thumbnail name (same second and digest collision)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThumbnailFingerprint {
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct ThumbnailClaim {
+    pub file_name: String,
+    /// The thumbnail was already generated (or is being generated by another worker)
+    pub existing: bool,
+    /// Thumbnail of the same digest stored under another name (another date, source or
+    /// modification time), which a new thumbnail can be hard linked to
+    pub duplicate_of: Option<PathBuf>,
+}
+
+#[derive(Default)]
+struct KnownThumbnails {
+    fingerprints: HashMap<PathBuf, ThumbnailFingerprint>,
+    /// First thumbnail known for each digest
+    digests: HashMap<Digest, PathBuf>,
+}
+
+/// Thumbnails known to the archive, shared by the sync workers to assign collision-free names
+pub struct ThumbnailRegistry {
+    thumbnails: Mutex<KnownThumbnails>,
+}
+
+impl ThumbnailRegistry {
+    pub fn load(target_base_dir: &Path, layout: &ArchiveLayout, cipher: Option<Arc<ArchiveCipher>>) -> anyhow::Result<Self> {
+        let mut thumbnails = KnownThumbnails::default();
+        PhotoArchiveRecordsStore::with_cipher(target_base_dir, cipher).for_each(|row| {
+            if row.no_thumbnail() {
+                return;
+            }
+            let thumbnail_path = build_record_paths(layout, target_base_dir, &row)
+                .and_then(|paths| Ok(paths.img_path.join(row.thumbnail_name(layout)?)));
+            match thumbnail_path {
+                Ok(path) => {
+                    thumbnails.digests.entry(row.digest().clone()).or_insert_with(|| path.clone());
+                    thumbnails.fingerprints.insert(path, ThumbnailFingerprint { width: row.width(), height: row.height() });
+                }
+                Err(err) => eprintln!("Error building thumbnail path - {err}"),
+            }
+        })?;
+
+        Ok(Self {
+            thumbnails: Mutex::new(thumbnails),
+        })
+    }
+
+    /// Reserve a thumbnail name in `img_path` for the photo with the given fingerprint.
+    ///
+    /// The base name is used unless it belongs to a different photo, in which case a numeric
+    /// suffix is appended until a free or matching name is found.
+    pub fn claim(&self, img_path: &Path, file_name: &str, digest: &Digest, fingerprint: ThumbnailFingerprint) -> ThumbnailClaim {
+        let mut thumbnails = self.thumbnails.lock().expect("Thumbnail registry lock poisoned");
+        let mut seq = 0;
+        loop {
+            let candidate = disambiguate_filename(file_name, seq);
+            let candidate_path = img_path.join(&candidate);
+            match thumbnails.fingerprints.get(&candidate_path) {
+                Some(existing) if *existing == fingerprint => {
+                    return ThumbnailClaim { file_name: candidate, existing: true, duplicate_of: None };
+                }
+                Some(_) => seq += 1,
+                None => {
+                    let existing = candidate_path.exists();
+                    let duplicate_of = match thumbnails.digests.get(digest) {
+                        Some(duplicate) => Some(duplicate.clone()),
+                        None => {
+                            thumbnails.digests.insert(digest.clone(), candidate_path.clone());
+                            None
+                        }
+                    };
+                    thumbnails.fingerprints.insert(candidate_path, fingerprint);
+                    return ThumbnailClaim { file_name: candidate, existing, duplicate_of };
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{Datelike, Days, Months, NaiveDate};
+use serde::Serialize;
+
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::PhotoArchiveRecordsStore;
+
+/// Size of the periods photos are counted by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimelineGranularity {
+    #[default]
+    Month,
+    Day,
+}
+
+impl Display for TimelineGranularity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimelineGranularity::Month => write!(f, "month"),
+            TimelineGranularity::Day => write!(f, "day"),
+        }
+    }
+}
+
+impl FromStr for TimelineGranularity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "month" => Ok(TimelineGranularity::Month),
+            "day" => Ok(TimelineGranularity::Day),
+            other => anyhow::bail!("Unknown timeline granularity '{other}', expected one of month, day"),
+        }
+    }
+}
+
+impl TimelineGranularity {
+    /// First day of the period `date` belongs to
+    fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            TimelineGranularity::Month => date.with_day(1).expect("First day of month"),
+            TimelineGranularity::Day => date,
+        }
+    }
+
+    fn next_period(&self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            TimelineGranularity::Month => start.checked_add_months(Months::new(1)),
+            TimelineGranularity::Day => start.checked_add_days(Days::new(1)),
+        }
+    }
+
+    fn label(&self, start: NaiveDate) -> String {
+        match self {
+            TimelineGranularity::Month => start.format("%Y-%m").to_string(),
+            TimelineGranularity::Day => start.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelinePeriod {
+    /// `YYYY-MM` or `YYYY-MM-DD` depending on the granularity
+    pub period: String,
+    pub photos: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Timeline {
+    /// Every period from the oldest to the newest photo, empty ones included
+    pub periods: Vec<TimelinePeriod>,
+    pub undated: usize,
+}
+
+/// Count the archived photos by capture period, optionally of a single source.
+///
+/// Photos archived from several sources are counted once, identified by their digest.
+pub fn build_timeline(
+    target: &Path,
+    granularity: TimelineGranularity,
+    source_id: Option<&str>,
+    secret: Option<&ArchiveSecret>,
+) -> anyhow::Result<Timeline> {
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let mut counted = HashSet::new();
+    let mut counts = BTreeMap::new();
+    let mut timeline = Timeline::default();
+    PhotoArchiveRecordsStore::with_cipher(target, manifest.cipher(secret)?).for_each(|row| {
+        if source_id.is_some_and(|source_id| source_id != row.source_id()) || !counted.insert(row.digest().clone()) {
+            return;
+        }
+        match row.timestamp() {
+            Some(ts) => *counts.entry(granularity.period_start(ts.date())).or_insert(0) += 1,
+            None => timeline.undated += 1,
+        }
+    })?;
+
+    let (Some(first), Some(last)) = (counts.keys().next().copied(), counts.keys().next_back().copied()) else {
+        return Ok(timeline);
+    };
+    let mut period = Some(first);
+    while let Some(start) = period.filter(|start| *start <= last) {
+        timeline.periods.push(TimelinePeriod {
+            period: granularity.label(start),
+            photos: counts.get(&start).copied().unwrap_or(0),
+        });
+        period = granularity.next_period(start);
+    }
+    Ok(timeline)
+}
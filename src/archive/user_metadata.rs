@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::digest::Digest;
+use crate::archive::encryption::ArchiveCipher;
+
+/// File of the archive root holding what users added to their photos
+pub const USER_METADATA_FILE: &str = "user_metadata.ndjson";
+
+/// What the user added to a photo, on top of what was read from it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PhotoUserMetadata {
+    #[serde(rename = "cap", default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    /// Stars from 0 to 5, replacing the rating embedded in the photo: 0 clears it
+    #[serde(rename = "rat", default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    #[serde(rename = "fav", default, skip_serializing_if = "std::ops::Not::not")]
+    pub favorite: bool,
+    /// Free-form tags, sorted and without duplicates ignoring case
+    #[serde(rename = "tag", default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl PhotoUserMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Attach a tag, returns false when it is empty or the photo already has it
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let tag = tag.trim();
+        if tag.is_empty() || self.has_tag(tag) {
+            return false;
+        }
+        self.tags.push(String::from(tag));
+        self.tags.sort_by_key(|tag| tag.to_lowercase());
+        true
+    }
+
+    /// Detach a tag, returns false when the photo did not have it
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let before = self.tags.len();
+        self.tags.retain(|existing| !existing.eq_ignore_ascii_case(tag.trim()));
+        self.tags.len() < before
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|existing| existing.eq_ignore_ascii_case(tag))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserMetadataLine {
+    crc: Digest,
+    #[serde(flatten)]
+    metadata: PhotoUserMetadata,
+}
+
+/// Metadata set by users on the photos of the archive, keyed by digest.
+///
+/// Kept apart from the index, which syncs rewrite: it applies to every copy of a photo and
+/// follows it when its source file is moved, re-synced or archived again from another source.
+#[derive(Default)]
+pub struct UserMetadata {
+    by_digest: HashMap<Digest, PhotoUserMetadata>,
+}
+
+impl UserMetadata {
+    /// Metadata of the archive, empty when none was set yet
+    pub fn load(target_base_dir: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<Self> {
+        let mut by_digest = HashMap::new();
+        let file = match File::open(user_metadata_path(target_base_dir)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        for res_line in BufReader::new(file).lines() {
+            let line = res_line?;
+            let line = match cipher {
+                Some(cipher) => cipher.open_line(&line)?,
+                None => line,
+            };
+            let entry: UserMetadataLine = serde_json::from_str(&line)?;
+            by_digest.insert(entry.crc, entry.metadata);
+        }
+        Ok(Self { by_digest })
+    }
+
+    pub fn get(&self, digest: &Digest) -> Option<&PhotoUserMetadata> {
+        self.by_digest.get(digest)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Digest, &PhotoUserMetadata)> {
+        self.by_digest.iter()
+    }
+
+    /// Change the metadata of a photo, entries left empty are dropped
+    pub fn update(&mut self, digest: &Digest, f: impl FnOnce(&mut PhotoUserMetadata)) {
+        let metadata = self.by_digest.entry(digest.clone()).or_default();
+        f(metadata);
+        if metadata.is_empty() {
+            self.by_digest.remove(digest);
+        }
+    }
+
+    pub fn store(&self, target_base_dir: &Path, cipher: Option<&ArchiveCipher>) -> anyhow::Result<()> {
+        let path = user_metadata_path(target_base_dir);
+        let temp_path = path.with_extension("ndjson.tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        for (digest, metadata) in self.by_digest.iter() {
+            let line = serde_json::to_string(&UserMetadataLine { crc: digest.clone(), metadata: metadata.clone() })?;
+            let line = match cipher {
+                Some(cipher) => cipher.seal_line(&line)?,
+                None => line,
+            };
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+fn user_metadata_path(target_base_dir: &Path) -> PathBuf {
+    target_base_dir.join(USER_METADATA_FILE)
+}
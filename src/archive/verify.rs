@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::archive::encryption::ArchiveSecret;
+use crate::archive::manifest::ArchiveManifest;
+use crate::archive::records_store::PhotoArchiveRecordsStore;
+use crate::archive::source_records::IndexedFile;
+use crate::archive::sync::{find_mount_info, find_registered_source, scan_for_images_with_callback, ScanItem, ScanOpts, SourceCoordinates};
+use crate::repository::sources::SourcesRepo;
+
+/// Differences between a mounted source and its records in the archive, paths are relative to the source root
+#[derive(Debug, Default)]
+pub struct SourceVerification {
+    pub source_id: String,
+    /// Images of the source that were never archived
+    pub missing: Vec<PathBuf>,
+    /// Missing images below the minimum dimension, ignored by the syncs unless indexed
+    pub too_small: Vec<PathBuf>,
+    /// Archived images whose file changed since
+    pub changed: Vec<PathBuf>,
+    /// Records whose file is no longer in the source and is not yet marked as deleted
+    pub orphaned: Vec<PathBuf>,
+    pub unchanged: usize,
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+/// Compare a mounted registered source with the archive without writing anything to either of them
+pub fn verify_source(target: &Path, coord: &SourceCoordinates, scan: &ScanOpts, secret: Option<&ArchiveSecret>) -> anyhow::Result<SourceVerification> {
+    let manifest = ArchiveManifest::load_or_default(target)?;
+    let mount_info = find_mount_info(coord)?;
+    let entry = find_registered_source(&SourcesRepo::new(target.to_path_buf()), &mount_info)?;
+    let source_id = entry.id;
+    let min_dimension = entry.min_dimension.unwrap_or(manifest.min_dimension);
+
+    // Later rows replace earlier ones, as when syncing
+    let mut records = HashMap::new();
+    PhotoArchiveRecordsStore::with_cipher(target, manifest.cipher(secret)?).for_each_of_source(&source_id, |row| {
+        records.insert(row.source_path(), (IndexedFile::from_row(&row), row.deleted_at().is_some()));
+    })?;
+
+    let mut verification = SourceVerification {
+        source_id,
+        ..SourceVerification::default()
+    };
+    let source_base_dir = mount_info.mount_point;
+    scan_for_images_with_callback(source_base_dir.clone(), scan, None, &mut |item| match item {
+        ScanItem::Image(path) => {
+            let source_path = path.strip_prefix(&source_base_dir).map(Path::to_path_buf).unwrap_or_else(|_| path.clone());
+            match records.get(&source_path) {
+                Some((indexed, _)) => match indexed.is_modified(&path, manifest.digest) {
+                    Ok(true) => verification.changed.push(source_path),
+                    Ok(false) => verification.unchanged += 1,
+                    Err(err) => verification.errors.push((source_path, format!("Error checking file changes - {err}"))),
+                },
+                None => match image::image_dimensions(&path) {
+                    Ok((width, height)) if width.min(height) < min_dimension => verification.too_small.push(source_path),
+                    _ => verification.missing.push(source_path),
+                },
+            }
+        }
+        ScanItem::Error { path, cause } => verification.errors.push((path, cause)),
+        ScanItem::Directory(_) => {}
+    });
+
+    verification.orphaned = records.into_iter()
+        .filter(|(source_path, (_, deleted))| !deleted && !source_base_dir.join(source_path).exists())
+        .map(|(source_path, _)| source_path)
+        .collect();
+    for paths in [&mut verification.missing, &mut verification.too_small, &mut verification.changed, &mut verification.orphaned] {
+        paths.sort();
+    }
+    Ok(verification)
+}
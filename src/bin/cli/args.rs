@@ -1,10 +1,41 @@
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
 use clap::{Args, Parser, Subcommand};
+use chrono::NaiveDate;
+use clap_complete::Shell;
+use photo_archive::archive::catalog::CatalogFilter;
+use photo_archive::archive::digest::DigestAlgorithm;
+use photo_archive::archive::export::index::IndexExportFormat;
+use photo_archive::archive::layout::{ArchiveLayout, LinkDirNaming};
+use photo_archive::archive::originals::{OriginalsCompression, OriginalsMode};
+use photo_archive::archive::link::{LinkStrategy, SymlinkStyle};
+use photo_archive::archive::replication::ReplicationDirection;
+use photo_archive::archive::records_store::IndexSharding;
+use photo_archive::archive::retry::RetryPolicy;
+use photo_archive::archive::sync::{FilterOpts, ParallelismOpts, ScanOpts, SymlinkPolicy};
+use photo_archive::archive::sync_report::ReportFormat;
+use photo_archive::archive::thumbnail::{ResizeFilter, ThumbnailOpts};
+use photo_archive::archive::timeline::TimelineGranularity;
+
+use crate::term_image::ImageProtocol;
 
 /// Simple program to index a multi-source photo archive
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct PhotoArchiveArgs {
+    /// Fail listing the missing arguments instead of prompting for them, implied when stdin is not a terminal
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+    /// Remove data without asking for confirmation (remove-source, sync-source --prune, duplicates)
+    #[arg(short = 'y', long, visible_alias = "force", global = true)]
+    pub yes: bool,
+    /// Print a line for every file synced instead of a status line updated in place, errors are always printed
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+    /// Archive registered under this name in the configuration, instead of --target
+    #[arg(long, global = true, value_name = "NAME")]
+    pub archive: Option<String>,
     #[clap(subcommand)]
     pub subcommand: PhotoArchiveCommand,
 }
@@ -15,10 +46,245 @@ pub enum PhotoArchiveCommand {
     ListSources,
     /// Import source into archive
     ImportSource(ImportSourceCliArgs),
+    /// Inspect the sources registered in an archive
+    #[command(subcommand)]
+    Sources(SourcesCommand),
     /// Import source into archive
     SyncSource(SyncSourceCliArgs),
+    /// Process again the files that failed in a previous sync, e.g. after fixing permissions or a flaky cable
+    Retry(RetryFailedCliArgs),
     /// Remove source from archive
     RemoveSource(RemoveSourceCliArgs),
+    /// Rewrite existing links to the configured symlink style
+    Relink(RelinkCliArgs),
+    /// Recreate the missing link directories and links from the archive index
+    RepairLinks(RepairLinksCliArgs),
+    /// Replace the identical thumbnails of photos archived more than once with hard links to a single file
+    DedupeThumbs(DedupeThumbsCliArgs),
+    /// Replicate the archive to a secondary directory, copying only what changed
+    Mirror(MirrorCliArgs),
+    /// Exchange the photos missing on either side with another archive, possibly on another host
+    Replicate(ReplicateCliArgs),
+    /// Compare a mounted source with the archive, without writing anything
+    VerifySource(VerifySourceCliArgs),
+    /// Check the archive health and suggest how to fix the problems found
+    Doctor(DoctorCliArgs),
+    /// Describe the archive: format, settings, sources, index and size on disk
+    Info(InfoCliArgs),
+    /// Show what each source contributes to the archive
+    Stats(StatsCliArgs),
+    /// Print how many photos were taken in each month or day, as a bar chart or JSON
+    Timeline(TimelineCliArgs),
+    /// Serve the archive metrics to Prometheus on /metrics until interrupted
+    ServeMetrics(ServeMetricsCliArgs),
+    /// Save the manifest, sources and index files into a compressed snapshot
+    ExportMetadata(ExportMetadataCliArgs),
+    /// Write the index, sources, user metadata and albums into a database to query with SQL
+    ExportIndex(ExportIndexCliArgs),
+    /// Restore the manifest, sources and index files from a snapshot
+    RestoreMetadata(RestoreMetadataCliArgs),
+    /// Navigate the archive photos by month or source, with inline previews
+    Browse(BrowseCliArgs),
+    /// Show the photos taken in a period fullscreen, one after the other
+    Slideshow(SlideshowCliArgs),
+    /// Show the paths of a photo and open it with the system viewer
+    Open(OpenCliArgs),
+    /// Review the photos archived more than once and drop the unwanted copies
+    Duplicates(DuplicatesCliArgs),
+    /// Set, edit or clear the caption of a photo, shared by all its copies
+    Caption(CaptionCliArgs),
+    /// Rate a photo or mark it favorite, shared by all its copies
+    Rate(RateCliArgs),
+    /// Attach or detach free-form tags to a photo, its current tags are printed without changes
+    Tag(TagCliArgs),
+    /// List the files that failed in a past sync, grouped by kind of error
+    Errors(ErrorsCliArgs),
+    /// List the past syncs of the archive and show the details of one
+    #[command(subcommand)]
+    History(HistoryCommand),
+    /// Organize photos into albums, on top of the date and source layout
+    #[command(subcommand)]
+    Album(AlbumCommand),
+    /// Upload the selected photos to an Immich server, with their dates, GPS position and tags
+    ExportImmich(ExportImmichCliArgs),
+    /// Copy the selected photos with YAML sidecars into a PhotoPrism import folder
+    ExportPhotoprism(ExportPhotoprismCliArgs),
+    /// Extract the videos of the selected motion photos from their originals into a folder
+    ExportMotionClips(ExportMotionClipsCliArgs),
+    /// Write a PDF per year with monthly sections, representative photos, counts and a map of the geotagged ones
+    ExportYearReview(ExportYearReviewCliArgs),
+    /// Print the shell completion script (bash, zsh, fish, elvish, powershell)
+    Completions(CompletionsCliArgs),
+    /// Print the ids of the registered sources, used by the completion scripts
+    #[command(hide = true)]
+    CompleteSourceIds(CompleteSourceIdsCliArgs),
+    /// Serve the replication protocol on stdin/stdout, started by `replicate` on the remote host
+    #[command(hide = true)]
+    ReplicaServe(ReplicaServeCliArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SourcesCommand {
+    /// Show where each registered source is mounted, its free space and its last sync
+    Status(SourcesStatusCliArgs),
+    /// Let another partition hold a registered source, e.g. after its medium was reformatted
+    Migrate(SourcesMigrateCliArgs),
+    /// Authorize access to the account of an API source, e.g. gphotos://me
+    Login(SourcesLoginCliArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// List the past syncs, most recent first, with their counts
+    List(HistoryListCliArgs),
+    /// Show the options, counts and failures of a sync
+    Show(HistoryShowCliArgs),
+    /// Write the report of a sync into the reports directory of the archive
+    Report(HistoryReportCliArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AlbumCommand {
+    /// List the albums with how many photos they hold
+    List(AlbumListCliArgs),
+    /// Create an empty album
+    Create(AlbumNameCliArgs),
+    /// Delete an album, its photos stay in the archive
+    Delete(AlbumNameCliArgs),
+    /// Add the photos matching the filters to an album, e.g. --digest or --date
+    Add(AlbumPhotosCliArgs),
+    /// Take the photos matching the filters out of an album
+    Remove(AlbumPhotosCliArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryListCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Only list the syncs of this source id
+    #[arg(short, long)]
+    pub source: Option<String>,
+    /// Number of syncs listed
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub limit: usize,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ErrorsCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Number of the sync as listed by `history list`, the last one by default
+    #[arg(long)]
+    pub run: Option<u64>,
+    /// Take the last sync of this source id
+    #[arg(short, long, conflicts_with = "run")]
+    pub source: Option<String>,
+    /// Print only the paths of the failed files, one per line
+    #[arg(long)]
+    pub paths: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryShowCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Number of the sync, as listed by `history list`
+    pub id: u64,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryReportCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Number of the sync, the last one by default
+    pub id: Option<u64>,
+    /// Format of the report (markdown, html)
+    #[arg(long, default_value_t = ReportFormat::Html)]
+    pub format: ReportFormat,
+    /// Open the report once written
+    #[arg(long)]
+    pub open: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct AlbumListCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct AlbumNameCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Name of the album
+    pub name: String,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct AlbumPhotosCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Name of the album
+    #[arg(value_name = "NAME")]
+    pub album_name: String,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct SourcesStatusCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct SourcesLoginCliArgs {
+    /// Url of the remote source
+    pub source_url: String,
+    /// Id of the OAuth client registered with the provider
+    #[arg(long)]
+    pub client_id: String,
+    /// Secret of the OAuth client registered with the provider
+    #[arg(long)]
+    pub client_secret: String,
+    /// File storing the accounts of the API sources (defaults to remotes.toml of the user configuration directory)
+    #[arg(long)]
+    pub remotes_config: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct SourcesMigrateCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Id of the registered source
+    #[arg(short, long)]
+    pub source_id: String,
+    /// Id of the partition now holding the source, chosen among the mounted unregistered ones when missing
+    #[arg(short, long)]
+    pub partition_id: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -38,9 +304,97 @@ pub struct ImportSourceCliArgs {
     /// Group of the source to import
     #[arg(long)]
     pub source_tags: Vec<String>,
-    /// Archive path
+    /// TOML file listing the sources to import, as [[source]] tables with one of id, path or url and a name, group and tags
+    #[arg(long, conflicts_with_all = ["source_id", "source_path", "source_url", "source_name", "source_group", "source_tags"])]
+    pub from_file: Option<PathBuf>,
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub remote: RemoteSourceCliArgs,
+    /// Create the archive when the target is not one yet, imports into other directories are refused
+    #[arg(long)]
+    pub init: bool,
+    /// Digest algorithm used when creating a new archive (crc32, crc32-file, xxh64, blake3)
+    #[arg(long)]
+    pub digest: Option<DigestAlgorithm>,
+    /// Link strategy used when creating a new archive (symlink, hardlink, copy, ref)
+    #[arg(long)]
+    pub link_strategy: Option<LinkStrategy>,
+    /// Symlink style used when creating a new archive (relative, absolute, root-relative)
+    #[arg(long)]
+    pub symlink_style: Option<SymlinkStyle>,
+    /// Date directories layout used when creating a new archive (day, month, year, flat, or a template such as '{year}/{month}')
+    #[arg(long)]
+    pub layout: Option<ArchiveLayout>,
+    /// Index files sharding used when creating a new archive (yearly, monthly), monthly suits archives with very large years
+    #[arg(long)]
+    pub index_sharding: Option<IndexSharding>,
+    /// Link directory names used when creating a new archive (crc, readable, or a template such as '{source_name}/{orig_dir}')
+    #[arg(long)]
+    pub link_dirs: Option<LinkDirNaming>,
+    /// Date photos without EXIF timestamp by their file modification time when creating a new archive
+    #[arg(long)]
+    pub undated_by_mtime: bool,
+    /// Files failing to be archived tolerated before exiting with status 3, any error counts by default
+    #[arg(long, default_value_t = 0)]
+    pub max_errors: u64,
+    #[command(flatten)]
+    pub originals: OriginalsCliArgs,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+    #[command(flatten)]
+    pub thumbnail: ThumbnailCliArgs,
+    #[command(flatten)]
+    pub parallelism: ParallelismCliArgs,
+    #[command(flatten)]
+    pub retry: RetryCliArgs,
+    #[command(flatten)]
+    pub scan: ScanCliArgs,
+    #[command(flatten)]
+    pub filter: FilterCliArgs,
+    #[command(flatten)]
+    pub report: SyncReportCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RetryFailedCliArgs {
+    /// Number of the sync as listed by `history list`
+    #[arg(long, required_unless_present = "last", conflicts_with = "last")]
+    pub run: Option<u64>,
+    /// Retry the failures of the last sync
+    #[arg(long)]
+    pub last: bool,
+    /// Id of the partition holding the source, defaults to the source of the sync
+    #[arg(short, long)]
+    pub source_id: Option<String>,
+    /// Path of the source, when it is not mounted where the sync found it
+    #[arg(long)]
+    pub source_path: Option<String>,
+    #[command(flatten)]
+    pub remote: RemoteSourceCliArgs,
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
     #[arg(short, long)]
-    pub target: PathBuf,
+    pub target: Option<PathBuf>,
+    /// Files failing to be archived tolerated before exiting with status 3, any error counts by default
+    #[arg(long, default_value_t = 0)]
+    pub max_errors: u64,
+    #[command(flatten)]
+    pub originals: OriginalsCliArgs,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+    #[command(flatten)]
+    pub thumbnail: ThumbnailCliArgs,
+    #[command(flatten)]
+    pub parallelism: ParallelismCliArgs,
+    #[command(flatten)]
+    pub retry: RetryCliArgs,
+    #[command(flatten)]
+    pub scan: ScanCliArgs,
+    #[command(flatten)]
+    pub filter: FilterCliArgs,
+    #[command(flatten)]
+    pub report: SyncReportCliArgs,
 }
 
 #[derive(Args, Debug)]
@@ -51,9 +405,85 @@ pub struct SyncSourceCliArgs {
     /// Path of the source to import
     #[arg(long)]
     pub source_path: Option<String>,
-    /// Archive path
+    /// Sync every registered source currently mounted, one after the other, and print a combined report
+    #[arg(long, conflicts_with_all = ["source_id", "source_path", "source_url"])]
+    pub all: bool,
+    #[command(flatten)]
+    pub remote: RemoteSourceCliArgs,
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
     #[arg(short, long)]
-    pub target: PathBuf,
+    pub target: Option<PathBuf>,
+    /// Drop records, links and orphaned thumbnails of files deleted from the source instead of marking them as deleted
+    #[arg(long)]
+    pub prune: bool,
+    /// Look for the link of every unchanged file, recreating missing ones, instead of skipping the files the last sync found archived
+    #[arg(long)]
+    pub full_check: bool,
+    /// Read every directory of the source, instead of only the ones changed since the last sync, e.g. after files were edited in place
+    #[arg(long)]
+    pub full_scan: bool,
+    /// Files failing to be archived tolerated before exiting with status 3, any error counts by default
+    #[arg(long, default_value_t = 0)]
+    pub max_errors: u64,
+    #[command(flatten)]
+    pub originals: OriginalsCliArgs,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+    #[command(flatten)]
+    pub thumbnail: ThumbnailCliArgs,
+    #[command(flatten)]
+    pub parallelism: ParallelismCliArgs,
+    #[command(flatten)]
+    pub retry: RetryCliArgs,
+    #[command(flatten)]
+    pub scan: ScanCliArgs,
+    #[command(flatten)]
+    pub filter: FilterCliArgs,
+    #[command(flatten)]
+    pub report: SyncReportCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct OriginalsCliArgs {
+    /// Store original files besides thumbnails (none, per-source, content-addressed)
+    #[arg(long)]
+    pub store_originals: Option<OriginalsMode>,
+    /// Compress newly stored originals with zstd (none, zstd)
+    #[arg(long)]
+    pub compress_originals: Option<OriginalsCompression>,
+}
+
+#[derive(Args, Debug)]
+pub struct ThumbnailCliArgs {
+    /// Copy the whole EXIF metadata (GPS position, camera serial, ...) into generated thumbnails,
+    /// by default they only carry the orientation of the photos
+    #[arg(long)]
+    pub keep_exif: bool,
+    /// Filter used to downscale photos into thumbnails (nearest, triangle, lanczos3)
+    #[arg(long, default_value_t = ResizeFilter::default())]
+    pub resize_filter: ResizeFilter,
+    /// Decode JPEGs at reduced scale, faster but pixel digests differ from full decodes
+    #[arg(long)]
+    pub fast_decode: bool,
+    /// Read back and decode every generated thumbnail, reporting write failures as errors
+    #[arg(long)]
+    pub verify_thumbnails: bool,
+    /// Generate thumbnails from the previews embedded in the EXIF data when large enough, much
+    /// faster on slow CPUs; ignored with pixel digests (crc32)
+    #[arg(long)]
+    pub exif_preview: bool,
+}
+
+impl From<ThumbnailCliArgs> for ThumbnailOpts {
+    fn from(args: ThumbnailCliArgs) -> Self {
+        Self {
+            keep_exif: args.keep_exif,
+            filter: args.resize_filter,
+            fast_decode: args.fast_decode,
+            verify: args.verify_thumbnails,
+            exif_preview: args.exif_preview,
+        }
+    }
 }
 
 #[derive(Args, Debug)]
@@ -61,7 +491,602 @@ pub struct RemoveSourceCliArgs {
     /// Id of the source to remove
     #[arg(short, long)]
     pub source_id: Option<String>,
-    /// Archive path
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RelinkCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// New symlink style stored in the archive manifest (relative, absolute, root-relative)
+    #[arg(long)]
+    pub symlink_style: Option<SymlinkStyle>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RepairLinksCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DedupeThumbsCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Only print the thumbnails that would be replaced and the space given back
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct MirrorCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Path of the mirror, created if missing
+    #[arg(short, long)]
+    pub mirror: PathBuf,
+    /// Hash the files already on the mirror and copy again the corrupted ones
+    #[arg(long)]
+    pub verify: bool,
+    /// Remove from the mirror the files no longer in the archive
+    #[arg(long)]
+    pub delete: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplicateCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Other archive, either a local path or `[user@]host:path` to reach it through ssh
+    #[arg(short, long)]
+    pub remote: String,
+    /// Which way photos are copied (pull, push, both)
+    #[arg(long, default_value_t = ReplicationDirection::default())]
+    pub direction: ReplicationDirection,
+    /// Command running this program on the remote host
+    #[arg(long, default_value = "cli")]
+    pub remote_command: String,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifySourceCliArgs {
+    /// Id of the source to verify
+    #[arg(short, long)]
+    pub source_id: Option<String>,
+    /// Path of the source to verify
+    #[arg(long)]
+    pub source_path: Option<String>,
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub scan: ScanCliArgs,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DoctorCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct InfoCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct StatsCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Id of the source to show, all the registered sources when missing
+    #[arg(short, long)]
+    pub source: Option<String>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct TimelineCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Period photos are counted by (month, day)
+    #[arg(long, default_value_t = TimelineGranularity::default())]
+    pub granularity: TimelineGranularity,
+    /// Only count the photos of this source
+    #[arg(short, long)]
+    pub source: Option<String>,
+    /// Print the counts as JSON instead of a bar chart
+    #[arg(long)]
+    pub json: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct BrowseCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
     #[arg(short, long)]
-    pub target: PathBuf,
-}
\ No newline at end of file
+    pub target: Option<PathBuf>,
+    /// Terminal image protocol used for the previews (auto, kitty, iterm, sixel, none)
+    #[arg(long, default_value_t = ImageProtocol::default())]
+    pub image_protocol: ImageProtocol,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct SlideshowCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Seconds each photo is shown for
+    #[arg(long, default_value_t = 5)]
+    pub interval: u64,
+    /// Show the originals of the mounted sources instead of the thumbnails
+    #[arg(long)]
+    pub originals: bool,
+    /// External viewer command (e.g. "feh -F"), photos are drawn in the terminal when missing
+    #[arg(long)]
+    pub viewer: Option<String>,
+    /// Terminal image protocol (auto, kitty, iterm, sixel, none)
+    #[arg(long, default_value_t = ImageProtocol::default())]
+    pub image_protocol: ImageProtocol,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct OpenCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Only print the paths, without launching the viewer
+    #[arg(long)]
+    pub print: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CaptionCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// New caption of the photo, the current one is prompted for editing when missing
+    #[arg(long, conflicts_with = "clear")]
+    pub set: Option<String>,
+    /// Drop the caption, the description embedded in the photo is shown again
+    #[arg(long)]
+    pub clear: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RateCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Stars of the photo, 0 clears the rating embedded in the photo
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=5))]
+    pub stars: Option<u8>,
+    /// Drop the rating set by the user, the one embedded in the photo is shown again
+    #[arg(long, conflicts_with = "stars")]
+    pub clear: bool,
+    /// Mark the photo favorite
+    #[arg(long, conflicts_with = "unfavorite")]
+    pub favorite: bool,
+    /// Drop the favorite mark of the photo
+    #[arg(long)]
+    pub unfavorite: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct TagCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Tag to attach, can be repeated
+    #[arg(long)]
+    pub add: Vec<String>,
+    /// Tag to detach, can be repeated
+    #[arg(long)]
+    pub remove: Vec<String>,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportImmichCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Url of the Immich server, e.g. https://photos.example.com
+    #[arg(long)]
+    pub server: String,
+    /// API key of the receiving user, read from $IMMICH_API_KEY or prompted when missing
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// Skip the photos whose original is not available instead of uploading their thumbnail
+    #[arg(long)]
+    pub originals_only: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportPhotoprismCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Import folder of PhotoPrism, e.g. storage/import of its installation
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Skip the photos whose original is not available instead of copying their thumbnail
+    #[arg(long)]
+    pub originals_only: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportMotionClipsCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Folder receiving the videos
+    #[arg(short, long)]
+    pub output: PathBuf,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DuplicatesCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    #[command(flatten)]
+    pub filter: CatalogFilterCliArgs,
+    /// Only print the copies the review would drop
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CatalogFilterCliArgs {
+    /// First capture day included (YYYY-MM-DD)
+    #[arg(long, conflicts_with = "date")]
+    pub from: Option<NaiveDate>,
+    /// Last capture day included (YYYY-MM-DD)
+    #[arg(long, conflicts_with = "date")]
+    pub to: Option<NaiveDate>,
+    /// Capture day of the photos (YYYY-MM-DD)
+    #[arg(long)]
+    pub date: Option<NaiveDate>,
+    /// Only the photos of this source
+    #[arg(short, long)]
+    pub source: Option<String>,
+    /// File name of the photo
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Digest of the photo
+    #[arg(long)]
+    pub digest: Option<String>,
+    /// Part of the make or model of the camera, e.g. 'canon' or 'EOS 5D'
+    #[arg(long)]
+    pub camera: Option<String>,
+    /// Only the photos rated with at least this many stars
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+    pub min_rating: Option<u8>,
+    /// Only the photos marked favorite
+    #[arg(long)]
+    pub favorites: bool,
+    /// Only the motion photos, carrying a video clip
+    #[arg(long)]
+    pub motion: bool,
+    /// Only the photos of this album
+    #[arg(long)]
+    pub album: Option<String>,
+    /// Only the photos with this tag, can be repeated to require several
+    #[arg(long = "tag")]
+    pub tags: Vec<String>,
+}
+
+impl From<CatalogFilterCliArgs> for CatalogFilter {
+    fn from(args: CatalogFilterCliArgs) -> Self {
+        Self {
+            from: args.date.or(args.from),
+            to: args.date.or(args.to),
+            source_id: args.source,
+            name: args.name,
+            digest: args.digest,
+            camera: args.camera,
+            min_rating: args.min_rating,
+            favorites: args.favorites,
+            motion: args.motion,
+            album: args.album,
+            tags: args.tags,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CompletionsCliArgs {
+    /// Shell to generate the completions for
+    pub shell: Shell,
+}
+
+#[derive(Args, Debug)]
+pub struct CompleteSourceIdsCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportMetadataCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Snapshot file to write (.tar.zst)
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeMetricsCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Address and port to listen on
+    #[arg(long, default_value = "127.0.0.1:9898")]
+    pub listen: String,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportYearReviewCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Directory the year-review-<year>.pdf files are written to
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Year to review, can be repeated; every year with dated photos by default
+    #[arg(long = "year")]
+    pub years: Vec<i32>,
+    /// Only the photos of this source
+    #[arg(short, long)]
+    pub source: Option<String>,
+    /// Photos shown in the section of each month
+    #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u16).range(1..))]
+    pub photos_per_month: u16,
+    /// Replace the documents already in the output directory
+    #[arg(long)]
+    pub overwrite: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportIndexCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Database file to write, e.g. index.sqlite
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Format of the database (sqlite)
+    #[arg(long, default_value_t = IndexExportFormat::default())]
+    pub format: IndexExportFormat,
+    /// Replace the database file when it already exists
+    #[arg(long)]
+    pub overwrite: bool,
+    #[command(flatten)]
+    pub encryption: EncryptionCliArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RestoreMetadataCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+    /// Snapshot file to restore
+    #[arg(short, long)]
+    pub input: PathBuf,
+    /// Replace the metadata already in the archive
+    #[arg(long)]
+    pub overwrite: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplicaServeCliArgs {
+    /// Archive path, defaults to the archive holding the current directory, then to $PHOTO_ARCHIVE_HOME or to the default archive of the configuration
+    #[arg(short, long)]
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct RemoteSourceCliArgs {
+    /// Url of a remote source, e.g. davs://user@cloud.example.com/remote.php/dav/files/user/Photos, gphotos://me (photos picked in the browser), dropbox://me/Camera Uploads, onedrive://me/Pictures, rclone://remote:path or adb:// (Android device)
+    #[arg(long, conflicts_with_all = ["source_id", "source_path"])]
+    pub source_url: Option<String>,
+    /// Prompt for the password of the remote source, when not part of its url
+    #[arg(long, requires = "source_url")]
+    pub source_password: bool,
+    /// Directory remote sources are downloaded into before being synchronized (defaults to the user cache directory)
+    #[arg(long, requires = "source_url")]
+    pub staging_dir: Option<PathBuf>,
+    /// File storing the accounts of the API sources (defaults to remotes.toml of the user configuration directory)
+    #[arg(long, requires = "source_url")]
+    pub remotes_config: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct EncryptionCliArgs {
+    /// File whose content is the secret of an encrypted archive, creating an archive with it enables encryption
+    #[arg(long, conflicts_with = "passphrase")]
+    pub key_file: Option<PathBuf>,
+    /// Prompt for the passphrase of an encrypted archive, creating an archive with it enables encryption
+    #[arg(long)]
+    pub passphrase: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ParallelismCliArgs {
+    /// Minimum number of image processing workers
+    #[arg(long)]
+    pub min_workers: Option<usize>,
+    /// Maximum number of image processing workers (defaults to the number of CPUs)
+    #[arg(long)]
+    pub max_workers: Option<usize>,
+    /// Maximum memory (in MiB) used by concurrent image decodes
+    #[arg(long)]
+    pub memory_budget: Option<u64>,
+    /// Run with the lowest CPU priority and idle I/O scheduling, so that background syncs
+    /// do not slow down the desktop
+    #[arg(long)]
+    pub nice: bool,
+}
+
+impl From<ParallelismCliArgs> for ParallelismOpts {
+    fn from(args: ParallelismCliArgs) -> Self {
+        let defaults = ParallelismOpts::default();
+        Self {
+            min_workers: args.min_workers.unwrap_or(defaults.min_workers),
+            max_workers: args.max_workers.unwrap_or(defaults.max_workers),
+            nice: args.nice,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SyncReportCliArgs {
+    /// Write a report of each sync into the reports directory of the archive (markdown, html)
+    #[arg(long)]
+    pub report: Option<ReportFormat>,
+    /// Open the reports once written, implies --report html when no format is given
+    #[arg(long)]
+    pub open_report: bool,
+}
+
+impl SyncReportCliArgs {
+    pub fn format(&self) -> Option<ReportFormat> {
+        self.report.or(self.open_report.then_some(ReportFormat::Html))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct RetryCliArgs {
+    /// Number of times a photo is read again after a transient I/O error
+    #[arg(long)]
+    pub retries: Option<u32>,
+    /// Delay (in milliseconds) before the first retry, doubled at each following one
+    #[arg(long)]
+    pub retry_backoff: Option<u64>,
+}
+
+impl From<RetryCliArgs> for RetryPolicy {
+    fn from(args: RetryCliArgs) -> Self {
+        let defaults = RetryPolicy::default();
+        Self {
+            retries: args.retries.unwrap_or(defaults.retries),
+            backoff: args.retry_backoff.map(Duration::from_millis).unwrap_or(defaults.backoff),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ScanCliArgs {
+    /// Also scan hidden directories and system ones such as @eaDir, $RECYCLE.BIN or System Volume Information
+    #[arg(long)]
+    pub include_junk_dirs: bool,
+    /// Symlinked directories to scan (never, within-source, all)
+    #[arg(long, default_value_t = SymlinkPolicy::default())]
+    pub follow_symlinks: SymlinkPolicy,
+    /// Number of directories read concurrently while scanning the source
+    #[arg(long)]
+    pub scan_threads: Option<NonZeroUsize>,
+}
+
+impl From<ScanCliArgs> for ScanOpts {
+    fn from(args: ScanCliArgs) -> Self {
+        let defaults = ScanOpts::default();
+        Self {
+            include_junk_dirs: args.include_junk_dirs,
+            follow_symlinks: args.follow_symlinks,
+            threads: args.scan_threads.unwrap_or(defaults.threads),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct FilterCliArgs {
+    /// Ignore files larger than this size (in MiB)
+    #[arg(long)]
+    pub max_file_size: Option<u64>,
+    /// Ignore images whose smaller side is below this size (in pixels, 0 keeps them all), remembered for the source
+    #[arg(long)]
+    pub min_dimension: Option<u32>,
+    /// Record the images below the minimum dimension in the index, without generating their thumbnails
+    #[arg(long)]
+    pub index_small_images: bool,
+}
+
+impl From<FilterCliArgs> for FilterOpts {
+    fn from(args: FilterCliArgs) -> Self {
+        Self {
+            max_file_size: args.max_file_size.map(|mb| mb * 1024 * 1024),
+            min_dimension: args.min_dimension,
+            index_small: args.index_small_images,
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::io::{stdout, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use photo_archive::archive::catalog::{Catalog, CatalogEntry};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::term_image::ImageProtocol;
+use crate::viewer::{copy_to_clipboard, open_with_system_viewer};
+
+const PAGE_SIZE: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Grouping {
+    Month,
+    Source,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Groups,
+    Photos,
+}
+
+struct Group {
+    label: String,
+    /// Indexes of the catalog entries of the group, in catalog order
+    entries: Vec<usize>,
+}
+
+struct Browser {
+    catalog: Catalog,
+    protocol: ImageProtocol,
+    grouping: Grouping,
+    groups: Vec<Group>,
+    focus: Focus,
+    group_state: ListState,
+    photo_state: ListState,
+    /// Area of the preview drawn by the last frame
+    preview_area: Rect,
+    /// The selected photo changed since its preview was drawn
+    preview_outdated: bool,
+    status: String,
+}
+
+/// Navigate the archive by month or by source in a terminal UI, previewing the thumbnails
+/// inline when the terminal supports an image protocol
+pub fn browse(catalog: Catalog, protocol: ImageProtocol) -> anyhow::Result<()> {
+    if catalog.entries().is_empty() {
+        anyhow::bail!("Archive holds no photo");
+    }
+
+    let mut browser = Browser {
+        catalog,
+        protocol: protocol.resolve(),
+        grouping: Grouping::Month,
+        groups: Vec::new(),
+        focus: Focus::Groups,
+        group_state: ListState::default(),
+        photo_state: ListState::default(),
+        preview_area: Rect::default(),
+        preview_outdated: true,
+        status: String::from("Tab: group by month/source  Enter: open  y: copy path  q: quit"),
+    };
+    browser.regroup();
+
+    let mut terminal = ratatui::init();
+    let out = browser.run(&mut terminal);
+    browser.protocol.clear(&mut stdout())?;
+    ratatui::restore();
+    out
+}
+
+impl Browser {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        loop {
+            if self.preview_outdated && self.protocol != ImageProtocol::Kitty {
+                // Images drawn with escape sequences are not tracked by the UI, a full repaint wipes the previous one
+                terminal.clear()?;
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if self.preview_outdated {
+                self.draw_preview();
+                self.preview_outdated = false;
+            }
+
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(..) => {
+                    self.preview_outdated = true;
+                    continue;
+                }
+                _ => continue,
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Tab => {
+                    self.grouping = match self.grouping {
+                        Grouping::Month => Grouping::Source,
+                        Grouping::Source => Grouping::Month,
+                    };
+                    self.regroup();
+                }
+                KeyCode::Left | KeyCode::Char('h') => self.focus = Focus::Groups,
+                KeyCode::Right | KeyCode::Char('l') => self.focus = Focus::Photos,
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::PageUp => self.move_selection(-(PAGE_SIZE as isize)),
+                KeyCode::PageDown => self.move_selection(PAGE_SIZE as isize),
+                KeyCode::Home => self.move_selection(isize::MIN),
+                KeyCode::End => self.move_selection(isize::MAX),
+                KeyCode::Enter | KeyCode::Char('o') => self.open_selected(),
+                KeyCode::Char('y') => self.copy_selected_path(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Rebuild the groups after the grouping changed, selecting the first photo of the first group
+    fn regroup(&mut self) {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut labels = self.catalog.entries().iter()
+            .enumerate()
+            .map(|(idx, entry)| (self.group_label(entry), idx))
+            .collect::<Vec<_>>();
+        if self.grouping == Grouping::Source {
+            labels.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        for (label, idx) in labels {
+            match groups.last_mut().filter(|group| group.label == label) {
+                Some(group) => group.entries.push(idx),
+                None => groups.push(Group { label, entries: vec![idx] }),
+            }
+        }
+
+        self.groups = groups;
+        self.group_state.select(Some(0));
+        self.photo_state.select(Some(0));
+        self.preview_outdated = true;
+    }
+
+    fn group_label(&self, entry: &CatalogEntry) -> String {
+        match self.grouping {
+            Grouping::Month => entry.timestamp
+                .map(|ts| ts.format("%Y-%m").to_string())
+                .unwrap_or_else(|| String::from("no date")),
+            Grouping::Source => String::from(self.catalog.source_name(entry)),
+        }
+    }
+
+    fn selected_group(&self) -> &Group {
+        &self.groups[self.group_state.selected().unwrap_or(0).min(self.groups.len() - 1)]
+    }
+
+    fn selected_entry(&self) -> &CatalogEntry {
+        let group = self.selected_group();
+        let idx = group.entries[self.photo_state.selected().unwrap_or(0).min(group.entries.len() - 1)];
+        &self.catalog.entries()[idx]
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let photos = self.selected_group().entries.len();
+        let (state, len) = match self.focus {
+            Focus::Groups => (&mut self.group_state, self.groups.len()),
+            Focus::Photos => (&mut self.photo_state, photos),
+        };
+        let current = state.selected().unwrap_or(0);
+        let next = current.saturating_add_signed(delta).min(len - 1);
+        if next == current {
+            return;
+        }
+        state.select(Some(next));
+        if self.focus == Focus::Groups {
+            self.photo_state.select(Some(0));
+        }
+        self.preview_outdated = true;
+    }
+
+    fn open_selected(&mut self) {
+        let entry = self.selected_entry();
+        self.status = match self.catalog.viewable_file(entry).and_then(|path| open_with_system_viewer(&path).map(|_| path)) {
+            Ok(path) => format!("Opened {}", path.display()),
+            Err(err) => format!("Error opening {} - {err}", entry.file_name()),
+        };
+    }
+
+    fn copy_selected_path(&mut self) {
+        let entry = self.selected_entry();
+        let Some(path) = self.catalog.mounted_original(entry).or_else(|| entry.thumbnail_path.clone()) else {
+            self.status = format!("{} has neither a mounted original nor a thumbnail", entry.file_name());
+            return;
+        };
+        self.status = match copy_to_clipboard(&mut stdout(), &path.to_string_lossy()) {
+            Ok(()) => format!("Copied {}", path.display()),
+            Err(err) => format!("Error copying path - {err}"),
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, status_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [groups_area, photos_area, preview_area] = Layout::horizontal([
+            Constraint::Percentage(20),
+            Constraint::Percentage(35),
+            Constraint::Percentage(45),
+        ]).areas(main_area);
+        let [image_area, details_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(8)]).areas(preview_area);
+
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let focused_block = |title: &str, focused: bool| {
+            let block = Block::bordered().title(String::from(title));
+            if focused { block.border_style(Style::default().add_modifier(Modifier::BOLD)) } else { block }
+        };
+
+        let groups_title = match self.grouping {
+            Grouping::Month => "Months",
+            Grouping::Source => "Sources",
+        };
+        let groups = self.groups.iter()
+            .map(|group| ListItem::new(format!("{} ({})", group.label, group.entries.len())))
+            .collect::<Vec<_>>();
+        frame.render_stateful_widget(
+            List::new(groups).block(focused_block(groups_title, self.focus == Focus::Groups)).highlight_style(highlight),
+            groups_area,
+            &mut self.group_state,
+        );
+
+        let photos = self.selected_group().entries.iter()
+            .map(|idx| {
+                let entry = &self.catalog.entries()[*idx];
+                let date = entry.timestamp.map(|ts| ts.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+                ListItem::new(format!("{date:16} {}", entry.file_name()))
+            })
+            .collect::<Vec<_>>();
+        frame.render_stateful_widget(
+            List::new(photos).block(focused_block("Photos", self.focus == Focus::Photos)).highlight_style(highlight),
+            photos_area,
+            &mut self.photo_state,
+        );
+
+        let image_block = Block::bordered().title("Preview");
+        self.preview_area = image_block.inner(image_area);
+        if self.protocol == ImageProtocol::None {
+            frame.render_widget(Paragraph::new("Terminal without image support").block(image_block), image_area);
+        } else {
+            frame.render_widget(image_block, image_area);
+        }
+
+        let entry = self.selected_entry();
+        let original = match self.catalog.mounted_original(entry) {
+            Some(path) => path.display().to_string(),
+            None => String::from("source not mounted"),
+        };
+        let details = vec![
+            Line::from(format!("Source:   {} ({})", self.catalog.source_name(entry), entry.source_id)),
+            Line::from(format!("Path:     {}{}", entry.source_path.display(), if entry.deleted { " (deleted)" } else { "" })),
+            Line::from(format!("Date:     {}", entry.timestamp.map(|ts| ts.to_string()).unwrap_or_else(|| String::from("unknown")))),
+            Line::from(format!("Size:     {}x{}, {:.1} MiB", entry.width, entry.height, entry.size as f64 / (1024.0 * 1024.0))),
+            Line::from(format!("Digest:   {}", entry.digest)),
+            Line::from(format!("Original: {original}")),
+        ];
+        frame.render_widget(Paragraph::new(details).block(Block::bordered().title("Details")), details_area);
+        frame.render_widget(Paragraph::new(self.status.as_str()), status_area);
+    }
+
+    fn draw_preview(&mut self) {
+        let mut out = stdout();
+        let area = self.preview_area;
+        let drawn = self.protocol.clear(&mut out).and_then(|_| {
+            let content = self.catalog.read_thumbnail(self.selected_entry())?;
+            self.protocol.draw(&mut out, &content, area.x, area.y, area.width, area.height)
+        });
+        if let Err(err) = drawn {
+            self.status = format!("Error drawing preview - {err}");
+        }
+        let _ = out.flush();
+    }
+}
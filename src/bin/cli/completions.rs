@@ -0,0 +1,167 @@
+use std::io::Write;
+use std::path::Path;
+
+use clap::{Command, CommandFactory};
+use clap_complete::Shell;
+use photo_archive::repository::sources::SourcesRepo;
+
+use crate::args::{CompletionsCliArgs, PhotoArchiveArgs};
+
+/// Subcommands whose source id argument names an already registered source
+const REGISTERED_SOURCE_COMMANDS: &[&str] = &["sync-source", "remove-source", "verify-source", "sources", "stats", "timeline", "slideshow", "open"];
+const SOURCE_ID_ARGS: [&str; 2] = ["source_id", "source"];
+
+/// Print the completion script of `shell`, the static one generated by clap followed by the
+/// functions completing the ids of the sources registered in the archive given with `--target`,
+/// or in the default one
+pub fn print_completions(args: CompletionsCliArgs) -> anyhow::Result<()> {
+    let mut cmd = PhotoArchiveArgs::command();
+    let bin_name = std::env::args()
+        .next()
+        .and_then(|arg| Path::new(&arg).file_name().map(|name| name.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| String::from(cmd.get_name()));
+
+    let mut out = std::io::stdout().lock();
+    clap_complete::generate(args.shell, &mut cmd, &bin_name, &mut out);
+    let (flags, commands) = source_id_flags(&cmd);
+    let script = match args.shell {
+        Shell::Bash => bash_source_ids(&bin_name, &flags, &commands),
+        Shell::Zsh => zsh_source_ids(&bin_name, &flags, &commands),
+        Shell::Fish => fish_source_ids(&bin_name, &flags, &commands),
+        _ => return Ok(()),
+    };
+    out.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// Print the ids of the registered sources, one per line, for the completion scripts
+pub fn print_source_ids(target: &Path) -> anyhow::Result<()> {
+    for source in SourcesRepo::new(target.to_path_buf()).all()? {
+        println!("{}", source.id);
+    }
+    Ok(())
+}
+
+/// Flags (`-s`, `--source-id`, ...) taking a registered source id, and the subcommands having them
+/// directly or through their own subcommands
+fn source_id_flags(cmd: &Command) -> (Vec<String>, Vec<String>) {
+    let mut flags = Vec::new();
+    let mut commands = Vec::new();
+    for subcommand in cmd.get_subcommands().filter(|subcommand| REGISTERED_SOURCE_COMMANDS.contains(&subcommand.get_name())) {
+        commands.push(String::from(subcommand.get_name()));
+        let arguments = subcommand.get_arguments()
+            .chain(subcommand.get_subcommands().flat_map(Command::get_arguments));
+        for arg in arguments.filter(|arg| SOURCE_ID_ARGS.contains(&arg.get_id().as_str())) {
+            let arg_flags = arg.get_short().map(|short| format!("-{short}"))
+                .into_iter()
+                .chain(arg.get_long().map(|long| format!("--{long}")));
+            for flag in arg_flags {
+                if !flags.contains(&flag) {
+                    flags.push(flag);
+                }
+            }
+        }
+    }
+    (flags, commands)
+}
+
+fn function_name(bin_name: &str) -> String {
+    bin_name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+fn bash_source_ids(bin_name: &str, flags: &[String], commands: &[String]) -> String {
+    let name = function_name(bin_name);
+    format!(r#"
+_{name}_source_ids() {{
+    local target="" i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${{COMP_WORDS[i]}}" in
+            -t|--target) target="${{COMP_WORDS[i+1]}}" ;;
+            --target=*) target="${{COMP_WORDS[i]#--target=}}" ;;
+        esac
+    done
+    {bin_name} complete-source-ids ${{target:+--target "$target"}} 2>/dev/null
+}}
+
+_{name}_with_source_ids() {{
+    if [[ " {commands} " == *" ${{COMP_WORDS[1]}} "* ]]; then
+        case "${{COMP_WORDS[COMP_CWORD-1]}}" in
+            {flags})
+                COMPREPLY=($(compgen -W "$(_{name}_source_ids)" -- "${{COMP_WORDS[COMP_CWORD]}}"))
+                return 0
+                ;;
+        esac
+    fi
+    _{name} "$@"
+}}
+
+complete -F _{name}_with_source_ids -o bashdefault -o default {bin_name}
+"#, flags = flags.join("|"), commands = commands.join(" "))
+}
+
+fn zsh_source_ids(bin_name: &str, flags: &[String], commands: &[String]) -> String {
+    let name = function_name(bin_name);
+    format!(r#"
+_{name}_source_ids() {{
+    local target="" i
+    for ((i = 2; i < CURRENT; i++)); do
+        case "${{words[i]}}" in
+            -t|--target) target="${{words[i+1]}}" ;;
+            --target=*) target="${{words[i]#--target=}}" ;;
+        esac
+    done
+    {bin_name} complete-source-ids ${{target:+--target "$target"}} 2>/dev/null
+}}
+
+_{name}_with_source_ids() {{
+    if [[ " {commands} " == *" ${{words[2]}} "* ]]; then
+        case "${{words[CURRENT-1]}}" in
+            {flags})
+                local -a source_ids
+                source_ids=(${{(f)"$(_{name}_source_ids)"}})
+                compadd -a source_ids
+                return
+                ;;
+        esac
+    fi
+    _{name} "$@"
+}}
+
+compdef _{name}_with_source_ids {bin_name}
+"#, flags = flags.join("|"), commands = commands.join(" "))
+}
+
+fn fish_source_ids(bin_name: &str, flags: &[String], commands: &[String]) -> String {
+    let name = function_name(bin_name);
+    let mut script = format!(r#"
+function __{name}_source_ids
+    set -l tokens (commandline -opc)
+    set -l target
+    for i in (seq (count $tokens))
+        switch $tokens[$i]
+            case -t --target
+                set target $tokens[(math $i + 1)]
+            case '--target=*'
+                set target (string replace -- --target= '' $tokens[$i])
+        end
+    end
+    if test -n "$target"
+        {bin_name} complete-source-ids --target "$target" 2>/dev/null
+    else
+        {bin_name} complete-source-ids 2>/dev/null
+    end
+end
+"#);
+    let flag_opts = flags.iter()
+        .map(|flag| match flag.strip_prefix("--") {
+            Some(long) => format!("-l {long}"),
+            None => format!("-s {}", flag.trim_start_matches('-')),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    script.push_str(&format!(
+        "complete -c {bin_name} -n \"__fish_seen_subcommand_from {}\" {flag_opts} -x -a \"(__{name}_source_ids)\"\n",
+        commands.join(" "),
+    ));
+    script
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use photo_archive::archive::notifications::SyncHook;
+use serde::Deserialize;
+
+/// Environment variable naming the archive of the commands run without `--target`
+pub const ARCHIVE_HOME_VAR: &str = "PHOTO_ARCHIVE_HOME";
+/// File of the user configuration directory holding the settings of the command line tool
+pub const CLI_CONFIG_FILE: &str = "config.toml";
+
+/// Settings of the command line tool
+#[derive(Debug, Default, Deserialize)]
+pub struct CliConfig {
+    /// Archive of the commands run without `--target` when `PHOTO_ARCHIVE_HOME` is not set, the
+    /// name of a registered archive or a path
+    #[serde(default)]
+    pub default_archive: Option<String>,
+    /// Archives selected by name with `--archive`, e.g. `family = "/mnt/photos/family"`
+    #[serde(default)]
+    pub archives: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+}
+
+/// How the end of the syncs is announced
+#[derive(Debug, Default, Deserialize)]
+pub struct NotificationsConfig {
+    /// Show a desktop notification when the syncs started from a terminal are over
+    #[serde(default)]
+    pub desktop: bool,
+    /// Services told about every sync, e.g. `{ kind = "ntfy", url = "https://ntfy.sh/photos" }`
+    #[serde(default)]
+    pub hooks: Vec<SyncHook>,
+}
+
+impl CliConfig {
+    /// Read the configuration, the default one when the file does not exist
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if path.is_file() {
+            Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Path of the archive registered under `name`
+    pub fn archive(&self, name: &str) -> anyhow::Result<PathBuf> {
+        self.archives.get(name).cloned().ok_or_else(|| {
+            let names = self.archives.keys().map(String::as_str).collect::<Vec<_>>();
+            match names.is_empty() {
+                true => anyhow!("No archive named {name}, register the archives in the [archives] table of the configuration"),
+                false => anyhow!("No archive named {name}, registered archives: {}", names.join(", ")),
+            }
+        })
+    }
+
+    /// Path of the default archive, looked up among the registered ones first
+    pub fn default_archive(&self) -> Option<PathBuf> {
+        self.default_archive.as_ref()
+            .map(|default| self.archives.get(default).cloned().unwrap_or_else(|| PathBuf::from(default)))
+    }
+}
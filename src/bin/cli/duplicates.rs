@@ -0,0 +1,211 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use photo_archive::archive::catalog::{Catalog, CatalogEntry};
+use photo_archive::archive::duplicates::{DuplicateGroup, PlannedRemoval, RetainPlan};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::viewer::open_with_system_viewer;
+
+const PAGE_SIZE: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Groups,
+    Copies,
+}
+
+struct Reviewer<'a> {
+    catalog: &'a Catalog,
+    groups: Vec<DuplicateGroup>,
+    /// Whether each copy of each group is kept, all of them are at first
+    keep: Vec<Vec<bool>>,
+    focus: Focus,
+    group_state: ListState,
+    copy_state: TableState,
+    status: String,
+}
+
+/// Review the duplicate groups in a terminal UI, marking the copies to keep.
+///
+/// Returns the plan of the copies to drop, `None` when the review is abandoned.
+pub fn review_duplicates(catalog: &Catalog, groups: Vec<DuplicateGroup>) -> anyhow::Result<Option<RetainPlan>> {
+    if groups.is_empty() {
+        anyhow::bail!("Archive holds no duplicate");
+    }
+
+    let mut reviewer = Reviewer {
+        catalog,
+        keep: groups.iter().map(|group| vec![true; group.copies.len()]).collect(),
+        groups,
+        focus: Focus::Groups,
+        group_state: ListState::default().with_selected(Some(0)),
+        copy_state: TableState::default().with_selected(Some(0)),
+        status: String::from("Space: keep/drop  o: keep only this  a: keep all  Enter: open  w: apply  q: quit"),
+    };
+
+    let mut terminal = ratatui::init();
+    let out = reviewer.run(&mut terminal);
+    ratatui::restore();
+    Ok(out?.then(|| reviewer.plan()))
+}
+
+impl Reviewer<'_> {
+    /// Handle the keys until the review is applied (`true`) or abandoned (`false`)
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Char('w') => return Ok(true),
+                KeyCode::Left | KeyCode::Char('h') => self.focus = Focus::Groups,
+                KeyCode::Right | KeyCode::Char('l') | KeyCode::Tab => self.focus = Focus::Copies,
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::PageUp => self.move_selection(-(PAGE_SIZE as isize)),
+                KeyCode::PageDown => self.move_selection(PAGE_SIZE as isize),
+                KeyCode::Home => self.move_selection(isize::MIN),
+                KeyCode::End => self.move_selection(isize::MAX),
+                KeyCode::Char(' ') => self.toggle_selected(),
+                KeyCode::Char('o') => self.keep_only_selected(),
+                KeyCode::Char('a') => {
+                    let group = self.selected_group();
+                    self.keep[group].fill(true);
+                }
+                KeyCode::Enter => self.open_selected(),
+                _ => {}
+            }
+        }
+    }
+
+    fn selected_group(&self) -> usize {
+        self.group_state.selected().unwrap_or(0).min(self.groups.len() - 1)
+    }
+
+    fn selected_copy(&self) -> usize {
+        self.copy_state.selected().unwrap_or(0).min(self.groups[self.selected_group()].copies.len() - 1)
+    }
+
+    fn selected_entry(&self) -> &CatalogEntry {
+        &self.groups[self.selected_group()].copies[self.selected_copy()]
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let copies = self.groups[self.selected_group()].copies.len();
+        let (current, len) = match self.focus {
+            Focus::Groups => (self.selected_group(), self.groups.len()),
+            Focus::Copies => (self.selected_copy(), copies),
+        };
+        let next = Some(current.saturating_add_signed(delta).min(len - 1));
+        match self.focus {
+            Focus::Groups => {
+                self.group_state.select(next);
+                self.copy_state.select(Some(0));
+            }
+            Focus::Copies => self.copy_state.select(next),
+        }
+    }
+
+    fn toggle_selected(&mut self) {
+        let (group, copy) = (self.selected_group(), self.selected_copy());
+        let keep = &mut self.keep[group];
+        if keep[copy] && keep.iter().filter(|kept| **kept).count() == 1 {
+            self.status = String::from("At least one copy of each photo is kept");
+            return;
+        }
+        keep[copy] = !keep[copy];
+    }
+
+    fn keep_only_selected(&mut self) {
+        let (group, copy) = (self.selected_group(), self.selected_copy());
+        for (idx, keep) in self.keep[group].iter_mut().enumerate() {
+            *keep = idx == copy;
+        }
+    }
+
+    fn open_selected(&mut self) {
+        let entry = self.selected_entry();
+        self.status = match self.catalog.viewable_file(entry).and_then(|path| open_with_system_viewer(&path).map(|_| path)) {
+            Ok(path) => format!("Opened {}", path.display()),
+            Err(err) => format!("Error opening {} - {err}", entry.file_name()),
+        };
+    }
+
+    fn plan(&self) -> RetainPlan {
+        let remove = self.groups.iter()
+            .zip(&self.keep)
+            .flat_map(|(group, keep)| group.copies.iter().zip(keep).filter(|(_, kept)| !**kept))
+            .map(|(entry, _)| PlannedRemoval {
+                source_id: entry.source_id.clone(),
+                source_path: entry.source_path.clone(),
+                digest: entry.digest.clone(),
+            })
+            .collect();
+        RetainPlan { remove }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, status_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [groups_area, copies_area] = Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(main_area);
+
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let focused_block = |title: String, focused: bool| {
+            let block = Block::bordered().title(title);
+            if focused { block.border_style(Style::default().add_modifier(Modifier::BOLD)) } else { block }
+        };
+
+        let groups = self.groups.iter()
+            .zip(&self.keep)
+            .map(|(group, keep)| {
+                let first = &group.copies[0];
+                let date = first.timestamp.map(|ts| ts.format("%Y-%m-%d").to_string()).unwrap_or_else(|| String::from("no date"));
+                let dropped = keep.iter().filter(|kept| !**kept).count();
+                let marker = if dropped > 0 { format!(", -{dropped}") } else { String::new() };
+                ListItem::new(format!("{date} {} ({}{marker})", first.file_name(), group.copies.len()))
+            })
+            .collect::<Vec<_>>();
+        let dropped = self.keep.iter().flatten().filter(|kept| !**kept).count();
+        frame.render_stateful_widget(
+            List::new(groups)
+                .block(focused_block(format!("Duplicates ({} groups, {dropped} copies dropped)", self.groups.len()), self.focus == Focus::Groups))
+                .highlight_style(highlight),
+            groups_area,
+            &mut self.group_state,
+        );
+
+        let group = self.selected_group();
+        let rows = self.groups[group].copies.iter()
+            .zip(&self.keep[group])
+            .map(|(entry, kept)| Row::new(vec![
+                String::from(if *kept { "keep" } else { "DROP" }),
+                String::from(self.catalog.source_name(entry)),
+                format!("{}{}", entry.source_path.display(), if entry.deleted { " (deleted)" } else { "" }),
+                format!("{}x{}", entry.width, entry.height),
+                format!("{:.1} MiB", entry.size as f64 / (1024.0 * 1024.0)),
+                entry.timestamp.map(|ts| ts.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default(),
+            ]))
+            .collect::<Vec<_>>();
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Percentage(15),
+            Constraint::Min(20),
+            Constraint::Length(11),
+            Constraint::Length(10),
+            Constraint::Length(16),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(vec!["", "Source", "Path", "Resolution", "Size", "Date"]).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(focused_block(format!("Copies of {}", self.groups[group].digest), self.focus == Focus::Copies))
+            .row_highlight_style(highlight);
+        frame.render_stateful_widget(table, copies_area, &mut self.copy_state);
+        frame.render_widget(Paragraph::new(self.status.as_str()), status_area);
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Sources imported by `import-source --from-file`, one `[[source]]` table each:
+///
+/// ```toml
+/// [[source]]
+/// path = "/media/disk-2009"
+/// name = "disk-2009"
+/// group = "old-disks"
+/// tags = ["family"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportBatch {
+    #[serde(rename = "source", default)]
+    pub sources: Vec<BatchSource>,
+}
+
+/// Source of the batch, located by exactly one of `id`, `path` or `url`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchSource {
+    /// Id of the partition holding the source
+    pub id: Option<String>,
+    pub path: Option<String>,
+    /// Url of a remote source, staged like with `--source-url`
+    pub url: Option<String>,
+    pub name: String,
+    pub group: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl ImportBatch {
+    /// Read the batch, checking every source is located before anything is imported
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let batch: Self = toml::from_str(&std::fs::read_to_string(path)?)?;
+        if batch.sources.is_empty() {
+            anyhow::bail!("No [[source]] found");
+        }
+        for source in batch.sources.iter() {
+            let locations = [&source.id, &source.path, &source.url].into_iter().filter(|location| location.is_some()).count();
+            if locations != 1 {
+                anyhow::bail!("Source '{}' must have exactly one of id, path or url", source.name);
+            }
+        }
+        Ok(batch)
+    }
+}
@@ -1,32 +1,136 @@
+use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::fs::create_dir_all;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 use anyhow::{anyhow, Context};
+use chrono::{DateTime, Local};
 use clap::Parser;
-use inquire::{Select, Text};
-use photo_archive::archive::remove::remove_by_source;
-use photo_archive::archive::sync::{SourceCoordinates, SynchronizationEvent, synchronize_source, SyncOpts, SyncSource};
+use inquire::{Password, Select, Text};
+use photo_archive::archive::catalog::{Catalog, CatalogEntry, CatalogFilter};
+use photo_archive::archive::digest::Digest;
+use photo_archive::archive::duplicates::{execute_plan, find_duplicates, preview_plan};
+use photo_archive::archive::doctor::{diagnose_archive, Severity};
+use photo_archive::archive::info::describe_archive;
+use photo_archive::archive::encryption::ArchiveSecret;
+use photo_archive::archive::export::{ExportOutcome, ExportStats};
+use photo_archive::archive::export::immich::{export_to_immich, ImmichOpts};
+use photo_archive::archive::export::index::export_index;
+use photo_archive::archive::export::motion_clips::export_motion_clips;
+use photo_archive::archive::export::photoprism::{export_to_photoprism, PhotoPrismOpts};
+use photo_archive::archive::export::year_review::{export_year_review, YearReviewOpts};
+use photo_archive::archive::manifest::ArchiveManifest;
+use photo_archive::archive::metrics::serve_metrics;
+use photo_archive::archive::metadata_backup::{export_metadata, restore_metadata};
+use photo_archive::archive::mirror::{mirror_archive, MirrorOpts};
+use photo_archive::archive::photo_archive::PhotoArchive;
+use photo_archive::archive::remote::{login_remote_source, open_remote_source, stage_remote_source, staging_dir_name, RemoteCredentials};
+use photo_archive::archive::remote::config::{OAuthClient, RemotesConfig};
+use photo_archive::archive::relink::{relink_archive, repair_links};
+use photo_archive::archive::replication::{replicate, serve_replica, LocalReplica, RemoteReplica, ReplicaEndpoint, ReplicationDirection, ReplicationStats};
+use photo_archive::archive::remove::{preview_prune, preview_remove_by_source, remove_by_source, RemovalSummary};
+use photo_archive::archive::sources_status::{sources_status, SourceLocation};
+use photo_archive::archive::stats::source_stats;
+use photo_archive::archive::timeline::build_timeline;
+use photo_archive::archive::verify::verify_source;
+use photo_archive::archive::retry::RetryPolicy;
+use photo_archive::archive::sync::{FilterOpts, ParallelismOpts, Rerun, ScanOpts, SourceCoordinates, synchronize_source, SyncOpts, SyncSource};
+use photo_archive::archive::sync_report::write_sync_report;
+use photo_archive::archive::thumbnail::ThumbnailOpts;
+use photo_archive::archive::thumbnail_dedupe::dedupe_thumbnails;
 
 use photo_archive::common::fs::{list_mounted_partitions, partition_by_id};
+use photo_archive::common::fs::model::{MountedPartitionInfo, PartitionLookupError};
 use photo_archive::common::fs::common::partition_by_path;
-use photo_archive::repository::sources::SourcesRepo;
+use photo_archive::repository::sources::{SourceJsonRow, SourcesRepo};
 
-use crate::args::{ImportSourceCliArgs, PhotoArchiveArgs, PhotoArchiveCommand, RemoveSourceCliArgs, SyncSourceCliArgs};
+use crate::browse::browse;
+use crate::completions::{print_completions, print_source_ids};
+use crate::config::{CliConfig, ARCHIVE_HOME_VAR, CLI_CONFIG_FILE};
+use crate::notify::Notifier;
+use crate::prompt::{confirm, ensure_interactive};
+use crate::duplicates::review_duplicates;
+use crate::import_batch::{BatchSource, ImportBatch};
+use crate::slideshow::{slideshow, SlideshowOpts};
+use crate::sync_output::{print_events, SyncReport, SyncTotals, TooManyErrors, ERRORED_FILES_EXIT_CODE};
+use crate::viewer::open_with_system_viewer;
+use crate::args::{DedupeThumbsCliArgs, ExportYearReviewCliArgs, ErrorsCliArgs, RetryFailedCliArgs, HistoryCommand, HistoryListCliArgs, HistoryReportCliArgs, HistoryShowCliArgs, SyncReportCliArgs, AlbumCommand, AlbumListCliArgs, AlbumNameCliArgs, AlbumPhotosCliArgs, BrowseCliArgs, CaptionCliArgs, CatalogFilterCliArgs, RateCliArgs, TagCliArgs, RemoteSourceCliArgs, DuplicatesCliArgs, OpenCliArgs, SourcesCommand, SourcesLoginCliArgs, SourcesMigrateCliArgs, SourcesStatusCliArgs, SlideshowCliArgs, DoctorCliArgs, InfoCliArgs, EncryptionCliArgs, ExportImmichCliArgs, ExportIndexCliArgs, ExportPhotoprismCliArgs, ExportMotionClipsCliArgs, ExportMetadataCliArgs, ImportSourceCliArgs, MirrorCliArgs, PhotoArchiveArgs, ReplicateCliArgs, RestoreMetadataCliArgs, ServeMetricsCliArgs, StatsCliArgs, TimelineCliArgs, VerifySourceCliArgs, PhotoArchiveCommand, RelinkCliArgs, RemoveSourceCliArgs, RepairLinksCliArgs, SyncSourceCliArgs};
 
 mod args;
+mod browse;
+mod completions;
+mod config;
+mod prompt;
+mod duplicates;
+mod import_batch;
+mod notify;
+mod slideshow;
+mod sync_output;
+mod term_image;
+mod viewer;
+
+/// Name given with `--archive`, resolved by the commands through the configuration
+static ARCHIVE_NAME: OnceLock<Option<String>> = OnceLock::new();
 
 pub fn main() {
     let args: PhotoArchiveArgs = PhotoArchiveArgs::parse();
+    prompt::init(args.non_interactive, args.yes);
+    let _ = ARCHIVE_NAME.set(args.archive);
+    let verbose = args.verbose;
 
     let out = match args.subcommand {
         PhotoArchiveCommand::ListSources => fetch_and_print_sources(),
-        PhotoArchiveCommand::ImportSource(args) => import_source(args),
-        PhotoArchiveCommand::SyncSource(args) => sync_source(args),
+        PhotoArchiveCommand::ImportSource(args) => import_source(args, verbose),
+        PhotoArchiveCommand::Sources(SourcesCommand::Status(args)) => print_sources_status(args),
+        PhotoArchiveCommand::Sources(SourcesCommand::Migrate(args)) => migrate_source(args),
+        PhotoArchiveCommand::Sources(SourcesCommand::Login(args)) => login_source(args),
+        PhotoArchiveCommand::SyncSource(args) => sync_source(args, verbose),
+        PhotoArchiveCommand::Retry(args) => retry_failed(args, verbose),
         PhotoArchiveCommand::RemoveSource(args) => remove_source(args),
+        PhotoArchiveCommand::Relink(args) => relink(args),
+        PhotoArchiveCommand::RepairLinks(args) => repair_archive_links(args),
+        PhotoArchiveCommand::DedupeThumbs(args) => dedupe_archive_thumbnails(args),
+        PhotoArchiveCommand::Mirror(args) => mirror(args),
+        PhotoArchiveCommand::Replicate(args) => replicate_archive(args),
+        PhotoArchiveCommand::VerifySource(args) => verify(args),
+        PhotoArchiveCommand::Doctor(args) => doctor(args),
+        PhotoArchiveCommand::Info(args) => print_info(args),
+        PhotoArchiveCommand::Stats(args) => print_stats(args),
+        PhotoArchiveCommand::Timeline(args) => print_timeline(args),
+        PhotoArchiveCommand::ServeMetrics(args) => serve_archive_metrics(args),
+        PhotoArchiveCommand::ExportMetadata(args) => export_metadata_snapshot(args),
+        PhotoArchiveCommand::ExportIndex(args) => export_index_database(args),
+        PhotoArchiveCommand::RestoreMetadata(args) => restore_metadata_snapshot(args),
+        PhotoArchiveCommand::Browse(args) => browse_archive(args),
+        PhotoArchiveCommand::Slideshow(args) => run_slideshow(args),
+        PhotoArchiveCommand::Open(args) => open_photo(args),
+        PhotoArchiveCommand::Duplicates(args) => review_archive_duplicates(args),
+        PhotoArchiveCommand::Caption(args) => edit_caption(args),
+        PhotoArchiveCommand::Rate(args) => rate_photo(args),
+        PhotoArchiveCommand::Tag(args) => tag_photo(args),
+        PhotoArchiveCommand::Errors(args) => list_sync_errors(args),
+        PhotoArchiveCommand::History(HistoryCommand::List(args)) => list_sync_history(args),
+        PhotoArchiveCommand::History(HistoryCommand::Show(args)) => show_sync_run(args),
+        PhotoArchiveCommand::History(HistoryCommand::Report(args)) => write_history_report(args),
+        PhotoArchiveCommand::Album(AlbumCommand::List(args)) => list_albums(args),
+        PhotoArchiveCommand::Album(AlbumCommand::Create(args)) => create_album(args),
+        PhotoArchiveCommand::Album(AlbumCommand::Delete(args)) => delete_album(args),
+        PhotoArchiveCommand::Album(AlbumCommand::Add(args)) => add_to_album(args),
+        PhotoArchiveCommand::Album(AlbumCommand::Remove(args)) => remove_from_album(args),
+        PhotoArchiveCommand::ExportImmich(args) => export_immich(args, verbose),
+        PhotoArchiveCommand::ExportPhotoprism(args) => export_photoprism(args, verbose),
+        PhotoArchiveCommand::ExportMotionClips(args) => export_clips(args, verbose),
+        PhotoArchiveCommand::ExportYearReview(args) => export_year_reviews(args),
+        PhotoArchiveCommand::Completions(args) => print_completions(args),
+        PhotoArchiveCommand::CompleteSourceIds(args) => archive_target(args.target).and_then(|target| print_source_ids(&target)),
+        PhotoArchiveCommand::ReplicaServe(args) => archive_target(args.target).and_then(|target| serve_replica(&target, std::io::stdin(), std::io::stdout())),
     };
 
     if let Err(err) = out {
         eprintln!("Error - {err}");
+        let code = if err.is::<TooManyErrors>() { ERRORED_FILES_EXIT_CODE } else { 1 };
+        std::process::exit(code);
     }
 }
 
@@ -40,16 +144,28 @@ fn fetch_and_print_sources() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn import_source(args: ImportSourceCliArgs) -> anyhow::Result<()> {
-    if !args.target.exists() {
-        create_dir_all(&args.target)
-            .context("Error during target dir creation")?;
-    } else if !args.target.is_dir() {
-        anyhow::bail!("Target path is not a directory")
+fn import_source(args: ImportSourceCliArgs, verbose: bool) -> anyhow::Result<()> {
+    let target = archive_target(args.target.clone())?;
+    if let Some(from_file) = &args.from_file {
+        let batch = ImportBatch::load(from_file).with_context(|| format!("Error reading {}", from_file.display()))?;
+        return import_batch(&target, batch, args, verbose);
+    }
+    // Every argument otherwise prompted for is reported at once
+    let missing = [
+        (args.remote.source_url.is_none() && args.source_path.is_none() && args.source_id.is_none()).then_some("--source-id, --source-path or --source-url"),
+        args.source_name.is_none().then_some("--source-name"),
+        args.source_group.is_none().then_some("--source-group"),
+    ];
+    let missing = missing.into_iter().flatten().collect::<Vec<_>>();
+    if !missing.is_empty() {
+        ensure_interactive(&missing)?;
     }
 
-    let source_part = args.source_path.as_ref().map(|p| partition_by_path(&PathBuf::from(p)).context("Error mapping path"))
-        .or_else(|| args.source_id.map(|source_id| partition_by_id(&source_id).context("Error mapping source_id")))
+    prepare_import_target(&target, args.init)?;
+
+    let source_part = args.remote.source_url.as_ref().map(|url| stage_source(&args.remote, url, verbose))
+        .or_else(|| args.source_path.as_ref().map(|p| partition_by_path(&PathBuf::from(p)).context("Error mapping path")))
+        .or_else(|| args.source_id.map(|source_id| resolve_partition(&source_id).context("Error mapping source_id")))
         .unwrap_or_else(|| {
             let available_partitions = list_mounted_partitions()?;
 
@@ -74,103 +190,501 @@ fn import_source(args: ImportSourceCliArgs) -> anyhow::Result<()> {
             .prompt()
     )?;
 
-    let task = synchronize_source(SyncOpts {
+    // New archives are encrypted with the provided secret, ask for it twice to avoid typos
+    let new_archive = ArchiveManifest::load(&target)?.is_none();
+    let secret = read_secret(&args.encryption, new_archive)?;
+
+    let label = format!("'{source_name}'");
+    let mut report = SyncReport::new(notifier()?);
+    let totals = run_sync(&target, SyncOpts {
         count_images: true,
         source: SyncSource::New {
             coord: args.source_path.as_ref().map(|path| SourceCoordinates::Path(PathBuf::from(path)))
-                .unwrap_or_else(|| SourceCoordinates::Id(source_part.info.partition_id)),
+                .unwrap_or(SourceCoordinates::Mounted(source_part)),
             name: source_name,
             group: source_group,
             tags: vec![],
         },
-    }, &args.target)?;
+        thumbnail: args.thumbnail.into(),
+        memory_budget: args.parallelism.memory_budget.map(|mb| mb * 1024 * 1024),
+        digest: args.digest,
+        link_strategy: args.link_strategy,
+        symlink_style: args.symlink_style,
+        layout: args.layout,
+        index_sharding: args.index_sharding,
+        link_dirs: args.link_dirs,
+        undated_by_mtime: args.undated_by_mtime,
+        originals: args.originals.store_originals,
+        originals_compression: args.originals.compress_originals,
+        secret,
+        prune: false,
+        init: args.init,
+        full_check: false,
+        full_scan: false,
+        rerun: None,
+        parallelism: args.parallelism.into(),
+        retry: args.retry.into(),
+        scan: args.scan.into(),
+        filter: args.filter.into(),
+    }, &args.report, verbose)?;
 
-    let mut total_images = 0;
-    let mut processed_images = 0;
+    report.push(label, Ok(totals));
+    report.finish(args.max_errors)
+}
 
-    while let Ok(evt) = task.evt_stream().recv() {
-        if let SynchronizationEvent::ScanProgress { count } | SynchronizationEvent::ScanCompleted { count } = &evt {
-            total_images = *count;
-        } else {
-            processed_images += 1;
-        }
-        println!("{processed_images}/{total_images} ({:02.02}%)", (processed_images as f32 / total_images as f32 * 100.0));
-        match evt {
-            SynchronizationEvent::Stored { src, dst, generated, partial } => println!("[STR] {src:?} -> {dst:?} [gen: {generated}; par: {partial}]"),
-            SynchronizationEvent::Skipped { src, existing } => println!("[SKP] {src:?} (existing: {existing:?})"),
-            SynchronizationEvent::Errored { src, cause } => println!("[ERR] {src:?} - {cause}"),
-            SynchronizationEvent::Ignored { src, cause } => println!("[IGN] {src:?} - {cause})"),
-            SynchronizationEvent::ScanProgress { .. } | SynchronizationEvent::ScanCompleted { .. } => {}
+/// Register and sync the sources of the batch one after the other, the ones already registered
+/// are only synced
+fn import_batch(target: &Path, batch: ImportBatch, args: ImportSourceCliArgs, verbose: bool) -> anyhow::Result<()> {
+    prepare_import_target(target, args.init)?;
+
+    let new_archive = ArchiveManifest::load(target)?.is_none();
+    let secret = read_secret(&args.encryption, new_archive)?;
+    let thumbnail = ThumbnailOpts::from(args.thumbnail);
+    let memory_budget = args.parallelism.memory_budget.map(|mb| mb * 1024 * 1024);
+    let parallelism = ParallelismOpts::from(args.parallelism);
+    let retry = RetryPolicy::from(args.retry);
+    let scan = ScanOpts::from(args.scan);
+    let filter = FilterOpts::from(args.filter);
+    let sync_opts = |source| SyncOpts {
+        count_images: true,
+        source,
+        thumbnail: thumbnail.clone(),
+        memory_budget,
+        digest: args.digest,
+        link_strategy: args.link_strategy,
+        symlink_style: args.symlink_style,
+        layout: args.layout.clone(),
+        index_sharding: args.index_sharding,
+        link_dirs: args.link_dirs.clone(),
+        undated_by_mtime: args.undated_by_mtime,
+        originals: args.originals.store_originals,
+        originals_compression: args.originals.compress_originals,
+        secret: secret.clone(),
+        prune: false,
+        init: args.init,
+        full_check: false,
+        full_scan: false,
+        rerun: None,
+        parallelism: parallelism.clone(),
+        retry: retry.clone(),
+        scan: scan.clone(),
+        filter: filter.clone(),
+    };
+
+    let repo = SourcesRepo::new(target.to_path_buf());
+    let mut report = SyncReport::new(notifier()?);
+    for source in batch.sources {
+        let label = format!("'{}'", source.name);
+        println!("Importing source {label}");
+        let out = batch_source_partition(&args.remote, &source, verbose).and_then(|partition| {
+            let registered = repo.find_by_partition_id(&partition.info.partition_id)?.is_some();
+            let coord = match &source.path {
+                Some(path) => SourceCoordinates::Path(PathBuf::from(path)),
+                None => SourceCoordinates::Mounted(partition),
+            };
+            let sync_source = if registered {
+                SyncSource::Existing { coord }
+            } else {
+                SyncSource::New { coord, name: source.name, group: source.group, tags: source.tags }
+            };
+            run_sync(target, sync_opts(sync_source), &args.report, verbose)
+        });
+        if let Err(err) = &out {
+            eprintln!("Error importing source {label} - {err}");
         }
+        report.push(label, out);
     }
+    report.finish(args.max_errors)
+}
 
-    task.join()?;
-    Ok(())
+/// Partition holding a source of an import batch, remote sources are staged first
+fn batch_source_partition(remote: &RemoteSourceCliArgs, source: &BatchSource, verbose: bool) -> anyhow::Result<MountedPartitionInfo> {
+    match (&source.id, &source.path, &source.url) {
+        (_, _, Some(url)) => stage_source(remote, url, verbose),
+        (_, Some(path), _) => partition_by_path(&PathBuf::from(path)).context("Error mapping path"),
+        (Some(id), _, _) => resolve_partition(id).context("Error mapping source_id"),
+        (None, None, None) => anyhow::bail!("Source '{}' has no id, path or url", source.name),
+    }
 }
 
-fn sync_source(args: SyncSourceCliArgs) -> anyhow::Result<()> {
-    if !args.target.exists() {
-        create_dir_all(&args.target)
+fn prepare_import_target(target: &Path, init: bool) -> anyhow::Result<()> {
+    if !target.exists() && init {
+        create_dir_all(target)
             .context("Error during target dir creation")?;
-    } else if !args.target.is_dir() {
+    } else if !target.exists() {
+        anyhow::bail!("Target path does not exist, pass --init to create an archive there")
+    } else if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+    Ok(())
+}
+
+fn sync_source(args: SyncSourceCliArgs, verbose: bool) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let coord = match &args.remote.source_url {
+        _ if args.all => None,
+        Some(url) => Some(SourceCoordinates::Mounted(stage_source(&args.remote, url, verbose)?)),
+        None => Some(select_registered_source(args.source_id, args.source_path, &target)?),
+    };
+    let sources = match coord {
+        Some(_) => Vec::new(),
+        None => mounted_registered_sources(&target)?,
+    };
+    if args.all && sources.is_empty() {
+        anyhow::bail!("None of the registered partitions is currently mounted");
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let thumbnail = ThumbnailOpts::from(args.thumbnail);
+    let memory_budget = args.parallelism.memory_budget.map(|mb| mb * 1024 * 1024);
+    let parallelism = ParallelismOpts::from(args.parallelism);
+    let retry = RetryPolicy::from(args.retry);
+    let scan = ScanOpts::from(args.scan);
+    let filter = FilterOpts::from(args.filter);
+    let sync_opts = |coord| SyncOpts {
+        count_images: true,
+        source: SyncSource::Existing { coord },
+        thumbnail: thumbnail.clone(),
+        memory_budget,
+        digest: None,
+        link_strategy: None,
+        symlink_style: None,
+        layout: None,
+        index_sharding: None,
+        link_dirs: None,
+        undated_by_mtime: false,
+        originals: args.originals.store_originals,
+        originals_compression: args.originals.compress_originals,
+        secret: secret.clone(),
+        prune: args.prune,
+        init: false,
+        full_check: args.full_check,
+        full_scan: args.full_scan,
+        rerun: None,
+        parallelism: parallelism.clone(),
+        retry: retry.clone(),
+        scan: scan.clone(),
+        filter: filter.clone(),
+    };
+
+    let mut report = SyncReport::new(notifier()?);
+    if let Some(coord) = coord {
+        let label = coord_label(&coord);
+        let totals = run_sync(&target, sync_opts(coord), &args.report, verbose)?;
+        report.push(label, Ok(totals));
+        return report.finish(args.max_errors);
+    }
+
+    // A source failing does not stop the others, the report tells which ones need another run
+    for (entry, partition) in sources {
+        let label = format!("{} ('{}')", entry.id, entry.name);
+        println!("Syncing source {label} from {}", partition.mount_point.display());
+        let out = run_sync(&target, sync_opts(SourceCoordinates::Mounted(partition)), &args.report, verbose);
+        if let Err(err) = &out {
+            eprintln!("Error syncing source {label} - {err}");
+        }
+        report.push(label, out);
+    }
+    report.finish(args.max_errors)
+}
+
+fn retry_failed(args: RetryFailedCliArgs, verbose: bool) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
         anyhow::bail!("Target path is not a directory")
     }
+    let secret = read_secret(&args.encryption, false)?;
+    let runs = PhotoArchive::open(&target, secret.clone())?.sync_history()?;
+    let run = match args.run {
+        Some(id) => runs.iter().find(|run| run.id == id).ok_or_else(|| anyhow!("No sync recorded with id {id}"))?,
+        None => runs.last().ok_or_else(|| anyhow!("No syncs recorded"))?,
+    };
+    if run.failures.is_empty() {
+        println!("Sync {} of {} had no failures, nothing to retry", run.id, run.source_name);
+        return Ok(());
+    }
+    let unrecorded = run.unrecorded_failures();
+    if unrecorded > 0 {
+        println!("Only the first {} failures were recorded, sync the source again to retry the other {unrecorded}", run.failures.len());
+    }
 
-    let source_part = args.source_path.as_ref().map(|p| partition_by_path(&PathBuf::from(p)).context("Error mapping path"))
-        .or_else(|| args.source_id.map(|source_id| partition_by_id(&source_id).context("Error mapping source_id")))
+    let coord = match &args.remote.source_url {
+        Some(url) => SourceCoordinates::Mounted(stage_source(&args.remote, url, verbose)?),
+        None => select_registered_source(args.source_id.or_else(|| Some(run.source_id.clone())), args.source_path, &target)?,
+    };
+    println!("Retrying {} failed files of sync {} of {}", run.failures.len(), run.id, run.source_name);
+    let mut report = SyncReport::new(notifier()?);
+    let totals = run_sync(&target, SyncOpts {
+        count_images: true,
+        source: SyncSource::Existing { coord },
+        thumbnail: args.thumbnail.into(),
+        memory_budget: args.parallelism.memory_budget.map(|mb| mb * 1024 * 1024),
+        digest: None,
+        link_strategy: None,
+        symlink_style: None,
+        layout: None,
+        index_sharding: None,
+        link_dirs: None,
+        undated_by_mtime: false,
+        originals: args.originals.store_originals,
+        originals_compression: args.originals.compress_originals,
+        secret,
+        prune: false,
+        init: false,
+        full_check: false,
+        full_scan: false,
+        rerun: Some(Rerun { run_id: run.id, paths: run.failures.iter().map(|failure| failure.path.clone()).collect() }),
+        parallelism: args.parallelism.into(),
+        retry: args.retry.into(),
+        scan: args.scan.into(),
+        filter: args.filter.into(),
+    }, &args.report, verbose)?;
+    report.push(format!("{} ('{}')", run.source_id, run.source_name), Ok(totals));
+    report.finish(args.max_errors)
+}
+
+fn coord_label(coord: &SourceCoordinates) -> String {
+    match coord {
+        SourceCoordinates::Id(id) => id.clone(),
+        SourceCoordinates::Path(path) => path.display().to_string(),
+        SourceCoordinates::Mounted(partition) => partition.info.partition_id.clone(),
+    }
+}
+
+/// Run the sync printing its events, `None` when the pruning was not confirmed
+fn run_sync(target: &Path, opts: SyncOpts, report: &SyncReportCliArgs, verbose: bool) -> anyhow::Result<Option<SyncTotals>> {
+    if let (true, SyncSource::Existing { coord }) = (opts.prune, &opts.source) {
+        let preview = preview_prune(target, coord, opts.secret.as_ref())?;
+        for src in preview.vanished.iter() {
+            println!("[PRN] {src:?}");
+        }
+        if !preview.vanished.is_empty() {
+            println!("Pruning drops {} from the archive, files moved in the source are followed instead", format_removal(&preview.removal));
+            if !confirm("Drop the records of the files deleted from the source?")? {
+                println!("Sync cancelled, the archive is unchanged");
+                return Ok(None);
+            }
+        }
+    }
+
+    let task = synchronize_source(opts, target)?;
+    let totals = print_events(&task, verbose);
+    task.join()?;
+    let run = totals.as_ref().and_then(|totals| totals.run.as_ref());
+    if let (Some(run), Some(format)) = (run, report.format()) {
+        // The sync is done, a report that cannot be written does not fail it
+        match write_sync_report(target, run, format) {
+            Ok(path) => {
+                println!("Report written to {}", path.display());
+                if report.open_report {
+                    open_with_system_viewer(&path)?;
+                }
+            }
+            Err(err) => eprintln!("Error writing sync report - {err}"),
+        }
+    }
+    Ok(totals)
+}
+
+/// Registered sources held by the mounted partitions, the first mount of each
+fn mounted_registered_sources(target: &Path) -> anyhow::Result<Vec<(SourceJsonRow, MountedPartitionInfo)>> {
+    let registered_sources = SourcesRepo::new(target.to_path_buf()).all()?;
+    let mounted_partitions = list_mounted_partitions().context("Error reading partitions")?;
+    Ok(registered_sources.into_iter()
+        .filter_map(|entry| {
+            let partition = mounted_partitions.iter()
+                .find(|partition| entry.identified_by(&partition.info.partition_id))?
+                .clone();
+            Some((entry, partition))
+        })
+        .collect())
+}
+
+/// Source given on the command line, or chosen among the mounted registered ones
+fn select_registered_source(source_id: Option<String>, source_path: Option<String>, target: &Path) -> anyhow::Result<SourceCoordinates> {
+    if let Some(source_path) = source_path {
+        return Ok(SourceCoordinates::Path(PathBuf::from(source_path)));
+    }
+    let repo = SourcesRepo::new(target.to_path_buf());
+    let source_part = source_id.map(|source_id| {
+            // Registered sources are also found through the partition ids they migrated to
+            let partition_ids = repo.find_by_id(&source_id)?
+                .map(|entry| entry.partition_ids().map(String::from).collect::<Vec<_>>())
+                .unwrap_or_else(|| vec![source_id.clone()]);
+            for partition_id in &partition_ids {
+                match partition_by_id(partition_id) {
+                    Err(PartitionLookupError::NotFound { .. }) => continue,
+                    Err(PartitionLookupError::Ambiguous { candidates, .. }) => return choose_mount(partition_id, candidates),
+                    found => return found.context("Error mapping source_id"),
+                }
+            }
+            anyhow::bail!("No mounted partition found for source {source_id}")
+        })
         .unwrap_or_else(|| {
-            let repo = SourcesRepo::new(args.target.clone());
             let registered_sources = repo.all()?;
             let mut available_partitions = list_mounted_partitions()?;
-            available_partitions.retain(|src| registered_sources.iter().any(|reg| reg.id.eq(&src.info.partition_id)));
+            available_partitions.retain(|src| registered_sources.iter().any(|reg| reg.identified_by(&src.info.partition_id)));
 
             if available_partitions.is_empty() {
                 anyhow::bail!("None of the registered partitions is currently mounted");
             }
 
+            ensure_interactive(&["--source-id or --source-path"])?;
             Select::new("Choose the source to scan", available_partitions)
                 .prompt()
                 .context("Error reading source_id")
         })?;
+    Ok(SourceCoordinates::Mounted(source_part))
+}
 
-    let task = synchronize_source(SyncOpts {
-        count_images: true,
-        source: SyncSource::Existing {
-            coord: args.source_path.as_ref().map(|path| SourceCoordinates::Path(PathBuf::from(path)))
-                .unwrap_or_else(|| SourceCoordinates::Id(source_part.info.partition_id)),
-        },
-    }, &args.target)?;
-
-    let mut total_images = 0;
-    let mut processed_images = 0;
+/// Download the images of the remote source into its staging directory, the staged copy is then
+/// synchronized as a mounted source
+fn stage_source(args: &RemoteSourceCliArgs, url: &str, verbose: bool) -> anyhow::Result<MountedPartitionInfo> {
+    if args.source_password {
+        ensure_interactive(&["a terminal to type the --source-password"])?;
+    }
+    let password = args.source_password
+        .then(|| Password::new("Source password").without_confirmation().prompt())
+        .transpose()
+        .context("Error reading source password")?;
+    let config_path = remotes_config_path(args.remotes_config.as_deref())?;
+    let config = RemotesConfig::load(&config_path).context("Error reading remote sources configuration")?;
+    let source = open_remote_source(url, &RemoteCredentials { password, config }, |url| {
+        println!("Open the following page to pick the photos to import:\n{url}");
+        let _ = open_with_system_viewer(Path::new(url));
+    })?;
+    let staging_root = match &args.staging_dir {
+        Some(staging_dir) => staging_dir.clone(),
+        None => default_staging_root()?,
+    };
+    let staging_dir = staging_root.join(staging_dir_name(&source.source_id()));
 
-    while let Ok(evt) = task.evt_stream().recv() {
-        if let SynchronizationEvent::ScanProgress { count } | SynchronizationEvent::ScanCompleted { count } = &evt {
-            total_images = *count;
-        } else {
-            processed_images += 1;
-        }
-        println!("{processed_images}/{total_images} ({:02.02}%)", (processed_images as f32 / total_images as f32 * 100.0));
-        match evt {
-            SynchronizationEvent::Stored { src, dst, generated, partial } => println!("[STR] {src:?} -> {dst:?} [gen: {generated}; par: {partial}]"),
-            SynchronizationEvent::Skipped { src, existing } => println!("[SKP] {src:?} (existing: {existing:?})"),
-            SynchronizationEvent::Errored { src, cause } => println!("[ERR] {src:?} - {cause}"),
-            SynchronizationEvent::Ignored { src, cause } => println!("[IGN] {src:?} - {cause}"),
-            SynchronizationEvent::ScanProgress { .. } | SynchronizationEvent::ScanCompleted { .. } => {}
+    println!("Staging {} into {}", source.location(), staging_dir.display());
+    let (partition, stats) = stage_remote_source(source.as_ref(), &staging_dir, |file| {
+        if verbose {
+            println!("[GET] {}", file.path.display());
         }
+    })?;
+    for (path, cause) in &stats.errors {
+        println!("[ERR] {} - {cause}", path.display());
     }
+    println!(
+        "Staged - downloaded: {}; unchanged: {}; removed: {}; errored: {}",
+        stats.downloaded,
+        stats.unchanged,
+        stats.removed,
+        stats.errors.len(),
+    );
+    Ok(partition)
+}
 
-    task.join()?;
+fn default_staging_root() -> anyhow::Result<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|cache_dir| cache_dir.join("photo-archive").join("sources"))
+        .ok_or_else(|| anyhow!("Could not find the cache directory, choose the staging directory with --staging-dir"))
+}
+
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|config_dir| config_dir.join("photo-archive"))
+}
+
+fn remotes_config_path(remotes_config: Option<&Path>) -> anyhow::Result<PathBuf> {
+    if let Some(remotes_config) = remotes_config {
+        return Ok(remotes_config.to_path_buf());
+    }
+    config_dir()
+        .map(|config_dir| config_dir.join("remotes.toml"))
+        .ok_or_else(|| anyhow!("Could not find the configuration directory, choose the configuration file with --remotes-config"))
+}
+
+/// Archive of the command: `--target` or the one registered as `--archive`, else the archive
+/// holding the current directory, else `$PHOTO_ARCHIVE_HOME`, else the default archive of the configuration
+fn archive_target(target: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    let name = ARCHIVE_NAME.get().and_then(Option::as_deref);
+    match (target, name) {
+        (Some(_), Some(_)) => anyhow::bail!("Both --target and --archive given, choose one"),
+        (Some(target), None) => return Ok(target),
+        (None, Some(name)) => return cli_config()?.archive(name),
+        (None, None) => {}
+    }
+    if let Some(root) = PhotoArchive::find_root(&std::env::current_dir()?)? {
+        return Ok(root);
+    }
+    if let Some(home) = std::env::var_os(ARCHIVE_HOME_VAR).filter(|home| !home.is_empty()) {
+        return Ok(PathBuf::from(home));
+    }
+    cli_config()?.default_archive()
+        .ok_or_else(|| anyhow!("No archive given, pass --target or --archive, set {ARCHIVE_HOME_VAR} or add default_archive to the configuration"))
+}
+
+fn cli_config() -> anyhow::Result<CliConfig> {
+    match config_dir() {
+        Some(config_dir) => CliConfig::load(&config_dir.join(CLI_CONFIG_FILE)).context("Error reading configuration"),
+        None => Ok(CliConfig::default()),
+    }
+}
+
+/// Notifications of the syncs, as set in the configuration
+fn notifier() -> anyhow::Result<Notifier> {
+    Ok(Notifier::new(cli_config()?.notifications))
+}
+
+fn login_source(args: SourcesLoginCliArgs) -> anyhow::Result<()> {
+    let config_path = remotes_config_path(args.remotes_config.as_deref())?;
+    let mut config = RemotesConfig::load(&config_path).context("Error reading remote sources configuration")?;
+    let client = OAuthClient { client_id: args.client_id, client_secret: args.client_secret };
+    login_remote_source(&args.source_url, &client, &mut config, |url| {
+        println!("Open the following page to authorize the access:\n{url}");
+        // The url is printed anyway, for terminals without a browser
+        let _ = open_with_system_viewer(Path::new(url));
+    })?;
+    config.store(&config_path)?;
+    println!("{} authorized, account stored in {}", args.source_url, config_path.display());
     Ok(())
 }
 
+/// Mounted partition with the given id, asking which mount to use when it is mounted more than once
+fn resolve_partition(partition_id: &str) -> anyhow::Result<MountedPartitionInfo> {
+    match partition_by_id(partition_id) {
+        Err(PartitionLookupError::Ambiguous { candidates, .. }) => choose_mount(partition_id, candidates),
+        found => Ok(found?),
+    }
+}
+
+fn choose_mount(partition_id: &str, candidates: Vec<MountedPartitionInfo>) -> anyhow::Result<MountedPartitionInfo> {
+    ensure_interactive(&["--source-path"])?;
+    Select::new(&format!("Partition {partition_id} is mounted more than once, choose the mount point to use"), candidates)
+        .prompt()
+        .context("Error reading mount point")
+}
+
+fn format_removal(summary: &RemovalSummary) -> String {
+    format!(
+        "{} records, {} files ({:.1} MiB) and {} links",
+        summary.records,
+        summary.files,
+        summary.bytes as f64 / (1024.0 * 1024.0),
+        summary.links,
+    )
+}
+
 fn remove_source(args: RemoveSourceCliArgs) -> anyhow::Result<()> {
-    if !args.target.exists() {
+    let target = archive_target(args.target)?;
+    if !target.exists() {
         anyhow::bail!("Target path does not exists")
-    } else if !args.target.is_dir() {
+    } else if !target.is_dir() {
         anyhow::bail!("Target path is not a directory")
     }
-    let repo = SourcesRepo::new(args.target.clone());
+    let repo = SourcesRepo::new(target.clone());
 
     let source_part = args.source_id
         .map(|source_id| {
@@ -185,12 +699,994 @@ fn remove_source(args: RemoveSourceCliArgs) -> anyhow::Result<()> {
                 anyhow::bail!("There are no registered sources in the specified archive");
             }
 
+            ensure_interactive(&["--source-id"])?;
             Select::new("Choose the source to remove", registered_sources)
                 .prompt()
                 .context("Error reading source_id")
         })?;
 
-    remove_by_source(args.target, &source_part.id)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let summary = preview_remove_by_source(&target, &source_part.id, secret.as_ref())?;
+    println!("Removing source {} ('{}') drops {} from the archive, source files are left untouched", source_part.id, source_part.name, format_removal(&summary));
+    if summary.records == 0 {
+        return Ok(());
+    }
+    if !confirm("Remove the photos of this source from the archive?")? {
+        println!("Source not removed, the archive is unchanged");
+        return Ok(());
+    }
+    remove_by_source(target, &source_part.id, secret.as_ref())?;
+
+    Ok(())
+}
+
+fn relink(args: RelinkCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let stats = relink_archive(&target, args.symlink_style, secret.as_ref())?;
+    println!("Relinked: {}, unchanged: {}, errors: {}", stats.relinked, stats.unchanged, stats.errors);
+    Ok(())
+}
+
+fn repair_archive_links(args: RepairLinksCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let stats = repair_links(&target, secret.as_ref())?;
+    for link in &stats.recreated {
+        println!("[LNK] {}", link.display());
+    }
+    for link in &stats.missing_thumbnail {
+        println!("[MIS] {} - thumbnail is missing", link.display());
+    }
+    println!(
+        "Recreated: {}, unchanged: {}, missing thumbnails: {}, errors: {}",
+        stats.recreated.len(),
+        stats.unchanged,
+        stats.missing_thumbnail.len(),
+        stats.errors,
+    );
+    Ok(())
+}
+
+fn dedupe_archive_thumbnails(args: DedupeThumbsCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let stats = dedupe_thumbnails(&target, secret.as_ref(), args.dry_run)?;
+    for thumbnail in &stats.linked {
+        println!("[LNK] {}", thumbnail.display());
+    }
+    println!(
+        "{}: {} ({:.1} MiB), already linked: {}, different: {}, errors: {}",
+        if args.dry_run { "To link" } else { "Linked" },
+        stats.linked.len(),
+        stats.bytes as f64 / (1024.0 * 1024.0),
+        stats.already_linked,
+        stats.different,
+        stats.errors,
+    );
+    Ok(())
+}
+
+fn mirror(args: MirrorCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let opts = MirrorOpts {
+        verify_existing: args.verify,
+        delete: args.delete,
+    };
+    let stats = mirror_archive(&target, &args.mirror, &opts)?;
+    println!(
+        "Copied: {} ({:.1} MiB), repaired: {}, unchanged: {}, deleted: {}, errors: {}",
+        stats.copied,
+        stats.bytes as f64 / (1024.0 * 1024.0),
+        stats.repaired,
+        stats.unchanged,
+        stats.deleted,
+        stats.errors,
+    );
+    Ok(())
+}
+
+fn replicate_archive(args: ReplicateCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let mut local = LocalReplica::open(&target)?;
+    let mut remote: Box<dyn ReplicaEndpoint> = match args.remote.split_once(':') {
+        Some((host, path)) if !host.contains('/') => Box::new(RemoteReplica::connect_ssh(host, &args.remote_command, Path::new(path))?),
+        _ => Box::new(LocalReplica::open(Path::new(&args.remote))?),
+    };
+
+    if matches!(args.direction, ReplicationDirection::Pull | ReplicationDirection::Both) {
+        print_replication_stats("Pulled", &replicate(remote.as_mut(), &mut local)?);
+    }
+    if matches!(args.direction, ReplicationDirection::Push | ReplicationDirection::Both) {
+        print_replication_stats("Pushed", &replicate(&mut local, remote.as_mut())?);
+    }
+    Ok(())
+}
+
+fn print_replication_stats(label: &str, stats: &ReplicationStats) {
+    println!(
+        "{label}: {} rows, {} files ({:.1} MiB), {} new sources, errors: {}",
+        stats.rows,
+        stats.files,
+        stats.bytes as f64 / (1024.0 * 1024.0),
+        stats.sources,
+        stats.errors,
+    );
+}
+
+fn verify(args: VerifySourceCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let coord = select_registered_source(args.source_id, args.source_path, &target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let verification = verify_source(&target, &coord, &args.scan.into(), secret.as_ref())?;
+
+    for path in &verification.missing {
+        println!("[MIS] {}", path.display());
+    }
+    for path in &verification.too_small {
+        println!("[SML] {}", path.display());
+    }
+    for path in &verification.changed {
+        println!("[CHG] {}", path.display());
+    }
+    for path in &verification.orphaned {
+        println!("[ORP] {}", path.display());
+    }
+    for (path, cause) in &verification.errors {
+        println!("[ERR] {} - {cause}", path.display());
+    }
+    println!(
+        "Source {} - unchanged: {}; missing: {}; too small: {}; changed: {}; orphaned records: {}; errors: {}",
+        verification.source_id,
+        verification.unchanged,
+        verification.missing.len(),
+        verification.too_small.len(),
+        verification.changed.len(),
+        verification.orphaned.len(),
+        verification.errors.len(),
+    );
+    Ok(())
+}
+
+fn doctor(args: DoctorCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let findings = diagnose_archive(&target, secret.as_ref())?;
+    if findings.is_empty() {
+        println!("No problem found");
+        return Ok(());
+    }
+
+    for finding in &findings {
+        let severity = match finding.severity {
+            Severity::Error => "ERR",
+            Severity::Warning => "WRN",
+        };
+        println!("[{severity}] {} ({})", finding.problem, finding.count);
+        for example in &finding.examples {
+            println!("      {example}");
+        }
+        if finding.count > finding.examples.len() {
+            println!("      ... and {} more", finding.count - finding.examples.len());
+        }
+        println!("      -> {}", finding.suggestion);
+    }
+    Ok(())
+}
+
+fn print_info(args: InfoCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let info = describe_archive(&target, secret.as_ref())?;
+    let manifest = &info.manifest;
+    let format_ts = |ts: Option<i64>| ts
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .map(|ts| ts.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+    let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+
+    println!("Archive {}", target.display());
+    match info.has_manifest {
+        true => println!("  format version: {}", manifest.version),
+        false => println!("  format version: legacy, no manifest"),
+    }
+    println!("  created: {}", format_ts(manifest.created_at));
+    println!("  encrypted: {}", if manifest.encryption.is_some() { "yes" } else { "no" });
+    println!("Settings");
+    println!("  digest: {}", manifest.digest);
+    println!("  layout: {}, link dirs: {}", manifest.layout, manifest.link_dirs);
+    println!("  links: {}, symlink style: {}", manifest.link, manifest.symlink_style);
+    println!("  index sharding: {}", manifest.index_sharding);
+    println!("  originals: {}, compression: {}", manifest.originals, manifest.originals_compression);
+    println!("  minimum dimension: {}, undated by mtime: {}", manifest.min_dimension, manifest.undated_by_mtime);
+    if let Some(thumbnails) = &manifest.thumbnails {
+        println!(
+            "  thumbnails: {}px, filter: {}, keep exif: {}, fast decode: {}, exif preview: {}",
+            thumbnails.size, thumbnails.filter, thumbnails.keep_exif, thumbnails.fast_decode, thumbnails.exif_preview,
+        );
+    }
+    println!("Sources: {}, last sync: {}", info.sources, format_ts(info.last_sync));
+    match &info.index {
+        Ok(index) => {
+            println!("Index: {} records ({} of deleted files) in {} files", index.records, index.deleted, index.index_files);
+            let healthy = index.unreadable_lines == 0 && index.unknown_source_records == 0;
+            match healthy {
+                true => println!("  health: ok"),
+                false => println!(
+                    "  health: {} unreadable lines, {} records of unknown sources, run doctor for details",
+                    index.unreadable_lines,
+                    index.unknown_source_records,
+                ),
+            }
+        }
+        Err(err) => println!("Index: not read - {err}"),
+    }
+    println!(
+        "Size on disk: {:.1} MiB in {} files, index: {:.1} MiB",
+        mib(info.disk_usage.bytes),
+        info.disk_usage.files,
+        mib(info.disk_usage.index_bytes),
+    );
+    Ok(())
+}
+
+fn print_sources_status(args: SourcesStatusCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    for status in sources_status(&target)? {
+        let location = match status.location {
+            SourceLocation::Mounted { mount_point, fs_type, free_bytes } => {
+                let free = free_bytes
+                    .map(|bytes| format!("{:.1} GiB free", bytes as f64 / (1024.0 * 1024.0 * 1024.0)))
+                    .unwrap_or_else(|| String::from("free space unknown"));
+                format!("mounted on {} ({fs_type}), {free}", mount_point.display())
+            }
+            SourceLocation::Unmounted { device_path } => format!("not mounted, partition attached as {}", device_path.display()),
+            SourceLocation::Unresolved => String::from("not found among the attached partitions, the disk may be missing, renamed or reformatted"),
+            SourceLocation::Ambiguous { mount_points } => format!(
+                "id shared by the partitions mounted on {}",
+                mount_points.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", "),
+            ),
+        };
+        let last_sync = status.source.last_sync
+            .map(format_timestamp)
+            .unwrap_or_else(|| String::from("never"));
+        println!("{}", status.source);
+        println!("      {location}");
+        println!("      last sync: {last_sync}");
+    }
+    Ok(())
+}
+
+fn migrate_source(args: SourcesMigrateCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+    let repo = SourcesRepo::new(target.clone());
+    let source = repo.find_by_id(&args.source_id)?
+        .ok_or_else(|| anyhow!("Could not find registered source with id {}", args.source_id))?;
+
+    let partition_id = match args.partition_id {
+        Some(partition_id) => partition_id,
+        None => {
+            let registered_sources = repo.all()?;
+            let mut candidates = list_mounted_partitions()?;
+            candidates.retain(|partition| !registered_sources.iter().any(|reg| reg.identified_by(&partition.info.partition_id)));
+            if candidates.is_empty() {
+                anyhow::bail!("None of the mounted partitions is unregistered");
+            }
+            // Partitions looking like the last known volume of the source come first
+            candidates.sort_by_key(|partition| {
+                let same_medium = source.fingerprint.as_ref()
+                    .zip(partition.fingerprint.as_ref())
+                    .is_some_and(|(known, current)| known.same_medium(current));
+                !same_medium
+            });
+            ensure_interactive(&["--partition-id"])?;
+            Select::new(&format!("Choose the partition now holding source {}", source.id), candidates)
+                .prompt()
+                .context("Error reading partition_id")?
+                .info
+                .partition_id
+        }
+    };
+
+    let entry = repo.migrate_partition_id(&source.id, &partition_id)?;
+    println!("Source {} ('{}') is now held by partition {partition_id}", entry.id, entry.name);
+    Ok(())
+}
+
+fn print_stats(args: StatsCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let format_ts = |ts: Option<i64>| ts
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .map(|ts| ts.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| String::from("-"));
+    for (source, stats) in source_stats(&target, args.source.as_deref(), secret.as_ref())? {
+        println!("{source}");
+        println!(
+            "      photos: {} ({} undated), originals: {:.1} MiB, thumbnails: {:.1} MiB, from {} to {}",
+            stats.photos,
+            stats.undated,
+            stats.original_bytes as f64 / (1024.0 * 1024.0),
+            stats.thumbnail_bytes as f64 / (1024.0 * 1024.0),
+            format_ts(stats.first_photo),
+            format_ts(stats.last_photo),
+        );
+    }
+    Ok(())
+}
+
+const TIMELINE_BAR_WIDTH: usize = 50;
+
+fn print_timeline(args: TimelineCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let timeline = build_timeline(&target, args.granularity, args.source.as_deref(), secret.as_ref())?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&timeline)?);
+        return Ok(());
+    }
+
+    let max_photos = timeline.periods.iter().map(|period| period.photos).max().unwrap_or(0).max(1);
+    let label_width = timeline.periods.first().map(|period| period.period.len()).unwrap_or(0);
+    for period in &timeline.periods {
+        // Periods with a handful of photos still get a visible bar, only empty ones have none
+        let bar = (period.photos * TIMELINE_BAR_WIDTH).div_ceil(max_photos);
+        let line = format!("{:label_width$} {:>6} {}", period.period, period.photos, "█".repeat(bar));
+        println!("{}", line.trim_end());
+    }
+    println!("Undated: {}", timeline.undated);
+    Ok(())
+}
+
+fn browse_archive(args: BrowseCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    browse(Catalog::load(&target, secret.as_ref())?, args.image_protocol)
+}
+
+/// Catalog holding at least the photos matching `filter`, only those of the digest when it is set
+fn load_filtered_catalog(target: &Path, secret: Option<&ArchiveSecret>, filter: &CatalogFilter) -> anyhow::Result<Catalog> {
+    match &filter.digest {
+        Some(digest) => Catalog::load_digest(target, secret, digest),
+        None => Catalog::load(target, secret),
+    }
+}
+
+fn run_slideshow(args: SlideshowCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    // Photos without thumbnail can only be shown from their original
+    let entries = catalog.filter(&filter)
+        .filter(|entry| entry.thumbnail_path.is_some() || (args.originals && catalog.mounted_original(entry).is_some()))
+        .collect::<Vec<_>>();
+    slideshow(&catalog, &entries, &SlideshowOpts {
+        interval: Duration::from_secs(args.interval.max(1)),
+        originals: args.originals,
+        viewer: args.viewer,
+        protocol: args.image_protocol,
+    })
+}
+
+fn open_photo(args: OpenCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let entries = catalog.filter(&filter).collect::<Vec<_>>();
+    if args.print && entries.len() > 1 {
+        for entry in entries {
+            print_photo_paths(&catalog, entry);
+        }
+        return Ok(());
+    }
+    let entry = choose_photo(&catalog, entries, "open", "filters matching a single photo or --print")?;
+
+    print_photo_paths(&catalog, entry);
+    if !args.print {
+        open_with_system_viewer(&catalog.viewable_file(entry)?)?;
+    }
+    Ok(())
+}
+
+/// Photo among the ones matching the filters, chosen by the user when there are several
+fn choose_photo<'a>(catalog: &Catalog, entries: Vec<&'a CatalogEntry>, action: &str, missing: &str) -> anyhow::Result<&'a CatalogEntry> {
+    match entries[..] {
+        [] => anyhow::bail!("No photo matches the given filters"),
+        [entry] => Ok(entry),
+        _ => {
+            let choices = entries.iter()
+                .map(|entry| format!(
+                    "{}  {}  {}",
+                    entry.timestamp.map(|ts| ts.to_string()).unwrap_or_else(|| String::from("no date")),
+                    catalog.source_name(entry),
+                    entry.source_path.display(),
+                ))
+                .collect::<Vec<_>>();
+            ensure_interactive(&[missing])?;
+            let choice = Select::new(&format!("{} photos match, choose the one to {action}", entries.len()), choices)
+                .raw_prompt()
+                .context("Error reading photo choice")?;
+            Ok(entries[choice.index])
+        }
+    }
+}
+
+fn edit_caption(args: CaptionCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let entry = choose_photo(&catalog, catalog.filter(&filter).collect(), "caption", "filters matching a single photo")?;
+
+    let caption = match (args.set, args.clear) {
+        (_, true) => None,
+        (Some(caption), _) => Some(caption),
+        (None, false) => {
+            ensure_interactive(&["--set or --clear"])?;
+            Text::new(&format!("Caption of {}", entry.source_path.display()))
+                .with_initial_value(entry.caption.as_deref().unwrap_or_default())
+                .prompt()
+                .map(Some)
+                .context("Error reading caption")?
+        }
+    };
+    let caption = caption.map(|caption| caption.trim().to_string()).filter(|caption| !caption.is_empty());
+    PhotoArchive::open(&target, secret)?.set_caption(&entry.digest, caption.clone())?;
+    match caption {
+        Some(caption) => println!("Caption of {} set to '{caption}'", entry.digest),
+        None => println!("Caption of {} cleared", entry.digest),
+    }
+    Ok(())
+}
+
+fn rate_photo(args: RateCliArgs) -> anyhow::Result<()> {
+    if args.stars.is_none() && !args.clear && !args.favorite && !args.unfavorite {
+        anyhow::bail!("Nothing to change, pass --stars, --clear, --favorite or --unfavorite")
+    }
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let entry = choose_photo(&catalog, catalog.filter(&filter).collect(), "rate", "filters matching a single photo")?;
+
+    PhotoArchive::open(&target, secret)?.update_user_metadata(&entry.digest, |metadata| {
+        if args.clear {
+            metadata.rating = None;
+        } else if let Some(stars) = args.stars {
+            metadata.rating = Some(stars);
+        }
+        if args.favorite || args.unfavorite {
+            metadata.favorite = args.favorite;
+        }
+    })?;
+    println!("Updated {} {}", entry.digest, entry.source_path.display());
+    Ok(())
+}
+
+fn tag_photo(args: TagCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let entry = choose_photo(&catalog, catalog.filter(&filter).collect(), "tag", "filters matching a single photo")?;
+
+    let mut tags = entry.tags.clone();
+    if !args.add.is_empty() || !args.remove.is_empty() {
+        PhotoArchive::open(&target, secret)?.update_user_metadata(&entry.digest, |metadata| {
+            for tag in args.remove.iter() {
+                metadata.remove_tag(tag);
+            }
+            for tag in args.add.iter() {
+                metadata.add_tag(tag);
+            }
+            tags = metadata.tags.clone();
+        })?;
+    }
+    match tags.is_empty() {
+        true => println!("{} {} has no tags", entry.digest, entry.source_path.display()),
+        false => println!("{} {} tagged {}", entry.digest, entry.source_path.display(), tags.join(", ")),
+    }
+    Ok(())
+}
+
+fn list_sync_errors(args: ErrorsCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let runs = PhotoArchive::open(&target, secret)?.sync_history()?;
+    let run = match (args.run, &args.source) {
+        (Some(id), _) => runs.iter().find(|run| run.id == id).ok_or_else(|| anyhow!("No sync recorded with id {id}"))?,
+        (None, Some(source)) => runs.iter().rev().find(|run| &run.source_id == source).ok_or_else(|| anyhow!("No sync recorded for source {source}"))?,
+        (None, None) => runs.last().ok_or_else(|| anyhow!("No syncs recorded"))?,
+    };
+    if args.paths {
+        for failure in run.failures.iter() {
+            println!("{}", failure.path.display());
+        }
+        return Ok(());
+    }
 
+    let failed = run.errored + run.scan_errors;
+    println!("Sync {} of {} ({}), {}: {failed} failures", run.id, run.source_name, run.source_id, format_timestamp(run.started_at));
+    for (kind, failures) in run.failures_by_kind() {
+        println!();
+        println!("{kind} ({})", failures.len());
+        for failure in failures {
+            match failure.details() {
+                Some(details) => println!("      {} - {details}", failure.path.display()),
+                None => println!("      {}", failure.path.display()),
+            }
+        }
+    }
+    let unrecorded = run.unrecorded_failures();
+    if unrecorded > 0 {
+        println!();
+        println!("{unrecorded} more failures were not recorded");
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+fn list_sync_history(args: HistoryListCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let runs = PhotoArchive::open(&target, secret)?.sync_history()?;
+    let runs = runs.iter()
+        .rev()
+        .filter(|run| args.source.as_ref().is_none_or(|source| &run.source_id == source))
+        .take(args.limit)
+        .collect::<Vec<_>>();
+    if runs.is_empty() {
+        println!("No syncs recorded");
+        return Ok(());
+    }
+    println!("{:>5}  {:<16}  {:>8}  {:<20}  {:>7}  {:>7}  {:>7}  {:>7}", "ID", "STARTED", "DURATION", "SOURCE", "STORED", "SKIPPED", "IGNORED", "ERRORS");
+    for run in runs {
+        println!(
+            "{:>5}  {:<16}  {:>8}  {:<20}  {:>7}  {:>7}  {:>7}  {:>7}",
+            run.id,
+            format_timestamp(run.started_at),
+            format!("{}s", run.ended_at - run.started_at),
+            run.source_name,
+            run.stored,
+            run.skipped,
+            run.ignored,
+            run.errored + run.scan_errors,
+        );
+    }
+    Ok(())
+}
+
+fn show_sync_run(args: HistoryShowCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let runs = PhotoArchive::open(&target, secret)?.sync_history()?;
+    let run = runs.iter()
+        .find(|run| run.id == args.id)
+        .ok_or_else(|| anyhow!("No sync recorded with id {}", args.id))?;
+    let options = &run.options;
+    println!("Sync {} of {} ({})", run.id, run.source_name, run.source_id);
+    if let Some(rerun_of) = run.rerun_of {
+        println!("      retried the failures of sync {rerun_of}");
+    }
+    println!("      started:   {}", format_timestamp(run.started_at));
+    println!("      ended:     {} ({}s)", format_timestamp(run.ended_at), run.ended_at - run.started_at);
+    println!(
+        "      options:   full scan {}, full check {}, prune {}, junk dirs {}, min dimension {}px, max size {}, {} workers, {} retries",
+        options.full_scan,
+        options.full_check,
+        options.prune,
+        options.include_junk_dirs,
+        options.min_dimension,
+        options.max_file_size.map(|size| format!("{size} bytes")).unwrap_or_else(|| String::from("none")),
+        options.max_workers,
+        options.retries,
+    );
+    println!(
+        "      counts:    stored {} ({} bytes), skipped {}, moved {}, ignored {}, pruned {}, errored {}, unreadable dirs {}",
+        run.stored, run.bytes, run.skipped, run.moved, run.ignored, run.pruned, run.errored, run.scan_errors,
+    );
+    if !run.failures.is_empty() {
+        println!("      failures:");
+        for failure in run.failures.iter() {
+            println!("        {} - {}", failure.path.display(), failure.cause);
+        }
+        let unrecorded = run.unrecorded_failures();
+        if unrecorded > 0 {
+            println!("        ... and {unrecorded} more");
+        }
+    }
+    Ok(())
+}
+
+fn write_history_report(args: HistoryReportCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let runs = PhotoArchive::open(&target, secret)?.sync_history()?;
+    let run = match args.id {
+        Some(id) => runs.iter().find(|run| run.id == id).ok_or_else(|| anyhow!("No sync recorded with id {id}"))?,
+        None => runs.last().ok_or_else(|| anyhow!("No syncs recorded"))?,
+    };
+    let path = write_sync_report(&target, run, args.format)?;
+    println!("Report written to {}", path.display());
+    if args.open {
+        open_with_system_viewer(&path)?;
+    }
+    Ok(())
+}
+
+fn format_timestamp(ts: i64) -> String {
+    DateTime::from_timestamp(ts, 0)
+        .map(|ts| ts.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn list_albums(args: AlbumListCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let albums = PhotoArchive::open(&target, secret)?.albums()?;
+    if albums.iter().next().is_none() {
+        println!("No albums, create one with `album create <name>`");
+    }
+    for album in albums.iter() {
+        println!("{:<30} {:>8} photos", album.name, album.photos.len());
+    }
+    Ok(())
+}
+
+fn create_album(args: AlbumNameCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    PhotoArchive::open(&target, secret)?.update_albums(|albums| albums.create(&args.name))?;
+    println!("Created album '{}'", args.name.trim());
+    Ok(())
+}
+
+fn delete_album(args: AlbumNameCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let archive = PhotoArchive::open(&target, secret)?;
+    let photos = archive.albums()?.get(&args.name)
+        .map(|album| album.photos.len())
+        .ok_or_else(|| anyhow::anyhow!("No album named '{}'", args.name))?;
+    println!("Album '{}' holds {photos} photos, they stay in the archive", args.name);
+    if confirm("Delete the album?")? {
+        archive.update_albums(|albums| albums.delete(&args.name))?;
+        println!("Deleted album '{}'", args.name);
+    }
+    Ok(())
+}
+
+/// Digests of the photos matching the filter, each listed once
+fn matching_digests(target: &Path, secret: Option<&ArchiveSecret>, filter: CatalogFilterCliArgs) -> anyhow::Result<Vec<Digest>> {
+    let filter = CatalogFilter::from(filter);
+    let catalog = load_filtered_catalog(target, secret, &filter)?;
+    let mut seen = HashSet::new();
+    Ok(catalog.filter(&filter)
+        .map(|entry| entry.digest.clone())
+        .filter(|digest| seen.insert(digest.clone()))
+        .collect())
+}
+
+fn add_to_album(args: AlbumPhotosCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let digests = matching_digests(&target, secret.as_ref(), args.filter)?;
+    let matching = digests.len();
+    let added = PhotoArchive::open(&target, secret)?.update_albums(|albums| albums.add(&args.album_name, digests))?;
+    println!("Added {added} photos to '{}', {} of the {matching} matching were already there", args.album_name, matching - added);
+    Ok(())
+}
+
+fn remove_from_album(args: AlbumPhotosCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    let secret = read_secret(&args.encryption, false)?;
+    let digests = matching_digests(&target, secret.as_ref(), args.filter)?;
+    let removed = PhotoArchive::open(&target, secret)?.update_albums(|albums| albums.remove(&args.album_name, digests))?;
+    println!("Took {removed} photos out of '{}'", args.album_name);
+    Ok(())
+}
+
+fn export_immich(args: ExportImmichCliArgs, verbose: bool) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let api_key = match args.api_key.or_else(|| std::env::var("IMMICH_API_KEY").ok().filter(|key| !key.is_empty())) {
+        Some(api_key) => api_key,
+        None => {
+            ensure_interactive(&["--api-key"])?;
+            Password::new("Immich API key")
+                .without_confirmation()
+                .prompt()
+                .context("Error reading API key")?
+        }
+    };
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let entries = catalog.filter(&filter).collect::<Vec<_>>();
+    let opts = ImmichOpts {
+        server: args.server,
+        api_key,
+        originals_only: args.originals_only,
+    };
+    let stats = export_to_immich(&catalog, &entries, &opts, |entry, outcome| print_export_outcome(entry, outcome, verbose))?;
+    print_export_stats(&stats)
+}
+
+fn export_photoprism(args: ExportPhotoprismCliArgs, verbose: bool) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let entries = catalog.filter(&filter).collect::<Vec<_>>();
+    let opts = PhotoPrismOpts {
+        originals_only: args.originals_only,
+    };
+    let stats = export_to_photoprism(&catalog, &entries, &args.output, &opts, |entry, outcome| print_export_outcome(entry, outcome, verbose))?;
+    print_export_stats(&stats)
+}
+
+fn export_clips(args: ExportMotionClipsCliArgs, verbose: bool) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter { motion: true, ..CatalogFilter::from(args.filter) };
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let entries = catalog.filter(&filter).collect::<Vec<_>>();
+    let stats = export_motion_clips(&catalog, &entries, &args.output, |entry, outcome| print_export_outcome(entry, outcome, verbose))?;
+    print_export_stats(&stats)
+}
+
+fn export_year_reviews(args: ExportYearReviewCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let opts = YearReviewOpts {
+        years: args.years,
+        source_id: args.source,
+        photos_per_month: usize::from(args.photos_per_month),
+        overwrite: args.overwrite,
+    };
+    for review in export_year_review(&target, secret.as_ref(), &args.output, &opts)? {
+        println!(
+            "{}: {} photos, {} geotagged, {} pages - {}",
+            review.year,
+            review.photos,
+            review.geotagged,
+            review.pages,
+            review.path.display(),
+        );
+    }
+    Ok(())
+}
+
+/// Line of an exported photo, only failures are printed without --verbose
+fn print_export_outcome(entry: &CatalogEntry, outcome: &ExportOutcome, verbose: bool) {
+    match outcome {
+        ExportOutcome::Failed(err) => eprintln!("[ERR] {} {} - {err}", entry.digest, entry.source_path.display()),
+        ExportOutcome::Exported if verbose => println!("[EXP] {} {}", entry.digest, entry.source_path.display()),
+        ExportOutcome::Existing if verbose => println!("[UPD] {} {}", entry.digest, entry.source_path.display()),
+        ExportOutcome::Skipped(reason) if verbose => println!("[SKP] {} {} - {reason}", entry.digest, entry.source_path.display()),
+        _ => {}
+    }
+}
+
+fn print_export_stats(stats: &ExportStats) -> anyhow::Result<()> {
+    println!("Exported: {}, already there: {}, skipped: {}, errors: {}", stats.exported, stats.existing, stats.skipped, stats.failed);
+    if stats.failed > 0 {
+        anyhow::bail!("{} photos could not be exported", stats.failed)
+    }
+    Ok(())
+}
+
+fn review_archive_duplicates(args: DuplicatesCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let filter = CatalogFilter::from(args.filter);
+    let catalog = load_filtered_catalog(&target, secret.as_ref(), &filter)?;
+    let groups = find_duplicates(&catalog, &filter);
+    let Some(plan) = review_duplicates(&catalog, groups)? else {
+        println!("Review abandoned, the archive is unchanged");
+        return Ok(());
+    };
+    if plan.is_empty() {
+        println!("All copies kept, the archive is unchanged");
+        return Ok(());
+    }
+
+    for removal in plan.remove.iter() {
+        println!("[DEL] {} {} {}", removal.digest, removal.source_id, removal.source_path.display());
+    }
+    let summary = preview_plan(&target, secret.as_ref(), &plan)?;
+    println!("Dropping {} copies removes {} from the archive, source files are left untouched", plan.remove.len(), format_removal(&summary));
+    if args.dry_run {
+        return Ok(());
+    }
+
+    if confirm("Drop these copies from the archive?")? {
+        execute_plan(target, secret.as_ref(), &plan)?;
+        println!("Dropped {} copies", plan.remove.len());
+    }
+    Ok(())
+}
+
+fn print_photo_paths(catalog: &Catalog, entry: &CatalogEntry) {
+    let thumbnail = entry.thumbnail_path.as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| String::from("indexed without thumbnail"));
+    let original = catalog.mounted_original(entry)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| String::from("source not mounted"));
+    println!("{} [{}] {}", entry.digest, catalog.source_name(entry), entry.source_path.display());
+    println!("      thumbnail: {thumbnail}");
+    println!("      original:  {original}");
+    if let Some(caption) = &entry.caption {
+        println!("      caption:   {caption}");
+    }
+    if entry.rating.is_some() || entry.favorite {
+        println!("      rating:    {}", format_rating(entry));
+    }
+    if !entry.albums.is_empty() {
+        println!("      albums:    {}", entry.albums.join(", "));
+    }
+    if !entry.tags.is_empty() {
+        println!("      tags:      {}", entry.tags.join(", "));
+    }
+    if !entry.exif.is_empty() {
+        println!("      exif:      {}", entry.exif);
+    }
+}
+
+/// Stars of the photo, e.g. '★★★☆☆ favorite'
+fn format_rating(entry: &CatalogEntry) -> String {
+    let stars = entry.rating.unwrap_or(0) as usize;
+    let mut rating = format!("{}{}", "★".repeat(stars), "☆".repeat(5 - stars));
+    if entry.favorite {
+        rating.push_str(" favorite");
+    }
+    rating
+}
+
+fn export_metadata_snapshot(args: ExportMetadataCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let stats = export_metadata(&target, &args.output)?;
+    println!("Exported {} files ({:.1} MiB) to {}", stats.files, stats.bytes as f64 / (1024.0 * 1024.0), args.output.display());
+    Ok(())
+}
+
+fn serve_archive_metrics(args: ServeMetricsCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    // The runs of encrypted archives are only exported given their secret, the other metrics need none
+    let cipher = match &secret {
+        Some(secret) => ArchiveManifest::load_or_default(&target)?.cipher(Some(secret))?,
+        None => None,
+    };
+    let listener = std::net::TcpListener::bind(&args.listen).with_context(|| format!("Error listening on {}", args.listen))?;
+    println!("Serving the metrics of {} on http://{}/metrics", target.display(), listener.local_addr()?);
+    serve_metrics(&target, listener, cipher.as_deref())
+}
+
+fn export_index_database(args: ExportIndexCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let secret = read_secret(&args.encryption, false)?;
+    let stats = export_index(&target, secret.as_ref(), &args.output, args.format, args.overwrite)?;
+    println!("Exported {} photos, {} sources and {} albums to {}", stats.photos, stats.sources, stats.albums, args.output.display());
+    Ok(())
+}
+
+fn restore_metadata_snapshot(args: RestoreMetadataCliArgs) -> anyhow::Result<()> {
+    let target = archive_target(args.target)?;
+    if !target.is_dir() {
+        anyhow::bail!("Target path is not a directory")
+    }
+
+    let stats = restore_metadata(&target, &args.input, args.overwrite)?;
+    println!("Restored {} files ({:.1} MiB), removed {} stale index files", stats.files, stats.bytes as f64 / (1024.0 * 1024.0), stats.removed);
+    Ok(())
+}
+
+fn read_secret(args: &EncryptionCliArgs, confirm: bool) -> anyhow::Result<Option<ArchiveSecret>> {
+    if let Some(key_file) = &args.key_file {
+        return Ok(Some(ArchiveSecret::from_key_file(key_file).context("Error reading key file")?));
+    }
+    if !args.passphrase {
+        return Ok(None);
+    }
+
+    ensure_interactive(&["--key-file"])?;
+    let mut prompt = Password::new("Archive passphrase");
+    if !confirm {
+        prompt = prompt.without_confirmation();
+    }
+    let passphrase = prompt.prompt().context("Error reading passphrase")?;
+    Ok(Some(ArchiveSecret::from_passphrase(&passphrase)))
+}
@@ -0,0 +1,43 @@
+use photo_archive::archive::notifications::{send_sync_hook, SyncHook};
+use photo_archive::archive::sync_history::SyncRun;
+
+use crate::config::NotificationsConfig;
+use crate::prompt::is_interactive;
+
+/// Announces the syncs of a command as configured by the user
+#[derive(Default)]
+pub struct Notifier {
+    desktop: bool,
+    hooks: Vec<SyncHook>,
+}
+
+impl Notifier {
+    pub fn new(config: NotificationsConfig) -> Self {
+        // Nobody is watching the desktop of scheduled syncs
+        Self { desktop: config.desktop && is_interactive(), hooks: config.hooks }
+    }
+
+    /// Tell the hooks about the run, a failing hook is reported without failing the sync
+    pub fn sync_completed(&self, run: &SyncRun) {
+        for hook in self.hooks.iter() {
+            if let Err(err) = send_sync_hook(hook, run) {
+                eprintln!("{err}");
+            }
+        }
+    }
+
+    /// Desktop notification sent once every sync of the command is over
+    pub fn syncs_finished(&self, title: &str, body: &str) {
+        if !self.desktop {
+            return;
+        }
+        let res = notify_rust::Notification::new()
+            .appname("photo-archive")
+            .summary(title)
+            .body(body)
+            .show();
+        if let Err(err) = res {
+            eprintln!("Error showing desktop notification - {err}");
+        }
+    }
+}
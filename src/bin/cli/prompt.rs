@@ -0,0 +1,41 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use inquire::Confirm;
+
+static INTERACTIVE: OnceLock<bool> = OnceLock::new();
+static ASSUME_YES: OnceLock<bool> = OnceLock::new();
+
+/// Allow prompts unless `non_interactive` is set or stdin is not a terminal, prompts would then
+/// wait for an answer that never comes (cron jobs, scripts, pipes). `assume_yes` answers the
+/// confirmations of destructive operations.
+pub fn init(non_interactive: bool, assume_yes: bool) {
+    let _ = INTERACTIVE.set(!non_interactive && std::io::stdin().is_terminal());
+    let _ = ASSUME_YES.set(assume_yes);
+}
+
+pub fn is_interactive() -> bool {
+    *INTERACTIVE.get_or_init(|| std::io::stdin().is_terminal())
+}
+
+/// Fail instead of prompting when running non-interactively, `missing` lists the arguments
+/// which would have spared the prompts
+pub fn ensure_interactive(missing: &[&str]) -> anyhow::Result<()> {
+    if !is_interactive() {
+        anyhow::bail!("Missing {}, prompts are disabled when running non-interactively", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Ask before deleting data, the summary of what goes away being already printed
+pub fn confirm(question: &str) -> anyhow::Result<bool> {
+    if ASSUME_YES.get().copied().unwrap_or(false) {
+        return Ok(true);
+    }
+    ensure_interactive(&["--yes to confirm the removal"])?;
+    Confirm::new(question)
+        .with_default(false)
+        .prompt()
+        .context("Error reading confirmation")
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io::{stdout, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use photo_archive::archive::catalog::{Catalog, CatalogEntry};
+
+use crate::term_image::ImageProtocol;
+
+const VIEWER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct SlideshowOpts {
+    pub interval: Duration,
+    /// Show the originals of the mounted sources instead of the thumbnails
+    pub originals: bool,
+    /// External viewer command, the photo path is appended to its arguments
+    pub viewer: Option<String>,
+    pub protocol: ImageProtocol,
+}
+
+/// Cycle through the given photos, drawn fullscreen in the terminal or shown by an external viewer
+pub fn slideshow(catalog: &Catalog, entries: &[&CatalogEntry], opts: &SlideshowOpts) -> anyhow::Result<()> {
+    if entries.is_empty() {
+        anyhow::bail!("No photo matches the given filters");
+    }
+    match &opts.viewer {
+        Some(viewer) => external_slideshow(catalog, entries, viewer, opts),
+        None => terminal_slideshow(catalog, entries, opts),
+    }
+}
+
+fn photo_file(catalog: &Catalog, entry: &CatalogEntry, originals: bool) -> anyhow::Result<std::path::PathBuf> {
+    match catalog.mounted_original(entry).filter(|_| originals) {
+        Some(original) => Ok(original),
+        None => catalog.thumbnail_file(entry),
+    }
+}
+
+fn caption(catalog: &Catalog, entry: &CatalogEntry) -> String {
+    let date = entry.timestamp.map(|ts| ts.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_else(|| String::from("no date"));
+    format!("{date}  {}  {}", catalog.source_name(entry), entry.source_path.display())
+}
+
+/// Show each photo with the external viewer for the interval, closing the viewer stops the slideshow
+fn external_slideshow(catalog: &Catalog, entries: &[&CatalogEntry], viewer: &str, opts: &SlideshowOpts) -> anyhow::Result<()> {
+    let mut viewer_args = viewer.split_whitespace();
+    let program = viewer_args.next().ok_or_else(|| anyhow::anyhow!("Viewer command is empty"))?;
+    let viewer_args = viewer_args.collect::<Vec<_>>();
+
+    for (idx, entry) in entries.iter().enumerate() {
+        let path = match photo_file(catalog, entry, opts.originals) {
+            Ok(path) => path,
+            Err(err) => {
+                eprintln!("Error showing {} - {err}", entry.source_path.display());
+                continue;
+            }
+        };
+        println!("[{}/{}] {}", idx + 1, entries.len(), caption(catalog, entry));
+        let mut child = Command::new(program)
+            .args(&viewer_args)
+            .arg(&path)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|err| anyhow::anyhow!("Error running {program} - {err}"))?;
+
+        let started = Instant::now();
+        while started.elapsed() < opts.interval {
+            if child.try_wait()?.is_some() {
+                println!("Viewer closed, slideshow stopped");
+                return Ok(());
+            }
+            thread::sleep(VIEWER_POLL_INTERVAL);
+        }
+        child.kill()?;
+        child.wait()?;
+    }
+    Ok(())
+}
+
+/// Draw each photo fullscreen with a caption, Space pauses, arrows move, q quits
+fn terminal_slideshow(catalog: &Catalog, entries: &[&CatalogEntry], opts: &SlideshowOpts) -> anyhow::Result<()> {
+    let protocol = opts.protocol.resolve();
+    if protocol == ImageProtocol::None {
+        anyhow::bail!("Terminal has no image support, choose an image protocol or an external viewer");
+    }
+
+    let mut out = stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, Hide)?;
+    let shown = run_terminal_slideshow(catalog, entries, opts, protocol, &mut out);
+    protocol.clear(&mut out)?;
+    execute!(out, Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    shown
+}
+
+fn run_terminal_slideshow(
+    catalog: &Catalog,
+    entries: &[&CatalogEntry],
+    opts: &SlideshowOpts,
+    protocol: ImageProtocol,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let mut idx = 0;
+    let mut paused = false;
+    loop {
+        let entry = entries[idx];
+        let (cols, rows) = terminal::size()?;
+        protocol.clear(out)?;
+        queue!(out, Clear(ClearType::All), MoveTo(0, rows.saturating_sub(1)))?;
+        let status = if paused { "  [paused]" } else { "" };
+        write!(out, "[{}/{}] {}{status}", idx + 1, entries.len(), caption(catalog, entry))?;
+        let drawn = photo_file(catalog, entry, opts.originals)
+            .and_then(|path| Ok(fs::read(path)?))
+            .and_then(|content| protocol.draw(out, &content, 0, 0, cols, rows.saturating_sub(1)));
+        if let Err(err) = drawn {
+            queue!(out, MoveTo(0, 0))?;
+            write!(out, "Error showing photo - {err}")?;
+        }
+        out.flush()?;
+
+        // Wait for the interval to elapse or for a key, whichever comes first
+        let shown_at = Instant::now();
+        let next = loop {
+            let timeout = if paused { PAUSED_POLL_INTERVAL } else { opts.interval.saturating_sub(shown_at.elapsed()) };
+            if !event::poll(timeout)? {
+                if paused {
+                    continue;
+                }
+                break (idx + 1) % entries.len();
+            }
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char(' ') => {
+                        paused = !paused;
+                        break idx;
+                    }
+                    KeyCode::Right | KeyCode::Char('l') => break (idx + 1) % entries.len(),
+                    KeyCode::Left | KeyCode::Char('h') => break (idx + entries.len() - 1) % entries.len(),
+                    _ => {}
+                },
+                Event::Resize(..) => break idx,
+                _ => {}
+            }
+        };
+        idx = next;
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt::{Display, Formatter};
+use std::io::{stdout, IsTerminal, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::MoveToColumn;
+use crossterm::queue;
+use crossterm::terminal::{Clear, ClearType};
+use photo_archive::archive::sync::{SynchronizationEvent, SyncrhonizationTask};
+use photo_archive::archive::sync_history::SyncRun;
+
+use crate::notify::Notifier;
+
+/// Exit status of the commands whose syncs left more errored files than tolerated
+pub const ERRORED_FILES_EXIT_CODE: i32 = 3;
+
+/// Status lines printed when stdout is not a terminal, e.g. in the logs of a scheduled sync
+const LOGGED_STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Counters of a completed sync
+pub struct SyncTotals {
+    pub stored: u64,
+    pub skipped: u64,
+    pub moved: u64,
+    pub ignored: u64,
+    pub errored: u64,
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Record of the run, missing from the totals of several syncs
+    pub run: Option<Box<SyncRun>>,
+}
+
+/// Syncs run by one command, one row per source
+pub struct SyncReport {
+    rows: Vec<(String, anyhow::Result<Option<SyncTotals>>)>,
+    notifier: Notifier,
+}
+
+impl SyncReport {
+    pub fn new(notifier: Notifier) -> Self {
+        Self { rows: Vec::new(), notifier }
+    }
+
+    /// Outcome of the sync of the source, `None` when it was cancelled
+    pub fn push(&mut self, label: String, out: anyhow::Result<Option<SyncTotals>>) {
+        if let Some(run) = out.as_ref().ok().and_then(Option::as_ref).and_then(|totals| totals.run.as_ref()) {
+            self.notifier.sync_completed(run);
+        }
+        self.rows.push((label, out));
+    }
+
+    /// Print the summary table, failing when a sync failed or more than `max_errors` files errored
+    pub fn finish(self, max_errors: u64) -> anyhow::Result<()> {
+        let completed = self.rows.iter().filter_map(|(_, out)| out.as_ref().ok().and_then(Option::as_ref)).collect::<Vec<_>>();
+        let total = (self.rows.len() > 1).then(|| SyncTotals {
+            stored: completed.iter().map(|totals| totals.stored).sum(),
+            skipped: completed.iter().map(|totals| totals.skipped).sum(),
+            moved: completed.iter().map(|totals| totals.moved).sum(),
+            ignored: completed.iter().map(|totals| totals.ignored).sum(),
+            errored: completed.iter().map(|totals| totals.errored).sum(),
+            bytes: completed.iter().map(|totals| totals.bytes).sum(),
+            duration: completed.iter().map(|totals| totals.duration).sum(),
+            run: None,
+        });
+        let errored = completed.iter().map(|totals| totals.errored).sum();
+
+        let label_width = self.rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0).max("SOURCE".len());
+        println!("{:label_width$}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>10}  {:>9}", "SOURCE", "STORED", "SKIPPED", "MOVED", "IGNORED", "ERRORED", "SIZE (MiB)", "DURATION");
+        for (label, out) in self.rows.iter() {
+            match out {
+                Ok(Some(totals)) => print_totals_row(label, label_width, totals),
+                Ok(None) => println!("{label:label_width$}  cancelled"),
+                Err(err) => println!("{label:label_width$}  failed - {err}"),
+            }
+        }
+        if let Some(total) = total {
+            print_totals_row("TOTAL", label_width, &total);
+        }
+
+        let failed = self.rows.iter().filter(|(_, out)| out.is_err()).count();
+        let title = match (failed, errored) {
+            (0, 0) => String::from("Photo archive sync completed"),
+            (0, errored) => format!("Photo archive sync completed, {errored} files errored"),
+            (failed, _) => format!("Photo archive sync failed for {failed} of {} sources", self.rows.len()),
+        };
+        let body = self.rows.iter()
+            .map(|(label, out)| match out {
+                Ok(Some(totals)) => format!("{label}: stored {}, errored {}", totals.stored, totals.errored),
+                Ok(None) => format!("{label}: cancelled"),
+                Err(err) => format!("{label}: failed - {err}"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.notifier.syncs_finished(&title, &body);
+
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} sources failed to sync", self.rows.len());
+        }
+        if errored > max_errors {
+            return Err(TooManyErrors { errored, max_errors }.into());
+        }
+        Ok(())
+    }
+}
+
+fn print_totals_row(label: &str, label_width: usize, totals: &SyncTotals) {
+    println!(
+        "{label:label_width$}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>10.1}  {:>9}",
+        totals.stored,
+        totals.skipped,
+        totals.moved,
+        totals.ignored,
+        totals.errored,
+        totals.bytes as f64 / (1024.0 * 1024.0),
+        format_duration(totals.duration),
+    );
+}
+
+/// Syncs completed leaving more errored files than tolerated, the command exits with
+/// [`ERRORED_FILES_EXIT_CODE`] so that scripts tell partial syncs from failed ones
+#[derive(Debug)]
+pub struct TooManyErrors {
+    pub errored: u64,
+    pub max_errors: u64,
+}
+
+impl Display for TooManyErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} files could not be archived ({} tolerated), sync again to retry them", self.errored, self.max_errors)
+    }
+}
+
+impl std::error::Error for TooManyErrors {}
+
+/// Print the events of the sync until it completes. A status line updated in place reports the
+/// progress, only errors get a line of their own unless `verbose` lists every file.
+pub fn print_events(task: &SyncrhonizationTask, verbose: bool) -> Option<SyncTotals> {
+    let mut status = StatusLine::new();
+    let mut totals = None;
+    while let Ok(evt) = task.evt_stream().recv() {
+        match evt {
+            SynchronizationEvent::Progress { processed, total, scan_completed, throughput, eta } => {
+                let position = if scan_completed {
+                    format!("{processed}/{total} ({:02.02}%)", processed as f32 / total.max(1) as f32 * 100.0)
+                } else {
+                    format!("{processed}/{total}+ (scanning)")
+                };
+                let eta = eta.filter(|_| scan_completed).map(format_duration).unwrap_or_else(|| String::from("-"));
+                status.update(format!(
+                    "{position} - {throughput:.1} img/s - stored: {}; skipped: {}; errored: {} - ETA {eta}",
+                    status.stored, status.skipped, status.errored,
+                ));
+            }
+            SynchronizationEvent::Stored { src, dst, generated, partial, .. } => {
+                status.stored += 1;
+                if verbose {
+                    status.print(format!("[STR] {src:?} -> {dst:?} [gen: {generated}; par: {partial}]"));
+                }
+            }
+            SynchronizationEvent::Skipped { src, existing } => {
+                status.skipped += 1;
+                if verbose {
+                    status.print(format!("[SKP] {src:?} (existing: {existing:?})"));
+                }
+            }
+            SynchronizationEvent::Moved { src, previous } if verbose => status.print(format!("[MOV] {src:?} (previous: {previous:?})")),
+            SynchronizationEvent::Ignored { src, cause } if verbose => status.print(format!("[IGN] {src:?} - {cause}")),
+            SynchronizationEvent::Pruned { src } if verbose => status.print(format!("[PRN] {src:?}")),
+            SynchronizationEvent::Errored { src, cause, retries } => {
+                status.errored += 1;
+                status.print(format!("[ERR] {src:?} - {cause} (retries: {retries})"));
+            }
+            SynchronizationEvent::ScanError { path, cause } => status.print(format!("[SCN] {path:?} - {cause}")),
+            SynchronizationEvent::SyncCompleted { stored, skipped, moved, ignored, errored, bytes, duration, run } => {
+                totals = Some(SyncTotals { stored, skipped, moved, ignored, errored, bytes, duration, run: Some(run) });
+            }
+            SynchronizationEvent::Moved { .. }
+            | SynchronizationEvent::Ignored { .. }
+            | SynchronizationEvent::Pruned { .. }
+            | SynchronizationEvent::ScanProgress { .. }
+            | SynchronizationEvent::ScanCompleted { .. }
+            | SynchronizationEvent::PipelineStats { .. } => {}
+        }
+    }
+    status.clear();
+    totals
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Last line of the terminal, rewritten at each progress report. Other lines are printed above
+/// it; without a terminal the status is printed as a regular line from time to time.
+struct StatusLine {
+    out: Stdout,
+    terminal: bool,
+    shown: bool,
+    last_logged: Option<Instant>,
+    stored: u64,
+    skipped: u64,
+    errored: u64,
+}
+
+impl StatusLine {
+    fn new() -> Self {
+        let out = stdout();
+        let terminal = out.is_terminal();
+        Self { out, terminal, shown: false, last_logged: None, stored: 0, skipped: 0, errored: 0 }
+    }
+
+    fn update(&mut self, line: String) {
+        if !self.terminal {
+            if self.last_logged.is_none_or(|last| last.elapsed() >= LOGGED_STATUS_INTERVAL) {
+                self.last_logged = Some(Instant::now());
+                println!("{line}");
+            }
+            return;
+        }
+        // Wrapped lines could not be rewritten in place
+        let width = crossterm::terminal::size().map_or(usize::MAX, |(columns, _)| usize::from(columns).saturating_sub(1));
+        let line = line.chars().take(width).collect::<String>();
+        let _ = queue!(self.out, MoveToColumn(0), Clear(ClearType::CurrentLine));
+        let _ = write!(self.out, "{line}");
+        let _ = self.out.flush();
+        self.shown = true;
+    }
+
+    fn print(&mut self, line: String) {
+        self.clear();
+        println!("{line}");
+    }
+
+    fn clear(&mut self) {
+        if self.shown {
+            let _ = queue!(self.out, MoveToColumn(0), Clear(ClearType::CurrentLine));
+            let _ = self.out.flush();
+            self.shown = false;
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crossterm::cursor::MoveTo;
+use crossterm::queue;
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// Kitty graphics protocol payloads must be sent in chunks of at most 4096 bytes
+const KITTY_CHUNK_SIZE: usize = 4096;
+/// Cell size assumed when the terminal does not report its size in pixels
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+/// How images are drawn inside the terminal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageProtocol {
+    /// Pick the protocol from the environment of the terminal
+    #[default]
+    Auto,
+    Kitty,
+    Iterm,
+    Sixel,
+    /// Do not draw images
+    None,
+}
+
+impl Display for ImageProtocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageProtocol::Auto => write!(f, "auto"),
+            ImageProtocol::Kitty => write!(f, "kitty"),
+            ImageProtocol::Iterm => write!(f, "iterm"),
+            ImageProtocol::Sixel => write!(f, "sixel"),
+            ImageProtocol::None => write!(f, "none"),
+        }
+    }
+}
+
+impl FromStr for ImageProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(ImageProtocol::Auto),
+            "kitty" => Ok(ImageProtocol::Kitty),
+            "iterm" => Ok(ImageProtocol::Iterm),
+            "sixel" => Ok(ImageProtocol::Sixel),
+            "none" => Ok(ImageProtocol::None),
+            other => anyhow::bail!("Unknown image protocol '{other}', expected one of auto, kitty, iterm, sixel, none"),
+        }
+    }
+}
+
+impl ImageProtocol {
+    /// Resolve `Auto` from the variables terminals export, terminals not recognized get no images
+    pub fn resolve(self) -> Self {
+        if self != ImageProtocol::Auto {
+            return self;
+        }
+        let var = |name: &str| std::env::var(name).unwrap_or_default().to_lowercase();
+        let (term, term_program) = (var("TERM"), var("TERM_PROGRAM"));
+        if std::env::var_os("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || term_program == "ghostty" {
+            ImageProtocol::Kitty
+        } else if ["iterm.app", "wezterm"].contains(&term_program.as_str()) {
+            ImageProtocol::Iterm
+        } else if term.contains("sixel") || term.starts_with("foot") || term == "mlterm" {
+            ImageProtocol::Sixel
+        } else {
+            ImageProtocol::None
+        }
+    }
+
+    /// Remove the images drawn so far, for the protocols whose images outlive a screen redraw
+    pub fn clear(&self, out: &mut impl Write) -> anyhow::Result<()> {
+        if *self == ImageProtocol::Kitty {
+            write!(out, "\x1b_Ga=d,d=A,q=2\x1b\\")?;
+        }
+        Ok(())
+    }
+
+    /// Draw `content` (any format the image crate decodes) centered in the given cell area,
+    /// scaled to fit while keeping its aspect ratio
+    pub fn draw(&self, out: &mut impl Write, content: &[u8], x: u16, y: u16, cols: u16, rows: u16) -> anyhow::Result<()> {
+        if matches!(self, ImageProtocol::Auto | ImageProtocol::None) || cols == 0 || rows == 0 {
+            return Ok(());
+        }
+
+        let img = image::load_from_memory(content)?;
+        let (cell_width, cell_height) = cell_size();
+        let scale = f64::min(
+            f64::from(u32::from(cols) * cell_width) / f64::from(img.width()),
+            f64::from(u32::from(rows) * cell_height) / f64::from(img.height()),
+        );
+        let width = ((f64::from(img.width()) * scale) as u32).max(1);
+        let height = ((f64::from(img.height()) * scale) as u32).max(1);
+        let used_cols = width.div_ceil(cell_width).min(u32::from(cols)) as u16;
+        let used_rows = height.div_ceil(cell_height).min(u32::from(rows)) as u16;
+        queue!(out, MoveTo(x + (cols - used_cols) / 2, y + (rows - used_rows) / 2))?;
+
+        match self {
+            ImageProtocol::Kitty => draw_kitty(out, &img.resize_exact(width, height, FilterType::Triangle), used_cols, used_rows)?,
+            ImageProtocol::Iterm => draw_iterm(out, content, used_cols, used_rows)?,
+            ImageProtocol::Sixel => draw_sixel(out, &img.resize_exact(width, height, FilterType::Triangle))?,
+            ImageProtocol::Auto | ImageProtocol::None => {}
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Size in pixels of a terminal cell
+fn cell_size() -> (u32, u32) {
+    crossterm::terminal::window_size()
+        .ok()
+        .filter(|size| size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0)
+        .map(|size| (u32::from(size.width / size.columns), u32::from(size.height / size.rows)))
+        .filter(|(width, height)| *width > 0 && *height > 0)
+        .unwrap_or(DEFAULT_CELL_SIZE)
+}
+
+fn draw_kitty(out: &mut impl Write, img: &DynamicImage, cols: u16, rows: u16) -> anyhow::Result<()> {
+    let rgb = img.to_rgb8();
+    let payload = STANDARD.encode(rgb.as_raw());
+    let chunks = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect::<Vec<_>>();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(idx + 1 < chunks.len());
+        if idx == 0 {
+            write!(out, "\x1b_Ga=T,f=24,s={},v={},c={cols},r={rows},q=2,m={more};", rgb.width(), rgb.height())?;
+        } else {
+            write!(out, "\x1b_Gm={more};")?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    Ok(())
+}
+
+fn draw_iterm(out: &mut impl Write, content: &[u8], cols: u16, rows: u16) -> anyhow::Result<()> {
+    write!(
+        out,
+        "\x1b]1337;File=inline=1;size={};width={cols};height={rows};preserveAspectRatio=1:{}\x07",
+        content.len(),
+        STANDARD.encode(content),
+    )?;
+    Ok(())
+}
+
+fn draw_sixel(out: &mut impl Write, img: &DynamicImage) -> anyhow::Result<()> {
+    let rgb = img.to_rgb8();
+    let sixel = icy_sixel::sixel_string(
+        rgb.as_raw(),
+        rgb.width() as i32,
+        rgb.height() as i32,
+        icy_sixel::PixelFormat::RGB888,
+        icy_sixel::DiffusionMethod::Stucki,
+        icy_sixel::MethodForLargest::Auto,
+        icy_sixel::MethodForRep::Auto,
+        icy_sixel::Quality::HIGH,
+    ).map_err(|err| anyhow::anyhow!("Error encoding sixel image - {err}"))?;
+    out.write_all(sixel.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+/// Open `path` with the default application of the desktop, without waiting for it to be closed
+pub fn open_with_system_viewer(path: &Path) -> anyhow::Result<()> {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    Command::new(opener)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| anyhow::anyhow!("Error running {opener} - {err}"))?;
+    Ok(())
+}
+
+/// Copy `text` to the clipboard through the OSC 52 escape sequence, which also works over ssh
+pub fn copy_to_clipboard(out: &mut impl Write, text: &str) -> anyhow::Result<()> {
+    write!(out, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    out.flush()?;
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use std::path::Path;
 use anyhow::bail;
 use serde::Deserialize;
-use crate::common::fs::model::{MountedPartitionInfo, PartitionInfo};
+use crate::common::fs::model::{MountedPartitionInfo, PartitionDetails, PartitionInfo};
 
 #[derive(Debug, Deserialize)]
 struct SourceMeta {
@@ -19,6 +19,8 @@ pub fn partition_by_path(path: &Path) -> anyhow::Result<MountedPartitionInfo> {
                 device_path: source_meta_file_path,
                 partition_id: meta.source_id,
             },
+            details: PartitionDetails::default(),
+            fingerprint: None,
         })
     } else {
         bail!("Could not find .photo-archive-source file in {path:?}")
@@ -1,12 +1,14 @@
-use anyhow::bail;
-use crate::common::fs::model::MountedPartitionInfo;
+use std::path::PathBuf;
+use crate::common::fs::model::{MountedPartitionInfo, PartitionLookupError};
 
 pub fn list_mounted_partitions() -> Result<Vec<MountedPartitionInfo>, std::io::Error> {
     eprintln!("!! partitions scan not yet implemented");
     Ok(Vec::new())
 }
 
-pub fn partition_by_id(partition_id: &str) -> anyhow::Result<MountedPartitionInfo> {
-    eprintln!("!! partitions scan not yet implemented");
-    bail!("no partition found")
-}
\ No newline at end of file
+pub fn partition_by_id(_partition_id: &str) -> Result<MountedPartitionInfo, PartitionLookupError> {
+    Err(PartitionLookupError::Unsupported)
+}
+pub fn partition_device_by_id(_partition_id: &str) -> Option<PathBuf> {
+    None
+}
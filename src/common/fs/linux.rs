@@ -1,26 +1,9 @@
 use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use anyhow::bail;
-use crate::common::fs::model::{MountedPartitionInfo, PartitionInfo, ProcMountEntry};
-
-fn disk_by_uuid_device_path(uuid: &str) -> PathBuf {
-    PathBuf::from("/dev/disk/by-uuid").join(uuid)
-}
-
-fn partition_info_by_uuid(uuid: &str) -> Result<PartitionInfo, std::io::Error> {
-    let device_path = disk_by_uuid_device_path(uuid);
-    let device_path = std::fs::read_link(&device_path)
-        .map(|rel| device_path.parent().unwrap().join(rel))
-        .and_then(std::fs::canonicalize)?;
-
-    Ok(PartitionInfo {
-        device_path,
-        partition_id: String::from(uuid),
-    })
-}
+use std::path::{Path, PathBuf};
+use crate::common::fs::lsblk::block_devices_details;
+use crate::common::fs::model::{MountedPartitionInfo, PartitionDetails, PartitionInfo, PartitionLookupError, ProcMountEntry, VolumeFingerprint};
 
 fn partitions_by_uuid_lookup() -> Result<HashMap<String, PartitionInfo>, std::io::Error> {
     let result = std::fs::read_dir("/dev/disk/by-uuid")?
@@ -45,30 +28,65 @@ fn partitions_by_uuid_lookup() -> Result<HashMap<String, PartitionInfo>, std::io
     Ok(result)
 }
 
-fn partitions_info_lookup() -> Result<HashMap<PathBuf, PartitionInfo>, std::io::Error> {
-    let mut result = partitions_by_uuid_lookup()?
-        .into_iter()
-        .map(|(partition_id, info)| (disk_by_uuid_device_path(&partition_id), info))
-        .flat_map(|(path, info)| [(info.device_path.clone(), info.clone()), (path, info)])
-        .collect::<HashMap<_, _>>();
+/// Device of the partition with the given id, when attached, whether it is mounted or not
+pub fn partition_device_by_id(partition_id: &str) -> Option<PathBuf> {
+    let lookup = PartitionsLookup::load().ok()?;
+    lookup.details.keys()
+        .chain(lookup.uuid_by_device.keys())
+        .find(|device| lookup.partition(device).is_some_and(|(info, _)| info.partition_id == partition_id))
+        .cloned()
+}
 
-    let mapped_devices = std::fs::read_dir("/dev/mapper")?
-        .filter_map(|path_res| path_res.ok())
-        .filter_map(|dir_entry| {
-            let device_path = std::fs::read_link(dir_entry.path())
-                .map(|rel| dir_entry.path().parent().unwrap().join(rel))
-                .and_then(std::fs::canonicalize)
-                .ok()?;
+/// Partitions indexed by the device nodes holding them
+struct PartitionsLookup {
+    uuid_by_device: HashMap<PathBuf, String>,
+    details: HashMap<PathBuf, PartitionDetails>,
+}
 
-            let current_entry = result.get(&device_path)?;
+impl PartitionsLookup {
+    fn load() -> Result<Self, std::io::Error> {
+        let uuid_by_device = partitions_by_uuid_lookup()?
+            .into_iter()
+            .map(|(partition_id, info)| (info.device_path, partition_id))
+            .collect::<HashMap<_, _>>();
+        Ok(Self { uuid_by_device, details: partition_details_lookup() })
+    }
 
-            Some((dir_entry.path(), current_entry.clone()))
-        })
-        .collect::<Vec<_>>();
+    /// Device node a partition is mounted from.
+    ///
+    /// Device-mapper names (`/dev/mapper/*`), LVM paths (`/dev/<vg>/<lv>`), `/dev/disk/by-uuid`
+    /// entries and the other aliases are chains of symlinks to a device node. Whatever the number
+    /// of stacked layers (e.g. LUKS over LVM over a partition), the topmost node is the one
+    /// holding the filesystem, and thus the one its UUID is registered on.
+    fn resolve(device: &str) -> PathBuf {
+        std::fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device))
+    }
 
-    result.extend(mapped_devices);
+    /// Info of the partition on the `device` node, identified by its filesystem UUID or, for
+    /// volumes without one, by the composite id of its fingerprint
+    fn partition(&self, device: &Path) -> Option<(PartitionInfo, Option<VolumeFingerprint>)> {
+        let serial = self.uuid_by_device.get(device).cloned();
+        let fingerprint = self.details.get(device)
+            .and_then(|details| Some(VolumeFingerprint {
+                serial: serial.clone(),
+                label: details.label.clone(),
+                capacity: details.size.filter(|size| *size > 0)?,
+            }));
+        let partition_id = serial.or_else(|| fingerprint.as_ref().map(VolumeFingerprint::composite_id))?;
+        Some((PartitionInfo { device_path: device.to_path_buf(), partition_id }, fingerprint))
+    }
 
-    Ok(result)
+    fn mounted_partition(&self, entry: ProcMountEntry) -> Option<MountedPartitionInfo> {
+        let device = Self::resolve(&entry.device);
+        let (info, fingerprint) = self.partition(&device)?;
+        Some(MountedPartitionInfo {
+            mount_point: entry.mount_point,
+            fs_type: entry.fs_type,
+            details: self.details.get(&device).cloned().unwrap_or_default(),
+            info,
+            fingerprint,
+        })
+    }
 }
 
 fn read_proc_mounts() -> Result<Vec<ProcMountEntry>, std::io::Error> {
@@ -80,10 +98,12 @@ fn read_proc_mounts() -> Result<Vec<ProcMountEntry>, std::io::Error> {
 
     while file.read_line(&mut line)? != 0 {
         let mut fields = line.split_whitespace();
-        let device = fields.next().unwrap();
-        let path = fields.next().unwrap().replace("\\040", " ");
-        let fs_type = fields.next().unwrap();
-        let mode = fields.next().unwrap();
+        let (Some(device), Some(path), Some(fs_type), Some(mode)) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            // Malformed lines are not expected from the kernel, they are skipped rather than trusted
+            line.clear();
+            continue;
+        };
+        let path = path.replace("\\040", " ");
 
         vdisks.push(ProcMountEntry {
             device: String::from(device),
@@ -102,48 +122,56 @@ fn read_proc_mounts() -> Result<Vec<ProcMountEntry>, std::io::Error> {
 }
 
 pub fn list_mounted_partitions() -> Result<Vec<MountedPartitionInfo>, std::io::Error> {
-    let lookup = partitions_info_lookup()?;
+    let lookup = PartitionsLookup::load()?;
 
     let vdisks = read_proc_mounts()?
         .into_iter()
         .filter(|entry| is_supported_fs(&entry.fs_type))
         .filter_map(|entry| {
-            let Some(partition_info) = lookup.get(&PathBuf::from(&entry.device)) else {
+            let partition = lookup.mounted_partition(entry);
+            if partition.is_none() {
                 eprintln!("No partition_info found");
-                return None;
-            };
-            Some(MountedPartitionInfo {
-                mount_point: entry.mount_point,
-                fs_type: entry.fs_type,
-                info: partition_info.clone(),
-            })
+            }
+            partition
         })
         .collect();
 
     Ok(vdisks)
 }
 
+/// Details of the block devices, empty when `lsblk` is not installed or fails: partitions with
+/// a filesystem UUID are still identified without them
+fn partition_details_lookup() -> HashMap<PathBuf, PartitionDetails> {
+    block_devices_details().unwrap_or_default()
+}
+
+/// Filesystems of removable media and Windows-formatted disks: FAT and exFAT camera cards, NTFS
+/// externals through the kernel drivers (`ntfs3`, legacy `ntfs`) and optical discs
+const SUPPORTED_FS: [&str; 5] = ["vfat", "exfat", "ntfs", "ntfs3", "iso9660"];
+/// FUSE drivers of the same filesystems: block-device backed ones are reported as `fuseblk`, the
+/// others with the `fuse.` prefix and their helper name
+const SUPPORTED_FUSE_HELPERS: [&str; 5] = ["exfat", "exfat-fuse", "ntfs-3g", "lowntfs-3g", "ntfs"];
+
 fn is_supported_fs(fs_type: &str) -> bool {
-    ["vfat", "ntfs3", "fuseblk", "iso9660"].contains(&fs_type)
+    fs_type == "fuseblk"
+        || SUPPORTED_FS.contains(&fs_type)
+        || fs_type.strip_prefix("fuse.").is_some_and(|helper| SUPPORTED_FUSE_HELPERS.contains(&helper))
 }
 
-pub fn partition_by_id(partition_id: &str) -> anyhow::Result<MountedPartitionInfo> {
-    let lookup = partitions_info_lookup()?;
-    let proc_mounts = read_proc_mounts()?
+/// The mounted partition with the given id, all the candidates are returned in the error when
+/// it is mounted more than once
+pub fn partition_by_id(partition_id: &str) -> Result<MountedPartitionInfo, PartitionLookupError> {
+    let lookup = PartitionsLookup::load()?;
+    let mut proc_mounts = read_proc_mounts()?
         .into_iter()
         .filter(|e| is_supported_fs(&e.fs_type))
-        .filter_map(|e| lookup.get(&PathBuf::from(&e.device)).map(|pi| (pi, e)))
-        .filter(|(pi, _e)| pi.partition_id.eq(partition_id))
-        .map(|(pi, e)| MountedPartitionInfo {
-            mount_point: e.mount_point,
-            fs_type: e.fs_type,
-            info: pi.clone(),
-        })
+        .filter_map(|e| lookup.mounted_partition(e))
+        .filter(|mpi| mpi.info.partition_id.eq(partition_id))
         .collect::<Vec<_>>();
 
-    match &proc_mounts[..] {
-        [] => bail!("No partition found"),
-        [mpi] => Ok(mpi.clone()),
-        [_, ..] => bail!("Multiple partitions with same id"),
+    match proc_mounts.len() {
+        0 => Err(PartitionLookupError::NotFound { partition_id: String::from(partition_id) }),
+        1 => Ok(proc_mounts.remove(0)),
+        _ => Err(PartitionLookupError::Ambiguous { partition_id: String::from(partition_id), candidates: proc_mounts }),
     }
 }
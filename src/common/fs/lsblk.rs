@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::common::fs::model::PartitionDetails;
+
+#[derive(Deserialize)]
+struct LsblkOutput {
+    blockdevices: Vec<LsblkDevice>,
+}
+
+/// Older util-linux releases print numbers and flags as strings, they are kept as raw values
+#[derive(Deserialize)]
+struct LsblkDevice {
+    name: String,
+    label: Option<String>,
+    size: Value,
+    model: Option<String>,
+    rm: Value,
+    pkname: Option<String>,
+}
+
+impl LsblkDevice {
+    fn size(&self) -> Option<u64> {
+        match &self.size {
+            Value::Number(size) => size.as_u64(),
+            Value::String(size) => size.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn removable(&self) -> bool {
+        match &self.rm {
+            Value::Bool(rm) => *rm,
+            Value::String(rm) => rm == "1",
+            Value::Number(rm) => rm.as_u64() == Some(1),
+            _ => false,
+        }
+    }
+}
+
+/// Label, size, model and removability of the block devices reported by `lsblk`, indexed by
+/// canonical device path.
+///
+/// Models and removability are reported on whole disks, partitions and device-mapper nodes
+/// inherit them by walking up their parents.
+pub(super) fn block_devices_details() -> anyhow::Result<HashMap<PathBuf, PartitionDetails>> {
+    let output = Command::new("lsblk")
+        .args(["--json", "--bytes", "--list", "--paths", "-o", "NAME,LABEL,SIZE,MODEL,RM,PKNAME"])
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!("lsblk exited with {}", output.status);
+    }
+    let devices = serde_json::from_slice::<LsblkOutput>(&output.stdout)?.blockdevices
+        .into_iter()
+        .map(|device| (device.name.clone(), device))
+        .collect::<HashMap<_, _>>();
+
+    let details = devices.values()
+        .map(|device| {
+            let mut model = device.model.clone();
+            let mut removable = device.removable();
+            let mut parent = device.pkname.as_ref().and_then(|name| devices.get(name));
+            // Bounded walk, device-mapper parents cannot form cycles but the output is not trusted
+            for _ in 0..devices.len() {
+                let Some(current) = parent else { break };
+                model = model.or_else(|| current.model.clone());
+                removable |= current.removable();
+                parent = current.pkname.as_ref().and_then(|name| devices.get(name));
+            }
+            let path = std::fs::canonicalize(&device.name).unwrap_or_else(|_| Path::new(&device.name).to_path_buf());
+            (path, PartitionDetails {
+                label: device.label.clone(),
+                size: device.size(),
+                model: model.map(|model| model.trim().to_string()).filter(|model| !model.is_empty()),
+                removable,
+            })
+        })
+        .collect();
+    Ok(details)
+}
@@ -1,5 +1,9 @@
+#[cfg(target_os = "linux")]
 mod linux;
+#[cfg(target_os = "linux")]
+mod lsblk;
 pub mod model;
+#[cfg(target_os = "freebsd")]
 mod freebsd;
 pub mod common;
 
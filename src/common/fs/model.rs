@@ -1,17 +1,77 @@
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the ids built from the fingerprint of volumes without serial
+pub const COMPOSITE_ID_PREFIX: &str = "vol-";
+
 #[derive(Debug, Clone)]
 pub struct PartitionInfo {
     pub device_path: PathBuf,
     pub partition_id: String,
 }
 
+/// Descriptive attributes of a partition, left empty when the platform does not report them
+#[derive(Debug, Clone, Default)]
+pub struct PartitionDetails {
+    pub label: Option<String>,
+    /// Size in bytes
+    pub size: Option<u64>,
+    /// Model of the disk holding the partition
+    pub model: Option<String>,
+    pub removable: bool,
+}
+
+/// What a volume reports about itself, identifying it even when its filesystem UUID is missing
+/// or changes on every reformat, as happens with some SD cards
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeFingerprint {
+    /// Volume serial (the filesystem UUID), missing on media not reporting one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Size of the partition in bytes
+    pub capacity: u64,
+}
+
+impl VolumeFingerprint {
+    /// Id of the volume made of its label and capacity, for media without serial
+    pub fn composite_id(&self) -> String {
+        let label = self.label.as_deref()
+            .map(|label| label.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect())
+            .unwrap_or_else(|| String::from("unlabeled"));
+        format!("{COMPOSITE_ID_PREFIX}{label}-{}", self.capacity)
+    }
+
+    /// Whether `other` is probably the same medium, possibly reformatted since: label and
+    /// capacity match, whatever the serial
+    pub fn same_medium(&self, other: &VolumeFingerprint) -> bool {
+        self.label == other.label && self.capacity == other.capacity
+    }
+}
+
+impl Display for VolumeFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "serial {}, label {}, {:.1} GiB",
+            self.serial.as_deref().unwrap_or("-"),
+            self.label.as_deref().unwrap_or("-"),
+            self.capacity as f64 / (1024.0 * 1024.0 * 1024.0),
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct MountedPartitionInfo {
     pub mount_point: PathBuf,
     pub fs_type: String,
     pub info: PartitionInfo,
+    pub details: PartitionDetails,
+    /// Missing when the capacity of the partition is unknown
+    pub fingerprint: Option<VolumeFingerprint>,
 }
 
 impl Display for MountedPartitionInfo {
@@ -25,10 +85,67 @@ impl Display for MountedPartitionInfo {
                 .to_str()
                 .map(ToString::to_string)
                 .unwrap_or_default()
-        )
+        )?;
+        if let Some(label) = &self.details.label {
+            write!(f, "\t{label}")?;
+        }
+        if let Some(size) = self.details.size {
+            write!(f, "\t{:.1} GiB", size as f64 / (1024.0 * 1024.0 * 1024.0))?;
+        }
+        if let Some(model) = &self.details.model {
+            write!(f, "\t{model}")?;
+        }
+        if self.details.removable {
+            write!(f, "\t[removable]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Why no single mounted partition could be found for an id
+#[derive(Debug)]
+pub enum PartitionLookupError {
+    /// No mounted partition has the id
+    NotFound { partition_id: String },
+    /// Several mounts share the id (e.g. bind mounts of the same partition), the caller picks one
+    Ambiguous { partition_id: String, candidates: Vec<MountedPartitionInfo> },
+    /// The mount table or the partitions of the machine could not be read
+    Io(std::io::Error),
+    /// Partitions cannot be listed on this platform
+    Unsupported,
+}
+
+impl Display for PartitionLookupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PartitionLookupError::NotFound { partition_id } => write!(f, "No mounted partition with id {partition_id}"),
+            PartitionLookupError::Ambiguous { partition_id, candidates } => write!(
+                f,
+                "Partition {partition_id} is mounted on {}",
+                candidates.iter().map(|candidate| candidate.mount_point.display().to_string()).collect::<Vec<_>>().join(", "),
+            ),
+            PartitionLookupError::Io(err) => write!(f, "Error reading partitions - {err}"),
+            PartitionLookupError::Unsupported => write!(f, "Partitions scan is not supported on this platform"),
+        }
+    }
+}
+
+impl std::error::Error for PartitionLookupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PartitionLookupError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PartitionLookupError {
+    fn from(err: std::io::Error) -> Self {
+        PartitionLookupError::Io(err)
     }
 }
 
+#[allow(dead_code)]
 pub (super) struct ProcMountEntry {
     pub device: String,
     pub mount_point: PathBuf,
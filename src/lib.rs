@@ -1,3 +1,5 @@
 pub mod common;
 pub mod archive;
 pub mod repository;
+
+pub use archive::photo_archive::PhotoArchive;
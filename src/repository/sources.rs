@@ -4,16 +4,79 @@ use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 
+use crate::common::fs::model::VolumeFingerprint;
+
 pub struct SourcesRepo {
     archive_dir: PathBuf,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SourceJsonRow {
     pub id: String,
     pub name: String,
     pub group: String,
     pub tags: Vec<String>,
+    /// Minimum size of the smaller side of the archived images, overrides the archive one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_dimension: Option<u32>,
+    /// Totals of the photos archived from the source, missing until its first sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<SourceStats>,
+    /// Unix timestamp of the end of the last sync, missing until its first sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<i64>,
+    /// What the volume of the source reported at its last sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<VolumeFingerprint>,
+    /// Other partition ids of the source, recorded when its id changed (e.g. after a reformat)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+/// Totals of the index rows of a source, kept up to date by the syncs
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceStats {
+    pub photos: u64,
+    /// Photos without a capture date
+    pub undated: u64,
+    /// Size of the photo files on the source
+    pub original_bytes: u64,
+    /// Size of the thumbnails stored in the archive
+    pub thumbnail_bytes: u64,
+    /// Unix timestamp of the oldest dated photo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_photo: Option<i64>,
+    /// Unix timestamp of the newest dated photo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_photo: Option<i64>,
+}
+
+impl SourceStats {
+    /// Account for one more photo taken at `timestamp`
+    pub fn add(&mut self, timestamp: Option<i64>, original_bytes: u64, thumbnail_bytes: u64) {
+        self.photos += 1;
+        self.original_bytes += original_bytes;
+        self.thumbnail_bytes += thumbnail_bytes;
+        match timestamp {
+            Some(ts) => {
+                self.first_photo = Some(self.first_photo.map_or(ts, |first| first.min(ts)));
+                self.last_photo = Some(self.last_photo.map_or(ts, |last| last.max(ts)));
+            }
+            None => self.undated += 1,
+        }
+    }
+}
+
+impl SourceJsonRow {
+    /// Whether the partition with the given id holds this source
+    pub fn identified_by(&self, partition_id: &str) -> bool {
+        self.id == partition_id || self.aliases.iter().any(|alias| alias == partition_id)
+    }
+
+    /// Partition ids the source can be found with, its id first
+    pub fn partition_ids(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.id.as_str()).chain(self.aliases.iter().map(String::as_str))
+    }
 }
 
 impl Display for SourceJsonRow {
@@ -33,6 +96,11 @@ impl SourcesRepo {
         self.archive_dir.join("sources.ndjson")
     }
 
+    /// Whether any source was ever registered in the archive
+    pub fn exists(&self) -> bool {
+        self.db_path().exists()
+    }
+
     pub fn find_by_id(&self, source_id: &str) -> anyhow::Result<Option<SourceJsonRow>> {
         let db_path = self.db_path();
         if db_path.exists() {
@@ -50,6 +118,36 @@ impl SourcesRepo {
         }
     }
 
+    /// Source held by the partition with the given id, through its id or one of its aliases
+    pub fn find_by_partition_id(&self, partition_id: &str) -> anyhow::Result<Option<SourceJsonRow>> {
+        Ok(self.all()?.into_iter().find(|entry| entry.identified_by(partition_id)))
+    }
+
+    /// Sources whose last known volume looks like the given one, candidates for a migration when
+    /// an unknown partition shows up
+    pub fn find_same_medium(&self, fingerprint: &VolumeFingerprint) -> anyhow::Result<Vec<SourceJsonRow>> {
+        Ok(self.all()?
+            .into_iter()
+            .filter(|entry| entry.fingerprint.as_ref().is_some_and(|known| known.same_medium(fingerprint)))
+            .collect())
+    }
+
+    /// Make the partition with the new id hold the given source from now on, e.g. after its
+    /// medium was reformatted or its id scheme changed.
+    ///
+    /// The source keeps its id, under which its photos are archived, the new partition id is
+    /// recorded as an alias.
+    pub fn migrate_partition_id(&self, source_id: &str, partition_id: &str) -> anyhow::Result<SourceJsonRow> {
+        if let Some(holder) = self.find_by_partition_id(partition_id)? {
+            anyhow::bail!("Partition {partition_id} already holds source {} ('{}')", holder.id, holder.name);
+        }
+        let mut entry = self.find_by_id(source_id)?
+            .ok_or_else(|| anyhow::anyhow!("Source with id {source_id} is not registered"))?;
+        entry.aliases.push(String::from(partition_id));
+        self.update_entry(&entry)?;
+        Ok(entry)
+    }
+
     pub fn all(&self) -> anyhow::Result<Vec<SourceJsonRow>> {
         let db_path = self.db_path();
         if db_path.exists() {
@@ -67,8 +165,27 @@ impl SourcesRepo {
         }
     }
 
+    /// Replace the registered entry having the same id
+    pub fn update_entry(&self, entry: &SourceJsonRow) -> anyhow::Result<()> {
+        let entries = self.all()?;
+        if !entries.iter().any(|existing_entry| existing_entry.id == entry.id) {
+            anyhow::bail!("Source with id {} is not registered", entry.id);
+        }
+
+        let mut rows = String::new();
+        for existing_entry in &entries {
+            let row = if existing_entry.id == entry.id { entry } else { existing_entry };
+            rows.push_str(&serde_json::to_string(row)?);
+            rows.push('\n');
+        }
+        let temp_path = self.db_path().with_extension("ndjson.tmp");
+        std::fs::write(&temp_path, rows)?;
+        std::fs::rename(temp_path, self.db_path())?;
+        Ok(())
+    }
+
     pub fn write_entry(&self, entry: SourceJsonRow) -> anyhow::Result<()> {
-        if let Some(existing_entry) = self.find_by_id(&entry.id)? {
+        if let Some(existing_entry) = self.find_by_partition_id(&entry.id)? {
             anyhow::bail!("Source with id {} is already registered with name '{}'", existing_entry.id, existing_entry.name);
         }
         let new_row = serde_json::to_string(&entry)?;
@@ -79,8 +196,8 @@ impl SourcesRepo {
             .create(true)
             .open(self.db_path())?;
 
-        db_file.write(new_row.as_bytes())?;
-        db_file.write(b"\n")?;
+        db_file.write_all(new_row.as_bytes())?;
+        db_file.write_all(b"\n")?;
         Ok(())
     }
 }
\ No newline at end of file